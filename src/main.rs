@@ -1,13 +1,22 @@
-mod server;
-
 use std::{
     net::SocketAddr,
     path::{Path, PathBuf},
 };
 
-use clap::Parser;
-use dotenvy::dotenv;
-use tavily_hikari::{DEFAULT_UPSTREAM, TavilyProxy};
+use clap::{Parser, ValueEnum};
+use tavily_hikari::{
+    DEFAULT_UPSTREAM, QuotaBackend, TavilyProxy, TokenAffinityConfig, TokenAffinityStrategy,
+    check_database_storage, effective_startup_max_clock_skew_secs,
+    effective_startup_min_free_disk_mb, effective_startup_self_check_enabled,
+    effective_trusted_proxies, load_dotenv, server,
+};
+use tracing_subscriber::EnvFilter;
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum LogFormat {
+    Text,
+    Json,
+}
 
 #[derive(Debug, Parser)]
 #[command(author, version, about = "Tavily reverse proxy with key rotation")]
@@ -37,6 +46,10 @@ struct Cli {
     #[arg(long, env = "PROXY_DB_PATH", default_value = "data/tavily_proxy.db")]
     db_path: String,
 
+    /// Token 配额计数后端：`sqlite`（默认，每个实例独立）或 `redis://[user:password@]host[:port][/db]`（多实例共享）
+    #[arg(long, env = "QUOTA_BACKEND", hide_env_values = true)]
+    quota_backend: Option<String>,
+
     /// Web 静态资源目录（指向打包后的前端 dist）
     #[arg(long, env = "WEB_STATIC_DIR")]
     static_dir: Option<PathBuf>,
@@ -68,12 +81,70 @@ struct Cli {
         default_value = "https://api.tavily.com"
     )]
     usage_base: String,
+
+    /// 事件通知 Webhook URL（逗号分隔或重复传参，覆盖 `WEBHOOK_URLS`）
+    #[arg(long = "webhook-url", value_delimiter = ',')]
+    webhook_urls: Vec<String>,
+
+    /// 按路径前缀路由到其他上游：`<prefix>=<url>`（逗号分隔或重复传参，覆盖 `UPSTREAM_ROUTES`）
+    #[arg(long = "upstream-route", value_delimiter = ',')]
+    upstream_routes: Vec<String>,
+
+    /// WebSocket MCP 上游（`ws://` 或 `wss://`，覆盖 `MCP_WS_UPSTREAM`）
+    #[arg(long = "mcp-ws-upstream")]
+    mcp_ws_upstream: Option<String>,
+
+    /// 主密钥：32 字节随机数的 hex 或 base64 编码（如 `openssl rand -base64 32`），设置后以 AES-256-GCM 加密存储上游 Tavily API Key（已有明文 Key 启动时自动加密）
+    #[arg(long, env = "MASTER_KEY", hide_env_values = true)]
+    master_key: Option<String>,
+
+    /// `/api/*` 每个客户端（ForwardAuth 用户或 IP）每秒允许的请求数，0 关闭（覆盖 `RATE_LIMIT_RPS`）
+    #[arg(long)]
+    rate_limit_rps: Option<f64>,
+
+    /// `/api/*` 每个客户端允许的突发请求数，0 表示每秒请求数的两倍（覆盖 `RATE_LIMIT_BURST`）
+    #[arg(long)]
+    rate_limit_burst: Option<u32>,
+
+    /// 可信反向代理（逗号分隔的 IP 或 CIDR），仅信任它们转发的 `X-Forwarded-For` / `X-Real-IP`（覆盖 `TRUSTED_PROXIES`）
+    #[arg(long)]
+    trusted_proxies: Option<String>,
+
+    /// token→key 亲和策略：ttl、sticky（直到 key 耗尽或不可用）或 disabled（覆盖 `TOKEN_AFFINITY_STRATEGY`）
+    #[arg(long)]
+    token_affinity_strategy: Option<String>,
+
+    /// ttl 策略下 token 复用同一 key 的秒数（覆盖 `TOKEN_AFFINITY_TTL_SECS`）
+    #[arg(long)]
+    token_affinity_ttl_secs: Option<i64>,
+
+    /// 内存中保留的亲和映射上限（覆盖 `TOKEN_AFFINITY_MAX_ENTRIES`）
+    #[arg(long)]
+    token_affinity_max_entries: Option<usize>,
+
+    /// 将亲和映射持久化到 SQLite，重启后恢复（覆盖 `TOKEN_AFFINITY_PERSIST`）
+    #[arg(long)]
+    token_affinity_persist: Option<bool>,
+
+    /// 日志输出格式（text 或 json）；级别过滤由 `RUST_LOG` 控制，默认 info
+    #[arg(long, env = "LOG_FORMAT", value_enum, default_value = "text")]
+    log_format: LogFormat,
+}
+
+fn init_tracing(format: LogFormat) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    match format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.json().with_current_span(true).init(),
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    dotenv().ok();
+    load_dotenv();
     let cli = Cli::parse();
+    init_tracing(cli.log_format);
 
     // Ensure parent directory for database exists when using nested path like data/tavily_proxy.db
     let db_path = Path::new(&cli.db_path);
@@ -82,9 +153,47 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     {
         std::fs::create_dir_all(parent)?;
     }
-    println!("Using database: {}", db_path.display());
+    tracing::info!("Using database: {}", db_path.display());
 
-    let proxy = TavilyProxy::with_endpoint(cli.keys, &cli.upstream, &cli.db_path).await?;
+    // Fail fast on a full disk, a read-only directory, a newer schema or a bad clock.
+    let self_check = effective_startup_self_check_enabled();
+    if self_check {
+        check_database_storage(db_path, effective_startup_min_free_disk_mb())?;
+    }
+    let mut affinity = TokenAffinityConfig::from_env();
+    if let Some(raw) = cli.token_affinity_strategy.as_deref() {
+        affinity.strategy = TokenAffinityStrategy::parse(raw).ok_or_else(|| {
+            format!("invalid --token-affinity-strategy '{raw}' (expected ttl, sticky or disabled)")
+        })?;
+    }
+    if let Some(ttl_secs) = cli.token_affinity_ttl_secs.filter(|secs| *secs > 0) {
+        affinity.ttl_secs = ttl_secs;
+    }
+    if let Some(max_entries) = cli.token_affinity_max_entries.filter(|max| *max > 0) {
+        affinity.max_entries = max_entries;
+    }
+    if let Some(persist) = cli.token_affinity_persist {
+        affinity.persist = persist;
+    }
+    let quota_backend = QuotaBackend::parse(cli.quota_backend.as_deref().unwrap_or_default())?;
+    let master_key = cli.master_key.filter(|key| !key.trim().is_empty());
+    let proxy = TavilyProxy::with_master_key(cli.keys, &cli.upstream, &cli.db_path, master_key)
+        .await?
+        .with_webhook_urls(cli.webhook_urls)
+        .with_upstream_routes(cli.upstream_routes)
+        .with_ws_upstream(cli.mcp_ws_upstream.as_deref())
+        .with_token_affinity(affinity)
+        .with_quota_backend(quota_backend)
+        .await?;
+    let restored = proxy.restore_token_affinity().await?;
+    if restored > 0 {
+        tracing::info!("Restored {restored} token affinity mappings");
+    }
+    if self_check {
+        proxy
+            .startup_self_check(effective_startup_max_clock_skew_secs())
+            .await?;
+    }
     let addr: SocketAddr = format!("{}:{}", cli.bind, cli.port).parse()?;
 
     let forward_auth_header = parse_header_name(cli.forward_auth_header, "FORWARD_AUTH_HEADER")?;
@@ -115,6 +224,18 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     });
 
+    let mut rate_limit = server::RateLimitConfig::from_env();
+    if let Some(rps) = cli.rate_limit_rps {
+        rate_limit.requests_per_sec = rps.max(0.0);
+    }
+    if let Some(burst) = cli.rate_limit_burst {
+        rate_limit.burst = burst;
+    }
+    let trusted_proxies = server::TrustedProxies::parse(
+        &cli.trusted_proxies
+            .unwrap_or_else(effective_trusted_proxies),
+    )?;
+
     server::serve(
         addr,
         proxy,
@@ -122,6 +243,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         forward_auth,
         cli.dev_open_admin,
         cli.usage_base,
+        rate_limit,
+        trusted_proxies,
     )
     .await?;
 
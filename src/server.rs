@@ -1,10 +1,11 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     fs,
-    io::Read,
-    net::SocketAddr,
+    io::{Read, Write},
+    net::{IpAddr, SocketAddr},
     path::{Path as FsPath, PathBuf},
-    sync::Arc,
+    sync::{Arc, Mutex as StdMutex},
+    time::Instant,
 };
 
 use async_stream::stream;
@@ -14,31 +15,60 @@ use axum::response::sse::{Event, KeepAlive, Sse};
 use axum::{
     Router,
     body::{self, Body},
-    extract::{Path, Query, State},
+    extract::{
+        ConnectInfo, FromRequestParts, Path, Query, State,
+        ws::{WebSocket, WebSocketUpgrade},
+    },
     http::{HeaderMap, HeaderName, Method, Request, Response, StatusCode},
+    middleware::{self, Next},
     response::{Json, Redirect},
-    routing::{any, delete, get, patch, post},
+    routing::{any, get},
 };
 use chrono::{DateTime, Datelike, Duration as ChronoDuration, Local, NaiveDate, TimeZone, Utc};
-use futures_util::Stream;
+use futures_util::{Stream, StreamExt};
+use ipnet::IpNet;
 use reqwest::header::{HeaderMap as ReqHeaderMap, HeaderValue as ReqHeaderValue};
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
 use url::form_urlencoded;
+use utoipa_axum::{router::OpenApiRouter, routes};
 type SummarySig = (i64, i64, i64, i64, i64, i64, Option<i64>);
-use std::time::Duration;
-use tavily_hikari::{
-    ApiKeyMetrics, AuthToken, ProxyError, ProxyRequest, ProxyResponse, ProxySummary, QuotaWindow,
-    RequestLogRecord, TavilyProxy, TokenHourlyBucket, TokenHourlyRequestVerdict, TokenLogRecord,
-    TokenQuotaVerdict, TokenSummary, TokenUsageBucket, effective_request_logs_gc_at,
-    effective_request_logs_retention_days, effective_token_daily_limit,
-    effective_token_hourly_limit, effective_token_hourly_request_limit,
-    effective_token_monthly_limit,
+use crate::{
+    AlertScope, AnalyticsCount, ApiKeyListQuery, ApiKeyMetrics, ApiKeySort, AuthToken,
+    BulkTokenOperation, ConfigChange, DatabaseBackup, DedupTicket, GroupQuotaUsage, GroupThrottle,
+    HTTP_API_TOOLS, IDEMPOTENCY_KEY_HEADER, JobLog, JobPause, JsonRpcValidation,
+    KeyAcquisitionSnapshot, KeyPlacement, KeyVerification, LatencyPercentiles, LogAnnotation,
+    LogCursor, LogKind, MaintenanceMode, McpSession, PoolDepletionForecast, ProxyBodyStream,
+    ProxyError, ProxyRequest, ProxyResponse, ProxyStream, ProxySummary, QuarantinedToken,
+    QuotaDrift, QuotaWindow, ReplicationChange, ReplicationRow, ReplicationSnapshot,
+    RequestLogRecord, RequestUpload, ResponseCacheSnapshot, ResponseHeaders,
+    TOKEN_INVITE_DEFAULT_TTL_SECS, TOKEN_TIER_DEFAULT, TavilyProxy, TokenClaim, TokenClaimOutcome,
+    TokenGroupSettings, TokenHourlyBucket, TokenHourlyRequestVerdict, TokenLogRecord,
+    TokenPriority, TokenQuotaStatus, TokenQuotaVerdict, TokenSla, TokenSummary, TokenTierChange,
+    TokenUsageBucket, ToolUsage, UpstreamHeaderRules, UpstreamHealthProbe, UpstreamResponse,
+    UsageAlert, UsageAlertThreshold, WEBSOCKET_MAX_MESSAGE_BYTES, WebhookDelivery, WsExchange,
+    effective_access_log_max_bytes, effective_access_log_max_files, effective_access_log_target,
+    effective_backup_interval_secs, effective_backup_keep, effective_cors_allowed_headers,
+    effective_cors_allowed_methods, effective_cors_allowed_origins, effective_cors_max_age_secs,
+    effective_mcp_jsonrpc_validation, effective_mcp_stream_body_bytes,
+    effective_public_ip_hourly_limit, effective_quota_sync_concurrency, effective_rate_limit_burst,
+    effective_rate_limit_rps, effective_replication_auth_header,
+    effective_replication_interval_secs, effective_replication_primary_url,
+    effective_request_analytics_enabled, effective_request_logs_gc_at, effective_runtime_settings,
+    effective_swagger_ui_enabled, effective_token_daily_limit, effective_token_hourly_limit,
+    effective_token_hourly_request_limit, effective_token_monthly_limit, effective_trusted_proxies,
+    effective_upstream_health_interval_secs, effective_wal_checkpoint_interval_secs,
+    effective_wal_checkpoint_threshold_mb, is_uuid, normalize_response_headers,
+    normalize_upstream_header_rules, request_tool_name,
 };
+use std::time::Duration;
 use tokio::signal;
 #[cfg(unix)]
 use tokio::signal::unix::{SignalKind, signal as unix_signal};
+use tokio::sync::watch;
 use tower_http::services::{ServeDir, ServeFile};
+use tracing::Instrument;
 
 #[derive(Clone)]
 struct AppState {
@@ -47,6 +77,11 @@ struct AppState {
     forward_auth: ForwardAuthConfig,
     dev_open_admin: bool,
     usage_base: String,
+    access_log: Option<Arc<AccessLog>>,
+    cors: Option<Arc<CorsPolicy>>,
+    public_quota: Arc<PublicIpQuota>,
+    rate_limiter: Option<Arc<ClientRateLimiter>>,
+    trusted_proxies: Arc<TrustedProxies>,
 }
 
 #[derive(Clone, Debug)]
@@ -210,6 +245,14 @@ struct IsAdminDebug {
     user_value: Option<String>,
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/debug/is-admin",
+    tag = "debug",
+    summary = "Whether the caller is an admin.",
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
 async fn debug_is_admin(
     State(state): State<Arc<AppState>>,
     headers: HeaderMap,
@@ -226,22 +269,59 @@ async fn debug_is_admin(
     }))
 }
 
+#[utoipa::path(
+    get,
+    path = "/health",
+    tag = "system",
+    summary = "Liveness probe.",
+    responses((status = 200, description = "OK"))
+)]
 async fn health_check() -> &'static str {
     "ok"
 }
 
-fn random_delay_secs() -> u64 {
-    use rand::Rng;
-    let mut rng = rand::thread_rng();
-    rng.gen_range(0..=300)
-}
-
 fn twenty_four_hours_secs() -> i64 {
     24 * 60 * 60
 }
 
+/// Scheduled job types an operator may pause via `/api/jobs/:type/pause`.
+const PAUSABLE_JOB_TYPES: &[&str] = &[
+    "quota_sync",
+    "token_usage_rollup",
+    "token_quota_snapshot",
+    "auth_token_logs_gc",
+    "request_logs_gc",
+    "request_analytics",
+    "wal_checkpoint",
+    "database_backup",
+    "key_error_guard",
+    "group_error_budget",
+    "token_tier_policy",
+    "token_expiry",
+    "replication_sync",
+    "quota_reconcile",
+    "availability_report",
+    "upstream_health",
+];
+
+/// Whether an operator paused `job_type`; scheduler loops skip their run while it is.
+/// Lookup errors count as not paused so a flaky meta read never stalls maintenance.
+async fn job_paused(state: &AppState, job_type: &str) -> bool {
+    match state.proxy.job_paused(job_type).await {
+        Ok(paused) => paused,
+        Err(err) => {
+            tracing::error!("{job_type}: pause lookup error: {err}");
+            false
+        }
+    }
+}
+
+/// How often the quota sync scheduler looks for keys whose persisted next sync time passed.
+const QUOTA_SYNC_POLL_SECS: u64 = 60;
+
 fn spawn_quota_sync_scheduler(state: Arc<AppState>) {
     tokio::spawn(async move {
+        let concurrency = effective_quota_sync_concurrency();
         loop {
             // Initial cycle runs immediately on startup
             let keys = match state
@@ -251,65 +331,64 @@ fn spawn_quota_sync_scheduler(state: Arc<AppState>) {
             {
                 Ok(list) => list,
                 Err(err) => {
-                    eprintln!("quota-sync: list pending error: {err}");
+                    tracing::error!("quota-sync: list pending error: {err}");
                     vec![]
                 }
             };
 
-            for key_id in keys {
-                let delay = random_delay_secs();
-                tokio::time::sleep(Duration::from_secs(delay)).await;
-                let job_id = match state
-                    .proxy
-                    .scheduled_job_start("quota_sync", Some(&key_id), 1)
-                    .await
-                {
-                    Ok(id) => id,
-                    Err(err) => {
-                        eprintln!("quota-sync: start job error: {err}");
-                        continue;
-                    }
-                };
-                match state.proxy.sync_key_quota(&key_id, &state.usage_base).await {
-                    Ok((limit, remaining)) => {
-                        let msg = format!("limit={limit} remaining={remaining}");
-                        let _ = state
-                            .proxy
-                            .scheduled_job_finish(job_id, "success", Some(&msg))
-                            .await;
-                    }
-                    Err(ProxyError::QuotaDataMissing { reason }) => {
-                        let msg = format!("quota_data_missing: {reason}");
-                        let _ = state
-                            .proxy
-                            .scheduled_job_finish(job_id, "error", Some(&msg))
-                            .await;
-                    }
-                    Err(ProxyError::UsageHttp { status, body }) => {
-                        let msg = format!("usage_http {status}: {body}");
-                        let _ = state
-                            .proxy
-                            .scheduled_job_finish(job_id, "error", Some(&msg))
-                            .await;
-                    }
-                    Err(err) => {
-                        let _ = state
-                            .proxy
-                            .scheduled_job_finish(job_id, "error", Some(&err.to_string()))
-                            .await;
+            // Each sync persists the key's next due time (a day out plus jitter, or a
+            // backoff after a usage API failure), so a cycle only picks up keys now due.
+            futures_util::stream::iter(keys)
+                .for_each_concurrent(concurrency, |key_id| {
+                    let state = state.clone();
+                    async move {
+                        if !job_paused(&state, "quota_sync").await {
+                            run_quota_sync_job(&state, &key_id).await;
+                        }
                     }
-                }
-            }
+                })
+                .await;
 
-            // Sleep one hour before next cycle
-            tokio::time::sleep(Duration::from_secs(3600)).await;
+            tokio::time::sleep(Duration::from_secs(QUOTA_SYNC_POLL_SECS)).await;
         }
     });
 }
 
+async fn run_quota_sync_job(state: &AppState, key_id: &str) {
+    let job_id = match state
+        .proxy
+        .scheduled_job_start("quota_sync", Some(key_id), 1)
+        .await
+    {
+        Ok(id) => id,
+        Err(err) => {
+            tracing::error!("quota-sync: start job error: {err}");
+            return;
+        }
+    };
+    let (status, msg) = match state.proxy.sync_key_quota(key_id, &state.usage_base).await {
+        Ok((limit, remaining)) => ("success", format!("limit={limit} remaining={remaining}")),
+        Err(ProxyError::QuotaDataMissing { reason }) => {
+            ("error", format!("quota_data_missing: {reason}"))
+        }
+        Err(ProxyError::UsageHttp { status, body }) => {
+            ("error", format!("usage_http {status}: {body}"))
+        }
+        Err(err) => ("error", err.to_string()),
+    };
+    let _ = state
+        .proxy
+        .scheduled_job_finish(job_id, status, Some(&msg))
+        .await;
+}
+
 fn spawn_token_usage_rollup_scheduler(state: Arc<AppState>) {
     tokio::spawn(async move {
         loop {
+            if job_paused(&state, "token_usage_rollup").await {
+                tokio::time::sleep(Duration::from_secs(300)).await;
+                continue;
+            }
             let job_id = match state
                 .proxy
                 .scheduled_job_start("token_usage_rollup", None, 1)
@@ -317,7 +396,7 @@ fn spawn_token_usage_rollup_scheduler(state: Arc<AppState>) {
             {
                 Ok(id) => id,
                 Err(err) => {
-                    eprintln!("token-usage-rollup: start job error: {err}");
+                    tracing::error!("token-usage-rollup: start job error: {err}");
                     tokio::time::sleep(Duration::from_secs(300)).await;
                     continue;
                 }
@@ -325,10 +404,24 @@ fn spawn_token_usage_rollup_scheduler(state: Arc<AppState>) {
 
             match state.proxy.rollup_token_usage_stats().await {
                 Ok((rows, last_ts)) => {
-                    let msg = match last_ts {
+                    let mut msg = match last_ts {
                         Some(ts) => format!("rows={rows} last_rollup_ts={ts}"),
                         None => format!("rows={rows} last_rollup_ts=none"),
                     };
+                    match state.proxy.rollup_key_usage_stats().await {
+                        Ok((key_rows, _)) => msg.push_str(&format!(" key_rows={key_rows}")),
+                        Err(err) => {
+                            tracing::error!("token-usage-rollup: key usage rollup error: {err}");
+                            msg.push_str(" key_rows=error");
+                        }
+                    }
+                    match state.proxy.evaluate_usage_alerts().await {
+                        Ok(opened) => msg.push_str(&format!(" alerts={opened}")),
+                        Err(err) => {
+                            tracing::error!("token-usage-rollup: usage alert error: {err}");
+                            msg.push_str(" alerts=error");
+                        }
+                    }
                     let _ = state
                         .proxy
                         .scheduled_job_finish(job_id, "success", Some(&msg))
@@ -348,9 +441,57 @@ fn spawn_token_usage_rollup_scheduler(state: Arc<AppState>) {
     });
 }
 
+fn spawn_token_quota_snapshot_scheduler(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        loop {
+            if job_paused(&state, "token_quota_snapshot").await {
+                tokio::time::sleep(Duration::from_secs(300)).await;
+                continue;
+            }
+            let job_id = match state
+                .proxy
+                .scheduled_job_start("token_quota_snapshot", None, 1)
+                .await
+            {
+                Ok(id) => id,
+                Err(err) => {
+                    tracing::error!("token-quota-snapshot: start job error: {err}");
+                    tokio::time::sleep(Duration::from_secs(300)).await;
+                    continue;
+                }
+            };
+
+            match state.proxy.snapshot_token_quotas().await {
+                Ok(tokens) => {
+                    let msg = format!("tokens={tokens}");
+                    let _ = state
+                        .proxy
+                        .scheduled_job_finish(job_id, "success", Some(&msg))
+                        .await;
+                }
+                Err(err) => {
+                    let _ = state
+                        .proxy
+                        .scheduled_job_finish(job_id, "error", Some(&err.to_string()))
+                        .await;
+                }
+            }
+
+            // Snapshots are keyed by hour, so wake up shortly after the next hour starts.
+            let now = Utc::now().timestamp();
+            let next_hour = now - now.rem_euclid(3600) + 3600;
+            tokio::time::sleep(Duration::from_secs((next_hour - now + 5) as u64)).await;
+        }
+    });
+}
+
 fn spawn_auth_token_logs_gc_scheduler(state: Arc<AppState>) {
     tokio::spawn(async move {
         loop {
+            if job_paused(&state, "auth_token_logs_gc").await {
+                tokio::time::sleep(Duration::from_secs(3600)).await;
+                continue;
+            }
             let job_id = match state
                 .proxy
                 .scheduled_job_start("auth_token_logs_gc", None, 1)
@@ -358,7 +499,7 @@ fn spawn_auth_token_logs_gc_scheduler(state: Arc<AppState>) {
             {
                 Ok(id) => id,
                 Err(err) => {
-                    eprintln!("auth-token-logs-gc: start job error: {err}");
+                    tracing::error!("auth-token-logs-gc: start job error: {err}");
                     tokio::time::sleep(Duration::from_secs(3600)).await;
                     continue;
                 }
@@ -425,11 +566,15 @@ fn spawn_request_logs_gc_scheduler(state: Arc<AppState>) {
                 .to_std()
                 .unwrap_or_else(|_| Duration::from_secs(0));
             tokio::time::sleep(sleep_for).await;
+            if job_paused(&state, "request_logs_gc").await {
+                // Skip today's window; the next one is a day away.
+                continue;
+            }
 
             // After we reach the scheduled time, keep retrying until we either run the job
             // successfully or record an error for this run window.
             loop {
-                let retention_days = effective_request_logs_retention_days();
+                let retention_days = state.proxy.runtime_config().request_logs_retention_days;
                 let job_id = match state
                     .proxy
                     .scheduled_job_start("request_logs_gc", None, 1)
@@ -437,15 +582,27 @@ fn spawn_request_logs_gc_scheduler(state: Arc<AppState>) {
                 {
                     Ok(id) => id,
                     Err(err) => {
-                        eprintln!("request-logs-gc: start job error: {err}");
+                        tracing::error!("request-logs-gc: start job error: {err}");
                         tokio::time::sleep(Duration::from_secs(300)).await;
                         continue;
                     }
                 };
 
                 match state.proxy.gc_request_logs().await {
-                    Ok(deleted) => {
-                        let msg = format!("deleted_rows={deleted} retention_days={retention_days}");
+                    Ok(gc) => {
+                        let mut msg = format!(
+                            "deleted_rows={} retention_days={retention_days}",
+                            gc.deleted
+                        );
+                        if gc.rolled > 0 {
+                            msg.push_str(&format!(" rolled_rows={}", gc.rolled));
+                        }
+                        if gc.dropped_partitions > 0 {
+                            msg.push_str(&format!(" dropped_partitions={}", gc.dropped_partitions));
+                        }
+                        if let Some(pages) = gc.vacuumed_pages {
+                            msg.push_str(&format!(" vacuumed_pages={pages}"));
+                        }
                         let _ = state
                             .proxy
                             .scheduled_job_finish(job_id, "success", Some(&msg))
@@ -465,855 +622,1004 @@ fn spawn_request_logs_gc_scheduler(state: Arc<AppState>) {
     });
 }
 
-// kept for potential future direct serving; currently ServeDir handles '/'
-#[allow(dead_code)]
-async fn load_spa_response(
-    state: &AppState,
-    file_name: &str,
-) -> Result<Response<Body>, StatusCode> {
-    let Some(dir) = state.static_dir.as_ref() else {
-        return Err(StatusCode::NOT_FOUND);
-    };
-    let path = dir.join(file_name);
-    let Ok(bytes) = tokio::fs::read(path).await else {
-        return Err(StatusCode::NOT_FOUND);
-    };
-    Response::builder()
-        .status(StatusCode::OK)
-        .header(CONTENT_TYPE, "text/html; charset=utf-8")
-        .body(Body::from(bytes))
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
-}
+fn spawn_request_analytics_scheduler(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        loop {
+            if job_paused(&state, "request_analytics").await {
+                tokio::time::sleep(Duration::from_secs(3600)).await;
+                continue;
+            }
+            let job_id = match state
+                .proxy
+                .scheduled_job_start("request_analytics", None, 1)
+                .await
+            {
+                Ok(id) => id,
+                Err(err) => {
+                    tracing::error!("request-analytics: start job error: {err}");
+                    tokio::time::sleep(Duration::from_secs(3600)).await;
+                    continue;
+                }
+            };
 
-async fn serve_index(
-    State(state): State<Arc<AppState>>,
-    headers: HeaderMap,
-) -> Result<Response<Body>, StatusCode> {
-    // Only auto-redirect to admin when explicit dev convenience flag is enabled.
-    // Admin users should still be able to access the public page without forced redirection.
-    if state.dev_open_admin {
-        return Ok(Redirect::temporary("/admin").into_response());
-    }
+            match state.proxy.aggregate_request_analytics().await {
+                Ok((scanned, sampled)) => {
+                    let msg = format!("scanned_rows={scanned} sampled_searches={sampled}");
+                    let _ = state
+                        .proxy
+                        .scheduled_job_finish(job_id, "success", Some(&msg))
+                        .await;
+                }
+                Err(err) => {
+                    let _ = state
+                        .proxy
+                        .scheduled_job_finish(job_id, "error", Some(&err.to_string()))
+                        .await;
+                }
+            }
 
-    let _ = headers; // keep parameter for potential future use
-    load_spa_response(state.as_ref(), "index.html").await
+            // Hourly is plenty: analytics are day-bucketed and only need to trail the logs.
+            tokio::time::sleep(Duration::from_secs(3600)).await;
+        }
+    });
 }
 
-async fn serve_admin_index(
-    State(state): State<Arc<AppState>>,
-    headers: HeaderMap,
-) -> Result<Response<Body>, StatusCode> {
-    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
-        return Err(StatusCode::FORBIDDEN);
-    }
-
-    load_spa_response(state.as_ref(), "admin.html").await
-}
+const WAL_CHECK_INTERVAL_SECS: u64 = 60;
 
-const BASE_404_STYLES: &str = r#"
-  :root {
-    color-scheme: light;
-    font-family: 'Inter', 'SF Pro Display', -apple-system, BlinkMacSystemFont, 'Segoe UI', sans-serif;
-    text-rendering: optimizeLegibility;
-  }
+fn spawn_wal_checkpoint_scheduler(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        let mut last_checkpoint = Instant::now();
+        loop {
+            tokio::time::sleep(Duration::from_secs(WAL_CHECK_INTERVAL_SECS)).await;
+            if job_paused(&state, "wal_checkpoint").await {
+                continue;
+            }
 
-  body {
-    margin: 0;
-    min-height: 100vh;
-    display: flex;
-    align-items: center;
-    justify-content: center;
-    background: radial-gradient(circle at top left, rgba(99, 102, 241, 0.12), transparent 45%),
-      radial-gradient(circle at bottom right, rgba(236, 72, 153, 0.12), transparent 50%),
-      #f5f6fb;
-    color: #1f2937;
-  }
+            // Only checkpoints are recorded as jobs; the cheap size probe stays silent.
+            let threshold = effective_wal_checkpoint_threshold_mb() * 1024 * 1024;
+            let interval = effective_wal_checkpoint_interval_secs() as u64;
+            let wal_size = state.proxy.wal_size_bytes();
+            let trigger = if wal_size >= threshold {
+                "threshold"
+            } else if interval > 0
+                && wal_size > 0
+                && last_checkpoint.elapsed() >= Duration::from_secs(interval)
+            {
+                "interval"
+            } else {
+                continue;
+            };
+            last_checkpoint = Instant::now();
 
-  @media (prefers-color-scheme: dark) {
-    :root {
-      color-scheme: dark;
-    }
-    body {
-      background: radial-gradient(circle at top left, rgba(129, 140, 248, 0.22), transparent 45%),
-        radial-gradient(circle at bottom right, rgba(236, 72, 153, 0.18), transparent 50%),
-        #0f172a;
-      color: #e2e8f0;
-    }
-  }
+            let job_id = match state
+                .proxy
+                .scheduled_job_start("wal_checkpoint", None, 1)
+                .await
+            {
+                Ok(id) => id,
+                Err(err) => {
+                    tracing::error!("wal-checkpoint: start job error: {err}");
+                    continue;
+                }
+            };
 
-  .not-found-shell {
-    max-width: 520px;
-    width: calc(100% - 48px);
-    padding: 48px 40px;
-    border-radius: 28px;
-    background: rgba(255, 255, 255, 0.82);
-    border: 1px solid rgba(15, 23, 42, 0.08);
-    backdrop-filter: blur(18px);
-    box-shadow: 0 28px 65px rgba(15, 23, 42, 0.12);
-    text-align: center;
-  }
+            match state.proxy.checkpoint_wal().await {
+                Ok(outcome) => {
+                    let msg = format!(
+                        "trigger={trigger} mode={} wal_before={} wal_after={} busy={} frames={}/{} duration_ms={}",
+                        outcome.mode,
+                        outcome.wal_before_bytes,
+                        outcome.wal_after_bytes,
+                        outcome.busy,
+                        outcome.checkpointed_frames,
+                        outcome.log_frames,
+                        outcome.duration_ms
+                    );
+                    let _ = state
+                        .proxy
+                        .scheduled_job_finish(job_id, "success", Some(&msg))
+                        .await;
+                }
+                Err(err) => {
+                    let _ = state
+                        .proxy
+                        .scheduled_job_finish(job_id, "error", Some(&err.to_string()))
+                        .await;
+                }
+            }
+        }
+    });
+}
 
-  @media (prefers-color-scheme: dark) {
-    .not-found-shell {
-      background: rgba(15, 23, 42, 0.7);
-      border: 1px solid rgba(148, 163, 184, 0.18);
-      box-shadow: 0 32px 65px rgba(15, 23, 42, 0.5);
-    }
-  }
+/// Snapshot message shared by the scheduled and manual backup jobs.
+fn backup_job_summary(backup: &DatabaseBackup) -> String {
+    format!(
+        "file={} bytes={} removed={} duration_ms={}",
+        backup.file, backup.bytes, backup.removed, backup.duration_ms
+    )
+}
 
-  .not-found-badge {
-    display: inline-flex;
-    align-items: center;
-    justify-content: center;
-    gap: 6px;
-    padding: 8px 16px;
-    border-radius: 999px;
-    background: rgba(99, 102, 241, 0.16);
-    color: #4338ca;
-    font-size: 0.85rem;
-    font-weight: 600;
-    letter-spacing: 0.02em;
-    text-transform: uppercase;
-  }
+fn spawn_database_backup_scheduler(state: Arc<AppState>, interval_secs: u64) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(interval_secs)).await;
+            if job_paused(&state, "database_backup").await {
+                continue;
+            }
+            let job_id = match state
+                .proxy
+                .scheduled_job_start("database_backup", None, 1)
+                .await
+            {
+                Ok(id) => id,
+                Err(err) => {
+                    tracing::error!("database-backup: start job error: {err}");
+                    continue;
+                }
+            };
 
-  .not-found-code {
-    margin: 28px 0 12px;
-    font-size: clamp(4rem, 13vw, 6rem);
-    font-weight: 800;
-    line-height: 1;
-    letter-spacing: -0.04em;
-    color: #4f46e5;
-  }
+            match state
+                .proxy
+                .backup_database(effective_backup_keep() as usize)
+                .await
+            {
+                Ok(backup) => {
+                    let msg = backup_job_summary(&backup);
+                    let _ = state
+                        .proxy
+                        .scheduled_job_finish(job_id, "success", Some(&msg))
+                        .await;
+                }
+                Err(err) => {
+                    tracing::error!("database-backup: {err}");
+                    let _ = state
+                        .proxy
+                        .scheduled_job_finish(job_id, "error", Some(&err.to_string()))
+                        .await;
+                }
+            }
+        }
+    });
+}
 
-  @media (prefers-color-scheme: dark) {
-    .not-found-code {
-      color: #a5b4fc;
-    }
-  }
+const KEY_ERROR_GUARD_INTERVAL_SECS: u64 = 5 * 60;
 
-  .not-found-title {
-    margin: 0;
-    font-size: clamp(1.5rem, 4vw, 2.25rem);
-    font-weight: 700;
-    letter-spacing: -0.01em;
-  }
+fn spawn_key_error_guard_scheduler(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(KEY_ERROR_GUARD_INTERVAL_SECS)).await;
+            if job_paused(&state, "key_error_guard").await {
+                continue;
+            }
+            // Failures during an upstream outage say nothing about the keys.
+            if state.proxy.upstream_down().await.unwrap_or(false) {
+                tracing::warn!("key-error-guard: upstream down, skipping run");
+                continue;
+            }
 
-  .not-found-description {
-    margin: 20px 0 30px;
-    color: rgba(100, 116, 139, 0.95);
-    font-size: 1rem;
-    line-height: 1.7;
-  }
+            // Runs that disable nothing are not recorded to keep the job log readable.
+            match state.proxy.auto_disable_failing_keys().await {
+                Ok(trips) if trips.is_empty() => {}
+                Ok(trips) => {
+                    let msg = trips
+                        .iter()
+                        .map(|t| format!("{}:{}/{}", t.key_id, t.errors, t.requests))
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    let msg = format!("disabled={} {msg}", trips.len());
+                    if let Ok(job_id) = state
+                        .proxy
+                        .scheduled_job_start("key_error_guard", None, 1)
+                        .await
+                    {
+                        let _ = state
+                            .proxy
+                            .scheduled_job_finish(job_id, "success", Some(&msg))
+                            .await;
+                    }
+                }
+                Err(err) => {
+                    tracing::error!("key-error-guard: {err}");
+                    if let Ok(job_id) = state
+                        .proxy
+                        .scheduled_job_start("key_error_guard", None, 1)
+                        .await
+                    {
+                        let _ = state
+                            .proxy
+                            .scheduled_job_finish(job_id, "error", Some(&err.to_string()))
+                            .await;
+                    }
+                }
+            }
+        }
+    });
+}
 
-  @media (prefers-color-scheme: dark) {
-    .not-found-description {
-      color: rgba(203, 213, 225, 0.82);
-    }
-  }
+const AVAILABILITY_REPORT_INTERVAL_SECS: u64 = 3600;
 
-  .not-found-actions {
-    display: flex;
-    align-items: center;
-    justify-content: center;
-    margin-top: 28px;
-  }
+fn spawn_availability_report_scheduler(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        loop {
+            // Only complete days are reported, so most hourly runs have nothing to do and are
+            // not recorded.
+            if !job_paused(&state, "availability_report").await {
+                let (status, msg) = match state.proxy.update_availability_report().await {
+                    Ok(0) => (None, String::new()),
+                    Ok(days) => (Some("success"), format!("days={days}")),
+                    Err(err) => {
+                        tracing::error!("availability-report: {err}");
+                        (Some("error"), err.to_string())
+                    }
+                };
+                if let Some(status) = status
+                    && let Ok(job_id) = state
+                        .proxy
+                        .scheduled_job_start("availability_report", None, 1)
+                        .await
+                {
+                    let _ = state
+                        .proxy
+                        .scheduled_job_finish(job_id, status, Some(&msg))
+                        .await;
+                }
+            }
+            tokio::time::sleep(Duration::from_secs(AVAILABILITY_REPORT_INTERVAL_SECS)).await;
+        }
+    });
+}
 
-  .not-found-primary {
-    display: inline-flex;
-    align-items: center;
-    justify-content: center;
-    gap: 8px;
-    padding: 12px 22px;
-    border-radius: 999px;
-    font-weight: 600;
-    color: #fff;
-    background: linear-gradient(135deg, #6366f1, #8b5cf6);
-    box-shadow: 0 16px 35px rgba(99, 102, 241, 0.35);
-    text-decoration: none;
-    transition: transform 0.12s ease, box-shadow 0.12s ease;
-  }
+/// Re-read the environment and `.env` and swap in the runtime-tunable settings. Shared by
+/// SIGHUP and `POST /api/admin/reload-config`.
+async fn reload_runtime_config(state: &AppState, actor: &str) -> Result<usize, ProxyError> {
+    state.proxy.reload_config(actor).await
+}
 
-  .not-found-primary:hover {
-    transform: translateY(-1px);
-    box-shadow: 0 20px 40px rgba(99, 102, 241, 0.4);
-  }
+#[cfg(unix)]
+fn spawn_config_reload_on_sighup(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        let mut hangup = match unix_signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(err) => {
+                tracing::error!("Failed to listen for SIGHUP: {err}");
+                return;
+            }
+        };
+        while hangup.recv().await.is_some() {
+            match reload_runtime_config(&state, "sighup").await {
+                Ok(changed) => tracing::info!("Config reloaded on SIGHUP: {changed} changed"),
+                Err(err) => tracing::error!("config reload error: {err}"),
+            }
+        }
+    });
+}
 
-  .not-found-footer {
-    margin-top: 36px;
-    font-size: 0.85rem;
-    color: rgba(100, 116, 139, 0.75);
-  }
+fn spawn_upstream_health_scheduler(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        let interval = Duration::from_secs(effective_upstream_health_interval_secs() as u64);
+        loop {
+            // Healthy probes are kept in `upstream_health` only, so the job log stays readable.
+            if !job_paused(&state, "upstream_health").await {
+                let failure = match state.proxy.probe_upstream_health().await {
+                    Ok(probe) if probe.ok => None,
+                    Ok(probe) => Some(format!(
+                        "status={} error={}",
+                        probe
+                            .status_code
+                            .map(|code| code.to_string())
+                            .unwrap_or_else(|| "none".to_string()),
+                        probe.error.as_deref().unwrap_or("")
+                    )),
+                    Err(err) => {
+                        tracing::error!("upstream-health: {err}");
+                        Some(err.to_string())
+                    }
+                };
+                if let Some(msg) = failure
+                    && let Ok(job_id) = state
+                        .proxy
+                        .scheduled_job_start("upstream_health", None, 1)
+                        .await
+                {
+                    let _ = state
+                        .proxy
+                        .scheduled_job_finish(job_id, "error", Some(&msg))
+                        .await;
+                }
+            }
+            tokio::time::sleep(interval).await;
+        }
+    });
+}
 
-  @media (prefers-color-scheme: dark) {
-    .not-found-footer {
-      color: rgba(148, 163, 184, 0.78);
-    }
-  }
-"#;
+const GROUP_ERROR_BUDGET_INTERVAL_SECS: u64 = 5 * 60;
+const TOKEN_TIER_POLICY_INTERVAL_SECS: u64 = 60 * 60;
 
-fn find_frontend_css_href(static_dir: Option<&FsPath>) -> Option<String> {
-    let dir = static_dir?;
-    let index_path = dir.join("index.html");
-    let mut s = String::new();
-    if fs::File::open(&index_path)
-        .ok()?
-        .read_to_string(&mut s)
-        .is_ok()
-    {
-        // naive scan for first stylesheet href
-        if let Some(idx) = s.find("rel=\"stylesheet\"") {
-            let frag = &s[idx..];
-            if let Some(href_idx) = frag.find("href=\"") {
-                let frag2 = &frag[href_idx + 6..];
-                if let Some(end_idx) = frag2.find('\"') {
-                    let href = &frag2[..end_idx];
-                    return Some(href.to_string());
+fn spawn_group_error_budget_scheduler(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(GROUP_ERROR_BUDGET_INTERVAL_SECS)).await;
+            if job_paused(&state, "group_error_budget").await {
+                continue;
+            }
+
+            // Like the key error guard, only runs that throttle something are recorded.
+            let (status, msg) = match state.proxy.enforce_group_error_budgets().await {
+                Ok(trips) if trips.is_empty() => continue,
+                Ok(trips) => {
+                    let msg = trips
+                        .iter()
+                        .map(|t| format!("{}:{}/{}", t.group_name, t.external_failures, t.requests))
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    ("success", format!("throttled={} {msg}", trips.len()))
                 }
+                Err(err) => {
+                    tracing::error!("group-error-budget: {err}");
+                    ("error", err.to_string())
+                }
+            };
+            if let Ok(job_id) = state
+                .proxy
+                .scheduled_job_start("group_error_budget", None, 1)
+                .await
+            {
+                let _ = state
+                    .proxy
+                    .scheduled_job_finish(job_id, status, Some(&msg))
+                    .await;
             }
         }
-    }
-    None
+    });
 }
 
-fn load_frontend_css_content(static_dir: Option<&FsPath>) -> Option<String> {
-    let dir = static_dir?;
-    let href = find_frontend_css_href(Some(dir))?;
-    // href like "/assets/index-xxxx.css" => remove leading slash and read from static_dir root
-    let rel = href.trim_start_matches('/');
-    let path = dir.join(
-        rel.strip_prefix("assets/")
-            .map(|s| FsPath::new("assets").join(s))
-            .unwrap_or_else(|| FsPath::new(rel).to_path_buf()),
-    );
-    fs::read_to_string(path).ok()
-}
+fn spawn_token_tier_policy_scheduler(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(TOKEN_TIER_POLICY_INTERVAL_SECS)).await;
+            if job_paused(&state, "token_tier_policy").await {
+                continue;
+            }
 
-#[derive(Deserialize)]
-struct FallbackQuery {
-    path: Option<String>,
+            // Only runs that move tokens (or fail) are recorded, like the group error budget.
+            let (status, msg) = match state.proxy.enforce_token_tier_policies().await {
+                Ok(changes) if changes.is_empty() => continue,
+                Ok(changes) => {
+                    let msg = changes
+                        .iter()
+                        .map(|c| {
+                            format!(
+                                "{}:{}->{}",
+                                c.token_id,
+                                c.from_tier.as_deref().unwrap_or(TOKEN_TIER_DEFAULT),
+                                c.to_tier.as_deref().unwrap_or(TOKEN_TIER_DEFAULT)
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    ("success", format!("moved={} {msg}", changes.len()))
+                }
+                Err(err) => {
+                    tracing::error!("token-tier-policy: {err}");
+                    ("error", err.to_string())
+                }
+            };
+            if let Ok(job_id) = state
+                .proxy
+                .scheduled_job_start("token_tier_policy", None, 1)
+                .await
+            {
+                let _ = state
+                    .proxy
+                    .scheduled_job_finish(job_id, status, Some(&msg))
+                    .await;
+            }
+        }
+    });
 }
 
-async fn not_found_landing(
-    State(state): State<Arc<AppState>>,
-    Query(q): Query<FallbackQuery>,
-) -> Response<Body> {
-    let css = load_frontend_css_content(state.static_dir.as_deref());
-    let html = build_404_landing_inline(css.as_deref(), q.path.unwrap_or_else(|| "/".to_string()));
-    Response::builder()
-        .status(StatusCode::NOT_FOUND)
-        .header(CONTENT_TYPE, "text/html; charset=utf-8")
-        .header(CONTENT_LENGTH, html.len().to_string())
-        .body(Body::from(html))
-        .unwrap_or_else(|_| Response::builder().status(500).body(Body::empty()).unwrap())
-}
+/// How often expired tokens are looked for and disabled.
+const TOKEN_EXPIRY_INTERVAL_SECS: u64 = 60;
 
-fn build_404_landing_inline(css_content: Option<&str>, original: String) -> String {
-    let mut style_block = String::from("<style>\n");
-    style_block.push_str(BASE_404_STYLES);
-    if let Some(content) = css_content {
-        style_block.push_str(content);
-    }
-    style_block.push_str("\n</style>\n");
-    // Safer: pass original path via data attribute and read it in script without string concatenation
-    let script = format!(
-        "<script data-p=\"{}\">!function(){{try{{var s=document.currentScript;var p=s&&s.getAttribute('data-p')||'/';history.replaceState(null,'', p)}}catch(_e){{}}}}()</script>",
-        html_escape::encode_double_quoted_attribute(&original)
-    );
-    format!(
-        "<!doctype html>\n<html lang=\"en\">\n  <head>\n    <meta charset=\"UTF-8\" />\n    <meta name=\"viewport\" content=\"width=device-width, initial-scale=1.0\" />\n    <title>404 Not Found</title>\n    {}  </head>\n  <body>\n    <main class=\"not-found-shell\" role=\"main\">\n      <span class=\"not-found-badge\" aria-hidden=\"true\">Tavily Hikari Proxy</span>\n      <p class=\"not-found-code\">404</p>\n      <h1 class=\"not-found-title\">Page not found</h1>\n      <p class=\"not-found-description\">The page you’re trying to visit, <code>{}</code>, isn’t available right now.</p>\n      <div class=\"not-found-actions\">\n        <a href=\"/\" class=\"not-found-primary\" aria-label=\"Back to dashboard\">Return to dashboard</a>\n      </div>\n      <p class=\"not-found-footer\">Error reference: 404</p>\n    </main>\n    {}\n  </body>\n</html>",
-        style_block,
-        html_escape::encode_text(&original),
-        script
-    )
-}
+fn spawn_token_expiry_scheduler(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(TOKEN_EXPIRY_INTERVAL_SECS)).await;
+            if job_paused(&state, "token_expiry").await {
+                continue;
+            }
 
-#[axum::debug_handler]
-async fn tavily_http_search(
-    State(state): State<Arc<AppState>>,
-    req: Request<Body>,
-) -> Result<Response<Body>, StatusCode> {
-    let (parts, body) = req.into_parts();
-    let method = parts.method.clone();
-    let path = parts.uri.path().to_owned();
+            // Only runs that disable tokens (or fail) are recorded.
+            let (status, msg) = match state.proxy.disable_expired_tokens().await {
+                Ok(ids) if ids.is_empty() => continue,
+                Ok(ids) => (
+                    "success",
+                    format!("disabled={} {}", ids.len(), ids.join(" ")),
+                ),
+                Err(err) => {
+                    tracing::error!("token-expiry: {err}");
+                    ("error", err.to_string())
+                }
+            };
+            if let Ok(job_id) = state
+                .proxy
+                .scheduled_job_start("token_expiry", None, 1)
+                .await
+            {
+                let _ = state
+                    .proxy
+                    .scheduled_job_finish(job_id, status, Some(&msg))
+                    .await;
+            }
+        }
+    });
+}
 
-    // Read raw body with a reasonable upper bound to avoid memory abuse.
-    let body_bytes = body::to_bytes(body, BODY_LIMIT)
-        .await
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
+/// Result of one standby pull from the primary.
+#[derive(Debug)]
+enum ReplicationSync {
+    Snapshot(usize),
+    Changes,
+}
 
-    let mut options: Value =
-        serde_json::from_slice(&body_bytes).map_err(|_| StatusCode::BAD_REQUEST)?;
-    if !options.is_object() {
-        return Err(StatusCode::BAD_REQUEST);
+async fn fetch_replication_json<T: serde::de::DeserializeOwned>(
+    client: &reqwest::Client,
+    url: String,
+    auth: Option<&(String, String)>,
+) -> Result<T, String> {
+    let mut request = client.get(&url);
+    if let Some((name, value)) = auth {
+        request = request.header(name.as_str(), value.as_str());
     }
+    let response = request
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|err| format!("GET {url}: {err}"))?;
+    response
+        .json::<T>()
+        .await
+        .map_err(|err| format!("GET {url}: {err}"))
+}
 
-    // Prefer Authorization: Bearer th-<id>-<secret>, fall back to JSON api_key.
-    let auth_bearer = parts
-        .headers
-        .get(axum::http::header::AUTHORIZATION)
-        .and_then(|v| v.to_str().ok())
-        .map(|s| s.trim().to_string());
-    let header_token = auth_bearer
-        .as_deref()
-        .and_then(|raw| raw.strip_prefix("Bearer "))
-        .map(str::trim)
-        .filter(|t| !t.is_empty())
-        .map(|t| t.to_string());
-
-    let body_token = options
-        .get("api_key")
-        .and_then(|v| v.as_str())
-        .map(str::trim)
-        .filter(|v| !v.is_empty())
-        .map(|v| v.to_string());
-
-    let token = if let Some(t) = header_token {
-        t
-    } else if let Some(t) = body_token {
-        t
-    } else if state.dev_open_admin {
-        "th-dev-override".to_string()
-    } else {
-        let resp = Response::builder()
-            .status(StatusCode::UNAUTHORIZED)
-            .header(CONTENT_TYPE, "application/json; charset=utf-8")
-            .body(Body::from("{\"error\":\"missing token\"}"))
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-        return Ok(resp);
-    };
-
-    let valid = if state.dev_open_admin {
-        true
-    } else {
-        state
-            .proxy
-            .validate_access_token(&token)
-            .await
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-    };
-    if !valid {
-        let resp = Response::builder()
-            .status(StatusCode::UNAUTHORIZED)
-            .header(CONTENT_TYPE, "application/json; charset=utf-8")
-            .body(Body::from("{\"error\":\"invalid or disabled token\"}"))
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-        return Ok(resp);
+/// Pull from the primary once: a full snapshot on first run (or after the primary's journal
+/// was reset), otherwise every pending change batch.
+async fn replication_sync_once(
+    proxy: &TavilyProxy,
+    client: &reqwest::Client,
+    primary: &str,
+    auth: Option<&(String, String)>,
+) -> Result<ReplicationSync, String> {
+    let progress = proxy
+        .replication_progress()
+        .await
+        .map_err(|err| err.to_string())?;
+
+    if let Some(progress) = progress {
+        let mut changes = Vec::new();
+        let mut since_id = progress.cursor;
+        let mut primary_reset = false;
+        let watermark = loop {
+            let page: ReplicationChangesView = fetch_replication_json(
+                client,
+                format!("{primary}/api/replication/changes?since_id={since_id}&limit=1000"),
+                auth,
+            )
+            .await?;
+            if page.high_watermark < progress.cursor {
+                primary_reset = true;
+                break page.high_watermark;
+            }
+            changes.extend(page.changes.into_iter().map(|c| ReplicationChange {
+                id: c.id,
+                table: c.table,
+                key: c.key,
+                row: c.row,
+            }));
+            since_id = page.next_since_id;
+            if !page.has_more {
+                break page.high_watermark;
+            }
+        };
+        if !primary_reset {
+            proxy
+                .apply_replication_changes(&changes, since_id, watermark)
+                .await
+                .map_err(|err| err.to_string())?;
+            return Ok(ReplicationSync::Changes);
+        }
     }
 
-    let auth_token_id = if state.dev_open_admin {
-        Some("dev".to_string())
-    } else {
-        token
-            .strip_prefix("th-")
-            .and_then(|rest| rest.split_once('-').map(|(id, _)| id))
-            .map(|s| s.to_string())
-    };
+    let snapshot: ReplicationSnapshotView =
+        fetch_replication_json(client, format!("{primary}/api/replication/snapshot"), auth).await?;
+    let rows = proxy
+        .apply_replication_snapshot(&ReplicationSnapshot {
+            watermark: snapshot.watermark,
+            tables: snapshot.tables.into_iter().collect(),
+        })
+        .await
+        .map_err(|err| err.to_string())?;
+    Ok(ReplicationSync::Snapshot(rows))
+}
 
-    // Remove api_key from JSON body before forwarding upstream; it will be replaced by Tavily key.
-    if let Value::Object(ref mut map) = options {
-        map.remove("api_key");
-    }
+fn spawn_replication_follower(state: Arc<AppState>, primary: String) {
+    tokio::spawn(async move {
+        let client = reqwest::Client::new();
+        let auth = effective_replication_auth_header();
+        let interval = Duration::from_secs(effective_replication_interval_secs() as u64);
+        let mut last_error: Option<String> = None;
+        loop {
+            if job_paused(&state, "replication_sync").await {
+                tokio::time::sleep(interval).await;
+                continue;
+            }
+            // Routine change pulls are not recorded; snapshots and new errors are.
+            let (status, message) =
+                match replication_sync_once(&state.proxy, &client, &primary, auth.as_ref()).await {
+                    Ok(ReplicationSync::Changes) => {
+                        last_error = None;
+                        (None, String::new())
+                    }
+                    Ok(ReplicationSync::Snapshot(rows)) => {
+                        last_error = None;
+                        (Some("success"), format!("snapshot rows={rows}"))
+                    }
+                    Err(err) => {
+                        tracing::error!("replication: {err}");
+                        let repeated = last_error.as_deref() == Some(err.as_str());
+                        last_error = Some(err.clone());
+                        (if repeated { None } else { Some("error") }, err)
+                    }
+                };
+            if let Some(status) = status
+                && let Ok(job_id) = state
+                    .proxy
+                    .scheduled_job_start("replication_sync", None, 1)
+                    .await
+            {
+                let _ = state
+                    .proxy
+                    .scheduled_job_finish(job_id, status, Some(&message))
+                    .await;
+            }
+            tokio::time::sleep(interval).await;
+        }
+    });
+}
 
-    // Basic validation for obviously invalid numeric fields.
-    if let Value::Object(ref map) = options
-        && let Some(val) = map.get("max_results").and_then(|v| v.as_i64())
-        && val < 0
-    {
-        let payload = json!({
-            "error": "invalid_request",
-            "message": "max_results must be non-negative",
-        });
-        let resp = Response::builder()
-            .status(StatusCode::BAD_REQUEST)
-            .header(CONTENT_TYPE, "application/json; charset=utf-8")
-            .body(Body::from(payload.to_string()))
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-        return Ok(resp);
-    }
+const QUOTA_RECONCILE_INTERVAL_SECS: u64 = 7 * 24 * 3600;
 
-    let token_id_for_logs = auth_token_id.clone();
+fn quota_drift_summary(drifts: &[QuotaDrift]) -> String {
+    let monthly: i64 = drifts
+        .iter()
+        .map(|d| (d.monthly_after - d.monthly_before).abs())
+        .sum();
+    format!("tokens_corrected={} monthly_drift={monthly}", drifts.len())
+}
 
-    // Per-token hourly *any request* limit.
-    if let Some(ref tid) = auth_token_id
-        && !state.dev_open_admin
-    {
-        match state.proxy.check_token_hourly_requests(tid).await {
-            Ok(verdict) => {
-                if !verdict.allowed {
-                    let message = build_request_limit_error_message(&verdict);
-                    let _ = state
-                        .proxy
-                        .record_token_attempt(
-                            tid,
-                            &method,
-                            &path,
-                            None,
-                            Some(StatusCode::TOO_MANY_REQUESTS.as_u16() as i64),
-                            None,
-                            false,
-                            "quota_exhausted",
-                            Some(&message),
-                        )
-                        .await;
-                    let payload = json!({
-                        "error": "quota_exhausted",
-                        "message": "hourly request limit reached for this token",
-                    });
-                    let resp = Response::builder()
-                        .status(StatusCode::TOO_MANY_REQUESTS)
-                        .header(CONTENT_TYPE, "application/json; charset=utf-8")
-                        .body(Body::from(payload.to_string()))
-                        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-                    return Ok(resp);
-                }
+fn spawn_quota_reconcile_scheduler(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        loop {
+            // Counters only drift slowly, so give startup traffic a chance to settle first.
+            tokio::time::sleep(Duration::from_secs(QUOTA_RECONCILE_INTERVAL_SECS)).await;
+            if job_paused(&state, "quota_reconcile").await {
+                continue;
             }
-            Err(err) => {
-                eprintln!("hourly request limit check failed for /api/tavily/search: {err}");
-                if let Some(tid) = auth_token_id.as_deref() {
-                    let msg = err.to_string();
-                    let _ = state
-                        .proxy
-                        .record_token_attempt(
-                            tid,
-                            &method,
-                            &path,
-                            None,
-                            Some(StatusCode::INTERNAL_SERVER_ERROR.as_u16() as i64),
-                            None,
-                            true,
-                            "error",
-                            Some(msg.as_str()),
-                        )
-                        .await;
+
+            let job_id = match state
+                .proxy
+                .scheduled_job_start("quota_reconcile", None, 1)
+                .await
+            {
+                Ok(id) => id,
+                Err(err) => {
+                    tracing::error!("quota-reconcile: start job error: {err}");
+                    continue;
                 }
-                return Err(StatusCode::INTERNAL_SERVER_ERROR);
-            }
-        }
-    }
+            };
 
-    // Per-token business quota check (hour / day / month).
-    if let Some(ref tid) = auth_token_id {
-        match state.proxy.check_token_quota(tid).await {
-            Ok(verdict) => {
-                if !state.dev_open_admin && !verdict.allowed {
+            match state.proxy.reconcile_token_quota().await {
+                Ok(drifts) => {
+                    let msg = quota_drift_summary(&drifts);
                     let _ = state
                         .proxy
-                        .record_token_attempt(
-                            tid,
-                            &method,
-                            &path,
-                            None,
-                            Some(StatusCode::TOO_MANY_REQUESTS.as_u16() as i64),
-                            None,
-                            true,
-                            "quota_exhausted",
-                            Some("daily / hourly limit reached for this token"),
-                        )
+                        .scheduled_job_finish(job_id, "success", Some(&msg))
                         .await;
-                    let payload = json!({
-                        "error": "quota_exhausted",
-                        "message": "daily / hourly limit reached for this token",
-                    });
-                    let resp = Response::builder()
-                        .status(StatusCode::TOO_MANY_REQUESTS)
-                        .header(CONTENT_TYPE, "application/json; charset=utf-8")
-                        .body(Body::from(payload.to_string()))
-                        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-                    return Ok(resp);
                 }
-            }
-            Err(err) => {
-                eprintln!("quota check failed for /api/tavily/search: {err}");
-                if let Some(tid) = auth_token_id.as_deref() {
-                    let msg = err.to_string();
+                Err(err) => {
                     let _ = state
                         .proxy
-                        .record_token_attempt(
-                            tid,
-                            &method,
-                            &path,
-                            None,
-                            Some(StatusCode::INTERNAL_SERVER_ERROR.as_u16() as i64),
-                            None,
-                            true,
-                            "error",
-                            Some(msg.as_str()),
-                        )
+                        .scheduled_job_finish(job_id, "error", Some(&err.to_string()))
                         .await;
                 }
-                return Err(StatusCode::INTERNAL_SERVER_ERROR);
             }
         }
+    });
+}
+
+// kept for potential future direct serving; currently ServeDir handles '/'
+#[allow(dead_code)]
+async fn load_spa_response(
+    state: &AppState,
+    file_name: &str,
+) -> Result<Response<Body>, StatusCode> {
+    let Some(dir) = state.static_dir.as_ref() else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    let path = dir.join(file_name);
+    let Ok(bytes) = tokio::fs::read(path).await else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "text/html; charset=utf-8")
+        .body(Body::from(bytes))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+async fn serve_index(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Response<Body>, StatusCode> {
+    // Only auto-redirect to admin when explicit dev convenience flag is enabled.
+    // Admin users should still be able to access the public page without forced redirection.
+    if state.dev_open_admin {
+        return Ok(Redirect::temporary("/admin").into_response());
     }
 
-    // Clone headers into reqwest::HeaderMap so we can reuse sanitize logic, and avoid
-    // forwarding our Authorization header to Tavily upstream.
-    let mut headers = clone_headers(&parts.headers);
-    headers.remove(axum::http::header::AUTHORIZATION);
+    let _ = headers; // keep parameter for potential future use
+    load_spa_response(state.as_ref(), "index.html").await
+}
 
-    let result = state
-        .proxy
-        .proxy_http_search(
-            &state.usage_base,
-            auth_token_id.as_deref(),
-            &method,
-            &path,
-            options,
-            &headers,
-        )
-        .await;
+async fn serve_admin_index(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Response<Body>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
 
-    match result {
-        Ok((resp, analysis)) => {
-            if let Some(tid) = token_id_for_logs.as_deref() {
-                let http_code = resp.status.as_u16() as i64;
-                let _ = state
-                    .proxy
-                    .record_token_attempt(
-                        tid,
-                        &method,
-                        &path,
-                        None,
-                        Some(http_code),
-                        analysis.tavily_status_code,
-                        true,
-                        analysis.status,
-                        None,
-                    )
-                    .await;
-            }
-            Ok(build_response(resp))
-        }
-        Err(err) => {
-            eprintln!("tavily http /search proxy error: {err}");
-            if let Some(tid) = token_id_for_logs.as_deref() {
-                let msg = err.to_string();
-                let _ = state
-                    .proxy
-                    .record_token_attempt(
-                        tid,
-                        &method,
-                        &path,
-                        None,
-                        None,
-                        None,
-                        true,
-                        "error",
-                        Some(msg.as_str()),
-                    )
-                    .await;
-            }
-
-            let status = match err {
-                ProxyError::Http(_) | ProxyError::NoAvailableKeys => StatusCode::BAD_GATEWAY,
-                ProxyError::Database(_)
-                | ProxyError::InvalidEndpoint { .. }
-                | ProxyError::QuotaDataMissing { .. }
-                | ProxyError::UsageHttp { .. }
-                | ProxyError::Other(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            };
-
-            let payload = json!({
-                "error": "proxy_error",
-                "message": "upstream unavailable",
-            });
-            let resp = Response::builder()
-                .status(status)
-                .header(CONTENT_TYPE, "application/json; charset=utf-8")
-                .body(Body::from(payload.to_string()))
-                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-            Ok(resp)
-        }
-    }
+    load_spa_response(state.as_ref(), "admin.html").await
 }
 
-async fn tavily_http_extract(
-    State(state): State<Arc<AppState>>,
-    req: Request<Body>,
-) -> Result<Response<Body>, StatusCode> {
-    let (parts, body) = req.into_parts();
-    let method = parts.method.clone();
-    let path = parts.uri.path().to_owned();
+const BASE_404_STYLES: &str = r#"
+  :root {
+    color-scheme: light;
+    font-family: 'Inter', 'SF Pro Display', -apple-system, BlinkMacSystemFont, 'Segoe UI', sans-serif;
+    text-rendering: optimizeLegibility;
+  }
 
-    // Read raw body with a reasonable upper bound to avoid memory abuse.
-    let body_bytes = body::to_bytes(body, BODY_LIMIT)
-        .await
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
+  body {
+    margin: 0;
+    min-height: 100vh;
+    display: flex;
+    align-items: center;
+    justify-content: center;
+    background: radial-gradient(circle at top left, rgba(99, 102, 241, 0.12), transparent 45%),
+      radial-gradient(circle at bottom right, rgba(236, 72, 153, 0.12), transparent 50%),
+      #f5f6fb;
+    color: #1f2937;
+  }
 
-    let mut options: Value =
-        serde_json::from_slice(&body_bytes).map_err(|_| StatusCode::BAD_REQUEST)?;
-    if !options.is_object() {
-        return Err(StatusCode::BAD_REQUEST);
+  @media (prefers-color-scheme: dark) {
+    :root {
+      color-scheme: dark;
     }
+    body {
+      background: radial-gradient(circle at top left, rgba(129, 140, 248, 0.22), transparent 45%),
+        radial-gradient(circle at bottom right, rgba(236, 72, 153, 0.18), transparent 50%),
+        #0f172a;
+      color: #e2e8f0;
+    }
+  }
 
-    // Prefer Authorization: Bearer th-<id>-<secret>, fall back to JSON api_key.
-    let auth_bearer = parts
-        .headers
-        .get(axum::http::header::AUTHORIZATION)
-        .and_then(|v| v.to_str().ok())
-        .map(|s| s.trim().to_string());
-    let header_token = auth_bearer
-        .as_deref()
-        .and_then(|raw| raw.strip_prefix("Bearer "))
-        .map(str::trim)
-        .filter(|t| !t.is_empty())
-        .map(|t| t.to_string());
-
-    let body_token = options
-        .get("api_key")
-        .and_then(|v| v.as_str())
-        .map(str::trim)
-        .filter(|v| !v.is_empty())
-        .map(|v| v.to_string());
-
-    let token = if let Some(t) = header_token {
-        t
-    } else if let Some(t) = body_token {
-        t
-    } else if state.dev_open_admin {
-        "th-dev-override".to_string()
-    } else {
-        let resp = Response::builder()
-            .status(StatusCode::UNAUTHORIZED)
-            .header(CONTENT_TYPE, "application/json; charset=utf-8")
-            .body(Body::from("{\"error\":\"missing token\"}"))
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-        return Ok(resp);
-    };
+  .not-found-shell {
+    max-width: 520px;
+    width: calc(100% - 48px);
+    padding: 48px 40px;
+    border-radius: 28px;
+    background: rgba(255, 255, 255, 0.82);
+    border: 1px solid rgba(15, 23, 42, 0.08);
+    backdrop-filter: blur(18px);
+    box-shadow: 0 28px 65px rgba(15, 23, 42, 0.12);
+    text-align: center;
+  }
 
-    let valid = if state.dev_open_admin {
-        true
-    } else {
-        state
-            .proxy
-            .validate_access_token(&token)
-            .await
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-    };
-    if !valid {
-        let resp = Response::builder()
-            .status(StatusCode::UNAUTHORIZED)
-            .header(CONTENT_TYPE, "application/json; charset=utf-8")
-            .body(Body::from("{\"error\":\"invalid or disabled token\"}"))
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-        return Ok(resp);
+  @media (prefers-color-scheme: dark) {
+    .not-found-shell {
+      background: rgba(15, 23, 42, 0.7);
+      border: 1px solid rgba(148, 163, 184, 0.18);
+      box-shadow: 0 32px 65px rgba(15, 23, 42, 0.5);
     }
+  }
 
-    let auth_token_id = if state.dev_open_admin {
-        Some("dev".to_string())
-    } else {
-        token
-            .strip_prefix("th-")
-            .and_then(|rest| rest.split_once('-').map(|(id, _)| id))
-            .map(|s| s.to_string())
-    };
+  .not-found-badge {
+    display: inline-flex;
+    align-items: center;
+    justify-content: center;
+    gap: 6px;
+    padding: 8px 16px;
+    border-radius: 999px;
+    background: rgba(99, 102, 241, 0.16);
+    color: #4338ca;
+    font-size: 0.85rem;
+    font-weight: 600;
+    letter-spacing: 0.02em;
+    text-transform: uppercase;
+  }
 
-    // Remove api_key from JSON body before forwarding upstream; it will be replaced by Tavily key.
-    if let Value::Object(ref mut map) = options {
-        map.remove("api_key");
+  .not-found-code {
+    margin: 28px 0 12px;
+    font-size: clamp(4rem, 13vw, 6rem);
+    font-weight: 800;
+    line-height: 1;
+    letter-spacing: -0.04em;
+    color: #4f46e5;
+  }
+
+  @media (prefers-color-scheme: dark) {
+    .not-found-code {
+      color: #a5b4fc;
     }
+  }
 
-    let token_id_for_logs = auth_token_id.clone();
+  .not-found-title {
+    margin: 0;
+    font-size: clamp(1.5rem, 4vw, 2.25rem);
+    font-weight: 700;
+    letter-spacing: -0.01em;
+  }
 
-    // Per-token hourly *any request* limit.
-    if let Some(ref tid) = auth_token_id
-        && !state.dev_open_admin
-    {
-        match state.proxy.check_token_hourly_requests(tid).await {
-            Ok(verdict) => {
-                if !verdict.allowed {
-                    let message = build_request_limit_error_message(&verdict);
-                    let _ = state
-                        .proxy
-                        .record_token_attempt(
-                            tid,
-                            &method,
-                            &path,
-                            None,
-                            Some(StatusCode::TOO_MANY_REQUESTS.as_u16() as i64),
-                            None,
-                            false,
-                            "quota_exhausted",
-                            Some(&message),
-                        )
-                        .await;
-                    let payload = json!({
-                        "error": "quota_exhausted",
-                        "message": "hourly request limit reached for this token",
-                    });
-                    let resp = Response::builder()
-                        .status(StatusCode::TOO_MANY_REQUESTS)
-                        .header(CONTENT_TYPE, "application/json; charset=utf-8")
-                        .body(Body::from(payload.to_string()))
-                        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-                    return Ok(resp);
-                }
-            }
-            Err(err) => {
-                eprintln!("hourly request limit check failed for /api/tavily/extract: {err}");
-                if let Some(tid) = auth_token_id.as_deref() {
-                    let msg = err.to_string();
-                    let _ = state
-                        .proxy
-                        .record_token_attempt(
-                            tid,
-                            &method,
-                            &path,
-                            None,
-                            Some(StatusCode::INTERNAL_SERVER_ERROR.as_u16() as i64),
-                            None,
-                            true,
-                            "error",
-                            Some(msg.as_str()),
-                        )
-                        .await;
-                }
-                return Err(StatusCode::INTERNAL_SERVER_ERROR);
-            }
-        }
-    }
+  .not-found-description {
+    margin: 20px 0 30px;
+    color: rgba(100, 116, 139, 0.95);
+    font-size: 1rem;
+    line-height: 1.7;
+  }
 
-    // Per-token business quota check.
-    if let Some(ref tid) = auth_token_id {
-        match state.proxy.check_token_quota(tid).await {
-            Ok(verdict) => {
-                if !state.dev_open_admin && !verdict.allowed {
-                    let _ = state
-                        .proxy
-                        .record_token_attempt(
-                            tid,
-                            &method,
-                            &path,
-                            None,
-                            Some(StatusCode::TOO_MANY_REQUESTS.as_u16() as i64),
-                            None,
-                            true,
-                            "quota_exhausted",
-                            Some("daily / hourly limit reached for this token"),
-                        )
-                        .await;
-                    let payload = json!({
-                        "error": "quota_exhausted",
-                        "message": "daily / hourly limit reached for this token",
-                    });
-                    let resp = Response::builder()
-                        .status(StatusCode::TOO_MANY_REQUESTS)
-                        .header(CONTENT_TYPE, "application/json; charset=utf-8")
-                        .body(Body::from(payload.to_string()))
-                        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-                    return Ok(resp);
-                }
-            }
-            Err(err) => {
-                eprintln!("quota check failed for /api/tavily/extract: {err}");
-                if let Some(tid) = auth_token_id.as_deref() {
-                    let msg = err.to_string();
-                    let _ = state
-                        .proxy
-                        .record_token_attempt(
-                            tid,
-                            &method,
-                            &path,
-                            None,
-                            Some(StatusCode::INTERNAL_SERVER_ERROR.as_u16() as i64),
-                            None,
-                            true,
-                            "error",
-                            Some(msg.as_str()),
-                        )
-                        .await;
-                }
-                return Err(StatusCode::INTERNAL_SERVER_ERROR);
-            }
-        }
+  @media (prefers-color-scheme: dark) {
+    .not-found-description {
+      color: rgba(203, 213, 225, 0.82);
     }
+  }
 
-    // Clone headers into reqwest::HeaderMap so we can reuse sanitize logic, and avoid
-    // forwarding our Authorization header to Tavily upstream.
-    let mut headers = clone_headers(&parts.headers);
-    headers.remove(axum::http::header::AUTHORIZATION);
+  .not-found-actions {
+    display: flex;
+    align-items: center;
+    justify-content: center;
+    margin-top: 28px;
+  }
 
-    let result = state
-        .proxy
-        .proxy_http_json_endpoint(
-            &state.usage_base,
-            "/extract",
-            auth_token_id.as_deref(),
-            &method,
-            &path,
-            options,
-            &headers,
-        )
-        .await;
+  .not-found-primary {
+    display: inline-flex;
+    align-items: center;
+    justify-content: center;
+    gap: 8px;
+    padding: 12px 22px;
+    border-radius: 999px;
+    font-weight: 600;
+    color: #fff;
+    background: linear-gradient(135deg, #6366f1, #8b5cf6);
+    box-shadow: 0 16px 35px rgba(99, 102, 241, 0.35);
+    text-decoration: none;
+    transition: transform 0.12s ease, box-shadow 0.12s ease;
+  }
 
-    match result {
-        Ok((resp, analysis)) => {
-            if let Some(tid) = token_id_for_logs.as_deref() {
-                let http_code = resp.status.as_u16() as i64;
-                let _ = state
-                    .proxy
-                    .record_token_attempt(
-                        tid,
-                        &method,
-                        &path,
-                        None,
-                        Some(http_code),
-                        analysis.tavily_status_code,
-                        true,
-                        analysis.status,
-                        None,
-                    )
-                    .await;
+  .not-found-primary:hover {
+    transform: translateY(-1px);
+    box-shadow: 0 20px 40px rgba(99, 102, 241, 0.4);
+  }
+
+  .not-found-footer {
+    margin-top: 36px;
+    font-size: 0.85rem;
+    color: rgba(100, 116, 139, 0.75);
+  }
+
+  @media (prefers-color-scheme: dark) {
+    .not-found-footer {
+      color: rgba(148, 163, 184, 0.78);
+    }
+  }
+"#;
+
+fn find_frontend_css_href(static_dir: Option<&FsPath>) -> Option<String> {
+    let dir = static_dir?;
+    let index_path = dir.join("index.html");
+    let mut s = String::new();
+    if fs::File::open(&index_path)
+        .ok()?
+        .read_to_string(&mut s)
+        .is_ok()
+    {
+        // naive scan for first stylesheet href
+        if let Some(idx) = s.find("rel=\"stylesheet\"") {
+            let frag = &s[idx..];
+            if let Some(href_idx) = frag.find("href=\"") {
+                let frag2 = &frag[href_idx + 6..];
+                if let Some(end_idx) = frag2.find('\"') {
+                    let href = &frag2[..end_idx];
+                    return Some(href.to_string());
+                }
             }
-            Ok(build_response(resp))
         }
-        Err(err) => {
-            eprintln!("tavily http /extract proxy error: {err}");
-            if let Some(tid) = token_id_for_logs.as_deref() {
-                let msg = err.to_string();
-                let _ = state
-                    .proxy
-                    .record_token_attempt(
-                        tid,
-                        &method,
-                        &path,
-                        None,
-                        None,
-                        None,
-                        true,
-                        "error",
-                        Some(msg.as_str()),
-                    )
-                    .await;
-            }
+    }
+    None
+}
 
-            let status = match err {
-                ProxyError::Http(_) | ProxyError::NoAvailableKeys => StatusCode::BAD_GATEWAY,
-                ProxyError::Database(_)
-                | ProxyError::InvalidEndpoint { .. }
-                | ProxyError::QuotaDataMissing { .. }
-                | ProxyError::UsageHttp { .. }
-                | ProxyError::Other(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            };
+fn load_frontend_css_content(static_dir: Option<&FsPath>) -> Option<String> {
+    let dir = static_dir?;
+    let href = find_frontend_css_href(Some(dir))?;
+    // href like "/assets/index-xxxx.css" => remove leading slash and read from static_dir root
+    let rel = href.trim_start_matches('/');
+    let path = dir.join(
+        rel.strip_prefix("assets/")
+            .map(|s| FsPath::new("assets").join(s))
+            .unwrap_or_else(|| FsPath::new(rel).to_path_buf()),
+    );
+    fs::read_to_string(path).ok()
+}
 
-            let payload = json!({
-                "error": "proxy_error",
-                "message": "upstream unavailable",
-            });
-            let resp = Response::builder()
-                .status(status)
-                .header(CONTENT_TYPE, "application/json; charset=utf-8")
-                .body(Body::from(payload.to_string()))
-                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-            Ok(resp)
-        }
+#[derive(Deserialize)]
+struct FallbackQuery {
+    path: Option<String>,
+}
+
+async fn not_found_landing(
+    State(state): State<Arc<AppState>>,
+    Query(q): Query<FallbackQuery>,
+) -> Response<Body> {
+    let css = load_frontend_css_content(state.static_dir.as_deref());
+    let html = build_404_landing_inline(css.as_deref(), q.path.unwrap_or_else(|| "/".to_string()));
+    Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .header(CONTENT_TYPE, "text/html; charset=utf-8")
+        .header(CONTENT_LENGTH, html.len().to_string())
+        .body(Body::from(html))
+        .unwrap_or_else(|_| Response::builder().status(500).body(Body::empty()).unwrap())
+}
+
+fn build_404_landing_inline(css_content: Option<&str>, original: String) -> String {
+    let mut style_block = String::from("<style>\n");
+    style_block.push_str(BASE_404_STYLES);
+    if let Some(content) = css_content {
+        style_block.push_str(content);
     }
+    style_block.push_str("\n</style>\n");
+    // Safer: pass original path via data attribute and read it in script without string concatenation
+    let script = format!(
+        "<script data-p=\"{}\">!function(){{try{{var s=document.currentScript;var p=s&&s.getAttribute('data-p')||'/';history.replaceState(null,'', p)}}catch(_e){{}}}}()</script>",
+        html_escape::encode_double_quoted_attribute(&original)
+    );
+    format!(
+        "<!doctype html>\n<html lang=\"en\">\n  <head>\n    <meta charset=\"UTF-8\" />\n    <meta name=\"viewport\" content=\"width=device-width, initial-scale=1.0\" />\n    <title>404 Not Found</title>\n    {}  </head>\n  <body>\n    <main class=\"not-found-shell\" role=\"main\">\n      <span class=\"not-found-badge\" aria-hidden=\"true\">Tavily Hikari Proxy</span>\n      <p class=\"not-found-code\">404</p>\n      <h1 class=\"not-found-title\">Page not found</h1>\n      <p class=\"not-found-description\">The page you’re trying to visit, <code>{}</code>, isn’t available right now.</p>\n      <div class=\"not-found-actions\">\n        <a href=\"/\" class=\"not-found-primary\" aria-label=\"Back to dashboard\">Return to dashboard</a>\n      </div>\n      <p class=\"not-found-footer\">Error reference: 404</p>\n    </main>\n    {}\n  </body>\n</html>",
+        style_block,
+        html_escape::encode_text(&original),
+        script
+    )
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/tavily/search",
+    tag = "tavily",
+    summary = "Tavily /search through the key pool.",
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses(
+        (status = 200, description = "OK"),
+        (status = 401, description = "Missing or invalid token"),
+    ),
+    security(("hikariToken" = []))
+)]
+#[axum::debug_handler]
+async fn tavily_http_search(
+    State(state): State<Arc<AppState>>,
+    req: Request<Body>,
+) -> Result<Response<Body>, StatusCode> {
+    proxy_tavily_http(state, req, "search", KeyPlacement::BodyField).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/tavily/extract",
+    tag = "tavily",
+    summary = "Tavily /extract through the key pool.",
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses(
+        (status = 200, description = "OK"),
+        (status = 401, description = "Missing or invalid token"),
+    ),
+    security(("hikariToken" = []))
+)]
+async fn tavily_http_extract(
+    State(state): State<Arc<AppState>>,
+    req: Request<Body>,
+) -> Result<Response<Body>, StatusCode> {
+    proxy_tavily_http(state, req, "extract", KeyPlacement::BodyField).await
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/tavily/crawl",
+    tag = "tavily",
+    summary = "Tavily /crawl through the key pool.",
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses(
+        (status = 200, description = "OK"),
+        (status = 401, description = "Missing or invalid token"),
+    ),
+    security(("hikariToken" = []))
+)]
 async fn tavily_http_crawl(
     State(state): State<Arc<AppState>>,
     req: Request<Body>,
 ) -> Result<Response<Body>, StatusCode> {
+    proxy_tavily_http(state, req, "crawl", KeyPlacement::BodyField).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/tavily/map",
+    tag = "tavily",
+    summary = "Tavily /map through the key pool.",
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses(
+        (status = 200, description = "OK"),
+        (status = 401, description = "Missing or invalid token"),
+    ),
+    security(("hikariToken" = []))
+)]
+async fn tavily_http_map(
+    State(state): State<Arc<AppState>>,
+    req: Request<Body>,
+) -> Result<Response<Body>, StatusCode> {
+    proxy_tavily_http(state, req, "map", KeyPlacement::BodyField).await
+}
+
+/// Plain REST passthrough to `api.tavily.com` (`/v1/search`, `/v1/extract`, ...). Callers
+/// authenticate with `Authorization: Bearer th-...`; the pooled Tavily key is forwarded as
+/// `Authorization: Bearer tvly-...` while quota, gating and logging match the MCP path.
+#[utoipa::path(
+    post,
+    path = "/v1/{endpoint}",
+    tag = "tavily",
+    summary = "Tavily REST API passthrough with the pooled key as bearer.",
+    params(("endpoint" = String, Path)),
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses(
+        (status = 200, description = "OK"),
+        (status = 401, description = "Missing or invalid token"),
+    ),
+    security(("hikariToken" = []))
+)]
+async fn tavily_rest_passthrough(
+    State(state): State<Arc<AppState>>,
+    Path(endpoint): Path<String>,
+    req: Request<Body>,
+) -> Result<Response<Body>, StatusCode> {
+    let Some(tool) = HTTP_API_TOOLS
+        .iter()
+        .copied()
+        .find(|tool| *tool == endpoint)
+    else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    proxy_tavily_http(state, req, tool, KeyPlacement::BearerHeader).await
+}
+
+/// Shared body of the Tavily HTTP endpoints, `/api/tavily/<tool>` and `/v1/<tool>`:
+/// refuses calls during maintenance, authenticates the caller, runs the quarantine,
+/// tool-policy and quota gates, forwards the JSON body to `/<tool>` with the leased key
+/// placed per `placement`, and records the attempt.
+async fn proxy_tavily_http(
+    state: Arc<AppState>,
+    req: Request<Body>,
+    tool: &'static str,
+    placement: KeyPlacement,
+) -> Result<Response<Body>, StatusCode> {
+    if let Some(mode) = state.proxy.maintenance() {
+        return maintenance_response(&mode, Value::Null);
+    }
     let (parts, body) = req.into_parts();
     let method = parts.method.clone();
     let path = parts.uri.path().to_owned();
@@ -1322,7 +1628,6 @@ async fn tavily_http_crawl(
     let body_bytes = body::to_bytes(body, BODY_LIMIT)
         .await
         .map_err(|_| StatusCode::BAD_REQUEST)?;
-
     let mut options: Value =
         serde_json::from_slice(&body_bytes).map_err(|_| StatusCode::BAD_REQUEST)?;
     if !options.is_object() {
@@ -1330,58 +1635,50 @@ async fn tavily_http_crawl(
     }
 
     // Prefer Authorization: Bearer th-<id>-<secret>, fall back to JSON api_key.
-    let auth_bearer = parts
+    let header_token = parts
         .headers
         .get(axum::http::header::AUTHORIZATION)
         .and_then(|v| v.to_str().ok())
-        .map(|s| s.trim().to_string());
-    let header_token = auth_bearer
-        .as_deref()
-        .and_then(|raw| raw.strip_prefix("Bearer "))
+        .and_then(|raw| raw.trim().strip_prefix("Bearer "))
         .map(str::trim)
         .filter(|t| !t.is_empty())
-        .map(|t| t.to_string());
-
+        .map(str::to_string);
     let body_token = options
         .get("api_key")
         .and_then(|v| v.as_str())
         .map(str::trim)
         .filter(|v| !v.is_empty())
-        .map(|v| v.to_string());
-
-    let token = if let Some(t) = header_token {
-        t
-    } else if let Some(t) = body_token {
-        t
-    } else if state.dev_open_admin {
-        "th-dev-override".to_string()
-    } else {
-        let resp = Response::builder()
-            .status(StatusCode::UNAUTHORIZED)
+        .map(str::to_string);
+    let json_error = |status: StatusCode, payload: Value| {
+        Response::builder()
+            .status(status)
             .header(CONTENT_TYPE, "application/json; charset=utf-8")
-            .body(Body::from("{\"error\":\"missing token\"}"))
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-        return Ok(resp);
+            .body(Body::from(payload.to_string()))
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
     };
 
-    let valid = if state.dev_open_admin {
-        true
-    } else {
-        state
+    let token = match header_token.or(body_token) {
+        Some(token) => token,
+        None if state.dev_open_admin => "th-dev-override".to_string(),
+        None => {
+            return json_error(
+                StatusCode::UNAUTHORIZED,
+                json!({ "error": "missing token" }),
+            );
+        }
+    };
+    let valid = state.dev_open_admin
+        || state
             .proxy
             .validate_access_token(&token)
             .await
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-    };
-    if !valid {
-        let resp = Response::builder()
-            .status(StatusCode::UNAUTHORIZED)
-            .header(CONTENT_TYPE, "application/json; charset=utf-8")
-            .body(Body::from("{\"error\":\"invalid or disabled token\"}"))
             .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-        return Ok(resp);
+    if !valid {
+        return json_error(
+            StatusCode::UNAUTHORIZED,
+            json!({ "error": "invalid or disabled token" }),
+        );
     }
-
     let auth_token_id = if state.dev_open_admin {
         Some("dev".to_string())
     } else {
@@ -1391,373 +1688,167 @@ async fn tavily_http_crawl(
             .map(|s| s.to_string())
     };
 
+    if let Some(resp) = quarantine_gate(
+        &state,
+        auth_token_id.as_deref(),
+        &method,
+        &path,
+        None,
+        Some(tool),
+    )
+    .await?
+    {
+        return Ok(resp);
+    }
+    if let Some(resp) = tool_policy_gate(
+        &state,
+        auth_token_id.as_deref(),
+        &method,
+        &path,
+        None,
+        Some(tool),
+        None,
+    )
+    .await?
+    {
+        return Ok(resp);
+    }
+
     // Remove api_key from JSON body before forwarding upstream; it will be replaced by Tavily key.
     if let Value::Object(ref mut map) = options {
         map.remove("api_key");
     }
 
-    let token_id_for_logs = auth_token_id.clone();
-
-    // Per-token hourly *any request* limit.
-    if let Some(ref tid) = auth_token_id
-        && !state.dev_open_admin
+    // Basic validation for obviously invalid numeric fields.
+    if tool == "search"
+        && let Some(val) = options.get("max_results").and_then(|v| v.as_i64())
+        && val < 0
     {
-        match state.proxy.check_token_hourly_requests(tid).await {
-            Ok(verdict) => {
-                if !verdict.allowed {
-                    let message = build_request_limit_error_message(&verdict);
-                    let _ = state
-                        .proxy
-                        .record_token_attempt(
-                            tid,
-                            &method,
-                            &path,
-                            None,
-                            Some(StatusCode::TOO_MANY_REQUESTS.as_u16() as i64),
-                            None,
-                            false,
-                            "quota_exhausted",
-                            Some(&message),
-                        )
-                        .await;
-                    let payload = json!({
-                        "error": "quota_exhausted",
-                        "message": "hourly request limit reached for this token",
-                    });
-                    let resp = Response::builder()
-                        .status(StatusCode::TOO_MANY_REQUESTS)
-                        .header(CONTENT_TYPE, "application/json; charset=utf-8")
-                        .body(Body::from(payload.to_string()))
-                        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-                    return Ok(resp);
-                }
-            }
-            Err(err) => {
-                eprintln!("hourly request limit check failed for /api/tavily/crawl: {err}");
-                if let Some(tid) = auth_token_id.as_deref() {
-                    let msg = err.to_string();
-                    let _ = state
-                        .proxy
-                        .record_token_attempt(
-                            tid,
-                            &method,
-                            &path,
-                            None,
-                            Some(StatusCode::INTERNAL_SERVER_ERROR.as_u16() as i64),
-                            None,
-                            true,
-                            "error",
-                            Some(msg.as_str()),
-                        )
-                        .await;
-                }
-                return Err(StatusCode::INTERNAL_SERVER_ERROR);
-            }
-        }
-    }
-
-    // Per-token business quota check.
-    if let Some(ref tid) = auth_token_id {
-        match state.proxy.check_token_quota(tid).await {
-            Ok(verdict) => {
-                if !state.dev_open_admin && !verdict.allowed {
-                    let _ = state
-                        .proxy
-                        .record_token_attempt(
-                            tid,
-                            &method,
-                            &path,
-                            None,
-                            Some(StatusCode::TOO_MANY_REQUESTS.as_u16() as i64),
-                            None,
-                            true,
-                            "quota_exhausted",
-                            Some("daily / hourly limit reached for this token"),
-                        )
-                        .await;
-                    let payload = json!({
-                        "error": "quota_exhausted",
-                        "message": "daily / hourly limit reached for this token",
-                    });
-                    let resp = Response::builder()
-                        .status(StatusCode::TOO_MANY_REQUESTS)
-                        .header(CONTENT_TYPE, "application/json; charset=utf-8")
-                        .body(Body::from(payload.to_string()))
-                        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-                    return Ok(resp);
-                }
-            }
-            Err(err) => {
-                eprintln!("quota check failed for /api/tavily/crawl: {err}");
-                if let Some(tid) = auth_token_id.as_deref() {
-                    let msg = err.to_string();
-                    let _ = state
-                        .proxy
-                        .record_token_attempt(
-                            tid,
-                            &method,
-                            &path,
-                            None,
-                            Some(StatusCode::INTERNAL_SERVER_ERROR.as_u16() as i64),
-                            None,
-                            true,
-                            "error",
-                            Some(msg.as_str()),
-                        )
-                        .await;
-                }
-                return Err(StatusCode::INTERNAL_SERVER_ERROR);
-            }
-        }
+        return json_error(
+            StatusCode::BAD_REQUEST,
+            json!({
+                "error": "invalid_request",
+                "message": "max_results must be non-negative",
+            }),
+        );
     }
 
-    // Clone headers into reqwest::HeaderMap so we can reuse sanitize logic, and avoid
-    // forwarding our Authorization header to Tavily upstream.
-    let mut headers = clone_headers(&parts.headers);
-    headers.remove(axum::http::header::AUTHORIZATION);
-
-    let result = state
-        .proxy
-        .proxy_http_json_endpoint(
-            &state.usage_base,
-            "/crawl",
-            auth_token_id.as_deref(),
-            &method,
-            &path,
-            options,
-            &headers,
-        )
-        .await;
-
-    match result {
-        Ok((resp, analysis)) => {
-            if let Some(tid) = token_id_for_logs.as_deref() {
-                let http_code = resp.status.as_u16() as i64;
+    let record = |http_status: Option<StatusCode>, counts_business: bool, outcome, message| {
+        let state = state.clone();
+        let (method, path) = (method.clone(), path.clone());
+        let tid = auth_token_id.clone();
+        async move {
+            if let Some(tid) = tid {
                 let _ = state
                     .proxy
                     .record_token_attempt(
-                        tid,
+                        &tid,
                         &method,
                         &path,
                         None,
-                        Some(http_code),
-                        analysis.tavily_status_code,
-                        true,
-                        analysis.status,
+                        Some(tool),
+                        http_status.map(|status| status.as_u16() as i64),
                         None,
+                        counts_business,
+                        outcome,
+                        message,
                     )
                     .await;
             }
-            Ok(build_response(resp))
         }
-        Err(err) => {
-            eprintln!("tavily http /crawl proxy error: {err}");
-            if let Some(tid) = token_id_for_logs.as_deref() {
-                let msg = err.to_string();
-                let _ = state
-                    .proxy
-                    .record_token_attempt(
-                        tid,
-                        &method,
-                        &path,
-                        None,
-                        None,
-                        None,
+    };
+
+    if let Some(tid) = auth_token_id.as_deref() {
+        // Per-token hourly *any request* limit.
+        if !state.dev_open_admin {
+            match state.proxy.check_token_hourly_requests(tid).await {
+                Ok(verdict) if !verdict.allowed => {
+                    let message = build_request_limit_error_message(&verdict);
+                    record(
+                        Some(StatusCode::TOO_MANY_REQUESTS),
+                        false,
+                        "quota_exhausted",
+                        Some(message.as_str()),
+                    )
+                    .await;
+                    return json_error(
+                        StatusCode::TOO_MANY_REQUESTS,
+                        json!({
+                            "error": "quota_exhausted",
+                            "message": "hourly request limit reached for this token",
+                        }),
+                    );
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    tracing::error!("hourly request limit check failed for {path}: {err}");
+                    let msg = err.to_string();
+                    record(
+                        Some(StatusCode::INTERNAL_SERVER_ERROR),
                         true,
                         "error",
                         Some(msg.as_str()),
                     )
                     .await;
+                    return Err(StatusCode::INTERNAL_SERVER_ERROR);
+                }
             }
-
-            let status = match err {
-                ProxyError::Http(_) | ProxyError::NoAvailableKeys => StatusCode::BAD_GATEWAY,
-                ProxyError::Database(_)
-                | ProxyError::InvalidEndpoint { .. }
-                | ProxyError::QuotaDataMissing { .. }
-                | ProxyError::UsageHttp { .. }
-                | ProxyError::Other(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            };
-
-            let payload = json!({
-                "error": "proxy_error",
-                "message": "upstream unavailable",
-            });
-            let resp = Response::builder()
-                .status(status)
-                .header(CONTENT_TYPE, "application/json; charset=utf-8")
-                .body(Body::from(payload.to_string()))
-                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-            Ok(resp)
         }
-    }
-}
-
-async fn tavily_http_map(
-    State(state): State<Arc<AppState>>,
-    req: Request<Body>,
-) -> Result<Response<Body>, StatusCode> {
-    let (parts, body) = req.into_parts();
-    let method = parts.method.clone();
-    let path = parts.uri.path().to_owned();
-
-    // Read raw body with a reasonable upper bound to avoid memory abuse.
-    let body_bytes = body::to_bytes(body, BODY_LIMIT)
-        .await
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
-
-    let mut options: Value =
-        serde_json::from_slice(&body_bytes).map_err(|_| StatusCode::BAD_REQUEST)?;
-    if !options.is_object() {
-        return Err(StatusCode::BAD_REQUEST);
-    }
-
-    // Prefer Authorization: Bearer th-<id>-<secret>, fall back to JSON api_key.
-    let auth_bearer = parts
-        .headers
-        .get(axum::http::header::AUTHORIZATION)
-        .and_then(|v| v.to_str().ok())
-        .map(|s| s.trim().to_string());
-    let header_token = auth_bearer
-        .as_deref()
-        .and_then(|raw| raw.strip_prefix("Bearer "))
-        .map(str::trim)
-        .filter(|t| !t.is_empty())
-        .map(|t| t.to_string());
-
-    let body_token = options
-        .get("api_key")
-        .and_then(|v| v.as_str())
-        .map(str::trim)
-        .filter(|v| !v.is_empty())
-        .map(|v| v.to_string());
-
-    let token = if let Some(t) = header_token {
-        t
-    } else if let Some(t) = body_token {
-        t
-    } else if state.dev_open_admin {
-        "th-dev-override".to_string()
-    } else {
-        let resp = Response::builder()
-            .status(StatusCode::UNAUTHORIZED)
-            .header(CONTENT_TYPE, "application/json; charset=utf-8")
-            .body(Body::from("{\"error\":\"missing token\"}"))
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-        return Ok(resp);
-    };
-
-    let valid = if state.dev_open_admin {
-        true
-    } else {
-        state
-            .proxy
-            .validate_access_token(&token)
-            .await
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-    };
-    if !valid {
-        let resp = Response::builder()
-            .status(StatusCode::UNAUTHORIZED)
-            .header(CONTENT_TYPE, "application/json; charset=utf-8")
-            .body(Body::from("{\"error\":\"invalid or disabled token\"}"))
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-        return Ok(resp);
-    }
-
-    let auth_token_id = if state.dev_open_admin {
-        Some("dev".to_string())
-    } else {
-        token
-            .strip_prefix("th-")
-            .and_then(|rest| rest.split_once('-').map(|(id, _)| id))
-            .map(|s| s.to_string())
-    };
-
-    // Remove api_key from JSON body before forwarding upstream; it will be replaced by Tavily key.
-    if let Value::Object(ref mut map) = options {
-        map.remove("api_key");
-    }
-
-    let token_id_for_logs = auth_token_id.clone();
 
-    // Per-token quota check.
-    if let Some(ref tid) = auth_token_id {
+        // Per-token business quota check (hour / day / month).
         match state.proxy.check_token_quota(tid).await {
-            Ok(verdict) => {
-                if !state.dev_open_admin && !verdict.allowed {
-                    let _ = state
-                        .proxy
-                        .record_token_attempt(
-                            tid,
-                            &method,
-                            &path,
-                            None,
-                            Some(StatusCode::TOO_MANY_REQUESTS.as_u16() as i64),
-                            None,
-                            true,
-                            "quota_exhausted",
-                            Some("daily / hourly limit reached for this token"),
-                        )
-                        .await;
-                    let payload = json!({
-                        "error": "quota_exhausted",
-                        "message": "daily / hourly limit reached for this token",
-                    });
-                    let resp = Response::builder()
-                        .status(StatusCode::TOO_MANY_REQUESTS)
-                        .header(CONTENT_TYPE, "application/json; charset=utf-8")
-                        .body(Body::from(payload.to_string()))
-                        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-                    return Ok(resp);
-                }
+            Ok(verdict) if !state.dev_open_admin && !verdict.allowed => {
+                let message = "daily / hourly limit reached for this token";
+                record(
+                    Some(StatusCode::TOO_MANY_REQUESTS),
+                    true,
+                    "quota_exhausted",
+                    Some(message),
+                )
+                .await;
+                return json_error(
+                    StatusCode::TOO_MANY_REQUESTS,
+                    json!({ "error": "quota_exhausted", "message": message }),
+                );
             }
+            Ok(_) => {}
             Err(err) => {
-                eprintln!("quota check failed for /api/tavily/map: {err}");
-                if let Some(tid) = auth_token_id.as_deref() {
-                    let msg = err.to_string();
-                    let _ = state
-                        .proxy
-                        .record_token_attempt(
-                            tid,
-                            &method,
-                            &path,
-                            None,
-                            Some(StatusCode::INTERNAL_SERVER_ERROR.as_u16() as i64),
-                            None,
-                            true,
-                            "error",
-                            Some(msg.as_str()),
-                        )
-                        .await;
-                }
+                tracing::error!("quota check failed for {path}: {err}");
+                let msg = err.to_string();
+                record(
+                    Some(StatusCode::INTERNAL_SERVER_ERROR),
+                    true,
+                    "error",
+                    Some(msg.as_str()),
+                )
+                .await;
                 return Err(StatusCode::INTERNAL_SERVER_ERROR);
             }
         }
     }
 
-    // Clone headers into reqwest::HeaderMap so we can reuse sanitize logic, and avoid
-    // forwarding our Authorization header to Tavily upstream.
+    // Clone headers into reqwest::HeaderMap so we can reuse sanitize logic; our own bearer
+    // token must not reach Tavily, the proxy places the pooled key instead.
     let mut headers = clone_headers(&parts.headers);
     headers.remove(axum::http::header::AUTHORIZATION);
 
     let result = state
         .proxy
-        .proxy_http_json_endpoint(
+        .proxy_http_json_with(
             &state.usage_base,
-            "/map",
+            &format!("/{tool}"),
             auth_token_id.as_deref(),
             &method,
             &path,
             options,
             &headers,
+            placement,
         )
         .await;
 
     match result {
         Ok((resp, analysis)) => {
-            if let Some(tid) = token_id_for_logs.as_deref() {
-                let http_code = resp.status.as_u16() as i64;
+            if let Some(tid) = auth_token_id.as_deref() {
                 let _ = state
                     .proxy
                     .record_token_attempt(
@@ -1765,7 +1856,8 @@ async fn tavily_http_map(
                         &method,
                         &path,
                         None,
-                        Some(http_code),
+                        Some(tool),
+                        Some(resp.status.as_u16() as i64),
                         analysis.tavily_status_code,
                         true,
                         analysis.status,
@@ -1776,101 +1868,237 @@ async fn tavily_http_map(
             Ok(build_response(resp))
         }
         Err(err) => {
-            eprintln!("tavily http /map proxy error: {err}");
-            if let Some(tid) = token_id_for_logs.as_deref() {
-                let msg = err.to_string();
-                let _ = state
-                    .proxy
-                    .record_token_attempt(
-                        tid,
-                        &method,
-                        &path,
-                        None,
-                        None,
-                        None,
-                        true,
-                        "error",
-                        Some(msg.as_str()),
-                    )
-                    .await;
-            }
+            tracing::error!("tavily http {path} proxy error: {err}");
+            let msg = err.to_string();
+            record(None, true, "error", Some(msg.as_str())).await;
 
+            if let ProxyError::Overloaded { priority } = err {
+                return overloaded_response(priority);
+            }
             let status = match err {
                 ProxyError::Http(_) | ProxyError::NoAvailableKeys => StatusCode::BAD_GATEWAY,
+                ProxyError::KeysSaturated => StatusCode::SERVICE_UNAVAILABLE,
                 ProxyError::Database(_)
                 | ProxyError::InvalidEndpoint { .. }
                 | ProxyError::QuotaDataMissing { .. }
                 | ProxyError::UsageHttp { .. }
+                | ProxyError::Overloaded { .. }
+                | ProxyError::SelfCheck(_)
+                | ProxyError::QuotaBackend(_)
                 | ProxyError::Other(_) => StatusCode::INTERNAL_SERVER_ERROR,
             };
-
-            let payload = json!({
-                "error": "proxy_error",
-                "message": "upstream unavailable",
-            });
-            let resp = Response::builder()
-                .status(status)
-                .header(CONTENT_TYPE, "application/json; charset=utf-8")
-                .body(Body::from(payload.to_string()))
-                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-            Ok(resp)
+            json_error(
+                status,
+                json!({ "error": "proxy_error", "message": "upstream unavailable" }),
+            )
         }
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/summary",
+    tag = "system",
+    summary = "Success/failure totals and last activity.",
+    responses((status = 200, description = "OK"))
+)]
 async fn fetch_summary(
     State(state): State<Arc<AppState>>,
-) -> Result<Json<SummaryView>, StatusCode> {
+    headers: HeaderMap,
+) -> Result<Response<Body>, StatusCode> {
+    let etag = admin_list_etag(&state, "summary").await;
+    if let Some(resp) = not_modified(&headers, etag.as_ref()) {
+        return Ok(resp);
+    }
     state
         .proxy
         .summary()
         .await
-        .map(|summary| Json(summary.into()))
+        .map(|summary| with_etag(Json(SummaryView::from(summary)), etag))
         .map_err(|err| {
-            eprintln!("summary error: {err}");
+            tracing::error!("summary error: {err}");
             StatusCode::INTERNAL_SERVER_ERROR
         })
 }
 
-async fn get_public_metrics(
-    State(state): State<Arc<AppState>>,
-) -> Result<Json<PublicMetricsView>, StatusCode> {
-    state
-        .proxy
-        .success_breakdown()
-        .await
-        .map(|metrics| {
-            Json(PublicMetricsView {
-                monthly_success: metrics.monthly_success,
-                daily_success: metrics.daily_success,
-            })
-        })
-        .map_err(|err| {
-            eprintln!("public metrics error: {err}");
-            StatusCode::INTERNAL_SERVER_ERROR
-        })
+/// Weak ETag for an admin list, derived from [`TavilyProxy::admin_data_version`]. Token quota
+/// usage is reported over rolling windows, so the tokens tag also rolls over every minute.
+/// Errors only disable revalidation; the handler still serves the full body.
+async fn admin_list_etag(state: &AppState, resource: &str) -> Option<axum::http::HeaderValue> {
+    let version = match state.proxy.admin_data_version().await {
+        Ok(version) => version,
+        Err(err) => {
+            tracing::error!("admin data version error: {err}");
+            return None;
+        }
+    };
+    let mut tag = format!(
+        "W/\"{resource}-{}-{}-{}",
+        version.journal_id, version.request_log_id, version.token_log_id
+    );
+    if resource == "tokens" {
+        tag.push_str(&format!("-{}", Utc::now().timestamp() / 60));
+    }
+    tag.push('"');
+    axum::http::HeaderValue::from_str(&tag).ok()
 }
 
-#[derive(Debug, Serialize)]
-#[serde(rename_all = "camelCase")]
-struct TokenMetricsView {
-    monthly_success: i64,
-    daily_success: i64,
-    daily_failure: i64,
-    // Business quota (tools/call) windows
-    quota_hourly_used: i64,
-    quota_hourly_limit: i64,
-    quota_daily_used: i64,
-    quota_daily_limit: i64,
-    quota_monthly_used: i64,
-    quota_monthly_limit: i64,
+/// Caller of a key or token management endpoint that non-admin forward-auth users may use.
+enum ManageScope {
+    /// Admins (and `DEV_OPEN_ADMIN`) manage every key and token.
+    All,
+    /// A non-admin forward-auth user only manages what they created.
+    Owner(String),
 }
 
-#[derive(Deserialize)]
-struct TokenQuery {
-    token: String,
+impl ManageScope {
+    fn owner(&self) -> Option<&str> {
+        match self {
+            Self::All => None,
+            Self::Owner(owner) => Some(owner),
+        }
+    }
+}
+
+fn manage_scope(state: &AppState, headers: &HeaderMap) -> Result<ManageScope, StatusCode> {
+    if state.dev_open_admin || state.forward_auth.is_request_admin(headers) {
+        return Ok(ManageScope::All);
+    }
+    state
+        .forward_auth
+        .user_value(headers)
+        .map(|user| ManageScope::Owner(user.to_string()))
+        .ok_or(StatusCode::FORBIDDEN)
+}
+
+/// Keys of other owners answer 404 so a scoped caller cannot probe for their ids.
+async fn ensure_key_in_scope(
+    state: &AppState,
+    scope: &ManageScope,
+    key_id: &str,
+) -> Result<(), StatusCode> {
+    let Some(owner) = scope.owner() else {
+        return Ok(());
+    };
+    match state.proxy.api_key_owner(key_id).await {
+        Ok(found) if found.as_deref() == Some(owner) => Ok(()),
+        Ok(_) => Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            tracing::error!("api key owner lookup error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Tokens of other owners answer 404 so a scoped caller cannot probe for their ids.
+async fn ensure_token_in_scope(
+    state: &AppState,
+    scope: &ManageScope,
+    token_id: &str,
+) -> Result<(), StatusCode> {
+    let Some(owner) = scope.owner() else {
+        return Ok(());
+    };
+    match state.proxy.access_token_owner(token_id).await {
+        Ok(found) if found.as_deref() == Some(owner) => Ok(()),
+        Ok(_) => Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            tracing::error!("access token owner lookup error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// 304 response when `If-None-Match` already names `etag` (weak comparison).
+fn not_modified(
+    headers: &HeaderMap,
+    etag: Option<&axum::http::HeaderValue>,
+) -> Option<Response<Body>> {
+    let etag = etag?.to_str().ok()?;
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    let matched = headers
+        .get_all(axum::http::header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|candidate| candidate.trim() == "*" || opaque(candidate) == opaque(etag));
+    if !matched {
+        return None;
+    }
+    let mut resp = StatusCode::NOT_MODIFIED.into_response();
+    resp.headers_mut().insert(
+        axum::http::header::ETAG,
+        axum::http::HeaderValue::from_str(etag).ok()?,
+    );
+    Some(resp)
+}
+
+fn with_etag(body: impl IntoResponse, etag: Option<axum::http::HeaderValue>) -> Response<Body> {
+    let mut resp = body.into_response();
+    if let Some(etag) = etag {
+        resp.headers_mut().insert(axum::http::header::ETAG, etag);
+    }
+    resp
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/public/metrics",
+    tag = "public",
+    summary = "Aggregate public metrics.",
+    responses((status = 200, description = "OK"))
+)]
+async fn get_public_metrics(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<PublicMetricsView>, StatusCode> {
+    state
+        .proxy
+        .success_breakdown()
+        .await
+        .map(|metrics| {
+            Json(PublicMetricsView {
+                monthly_success: metrics.monthly_success,
+                daily_success: metrics.daily_success,
+            })
+        })
+        .map_err(|err| {
+            tracing::error!("public metrics error: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TokenMetricsView {
+    monthly_success: i64,
+    daily_success: i64,
+    daily_failure: i64,
+    // Business quota (tools/call) windows
+    quota_hourly_used: i64,
+    quota_hourly_limit: i64,
+    quota_daily_used: i64,
+    quota_daily_limit: i64,
+    quota_monthly_used: i64,
+    quota_monthly_limit: i64,
+    // Rolling service level
+    sla_window_days: i64,
+    sla_success_rate: Option<f64>,
+    sla_p95_latency_ms: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct TokenQuery {
+    token: String,
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/token/metrics",
+    tag = "public",
+    summary = "Usage metrics of the given token.",
+    params(("token" = Option<String>, Query)),
+    responses((status = 200, description = "OK"))
+)]
 async fn get_token_metrics_public(
     State(state): State<Arc<AppState>>,
     Query(q): Query<TokenQuery>,
@@ -1930,6 +2158,12 @@ async fn get_token_metrics_public(
         )
     };
 
+    let sla = state
+        .proxy
+        .token_sla(token_id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
     Ok(Json(TokenMetricsView {
         monthly_success,
         daily_success,
@@ -1940,6 +2174,9 @@ async fn get_token_metrics_public(
         quota_daily_limit,
         quota_monthly_used,
         quota_monthly_limit,
+        sla_window_days: sla.window_days,
+        sla_success_rate: sla.success_rate,
+        sla_p95_latency_ms: sla.p95_latency_ms,
     }))
 }
 
@@ -1958,6 +2195,17 @@ struct TavilyUsageView {
     monthly_quota_exhausted: i64,
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/tavily/usage",
+    tag = "tavily",
+    summary = "Quota usage of the calling token.",
+    responses(
+        (status = 200, description = "OK"),
+        (status = 401, description = "Missing or invalid token"),
+    ),
+    security(("hikariToken" = []))
+)]
 async fn tavily_http_usage(
     State(state): State<Arc<AppState>>,
     headers: HeaderMap,
@@ -2059,6 +2307,7 @@ struct PublicLogsQuery {
 #[serde(rename_all = "camelCase")]
 struct PublicTokenLogView {
     id: i64,
+    public_id: Option<String>,
     method: String,
     path: String,
     query: Option<String>,
@@ -2073,6 +2322,7 @@ impl From<TokenLogRecord> for PublicTokenLogView {
     fn from(r: TokenLogRecord) -> Self {
         Self {
             id: r.id,
+            public_id: r.public_id,
             method: r.method,
             path: r.path,
             query: r.query,
@@ -2139,6 +2389,126 @@ fn redact_sensitive(input: &str) -> String {
     out
 }
 
+/// Short id of a full `th-<id>-<secret>` token given to a public endpoint, once it is valid.
+async fn public_token_id(state: &AppState, token: &str) -> Result<String, StatusCode> {
+    if !state
+        .proxy
+        .validate_access_token(token)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    token
+        .strip_prefix("th-")
+        .and_then(|rest| rest.split_once('-').map(|(id, _)| id.to_string()))
+        .ok_or(StatusCode::BAD_REQUEST)
+}
+
+#[derive(Debug, Deserialize)]
+struct PublicUsageSeriesQuery {
+    token: String,
+    #[serde(flatten)]
+    series: UsageSeriesQuery,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/public/usage-series",
+    tag = "public",
+    summary = "Usage time series of the given token.",
+    params(
+        ("token" = Option<String>, Query),
+        ("since" = Option<String>, Query),
+        ("until" = Option<String>, Query),
+        ("bucket_secs" = Option<String>, Query),
+    ),
+    responses((status = 200, description = "OK"))
+)]
+async fn get_public_usage_series(
+    State(state): State<Arc<AppState>>,
+    Query(q): Query<PublicUsageSeriesQuery>,
+) -> Result<Json<Vec<TokenUsageBucketView>>, StatusCode> {
+    let token_id = public_token_id(&state, &q.token).await?;
+    token_usage_series_view(&state, &token_id, &q.series).await
+}
+
+/// A token's own quota standing: the usage, limits and reset times admins see for it.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PublicTokenQuotaView {
+    quota_state: String,
+    /// The window that currently blocks the token, if any.
+    exceeded_window: Option<String>,
+    quota_hourly_used: i64,
+    quota_hourly_limit: i64,
+    quota_daily_used: i64,
+    quota_daily_limit: i64,
+    quota_monthly_used: i64,
+    quota_monthly_limit: i64,
+    quota_hourly_reset_at: Option<i64>,
+    quota_daily_reset_at: Option<i64>,
+    quota_monthly_reset_at: Option<i64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/public/quota",
+    tag = "public",
+    summary = "Quota usage, verdict and reset times of the given token.",
+    params(("token" = Option<String>, Query)),
+    responses((status = 200, description = "OK"))
+)]
+async fn get_public_quota(
+    State(state): State<Arc<AppState>>,
+    Query(q): Query<TokenQuery>,
+) -> Result<Json<PublicTokenQuotaView>, StatusCode> {
+    let token_id = public_token_id(&state, &q.token).await?;
+    let status = state
+        .proxy
+        .token_quota_status(&token_id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let status = match status {
+        Some(status) => status,
+        None => TokenQuotaStatus {
+            verdict: state
+                .proxy
+                .token_quota_limits(&token_id)
+                .await
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+            hourly_reset_at: None,
+            daily_reset_at: None,
+            monthly_reset_at: None,
+        },
+    };
+    let view = PublicTokenQuotaView {
+        quota_state: status.verdict.state_key().to_string(),
+        exceeded_window: status
+            .verdict
+            .exceeded_window
+            .map(|window| window.as_str().to_string()),
+        quota_hourly_used: status.verdict.hourly_used,
+        quota_hourly_limit: status.verdict.hourly_limit,
+        quota_daily_used: status.verdict.daily_used,
+        quota_daily_limit: status.verdict.daily_limit,
+        quota_monthly_used: status.verdict.monthly_used,
+        quota_monthly_limit: status.verdict.monthly_limit,
+        quota_hourly_reset_at: status.hourly_reset_at,
+        quota_daily_reset_at: status.daily_reset_at,
+        quota_monthly_reset_at: status.monthly_reset_at,
+    };
+    Ok(Json(view))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/public/logs",
+    tag = "public",
+    summary = "Recent logs of the given token.",
+    params(("token" = Option<String>, Query), ("limit" = Option<String>, Query)),
+    responses((status = 200, description = "OK"))
+)]
 async fn get_public_logs(
     State(state): State<Arc<AppState>>,
     Query(q): Query<PublicLogsQuery>,
@@ -2195,6 +2565,14 @@ struct DashboardSnapshot {
     logs: Vec<RequestLogView>,
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/events",
+    tag = "system",
+    summary = "SSE stream of dashboard updates.",
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
 async fn sse_dashboard(
     State(state): State<Arc<AppState>>,
     headers: HeaderMap,
@@ -2205,6 +2583,7 @@ async fn sse_dashboard(
     let state = state.clone();
 
     let stream = stream! {
+        let mut changes = state.proxy.subscribe_changes();
         let mut last_log_id: Option<i64> = None;
         let mut last_sig: Option<SummarySig> = None;
 
@@ -2241,7 +2620,7 @@ async fn sse_dashboard(
                 }
             }
 
-            tokio::time::sleep(Duration::from_secs(2)).await;
+            wait_for_data_change(&mut changes).await;
         }
     };
 
@@ -2260,6 +2639,13 @@ struct PublicMetricsPayload {
     token: Option<TokenMetricsView>,
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/public/events",
+    tag = "public",
+    summary = "SSE stream of public metrics.",
+    responses((status = 200, description = "OK"))
+)]
 async fn sse_public(
     State(state): State<Arc<AppState>>,
     Query(q): Query<PublicEventsQuery>,
@@ -2273,12 +2659,14 @@ async fn sse_public(
         async fn compute(state: &Arc<AppState>, token_param: &Option<String>) -> Option<(PublicMetricsPayload, PublicSig)> {
             let m = state.proxy.success_breakdown().await.ok()?;
             let public = PublicMetricsView { monthly_success: m.monthly_success, daily_success: m.daily_success };
+            let mut token_sla: Option<TokenSla> = None;
             let token_sig: Option<TokenSig> = if let Some(token) = token_param.as_ref() {
                 let valid = state.proxy.validate_access_token(token).await.ok()?;
                 if !valid { None } else {
                     let id = token.strip_prefix("th-").and_then(|r| r.split_once('-').map(|(id, _)| id))?;
                     let (ms, ds, df) = state.proxy.token_success_breakdown(id).await.ok()?;
                     let quota_verdict = state.proxy.token_quota_snapshot(id).await.ok()?;
+                    token_sla = Some(state.proxy.token_sla(id).await.ok()?);
                     let (
                         quota_hourly_used,
                         quota_hourly_limit,
@@ -2318,8 +2706,8 @@ async fn sse_public(
                     ))
                 }
             } else { None };
-            let token = token_sig.map(
-                |(
+            let token = token_sig.zip(token_sla).map(
+                |((
                     ms,
                     ds,
                     df,
@@ -2329,7 +2717,7 @@ async fn sse_public(
                     quota_daily_limit,
                     quota_monthly_used,
                     quota_monthly_limit,
-                )| TokenMetricsView {
+                ), sla)| TokenMetricsView {
                     monthly_success: ms,
                     daily_success: ds,
                     daily_failure: df,
@@ -2339,6 +2727,9 @@ async fn sse_public(
                     quota_daily_limit,
                     quota_monthly_used,
                     quota_monthly_limit,
+                    sla_window_days: sla.window_days,
+                    sla_success_rate: sla.success_rate,
+                    sla_p95_latency_ms: sla.p95_latency_ms,
                 },
             );
             let sig: PublicSig = (public.monthly_success, public.daily_success, token_sig);
@@ -2346,6 +2737,7 @@ async fn sse_public(
             Some((payload, sig))
         }
 
+        let mut changes = state.proxy.subscribe_changes();
         let mut last_sig: Option<PublicSig> = None;
         if let Some((payload, sig)) = compute(&state, &token_param).await {
             let json = serde_json::to_string(&payload).unwrap_or_else(|_| "{}".to_string());
@@ -2362,13 +2754,29 @@ async fn sse_public(
                     yield Ok(Event::default().event("ping").data("{}"));
                 }
             }
-            tokio::time::sleep(Duration::from_secs(2)).await;
+            wait_for_data_change(&mut changes).await;
         }
     };
 
     Ok(Sse::new(stream).keep_alive(KeepAlive::new().interval(Duration::from_secs(15)).text("")))
 }
 
+/// Upper bound on how long SSE streams sleep without a data change, so time-based windows
+/// (day/month rollovers) still refresh.
+const SSE_IDLE_REFRESH_SECS: u64 = 30;
+/// Minimum spacing between SSE recomputations; bursts of writes coalesce into one snapshot.
+const SSE_MIN_INTERVAL_MS: u64 = 1_000;
+
+/// Park an SSE loop until the proxy reports a data change (or the idle refresh elapses).
+async fn wait_for_data_change(changes: &mut watch::Receiver<u64>) {
+    tokio::time::sleep(Duration::from_millis(SSE_MIN_INTERVAL_MS)).await;
+    let _ = tokio::time::timeout(
+        Duration::from_secs(SSE_IDLE_REFRESH_SECS),
+        changes.changed(),
+    )
+    .await;
+}
+
 async fn build_snapshot_event(state: &Arc<AppState>) -> Option<Event> {
     let summary = state.proxy.summary().await.ok()?;
     let keys = state.proxy.list_api_key_metrics().await.ok()?;
@@ -2423,8 +2831,39 @@ struct PaginatedJobsView {
     total: i64,
     page: usize,
     per_page: usize,
+    paused: Vec<JobPauseView>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JobPauseView {
+    job_type: String,
+    paused_until: i64,
+}
+
+impl From<JobPause> for JobPauseView {
+    fn from(pause: JobPause) -> Self {
+        Self {
+            job_type: pause.job_type,
+            paused_until: pause.paused_until,
+        }
+    }
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/jobs",
+    tag = "jobs",
+    summary = "Scheduled job runs.",
+    params(
+        ("limit" = Option<String>, Query),
+        ("group" = Option<String>, Query),
+        ("page" = Option<String>, Query),
+        ("per_page" = Option<String>, Query),
+    ),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
 async fn list_jobs(
     State(state): State<Arc<AppState>>,
     headers: HeaderMap,
@@ -2437,2721 +2876,12866 @@ async fn list_jobs(
     let per_page = q.per_page.or(q.limit).unwrap_or(10).clamp(1, 100);
     let group = q.group.as_deref().unwrap_or("all");
 
+    let paused = state
+        .proxy
+        .job_pauses()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
     state
         .proxy
         .list_recent_jobs_paginated(group, page, per_page)
         .await
         .map(|(items, total)| {
-            let view_items = items
-                .into_iter()
-                .map(|j| JobLogView {
-                    id: j.id,
-                    job_type: j.job_type,
-                    key_id: j.key_id,
-                    status: j.status,
-                    attempt: j.attempt,
-                    message: j.message,
-                    started_at: j.started_at,
-                    finished_at: j.finished_at,
-                })
-                .collect();
+            let view_items = items.into_iter().map(JobLogView::from).collect();
             Json(PaginatedJobsView {
                 items: view_items,
                 total,
                 page,
                 per_page,
+                paused: paused.into_iter().map(JobPauseView::from).collect(),
             })
         })
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
 }
 
-// ---- Key detail & manual quota sync ----
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PauseJobRequest {
+    duration_secs: Option<i64>,
+}
 
-async fn get_api_key_detail(
+#[utoipa::path(
+    post,
+    path = "/api/jobs/{type}/pause",
+    tag = "jobs",
+    summary = "Pause a scheduled job.",
+    params(("type" = String, Path)),
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn pause_job(
     State(state): State<Arc<AppState>>,
-    Path(id): Path<String>,
-) -> Result<Json<ApiKeyView>, StatusCode> {
-    let items = state
+    Path(job_type): Path<String>,
+    headers: HeaderMap,
+    payload: Option<Json<PauseJobRequest>>,
+) -> Result<Json<JobPauseView>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if !PAUSABLE_JOB_TYPES.contains(&job_type.as_str()) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let Json(payload) = payload.unwrap_or_default();
+    match state
         .proxy
-        .list_api_key_metrics()
+        .pause_job(&job_type, payload.duration_secs)
         .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    if let Some(found) = items.into_iter().find(|k| k.id == id) {
-        Ok(Json(ApiKeyView::from(found)))
-    } else {
-        Err(StatusCode::NOT_FOUND)
+    {
+        Ok(pause) => Ok(Json(JobPauseView::from(pause))),
+        Err(err) => {
+            tracing::error!("pause job error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
     }
 }
 
-async fn post_sync_key_usage(
+#[utoipa::path(
+    post,
+    path = "/api/jobs/{type}/resume",
+    tag = "jobs",
+    summary = "Resume a paused job.",
+    params(("type" = String, Path)),
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn resume_job(
     State(state): State<Arc<AppState>>,
-    Path(id): Path<String>,
+    Path(job_type): Path<String>,
     headers: HeaderMap,
-) -> Result<Response<Body>, StatusCode> {
+) -> Result<StatusCode, StatusCode> {
     if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
         return Err(StatusCode::FORBIDDEN);
     }
-    let job_id = state
-        .proxy
-        .scheduled_job_start("quota_sync/manual", Some(&id), 1)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    match state.proxy.sync_key_quota(&id, &state.usage_base).await {
-        Ok((limit, remaining)) => {
-            let msg = format!("limit={limit} remaining={remaining}");
-            let _ = state
-                .proxy
-                .scheduled_job_finish(job_id, "success", Some(&msg))
-                .await;
-            Ok(StatusCode::NO_CONTENT.into_response())
-        }
-        Err(ProxyError::QuotaDataMissing { reason }) => {
-            let msg = format!("quota_data_missing: {reason}");
-            let _ = state
-                .proxy
-                .scheduled_job_finish(job_id, "error", Some(&msg))
-                .await;
-            let body = Json(json!({
-                "error": "quota_data_missing",
-                "detail": reason,
-            }));
-            Ok((StatusCode::BAD_REQUEST, body).into_response())
-        }
-        Err(ProxyError::UsageHttp { status, body }) => {
-            let detail = format!("Tavily usage request failed with {status}: {body}");
-            let http_status = if status == reqwest::StatusCode::UNAUTHORIZED {
-                StatusCode::UNAUTHORIZED
-            } else if status == reqwest::StatusCode::FORBIDDEN {
-                StatusCode::FORBIDDEN
-            } else if status.is_client_error() {
-                StatusCode::BAD_REQUEST
-            } else {
-                StatusCode::BAD_GATEWAY
-            };
-            let _ = state
-                .proxy
-                .scheduled_job_finish(job_id, "error", Some(&detail))
-                .await;
-            let body = Json(json!({
-                "error": "usage_http",
-                "detail": detail,
-            }));
-            Ok((http_status, body).into_response())
-        }
+    if !PAUSABLE_JOB_TYPES.contains(&job_type.as_str()) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    match state.proxy.resume_job(&job_type).await {
+        Ok(_) => Ok(StatusCode::NO_CONTENT),
         Err(err) => {
-            let reason = err.to_string();
-            let _ = state
-                .proxy
-                .scheduled_job_finish(job_id, "error", Some(&reason))
-                .await;
-            let body = Json(json!({
-                "error": "sync_failed",
-                "detail": reason,
-            }));
-            Ok((StatusCode::BAD_GATEWAY, body).into_response())
+            tracing::error!("resume job error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
 }
 
-#[derive(Debug, Serialize)]
-#[serde(rename_all = "camelCase")]
-struct VersionView {
-    backend: String,
-    frontend: String,
-}
-
-async fn get_versions(State(state): State<Arc<AppState>>) -> Result<Json<VersionView>, StatusCode> {
-    let (backend, frontend) = detect_versions(state.static_dir.as_deref());
-    Ok(Json(VersionView { backend, frontend }))
-}
-
-#[derive(Debug, Serialize)]
-struct AdminDebug {
-    dev_open_admin: bool,
-}
+// ---- Incremental export for BI ingestion ----
 
-async fn get_admin_debug(
-    State(state): State<Arc<AppState>>,
-) -> Result<Json<AdminDebug>, StatusCode> {
-    Ok(Json(AdminDebug {
-        dev_open_admin: state.dev_open_admin,
-    }))
+#[derive(Deserialize)]
+struct ExportChangesQuery {
+    since_id: Option<i64>,
+    limit: Option<i64>,
 }
 
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
-struct ProfileView {
-    display_name: Option<String>,
-    is_admin: bool,
+struct ExportChangeView {
+    id: i64,
+    table: String,
+    op: String,
+    changed_at: i64,
+    row: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
-struct ForwardAuthDebugView {
-    enabled: bool,
-    user_header: Option<String>,
-    admin_value: Option<String>,
-    nickname_header: Option<String>,
+struct ExportChangesView {
+    since_id: i64,
+    next_since_id: i64,
+    high_watermark: i64,
+    has_more: bool,
+    changes: Vec<ExportChangeView>,
 }
 
-async fn get_forward_auth_debug(
+#[utoipa::path(
+    get,
+    path = "/api/export/changes",
+    tag = "replication",
+    summary = "Change feed for exporters.",
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_export_changes(
     State(state): State<Arc<AppState>>,
     headers: HeaderMap,
-) -> Result<Json<ForwardAuthDebugView>, StatusCode> {
+    Query(q): Query<ExportChangesQuery>,
+) -> Result<Json<ExportChangesView>, StatusCode> {
     if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
         return Err(StatusCode::FORBIDDEN);
     }
-    let cfg = &state.forward_auth;
-    Ok(Json(ForwardAuthDebugView {
-        enabled: cfg.is_enabled(),
-        user_header: cfg.user_header().map(|h| h.to_string()),
-        admin_value: None,
-        nickname_header: cfg.nickname_header().map(|h| h.to_string()),
-    }))
-}
-
-async fn debug_headers(
-    State(state): State<Arc<AppState>>,
-    headers: HeaderMap,
-) -> Result<(StatusCode, Json<serde_json::Value>), StatusCode> {
-    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
-        return Err(StatusCode::FORBIDDEN);
-    }
-    let mut map = serde_json::Map::new();
-    for (k, v) in headers.iter() {
-        map.insert(
-            k.as_str().to_string(),
-            serde_json::Value::String(v.to_str().unwrap_or("").to_string()),
-        );
+    let since_id = q.since_id.unwrap_or(0).max(0);
+    let limit = q.limit.unwrap_or(1000);
+
+    match state.proxy.export_changes(since_id, limit).await {
+        Ok(page) => Ok(Json(ExportChangesView {
+            since_id,
+            next_since_id: page.next_since_id,
+            high_watermark: page.high_watermark,
+            has_more: page.next_since_id < page.high_watermark,
+            changes: page
+                .changes
+                .into_iter()
+                .map(|c| ExportChangeView {
+                    id: c.id,
+                    table: c.table,
+                    op: c.op,
+                    changed_at: c.changed_at,
+                    row: c.row,
+                })
+                .collect(),
+        })),
+        Err(err) => {
+            tracing::error!("export changes error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
     }
-    Ok((StatusCode::OK, Json(serde_json::Value::Object(map))))
 }
 
-async fn get_profile(
-    State(state): State<Arc<AppState>>,
-    headers: HeaderMap,
-) -> Result<Json<ProfileView>, StatusCode> {
-    let config = &state.forward_auth;
-
-    if let Some(name) = config.admin_override_name() {
-        return Ok(Json(ProfileView {
-            display_name: Some(name.to_owned()),
-            is_admin: true,
-        }));
-    }
-
-    if state.dev_open_admin {
-        return Ok(Json(ProfileView {
-            display_name: Some("dev-mode".to_string()),
-            is_admin: true,
-        }));
-    }
-
-    if !config.is_enabled() {
-        return Ok(Json(ProfileView {
-            display_name: None,
-            is_admin: false,
-        }));
-    }
+// ---- Configuration audit trail ----
 
-    let user_value = config.user_value(&headers).map(str::to_string);
+#[derive(Deserialize)]
+struct ConfigHistoryQuery {
+    setting: Option<String>,
+    page: Option<usize>,
+    per_page: Option<usize>,
+}
 
-    let nickname = config
-        .nickname_value(&headers)
-        .or_else(|| user_value.clone());
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ConfigChangeView {
+    id: i64,
+    setting: String,
+    old_value: Option<String>,
+    new_value: Option<String>,
+    actor: String,
+    created_at: i64,
+}
 
-    if nickname.is_none() {
-        return Ok(Json(ProfileView {
-            display_name: None,
-            is_admin: false,
-        }));
+impl From<ConfigChange> for ConfigChangeView {
+    fn from(c: ConfigChange) -> Self {
+        Self {
+            id: c.id,
+            setting: c.setting,
+            old_value: c.old_value,
+            new_value: c.new_value,
+            actor: c.actor,
+            created_at: c.created_at,
+        }
     }
-
-    let is_admin = config.is_request_admin(&headers);
-
-    Ok(Json(ProfileView {
-        display_name: nickname,
-        is_admin,
-    }))
 }
 
-fn detect_versions(static_dir: Option<&FsPath>) -> (String, String) {
-    let backend_base = option_env!("APP_EFFECTIVE_VERSION")
-        .map(|s| s.to_string())
-        .unwrap_or_else(|| env!("CARGO_PKG_VERSION").to_string());
-    let backend = if cfg!(debug_assertions) {
-        format!("{}-dev", backend_base)
-    } else {
-        backend_base
-    };
-
-    // Try reading version.json produced by front-end build
-    let frontend_from_dist = static_dir.and_then(|dir| {
-        let path = dir.join("version.json");
-        fs::File::open(&path).ok().and_then(|mut f| {
-            let mut s = String::new();
-            if f.read_to_string(&mut s).is_ok() {
-                serde_json::from_str::<serde_json::Value>(&s)
-                    .ok()
-                    .and_then(|v| {
-                        v.get("version")
-                            .and_then(|v| v.as_str())
-                            .map(|s| s.to_string())
-                    })
-            } else {
-                None
-            }
-        })
-    });
-
-    // Fallback to web/package.json for dev setups
-    let frontend = frontend_from_dist
-        .or_else(|| {
-            let path = FsPath::new("web").join("package.json");
-            fs::File::open(&path).ok().and_then(|mut f| {
-                let mut s = String::new();
-                if f.read_to_string(&mut s).is_ok() {
-                    serde_json::from_str::<serde_json::Value>(&s)
-                        .ok()
-                        .and_then(|v| {
-                            v.get("version")
-                                .and_then(|v| v.as_str())
-                                .map(|s| s.to_string())
-                        })
-                } else {
-                    None
-                }
-            })
-        })
-        .unwrap_or_else(|| "unknown".to_string());
-
-    let frontend = if cfg!(debug_assertions) {
-        format!("{}-dev", frontend)
-    } else {
-        frontend
-    };
-
-    (backend, frontend)
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PaginatedConfigHistoryView {
+    items: Vec<ConfigChangeView>,
+    total: i64,
+    page: usize,
+    per_page: usize,
 }
 
-async fn list_keys(
+#[utoipa::path(
+    get,
+    path = "/api/config/history",
+    tag = "admin",
+    summary = "Audit trail of setting changes.",
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn list_config_history(
     State(state): State<Arc<AppState>>,
     headers: HeaderMap,
-) -> Result<Json<Vec<ApiKeyView>>, StatusCode> {
+    Query(q): Query<ConfigHistoryQuery>,
+) -> Result<Json<PaginatedConfigHistoryView>, StatusCode> {
     if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
         return Err(StatusCode::FORBIDDEN);
     }
+    let page = q.page.unwrap_or(1).max(1);
+    let per_page = q.per_page.unwrap_or(20).clamp(1, 200);
+    let setting = q
+        .setting
+        .as_deref()
+        .map(str::trim)
+        .filter(|v| !v.is_empty());
+
     state
         .proxy
-        .list_api_key_metrics()
+        .list_config_changes(setting, page, per_page)
         .await
-        .map(|metrics| Json(metrics.into_iter().map(ApiKeyView::from).collect()))
+        .map(|(items, total)| {
+            Json(PaginatedConfigHistoryView {
+                items: items.into_iter().map(ConfigChangeView::from).collect(),
+                total,
+                page,
+                per_page,
+            })
+        })
         .map_err(|err| {
-            eprintln!("list keys error: {err}");
+            tracing::error!("list config history error: {err}");
             StatusCode::INTERNAL_SERVER_ERROR
         })
 }
 
-#[derive(Debug, Deserialize)]
-struct CreateKeyRequest {
-    api_key: String,
+#[derive(Deserialize)]
+struct UpstreamHealthQuery {
+    limit: Option<usize>,
 }
 
 #[derive(Debug, Serialize)]
-struct CreateKeyResponse {
-    id: String,
+#[serde(rename_all = "camelCase")]
+struct UpstreamHealthProbeView {
+    target: String,
+    probed_at: i64,
+    ok: bool,
+    status_code: Option<i64>,
+    latency_ms: Option<i64>,
+    error: Option<String>,
 }
 
-const API_KEYS_BATCH_LIMIT: usize = 1000;
-
-#[derive(Debug, Deserialize)]
-struct BatchCreateKeysRequest {
-    api_keys: Vec<String>,
+impl From<UpstreamHealthProbe> for UpstreamHealthProbeView {
+    fn from(p: UpstreamHealthProbe) -> Self {
+        Self {
+            target: p.target,
+            probed_at: p.probed_at,
+            ok: p.ok,
+            status_code: p.status_code,
+            latency_ms: p.latency_ms,
+            error: p.error,
+        }
+    }
 }
 
-#[derive(Debug, Default, Serialize)]
-struct BatchCreateKeysSummary {
-    created: u64,
-    undeleted: u64,
-    existed: u64,
-    duplicate_in_input: u64,
-    failed: u64,
-    ignored_empty: u64,
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UpstreamHealthView {
+    interval_secs: i64,
+    /// Result of the latest probe; `None` before the first one.
+    up: Option<bool>,
+    /// Share of successful probes among those returned.
+    uptime_percent: Option<f64>,
+    probes: Vec<UpstreamHealthProbeView>,
 }
 
-#[derive(Debug, Serialize)]
-struct BatchCreateKeysResult {
-    api_key: String,
-    status: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    id: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    error: Option<String>,
+/// Recent upstream reachability probes, newest first. Query `limit` (default 60, ≤ 1440).
+#[utoipa::path(
+    get,
+    path = "/api/upstream/health",
+    tag = "admin",
+    summary = "Upstream probe history.",
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_upstream_health(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(q): Query<UpstreamHealthQuery>,
+) -> Result<Json<UpstreamHealthView>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let limit = q.limit.unwrap_or(60).clamp(1, 1440);
+    let probes = state
+        .proxy
+        .upstream_health_history(limit)
+        .await
+        .map_err(|err| {
+            tracing::error!("upstream health error: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    let uptime_percent = (!probes.is_empty()).then(|| {
+        let ok = probes.iter().filter(|p| p.ok).count();
+        ok as f64 * 100.0 / probes.len() as f64
+    });
+    Ok(Json(UpstreamHealthView {
+        interval_secs: effective_upstream_health_interval_secs(),
+        up: probes.first().map(|p| p.ok),
+        uptime_percent,
+        probes: probes
+            .into_iter()
+            .map(UpstreamHealthProbeView::from)
+            .collect(),
+    }))
 }
 
 #[derive(Debug, Serialize)]
-struct BatchCreateKeysResponse {
-    summary: BatchCreateKeysSummary,
-    results: Vec<BatchCreateKeysResult>,
+#[serde(rename_all = "camelCase")]
+struct HeaderPolicyView {
+    source: Option<String>,
+    passthrough_all: bool,
+    blocked: Vec<String>,
+    allowed: Vec<String>,
+    allowed_prefixes: Vec<String>,
 }
 
-async fn create_api_key(
+#[utoipa::path(
+    get,
+    path = "/api/config/header-policy",
+    tag = "admin",
+    summary = "Effective header policy.",
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_header_policy(
     State(state): State<Arc<AppState>>,
     headers: HeaderMap,
-    Json(payload): Json<CreateKeyRequest>,
-) -> Result<(StatusCode, Json<CreateKeyResponse>), StatusCode> {
+) -> Result<Json<HeaderPolicyView>, StatusCode> {
     if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
         return Err(StatusCode::FORBIDDEN);
     }
+    let policy = state.proxy.header_policy();
+    Ok(Json(HeaderPolicyView {
+        source: policy.source.clone(),
+        passthrough_all: policy.passthrough_all,
+        blocked: policy.blocked.clone(),
+        allowed: policy.allowed.clone(),
+        allowed_prefixes: policy.allowed_prefixes.clone(),
+    }))
+}
 
-    let api_key = payload.api_key.trim();
-    if api_key.is_empty() {
-        return Err(StatusCode::BAD_REQUEST);
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct UpstreamHeaderRulesView {
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    #[serde(default)]
+    tokens: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/config/upstream-headers",
+    tag = "admin",
+    summary = "Headers injected into forwarded requests.",
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_upstream_header_rules(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Json<UpstreamHeaderRulesView>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
     }
+    let rules = state.proxy.upstream_header_rules();
+    Ok(Json(UpstreamHeaderRulesView {
+        headers: rules.headers.clone(),
+        tokens: rules.tokens.clone(),
+    }))
+}
 
-    match state.proxy.add_or_undelete_key(api_key).await {
-        Ok(id) => Ok((StatusCode::CREATED, Json(CreateKeyResponse { id }))),
+#[utoipa::path(
+    put,
+    path = "/api/config/upstream-headers",
+    tag = "admin",
+    summary = "Replace the headers injected into forwarded requests.",
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn put_upstream_header_rules(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(payload): Json<UpstreamHeaderRulesView>,
+) -> Result<StatusCode, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let rules = normalize_upstream_header_rules(&UpstreamHeaderRules {
+        headers: payload.headers,
+        tokens: payload.tokens,
+    })
+    .map_err(|err| {
+        tracing::warn!("update upstream header rules rejected: {err}");
+        StatusCode::BAD_REQUEST
+    })?;
+    match state.proxy.set_upstream_header_rules(rules).await {
+        Ok(()) => Ok(StatusCode::NO_CONTENT),
         Err(err) => {
-            eprintln!("create api key error: {err}");
+            tracing::error!("update upstream header rules error: {err}");
             Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
 }
 
-async fn create_api_keys_batch(
+// ---- Request body analytics ----
+
+#[derive(Deserialize)]
+struct AnalyticsQuery {
+    days: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AnalyticsBucketView {
+    value: String,
+    count: i64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AnalyticsQueriesView {
+    since: i64,
+    sampled_searches: i64,
+    topics: Vec<AnalyticsBucketView>,
+    query_lengths: Vec<AnalyticsBucketView>,
+    top_domains: Vec<AnalyticsBucketView>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/analytics/queries",
+    tag = "analytics",
+    summary = "Sampled search query analytics.",
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_analytics_queries(
     State(state): State<Arc<AppState>>,
     headers: HeaderMap,
-    Json(payload): Json<BatchCreateKeysRequest>,
-) -> Result<Response<Body>, StatusCode> {
+    Query(q): Query<AnalyticsQuery>,
+) -> Result<Json<AnalyticsQueriesView>, StatusCode> {
     if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
         return Err(StatusCode::FORBIDDEN);
     }
+    let days = q.days.unwrap_or(30).clamp(1, 365);
+    let since = (Utc::now() - ChronoDuration::days(days)).timestamp();
 
-    let mut summary = BatchCreateKeysSummary::default();
-    let mut trimmed = Vec::<String>::with_capacity(payload.api_keys.len());
-    for api_key in payload.api_keys {
-        let api_key = api_key.trim();
-        if api_key.is_empty() {
-            summary.ignored_empty += 1;
-            continue;
+    let counts = state
+        .proxy
+        .analytics_query_counts(since)
+        .await
+        .map_err(|err| {
+            tracing::error!("analytics queries error: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let mut view = AnalyticsQueriesView {
+        since,
+        sampled_searches: 0,
+        topics: Vec::new(),
+        query_lengths: Vec::new(),
+        top_domains: Vec::new(),
+    };
+    for AnalyticsCount {
+        dimension,
+        value,
+        count,
+    } in counts
+    {
+        let bucket = AnalyticsBucketView { value, count };
+        match dimension.as_str() {
+            "sampled" => view.sampled_searches += bucket.count,
+            "topic" => view.topics.push(bucket),
+            "query_length" => view.query_lengths.push(bucket),
+            "domain" => view.top_domains.push(bucket),
+            _ => {}
         }
-        trimmed.push(api_key.to_string());
-    }
-
-    if trimmed.len() > API_KEYS_BATCH_LIMIT {
-        let body = Json(json!({
-            "error": "too_many_items",
-            "detail": format!("api_keys exceeds limit (max {})", API_KEYS_BATCH_LIMIT),
-        }));
-        return Ok((StatusCode::BAD_REQUEST, body).into_response());
     }
+    view.top_domains.truncate(20);
 
-    let mut results = Vec::with_capacity(trimmed.len());
-    let mut seen = HashSet::<String>::new();
+    Ok(Json(view))
+}
 
-    for api_key in trimmed {
-        if !seen.insert(api_key.clone()) {
-            summary.duplicate_in_input += 1;
-            results.push(BatchCreateKeysResult {
-                api_key,
-                status: "duplicate_in_input".to_string(),
-                id: None,
-                error: None,
-            });
-            continue;
-        }
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ToolUsageView {
+    tool: String,
+    requests: i64,
+    success_count: i64,
+    error_count: i64,
+    quota_exhausted_count: i64,
+    avg_latency_ms: Option<f64>,
+}
 
-        match state.proxy.add_or_undelete_key_with_status(&api_key).await {
-            Ok((id, status)) => {
-                match status.as_str() {
-                    "created" => summary.created += 1,
-                    "undeleted" => summary.undeleted += 1,
-                    "existed" => summary.existed += 1,
-                    _ => {}
-                }
-                results.push(BatchCreateKeysResult {
-                    api_key,
-                    status: status.as_str().to_string(),
-                    id: Some(id),
-                    error: None,
-                });
-            }
-            Err(err) => {
-                summary.failed += 1;
-                results.push(BatchCreateKeysResult {
-                    api_key,
-                    status: "failed".to_string(),
-                    id: None,
-                    error: Some(err.to_string()),
-                });
-            }
+impl From<ToolUsage> for ToolUsageView {
+    fn from(usage: ToolUsage) -> Self {
+        Self {
+            tool: usage.tool,
+            requests: usage.requests,
+            success_count: usage.success_count,
+            error_count: usage.error_count,
+            quota_exhausted_count: usage.quota_exhausted_count,
+            avg_latency_ms: usage.avg_latency_ms,
         }
     }
+}
 
-    Ok((
-        StatusCode::OK,
-        Json(BatchCreateKeysResponse { summary, results }),
-    )
-        .into_response())
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ToolUsageListView {
+    since: i64,
+    tools: Vec<ToolUsageView>,
 }
 
-async fn delete_api_key(
+#[utoipa::path(
+    get,
+    path = "/api/analytics/tools",
+    tag = "analytics",
+    summary = "Requests per tool.",
+    params(("days" = Option<String>, Query)),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_analytics_tools(
     State(state): State<Arc<AppState>>,
-    Path(id): Path<String>,
     headers: HeaderMap,
-) -> Result<StatusCode, StatusCode> {
+    Query(q): Query<AnalyticsQuery>,
+) -> Result<Json<ToolUsageListView>, StatusCode> {
     if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
         return Err(StatusCode::FORBIDDEN);
     }
-
-    match state.proxy.soft_delete_key_by_id(&id).await {
-        Ok(()) => Ok(StatusCode::NO_CONTENT),
-        Err(err) => {
-            eprintln!("delete api key error: {err}");
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
-        }
-    }
-}
-
-#[derive(Debug, Deserialize)]
-struct UpdateKeyStatus {
-    status: String,
+    let days = q.days.unwrap_or(30).clamp(1, 365);
+    let since = (Utc::now() - ChronoDuration::days(days)).timestamp();
+    let tools = state.proxy.tool_usage(since).await.map_err(|err| {
+        tracing::error!("tool usage error: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(ToolUsageListView {
+        since,
+        tools: tools.into_iter().map(ToolUsageView::from).collect(),
+    }))
 }
 
-async fn update_api_key_status(
+#[utoipa::path(
+    get,
+    path = "/api/tokens/{id}/tools",
+    tag = "tokens",
+    summary = "Requests per tool of a token.",
+    params(("id" = String, Path), ("days" = Option<String>, Query)),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_token_tools(
     State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
     headers: HeaderMap,
-    Json(payload): Json<UpdateKeyStatus>,
-) -> Result<StatusCode, StatusCode> {
+    Query(q): Query<AnalyticsQuery>,
+) -> Result<Json<ToolUsageListView>, StatusCode> {
     if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
         return Err(StatusCode::FORBIDDEN);
     }
+    let days = q.days.unwrap_or(30).clamp(1, 365);
+    let since = (Utc::now() - ChronoDuration::days(days)).timestamp();
+    let tools = state
+        .proxy
+        .token_tool_usage(&id, since)
+        .await
+        .map_err(|err| {
+            tracing::error!("token tool usage error: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(Json(ToolUsageListView {
+        since,
+        tools: tools.into_iter().map(ToolUsageView::from).collect(),
+    }))
+}
 
-    let status = payload.status.trim().to_ascii_lowercase();
-    match status.as_str() {
-        "disabled" => match state.proxy.disable_key_by_id(&id).await {
-            Ok(()) => Ok(StatusCode::NO_CONTENT),
-            Err(err) => {
-                eprintln!("disable api key error: {err}");
-                Err(StatusCode::INTERNAL_SERVER_ERROR)
-            }
-        },
-        "active" => match state.proxy.enable_key_by_id(&id).await {
-            Ok(()) => Ok(StatusCode::NO_CONTENT),
-            Err(err) => {
-                eprintln!("enable api key error: {err}");
-                Err(StatusCode::INTERNAL_SERVER_ERROR)
-            }
-        },
-        _ => Err(StatusCode::BAD_REQUEST),
+// ---- Key detail & manual quota sync ----
+
+#[utoipa::path(
+    get,
+    path = "/api/keys/{id}",
+    tag = "keys",
+    summary = "One key with its counters.",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_api_key_detail(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<ApiKeyView>, StatusCode> {
+    let scope = manage_scope(&state, &headers)?;
+    ensure_key_in_scope(&state, &scope, &id).await?;
+    let items = state
+        .proxy
+        .list_api_key_metrics()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if let Some(found) = items.into_iter().find(|k| k.id == id) {
+        Ok(Json(ApiKeyView::from(found)))
+    } else {
+        Err(StatusCode::NOT_FOUND)
     }
 }
 
-async fn get_api_key_secret(
+#[utoipa::path(
+    post,
+    path = "/api/keys/{id}/sync-usage",
+    tag = "keys",
+    summary = "Sync the key's quota now.",
+    params(("id" = String, Path)),
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn post_sync_key_usage(
     State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
     headers: HeaderMap,
-) -> Result<Json<ApiKeySecretView>, StatusCode> {
+) -> Result<Response<Body>, StatusCode> {
     if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
         return Err(StatusCode::FORBIDDEN);
     }
+    let job_id = state
+        .proxy
+        .scheduled_job_start("quota_sync/manual", Some(&id), 1)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
-    match state.proxy.get_api_key_secret(&id).await {
-        Ok(Some(secret)) => Ok(Json(ApiKeySecretView { api_key: secret })),
-        Ok(None) => Err(StatusCode::NOT_FOUND),
+    match state.proxy.sync_key_quota(&id, &state.usage_base).await {
+        Ok((limit, remaining)) => {
+            let msg = format!("limit={limit} remaining={remaining}");
+            let _ = state
+                .proxy
+                .scheduled_job_finish(job_id, "success", Some(&msg))
+                .await;
+            Ok(StatusCode::NO_CONTENT.into_response())
+        }
+        Err(ProxyError::QuotaDataMissing { reason }) => {
+            let msg = format!("quota_data_missing: {reason}");
+            let _ = state
+                .proxy
+                .scheduled_job_finish(job_id, "error", Some(&msg))
+                .await;
+            let body = Json(json!({
+                "error": "quota_data_missing",
+                "detail": reason,
+            }));
+            Ok((StatusCode::BAD_REQUEST, body).into_response())
+        }
+        Err(ProxyError::UsageHttp { status, body }) => {
+            let detail = format!("Tavily usage request failed with {status}: {body}");
+            let http_status = if status == reqwest::StatusCode::UNAUTHORIZED {
+                StatusCode::UNAUTHORIZED
+            } else if status == reqwest::StatusCode::FORBIDDEN {
+                StatusCode::FORBIDDEN
+            } else if status.is_client_error() {
+                StatusCode::BAD_REQUEST
+            } else {
+                StatusCode::BAD_GATEWAY
+            };
+            let _ = state
+                .proxy
+                .scheduled_job_finish(job_id, "error", Some(&detail))
+                .await;
+            let body = Json(json!({
+                "error": "usage_http",
+                "detail": detail,
+            }));
+            Ok((http_status, body).into_response())
+        }
         Err(err) => {
-            eprintln!("fetch api key secret error: {err}");
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
+            let reason = err.to_string();
+            let _ = state
+                .proxy
+                .scheduled_job_finish(job_id, "error", Some(&reason))
+                .await;
+            let body = Json(json!({
+                "error": "sync_failed",
+                "detail": reason,
+            }));
+            Ok((StatusCode::BAD_GATEWAY, body).into_response())
         }
     }
 }
 
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
-struct PaginatedLogsView {
-    items: Vec<RequestLogView>,
-    total: i64,
-    page: i64,
-    per_page: i64,
+struct KeyVerificationView {
+    key_id: String,
+    verdict: &'static str,
+    upstream_status: u16,
+    status: String,
+    quota_limit: Option<i64>,
+    quota_remaining: Option<i64>,
 }
 
-async fn list_logs(
+impl From<KeyVerification> for KeyVerificationView {
+    fn from(v: KeyVerification) -> Self {
+        Self {
+            key_id: v.key_id,
+            verdict: v.verdict.as_str(),
+            upstream_status: v.upstream_status,
+            status: v.status,
+            quota_limit: v.quota_limit,
+            quota_remaining: v.quota_remaining,
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/keys/{id}/verify",
+    tag = "keys",
+    summary = "Check the key against Tavily now.",
+    params(("id" = String, Path)),
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn post_verify_key(
     State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
     headers: HeaderMap,
-    Query(params): Query<LogsQuery>,
-) -> Result<Json<PaginatedLogsView>, StatusCode> {
+) -> Result<Response<Body>, StatusCode> {
     if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
         return Err(StatusCode::FORBIDDEN);
     }
-
-    let page = params.page.unwrap_or(1).max(1);
-    let per_page = params.per_page.unwrap_or(20).clamp(1, 200);
-
-    // Optional result_status filter: normalize to known values.
-    let result_status: Option<&str> = match params.result.as_deref().map(str::trim) {
-        Some(v) if v.eq_ignore_ascii_case("success") => Some("success"),
-        Some(v) if v.eq_ignore_ascii_case("error") => Some("error"),
-        Some(v) if v.eq_ignore_ascii_case("quota_exhausted") || v.eq_ignore_ascii_case("quota") => {
-            Some("quota_exhausted")
+    match state.proxy.verify_key(&id, &state.usage_base).await {
+        Ok(verification) => Ok(Json(KeyVerificationView::from(verification)).into_response()),
+        Err(ProxyError::Database(sqlx::Error::RowNotFound)) => Err(StatusCode::NOT_FOUND),
+        Err(ProxyError::QuotaDataMissing { reason }) => {
+            let body = Json(json!({
+                "error": "quota_data_missing",
+                "detail": reason,
+            }));
+            Ok((StatusCode::BAD_GATEWAY, body).into_response())
         }
-        _ => None,
-    };
-
-    state
-        .proxy
-        .recent_request_logs_page(result_status, page, per_page)
-        .await
-        .map(|(logs, total)| {
-            let view_items = logs.into_iter().map(RequestLogView::from).collect();
-            Json(PaginatedLogsView {
-                items: view_items,
-                total,
-                page,
-                per_page,
-            })
-        })
-        .map_err(|err| {
-            eprintln!("list logs error: {err}");
-            StatusCode::INTERNAL_SERVER_ERROR
-        })
+        Err(err) => {
+            let body = Json(json!({
+                "error": "verify_failed",
+                "detail": err.to_string(),
+            }));
+            Ok((StatusCode::BAD_GATEWAY, body).into_response())
+        }
+    }
 }
 
-// ----- Access token management handlers -----
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct VersionView {
+    backend: String,
+    frontend: String,
+    git_sha: String,
+    build_timestamp: Option<String>,
+    features: Vec<String>,
+}
 
-#[derive(Debug, Deserialize)]
-struct ListTokensQuery {
-    page: Option<i64>,
-    per_page: Option<i64>,
-    group: Option<String>,
-    no_group: Option<bool>,
+#[utoipa::path(
+    get,
+    path = "/api/version",
+    tag = "system",
+    summary = "Backend and frontend versions.",
+    responses((status = 200, description = "OK"))
+)]
+async fn get_versions(State(state): State<Arc<AppState>>) -> Result<Json<VersionView>, StatusCode> {
+    Ok(Json(detect_versions(state.static_dir.as_deref())))
+}
+
+#[derive(Debug, Serialize)]
+struct AdminDebug {
+    dev_open_admin: bool,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/debug/admin",
+    tag = "debug",
+    summary = "Whether DEV_OPEN_ADMIN is on.",
+    responses((status = 200, description = "OK"))
+)]
+async fn get_admin_debug(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<AdminDebug>, StatusCode> {
+    Ok(Json(AdminDebug {
+        dev_open_admin: state.dev_open_admin,
+    }))
 }
 
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
-struct ListTokensResponse {
-    items: Vec<AuthTokenView>,
-    total: i64,
-    page: i64,
-    per_page: i64,
+struct ProfileView {
+    display_name: Option<String>,
+    is_admin: bool,
 }
 
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
-struct TokenGroupView {
-    name: String,
-    token_count: i64,
-    latest_created_at: i64,
+struct ForwardAuthDebugView {
+    enabled: bool,
+    user_header: Option<String>,
+    admin_value: Option<String>,
+    nickname_header: Option<String>,
 }
 
-async fn list_tokens(
+#[utoipa::path(
+    get,
+    path = "/api/debug/forward-auth",
+    tag = "debug",
+    summary = "ForwardAuth configuration.",
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_forward_auth_debug(
     State(state): State<Arc<AppState>>,
     headers: HeaderMap,
-    Query(q): Query<ListTokensQuery>,
-) -> Result<Json<ListTokensResponse>, StatusCode> {
+) -> Result<Json<ForwardAuthDebugView>, StatusCode> {
     if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
         return Err(StatusCode::FORBIDDEN);
     }
-    let page = q.page.unwrap_or(1).max(1);
-    let per_page = q.per_page.unwrap_or(10).clamp(1, 200);
-    let group = q
-        .group
-        .as_deref()
-        .map(str::trim)
-        .filter(|value| !value.is_empty())
-        .map(str::to_owned);
-    let no_group = q.no_group.unwrap_or(false);
+    let cfg = &state.forward_auth;
+    Ok(Json(ForwardAuthDebugView {
+        enabled: cfg.is_enabled(),
+        user_header: cfg.user_header().map(|h| h.to_string()),
+        admin_value: None,
+        nickname_header: cfg.nickname_header().map(|h| h.to_string()),
+    }))
+}
 
-    if no_group {
-        match state.proxy.list_access_tokens().await {
-            Ok(items) => {
-                let filtered: Vec<AuthToken> = items
-                    .into_iter()
-                    .filter(|t| {
-                        t.group_name
-                            .as_deref()
-                            .map(str::trim)
-                            .map(|g| g.is_empty())
-                            .unwrap_or(true)
-                    })
-                    .collect();
-                let total = filtered.len() as i64;
-                let start = ((page - 1) * per_page).max(0) as usize;
-                let end = start.saturating_add(per_page as usize).min(total as usize);
-                let slice = if start >= total as usize {
-                    Vec::new()
-                } else {
-                    filtered[start..end].to_vec()
-                };
-                Ok(Json(ListTokensResponse {
-                    items: slice.into_iter().map(AuthTokenView::from).collect(),
-                    total,
-                    page,
-                    per_page,
-                }))
-            }
-            Err(err) => {
-                eprintln!("list tokens (no_group filter) error: {err}");
-                Err(StatusCode::INTERNAL_SERVER_ERROR)
-            }
-        }
-    } else if let Some(group) = group {
-        match state.proxy.list_access_tokens().await {
-            Ok(items) => {
-                let filtered: Vec<AuthToken> = items
-                    .into_iter()
-                    .filter(|t| t.group_name.as_deref() == Some(group.as_str()))
-                    .collect();
-                let total = filtered.len() as i64;
-                let start = ((page - 1) * per_page).max(0) as usize;
-                let end = start.saturating_add(per_page as usize).min(total as usize);
-                let slice = if start >= total as usize {
-                    Vec::new()
-                } else {
-                    filtered[start..end].to_vec()
-                };
-                Ok(Json(ListTokensResponse {
-                    items: slice.into_iter().map(AuthTokenView::from).collect(),
-                    total,
-                    page,
-                    per_page,
-                }))
-            }
-            Err(err) => {
-                eprintln!("list tokens (group filter) error: {err}");
-                Err(StatusCode::INTERNAL_SERVER_ERROR)
-            }
-        }
-    } else {
-        match state.proxy.list_access_tokens_paged(page, per_page).await {
-            Ok((items, total)) => Ok(Json(ListTokensResponse {
-                items: items.into_iter().map(AuthTokenView::from).collect(),
-                total,
-                page,
-                per_page,
-            })),
-            Err(err) => {
-                eprintln!("list tokens error: {err}");
-                Err(StatusCode::INTERNAL_SERVER_ERROR)
-            }
-        }
-    }
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RetryBudgetView {
+    percent: i64,
+    window_secs: i64,
+    window_requests: i64,
+    window_retries: i64,
+    window_rejected: i64,
+    remaining: i64,
+    exhausted: bool,
 }
 
-#[axum::debug_handler]
-async fn list_token_groups(
-    State(state): State<Arc<AppState>>,
-    headers: HeaderMap,
-) -> Result<Json<Vec<TokenGroupView>>, StatusCode> {
-    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
-        return Err(StatusCode::FORBIDDEN);
-    }
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SelfMetricsView {
+    retry_budget: RetryBudgetView,
+    public_quota: PublicQuotaView,
+    key_acquisition: KeyAcquisitionView,
+}
 
-    match state.proxy.list_access_tokens().await {
-        Ok(tokens) => {
-            let mut groups: HashMap<String, TokenGroupView> = HashMap::new();
-            for t in tokens {
-                let raw = t.group_name.as_deref().map(str::trim).unwrap_or("");
-                let key = raw.to_owned();
-                let entry = groups.entry(key.clone()).or_insert(TokenGroupView {
-                    name: key.clone(),
-                    token_count: 0,
-                    latest_created_at: t.created_at,
-                });
-                entry.token_count += 1;
-                if t.created_at > entry.latest_created_at {
-                    entry.latest_created_at = t.created_at;
-                }
-            }
-            let mut out: Vec<TokenGroupView> = groups.into_values().collect();
-            out.sort_by(|a, b| {
-                b.latest_created_at
-                    .cmp(&a.latest_created_at)
-                    .then_with(|| a.name.cmp(&b.name))
-            });
-            Ok(Json(out))
-        }
-        Err(err) => {
-            eprintln!("list token groups error: {err}");
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct KeyAcquisitionView {
+    since: i64,
+    paths: Vec<KeyAcquirePathView>,
+    stale_affinity: u64,
+    shared_leases: u64,
+    tag_fallbacks: u64,
+    session_hits: u64,
+    affinity_strategy: &'static str,
+    affinity_ttl_secs: i64,
+    affinity_mappings: usize,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct KeyAcquirePathView {
+    path: &'static str,
+    count: u64,
+    avg_micros: u64,
+    max_micros: u64,
+}
+
+impl From<KeyAcquisitionSnapshot> for KeyAcquisitionView {
+    fn from(snapshot: KeyAcquisitionSnapshot) -> Self {
+        Self {
+            since: snapshot.since,
+            paths: snapshot
+                .paths
+                .into_iter()
+                .map(|path| KeyAcquirePathView {
+                    path: path.path,
+                    count: path.count,
+                    avg_micros: path.avg_micros,
+                    max_micros: path.max_micros,
+                })
+                .collect(),
+            stale_affinity: snapshot.stale_affinity,
+            shared_leases: snapshot.shared_leases,
+            tag_fallbacks: snapshot.tag_fallbacks,
+            session_hits: snapshot.session_hits,
+            affinity_strategy: snapshot.affinity_strategy,
+            affinity_ttl_secs: snapshot.affinity_ttl_secs,
+            affinity_mappings: snapshot.affinity_mappings,
         }
     }
 }
 
-#[axum::debug_handler]
-async fn create_token(
-    State(state): State<Arc<AppState>>,
-    headers: HeaderMap,
-    Json(payload): Json<CreateTokenRequest>,
-) -> Result<(StatusCode, Json<AuthTokenSecretView>), StatusCode> {
-    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
-        return Err(StatusCode::FORBIDDEN);
-    }
-    state
-        .proxy
-        .create_access_token(payload.note.as_deref())
-        .await
-        .map(|secret| {
-            (
-                StatusCode::CREATED,
-                Json(AuthTokenSecretView {
-                    token: secret.token,
-                }),
-            )
-        })
-        .map_err(|err| {
-            eprintln!("create token error: {err}");
-            StatusCode::INTERNAL_SERVER_ERROR
-        })
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SchedulerStatsView {
+    key_acquisition: KeyAcquisitionView,
 }
 
-async fn delete_token(
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PublicQuotaView {
+    hourly_limit: i64,
+    window_start: i64,
+    tracked_ips: i64,
+    window_requests: i64,
+    window_rejected: i64,
+    /// Busiest clients of the window, as hashed IPs.
+    top_clients: Vec<PublicQuotaClientView>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PublicQuotaClientView {
+    client: String,
+    requests: i64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DbStatsView {
+    page_size: i64,
+    page_count: i64,
+    freelist_count: i64,
+    db_size_bytes: i64,
+    wal_size_bytes: i64,
+    wal_checkpoint_threshold_bytes: i64,
+    last_wal_checkpoint: Option<JobLogView>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/debug/db-stats",
+    tag = "debug",
+    summary = "Database file and table sizes.",
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_db_stats(
     State(state): State<Arc<AppState>>,
-    Path(id): Path<String>,
     headers: HeaderMap,
-) -> Result<StatusCode, StatusCode> {
+) -> Result<Json<DbStatsView>, StatusCode> {
     if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
         return Err(StatusCode::FORBIDDEN);
     }
-    state
+    let stats = state.proxy.database_stats().await.map_err(|err| {
+        tracing::error!("db stats error: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let last_wal_checkpoint = state
         .proxy
-        .delete_access_token(&id)
+        .list_recent_jobs_paginated("db", 1, 1)
         .await
-        .map(|_| StatusCode::NO_CONTENT)
         .map_err(|err| {
-            eprintln!("delete token error: {err}");
+            tracing::error!("db stats job lookup error: {err}");
             StatusCode::INTERNAL_SERVER_ERROR
-        })
+        })?
+        .0
+        .into_iter()
+        .next()
+        .map(JobLogView::from);
+    Ok(Json(DbStatsView {
+        page_size: stats.page_size,
+        page_count: stats.page_count,
+        freelist_count: stats.freelist_count,
+        db_size_bytes: stats.db_size_bytes,
+        wal_size_bytes: stats.wal_size_bytes,
+        wal_checkpoint_threshold_bytes: effective_wal_checkpoint_threshold_mb() * 1024 * 1024,
+        last_wal_checkpoint,
+    }))
 }
 
-#[derive(Debug, Deserialize)]
-struct UpdateTokenStatus {
-    enabled: bool,
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DbHealthView {
+    db_file_bytes: i64,
+    wal_size_bytes: i64,
+    page_size: i64,
+    page_count: i64,
+    freelist_count: i64,
+    wal_checkpoint_threshold_bytes: i64,
+    wal_checkpoint_interval_secs: i64,
+    last_wal_checkpoint: Option<JobLogView>,
+    pool: DbPoolView,
 }
 
-async fn update_token_status(
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DbPoolView {
+    size: u32,
+    idle: u32,
+    in_use: u32,
+    max_connections: u32,
+    /// In-use connections as a percentage of `max_connections`.
+    utilization_percent: f64,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/db-health",
+    tag = "admin",
+    summary = "Database, WAL and connection pool health.",
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_db_health(
     State(state): State<Arc<AppState>>,
-    Path(id): Path<String>,
     headers: HeaderMap,
-    Json(payload): Json<UpdateTokenStatus>,
-) -> Result<StatusCode, StatusCode> {
+) -> Result<Json<DbHealthView>, StatusCode> {
     if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
         return Err(StatusCode::FORBIDDEN);
     }
-    state
+    let stats = state.proxy.database_stats().await.map_err(|err| {
+        tracing::error!("db health error: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let last_wal_checkpoint = state
         .proxy
-        .set_access_token_enabled(&id, payload.enabled)
+        .list_recent_jobs_paginated("db", 1, 1)
         .await
-        .map(|_| StatusCode::NO_CONTENT)
         .map_err(|err| {
-            eprintln!("update token status error: {err}");
+            tracing::error!("db health job lookup error: {err}");
             StatusCode::INTERNAL_SERVER_ERROR
-        })
-}
-
-#[derive(Debug, Deserialize)]
-struct UpdateTokenNote {
-    note: String,
+        })?
+        .0
+        .into_iter()
+        .next()
+        .map(JobLogView::from);
+    let in_use = stats.pool_size.saturating_sub(stats.pool_idle);
+    Ok(Json(DbHealthView {
+        db_file_bytes: stats.db_file_bytes,
+        wal_size_bytes: stats.wal_size_bytes,
+        page_size: stats.page_size,
+        page_count: stats.page_count,
+        freelist_count: stats.freelist_count,
+        wal_checkpoint_threshold_bytes: effective_wal_checkpoint_threshold_mb() * 1024 * 1024,
+        wal_checkpoint_interval_secs: effective_wal_checkpoint_interval_secs(),
+        last_wal_checkpoint,
+        pool: DbPoolView {
+            size: stats.pool_size,
+            idle: stats.pool_idle,
+            in_use,
+            max_connections: stats.pool_max_connections,
+            utilization_percent: if stats.pool_max_connections == 0 {
+                0.0
+            } else {
+                f64::from(in_use) * 100.0 / f64::from(stats.pool_max_connections)
+            },
+        },
+    }))
 }
 
-async fn update_token_note(
+#[utoipa::path(
+    get,
+    path = "/api/debug/metrics",
+    tag = "debug",
+    summary = "Process and pool metrics.",
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_self_metrics(
     State(state): State<Arc<AppState>>,
-    Path(id): Path<String>,
     headers: HeaderMap,
-    Json(payload): Json<UpdateTokenNote>,
-) -> Result<StatusCode, StatusCode> {
+) -> Result<Json<SelfMetricsView>, StatusCode> {
     if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
         return Err(StatusCode::FORBIDDEN);
     }
-    state
-        .proxy
-        .update_access_token_note(&id, payload.note.trim())
-        .await
-        .map(|_| StatusCode::NO_CONTENT)
-        .map_err(|err| {
-            eprintln!("update token note error: {err}");
-            StatusCode::INTERNAL_SERVER_ERROR
-        })
+    let budget = state.proxy.retry_budget_snapshot().await;
+    Ok(Json(SelfMetricsView {
+        retry_budget: RetryBudgetView {
+            percent: budget.percent,
+            window_secs: budget.window_secs,
+            window_requests: budget.window_requests,
+            window_retries: budget.window_retries,
+            window_rejected: budget.window_rejected,
+            remaining: budget.remaining,
+            exhausted: budget.exhausted,
+        },
+        public_quota: state.public_quota.snapshot(Utc::now().timestamp()),
+        key_acquisition: state.proxy.key_acquisition_snapshot().await.into(),
+    }))
 }
 
-async fn get_token_secret(
+/// Key scheduling counters: how leases are obtained and how long that takes.
+#[utoipa::path(
+    get,
+    path = "/api/debug/scheduler-stats",
+    tag = "debug",
+    summary = "Key scheduler statistics.",
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_scheduler_stats(
     State(state): State<Arc<AppState>>,
-    Path(id): Path<String>,
     headers: HeaderMap,
-) -> Result<Json<AuthTokenSecretView>, StatusCode> {
+) -> Result<Json<SchedulerStatsView>, StatusCode> {
     if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
         return Err(StatusCode::FORBIDDEN);
     }
-    match state.proxy.get_access_token_secret(&id).await {
-        Ok(Some(secret)) => Ok(Json(AuthTokenSecretView {
-            token: secret.token,
-        })),
-        Ok(None) => Err(StatusCode::NOT_FOUND),
-        Err(err) => {
-            eprintln!("get token secret error: {err}");
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
+    Ok(Json(SchedulerStatsView {
+        key_acquisition: state.proxy.key_acquisition_snapshot().await.into(),
+    }))
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ResponseCacheView {
+    enabled: bool,
+    ttl_secs: i64,
+    max_entries: i64,
+    max_bytes: i64,
+    size_bytes: i64,
+    hits: u64,
+    misses: u64,
+    entries: Vec<ResponseCacheEntryView>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ResponseCacheEntryView {
+    key: String,
+    path: String,
+    stored_at: i64,
+    expires_at: i64,
+    hits: i64,
+    size_bytes: i64,
+}
+
+impl From<ResponseCacheSnapshot> for ResponseCacheView {
+    fn from(snapshot: ResponseCacheSnapshot) -> Self {
+        Self {
+            enabled: snapshot.enabled,
+            ttl_secs: snapshot.ttl_secs,
+            max_entries: snapshot.max_entries,
+            max_bytes: snapshot.max_bytes,
+            size_bytes: snapshot.size_bytes,
+            hits: snapshot.hits,
+            misses: snapshot.misses,
+            entries: snapshot
+                .entries
+                .into_iter()
+                .map(|entry| ResponseCacheEntryView {
+                    key: entry.key,
+                    path: entry.path,
+                    stored_at: entry.stored_at,
+                    expires_at: entry.expires_at,
+                    hits: entry.hits,
+                    size_bytes: entry.size_bytes,
+                })
+                .collect(),
         }
     }
 }
 
-#[axum::debug_handler]
-async fn rotate_token_secret(
+#[utoipa::path(
+    get,
+    path = "/api/cache",
+    tag = "system",
+    summary = "Response cache statistics.",
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_response_cache(
     State(state): State<Arc<AppState>>,
-    Path(id): Path<String>,
     headers: HeaderMap,
-) -> Result<Json<AuthTokenSecretView>, StatusCode> {
+) -> Result<Json<ResponseCacheView>, StatusCode> {
     if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
         return Err(StatusCode::FORBIDDEN);
     }
-    state
-        .proxy
-        .rotate_access_token_secret(&id)
-        .await
-        .map(|secret| {
-            Json(AuthTokenSecretView {
-                token: secret.token,
-            })
-        })
-        .map_err(|err| {
-            eprintln!("rotate token secret error: {err}");
-            StatusCode::INTERNAL_SERVER_ERROR
-        })
-}
-
-#[derive(Debug, Deserialize)]
-struct BatchCreateTokenRequest {
-    group: String,
-    count: usize,
-    note: Option<String>,
+    Ok(Json(state.proxy.response_cache_snapshot().await.into()))
 }
 
-#[derive(Debug, Serialize)]
-struct BatchCreateTokenResponse {
-    tokens: Vec<String>,
+#[utoipa::path(
+    delete,
+    path = "/api/cache",
+    tag = "system",
+    summary = "Flush the response cache.",
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn flush_response_cache(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let removed = state.proxy.flush_response_cache().await;
+    Ok(Json(json!({ "removed": removed })))
 }
 
-#[axum::debug_handler]
-async fn create_tokens_batch(
+#[utoipa::path(
+    get,
+    path = "/api/debug/headers",
+    tag = "debug",
+    summary = "Echo the request headers.",
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn debug_headers(
     State(state): State<Arc<AppState>>,
     headers: HeaderMap,
-    Json(payload): Json<BatchCreateTokenRequest>,
-) -> Result<Json<BatchCreateTokenResponse>, StatusCode> {
+) -> Result<(StatusCode, Json<serde_json::Value>), StatusCode> {
     if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
         return Err(StatusCode::FORBIDDEN);
     }
-    let group = payload.group.trim();
-    if group.is_empty() {
-        return Err(StatusCode::BAD_REQUEST);
+    let mut map = serde_json::Map::new();
+    for (k, v) in headers.iter() {
+        map.insert(
+            k.as_str().to_string(),
+            serde_json::Value::String(v.to_str().unwrap_or("").to_string()),
+        );
     }
-    let count = payload.count.clamp(1, 1000);
-    state
-        .proxy
-        .create_access_tokens_batch(group, count, payload.note.as_deref())
-        .await
-        .map(|secrets| {
-            Json(BatchCreateTokenResponse {
-                tokens: secrets.into_iter().map(|s| s.token).collect(),
-            })
-        })
-        .map_err(|err| {
-            eprintln!("batch create tokens error: {err}");
-            StatusCode::INTERNAL_SERVER_ERROR
-        })
+    Ok((StatusCode::OK, Json(serde_json::Value::Object(map))))
 }
 
-pub async fn serve(
-    addr: SocketAddr,
-    proxy: TavilyProxy,
-    static_dir: Option<PathBuf>,
-    forward_auth: ForwardAuthConfig,
-    dev_open_admin: bool,
-    usage_base: String,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let state = Arc::new(AppState {
-        proxy,
-        static_dir: static_dir.clone(),
-        forward_auth,
-        dev_open_admin,
-        usage_base: usage_base.clone(),
-    });
+#[utoipa::path(
+    get,
+    path = "/api/profile",
+    tag = "system",
+    summary = "Caller display name and admin flag.",
+    responses((status = 200, description = "OK"))
+)]
+async fn get_profile(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Json<ProfileView>, StatusCode> {
+    let config = &state.forward_auth;
 
-    if let Some(h) = state.forward_auth.user_header() {
-        println!(
-            "Forward-Auth: header='{}' admin_value='{}'",
-            h,
-            state.forward_auth.admin_value().unwrap_or("<none>")
-        );
-    } else {
-        println!(
-            "Forward-Auth: disabled (no user header), admin_override={} dev_open_admin={}",
-            state.forward_auth.admin_override_name().unwrap_or("<none>"),
-            state.dev_open_admin
-        );
+    if let Some(name) = config.admin_override_name() {
+        return Ok(Json(ProfileView {
+            display_name: Some(name.to_owned()),
+            is_admin: true,
+        }));
     }
 
-    let mut router = Router::new()
-        .route("/health", get(health_check))
-        .route("/api/debug/headers", get(debug_headers))
-        .route("/api/debug/is-admin", get(debug_is_admin))
-        .route("/api/debug/forward-auth", get(get_forward_auth_debug))
-        .route("/api/debug/admin", get(get_admin_debug))
-        .route("/api/public/events", get(sse_public))
-        .route("/api/public/logs", get(get_public_logs))
-        .route("/api/token/metrics", get(get_token_metrics_public))
-        .route("/api/events", get(sse_dashboard))
-        .route("/api/version", get(get_versions))
-        .route("/api/profile", get(get_profile))
-        .route("/api/tavily/search", post(tavily_http_search))
-        .route("/api/tavily/extract", post(tavily_http_extract))
-        .route("/api/tavily/crawl", post(tavily_http_crawl))
-        .route("/api/tavily/map", post(tavily_http_map))
-        .route("/api/tavily/usage", get(tavily_http_usage))
-        .route("/api/summary", get(fetch_summary))
-        .route("/api/public/metrics", get(get_public_metrics))
-        .route("/api/keys", get(list_keys))
-        .route("/api/keys", post(create_api_key))
-        .route("/api/keys/batch", post(create_api_keys_batch))
-        .route("/api/keys/:id", get(get_api_key_detail))
-        .route("/api/keys/:id/sync-usage", post(post_sync_key_usage))
-        .route("/api/keys/:id/secret", get(get_api_key_secret))
-        .route("/api/keys/:id", delete(delete_api_key))
-        .route("/api/keys/:id/status", patch(update_api_key_status))
-        .route("/api/jobs", get(list_jobs))
-        .route("/api/logs", get(list_logs))
-        // Key details
-        .route("/api/keys/:id/metrics", get(get_key_metrics))
-        .route("/api/keys/:id/logs", get(get_key_logs))
-        // Token details
-        .route("/api/tokens/:id", get(get_token_detail))
-        .route("/api/tokens/:id/metrics", get(get_token_metrics))
-        .route(
-            "/api/tokens/:id/metrics/usage-series",
-            get(get_token_usage_series),
-        )
-        .route(
-            "/api/tokens/:id/metrics/hourly",
-            get(get_token_hourly_breakdown),
-        )
-        .route("/api/tokens/leaderboard", get(get_token_leaderboard))
-        .route("/api/tokens/:id/logs", get(get_token_logs))
-        .route("/api/tokens/:id/logs/page", get(get_token_logs_page))
-        .route("/api/tokens/:id/events", get(sse_token))
-        // Access token management (admin only)
-        .route("/api/tokens", get(list_tokens))
-        .route("/api/tokens", post(create_token))
-        .route("/api/tokens/groups", get(list_token_groups))
-        .route("/api/tokens/batch", post(create_tokens_batch))
-        .route("/api/tokens/:id", delete(delete_token))
-        .route("/api/tokens/:id/status", patch(update_token_status))
-        .route("/api/tokens/:id/note", patch(update_token_note))
-        .route("/api/tokens/:id/secret", get(get_token_secret))
-        .route("/api/tokens/:id/secret/rotate", post(rotate_token_secret));
-
-    if let Some(dir) = static_dir.as_ref() {
-        if dir.is_dir() {
-            let index_file = dir.join("index.html");
-            if index_file.exists() {
-                router = router.nest_service("/assets", ServeDir::new(dir.join("assets")));
-                router = router.route("/", get(serve_index));
-                router = router.route("/admin", get(serve_admin_index));
-                router = router.route("/admin/", get(serve_admin_index));
-                router =
-                    router.route_service("/favicon.svg", ServeFile::new(dir.join("favicon.svg")));
-            } else {
-                eprintln!(
-                    "static index.html not found at {} — skip serving SPA",
-                    index_file.display()
-                );
-            }
-        } else {
-            eprintln!("static dir '{}' is not a directory", dir.display());
-        }
+    if state.dev_open_admin {
+        return Ok(Json(ProfileView {
+            display_name: Some("dev-mode".to_string()),
+            is_admin: true,
+        }));
     }
 
-    router = router
-        .route("/mcp", any(proxy_handler))
-        .route("/mcp/*path", any(proxy_handler));
-
-    // 404 landing page that updates URL back to original via history API
-    router = router.route("/__404", get(not_found_landing));
-
-    // Fallback: if UA/Accept 支持 HTML 则重定向到 __404；否则返回纯 404
-    async fn supports_html(headers: &HeaderMap) -> bool {
-        let accept = headers
-            .get(axum::http::header::ACCEPT)
-            .and_then(|v| v.to_str().ok())
-            .unwrap_or("")
-            .to_ascii_lowercase();
-        if accept.contains("text/html") {
-            return true;
-        }
-        let ua = headers
-            .get(axum::http::header::USER_AGENT)
-            .and_then(|v| v.to_str().ok())
-            .unwrap_or("")
-            .to_ascii_lowercase();
-        ua.contains("mozilla/")
+    if !config.is_enabled() {
+        return Ok(Json(ProfileView {
+            display_name: None,
+            is_admin: false,
+        }));
     }
 
-    router = router.fallback(|req: Request<Body>| async move {
-        let headers = req.headers().clone();
-        if supports_html(&headers).await {
-            // 302 for GET/HEAD; 303 for others
-            let uri = req.uri();
-            let pq = uri
-                .path_and_query()
-                .map(|v| v.as_str())
-                .unwrap_or(uri.path());
-            let target = format!("/__404?path={}", urlencoding::encode(pq));
-            match *req.method() {
-                Method::GET | Method::HEAD => Redirect::temporary(&target).into_response(),
-                _ => Redirect::to(&target).into_response(), // 303 See Other
-            }
-        } else {
-            (StatusCode::NOT_FOUND, Body::empty()).into_response()
-        }
-    });
-
-    let listener = tokio::net::TcpListener::bind(addr).await?;
-    let bound_addr = listener.local_addr()?;
-    println!("Tavily proxy listening on http://{bound_addr}");
-
-    // Spawn background schedulers
-    spawn_quota_sync_scheduler(state.clone());
-    spawn_token_usage_rollup_scheduler(state.clone());
-    spawn_auth_token_logs_gc_scheduler(state.clone());
-    spawn_request_logs_gc_scheduler(state.clone());
+    let user_value = config.user_value(&headers).map(str::to_string);
 
-    axum::serve(
-        listener,
-        router
-            .with_state(state)
-            .into_make_service_with_connect_info::<SocketAddr>(),
-    )
-    .with_graceful_shutdown(shutdown_signal())
-    .await?;
-    println!("Server shut down gracefully.");
-    Ok(())
-}
+    let nickname = config
+        .nickname_value(&headers)
+        .or_else(|| user_value.clone());
 
-async fn wait_for_ctrl_c() -> &'static str {
-    match signal::ctrl_c().await {
-        Ok(()) => "ctrl_c",
-        Err(err) => {
-            eprintln!("Failed to listen for Ctrl+C: {err}");
-            "ctrl_c_error"
-        }
+    if nickname.is_none() {
+        return Ok(Json(ProfileView {
+            display_name: None,
+            is_admin: false,
+        }));
     }
-}
 
-#[cfg(unix)]
-async fn wait_for_sigterm() -> &'static str {
-    match unix_signal(SignalKind::terminate()) {
-        Ok(mut sigterm) => {
-            sigterm.recv().await;
-            "sigterm"
-        }
-        Err(err) => {
-            eprintln!("Failed to listen for SIGTERM: {err}");
-            wait_for_ctrl_c().await
-        }
-    }
-}
+    let is_admin = config.is_request_admin(&headers);
 
-async fn shutdown_signal() {
-    let signal = {
-        #[cfg(unix)]
-        {
-            tokio::select! {
-                reason = wait_for_ctrl_c() => reason,
-                reason = wait_for_sigterm() => reason,
-            }
-        }
+    Ok(Json(ProfileView {
+        display_name: nickname,
+        is_admin,
+    }))
+}
 
-        #[cfg(not(unix))]
-        {
-            wait_for_ctrl_c().await
-        }
+fn detect_versions(static_dir: Option<&FsPath>) -> VersionView {
+    let backend_base = option_env!("APP_EFFECTIVE_VERSION")
+        .map(|s| s.to_string())
+        .unwrap_or_else(|| env!("CARGO_PKG_VERSION").to_string());
+    let backend = if cfg!(debug_assertions) {
+        format!("{}-dev", backend_base)
+    } else {
+        backend_base
     };
 
-    println!("Shutdown signal ({signal}) received, waiting for in-flight requests to finish...");
+    // Try reading version.json produced by front-end build
+    let frontend_from_dist = static_dir.and_then(|dir| {
+        let path = dir.join("version.json");
+        fs::File::open(&path).ok().and_then(|mut f| {
+            let mut s = String::new();
+            if f.read_to_string(&mut s).is_ok() {
+                serde_json::from_str::<serde_json::Value>(&s)
+                    .ok()
+                    .and_then(|v| {
+                        v.get("version")
+                            .and_then(|v| v.as_str())
+                            .map(|s| s.to_string())
+                    })
+            } else {
+                None
+            }
+        })
+    });
+
+    // Fallback to web/package.json for dev setups
+    let frontend = frontend_from_dist
+        .or_else(|| {
+            let path = FsPath::new("web").join("package.json");
+            fs::File::open(&path).ok().and_then(|mut f| {
+                let mut s = String::new();
+                if f.read_to_string(&mut s).is_ok() {
+                    serde_json::from_str::<serde_json::Value>(&s)
+                        .ok()
+                        .and_then(|v| {
+                            v.get("version")
+                                .and_then(|v| v.as_str())
+                                .map(|s| s.to_string())
+                        })
+                } else {
+                    None
+                }
+            })
+        })
+        .unwrap_or_else(|| "unknown".to_string());
+
+    let frontend = if cfg!(debug_assertions) {
+        format!("{}-dev", frontend)
+    } else {
+        frontend
+    };
+
+    // Build metadata is embedded by build.rs.
+    let build_timestamp = env!("APP_BUILD_TIMESTAMP")
+        .parse::<i64>()
+        .ok()
+        .filter(|ts| *ts > 0)
+        .and_then(|ts| Utc.timestamp_opt(ts, 0).single())
+        .map(|dt| dt.to_rfc3339());
+    let features = env!("APP_CARGO_FEATURES")
+        .split(',')
+        .filter(|f| !f.is_empty())
+        .map(str::to_string)
+        .collect();
+
+    VersionView {
+        backend,
+        frontend,
+        git_sha: env!("APP_GIT_SHA").to_string(),
+        build_timestamp,
+        features,
+    }
 }
 
-const BODY_LIMIT: usize = 16 * 1024 * 1024; // 16 MiB 默认限制
-const DEFAULT_LOG_LIMIT: usize = 200;
+#[derive(Debug, Default, Deserialize)]
+struct ListKeysQuery {
+    page: Option<i64>,
+    per_page: Option<i64>,
+    status: Option<String>,
+    q: Option<String>,
+    sort: Option<String>,
+    order: Option<String>,
+    include_deleted: Option<bool>,
+}
+
+impl ListKeysQuery {
+    fn is_empty(&self) -> bool {
+        self.page.is_none()
+            && self.per_page.is_none()
+            && self.status.is_none()
+            && self.q.is_none()
+            && self.sort.is_none()
+            && self.order.is_none()
+            && self.include_deleted.is_none()
+    }
+}
 
 #[derive(Debug, Serialize)]
-struct ApiKeyView {
+#[serde(rename_all = "camelCase")]
+struct ListKeysResponse {
+    items: Vec<ApiKeyView>,
+    total: i64,
+    page: i64,
+    per_page: i64,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/keys",
+    tag = "keys",
+    summary = "List keys; paged when any query parameter is set.",
+    params(
+        ("page" = Option<String>, Query),
+        ("per_page" = Option<String>, Query),
+        ("status" = Option<String>, Query),
+        ("q" = Option<String>, Query),
+        ("sort" = Option<String>, Query),
+        ("order" = Option<String>, Query),
+        ("include_deleted" = Option<String>, Query),
+    ),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn list_keys(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(q): Query<ListKeysQuery>,
+) -> Result<Response<Body>, StatusCode> {
+    let scope = manage_scope(&state, &headers)?;
+    // The data version does not track ownership, so scoped listings are not cached.
+    let etag = match scope {
+        ManageScope::All => admin_list_etag(&state, "keys").await,
+        ManageScope::Owner(_) => None,
+    };
+    if let Some(resp) = not_modified(&headers, etag.as_ref()) {
+        return Ok(resp);
+    }
+    if !q.is_empty() {
+        // Any paging, filter or sort parameter switches to the paged envelope.
+        let non_empty = |value: Option<&str>| {
+            value
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(str::to_owned)
+        };
+        let sort = match non_empty(q.sort.as_deref()) {
+            Some(raw) => ApiKeySort::parse(&raw).ok_or(StatusCode::BAD_REQUEST)?,
+            None => ApiKeySort::default(),
+        };
+        let descending = match non_empty(q.order.as_deref()).as_deref() {
+            None | Some("asc") => false,
+            Some("desc") => true,
+            Some(_) => return Err(StatusCode::BAD_REQUEST),
+        };
+        let query = ApiKeyListQuery {
+            status: non_empty(q.status.as_deref()),
+            owner: scope.owner().map(str::to_owned),
+            search: non_empty(q.q.as_deref()),
+            sort,
+            descending,
+            include_deleted: q.include_deleted.unwrap_or(false),
+        };
+        let page = q.page.unwrap_or(1).max(1);
+        let per_page = q.per_page.unwrap_or(50).clamp(1, 200);
+        return match state
+            .proxy
+            .list_api_key_metrics_paged(&query, page, per_page)
+            .await
+        {
+            Ok((items, total)) => Ok(with_etag(
+                Json(ListKeysResponse {
+                    items: items.into_iter().map(ApiKeyView::from).collect(),
+                    total,
+                    page,
+                    per_page,
+                }),
+                etag,
+            )),
+            Err(err) => {
+                tracing::error!("list keys (paged) error: {err}");
+                Err(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        };
+    }
+    let owned = match scope.owner() {
+        Some(owner) => Some(state.proxy.owned_api_key_ids(owner).await.map_err(|err| {
+            tracing::error!("list owned keys error: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?),
+        None => None,
+    };
+    state
+        .proxy
+        .list_api_key_metrics()
+        .await
+        .map(|metrics| {
+            let keys: Vec<ApiKeyView> = metrics
+                .into_iter()
+                .filter(|key| owned.as_ref().is_none_or(|owned| owned.contains(&key.id)))
+                .map(ApiKeyView::from)
+                .collect();
+            with_etag(Json(keys), etag)
+        })
+        .map_err(|err| {
+            tracing::error!("list keys error: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateKeyRequest {
+    api_key: String,
+    /// Forward-auth user who may manage the key alongside admins.
+    #[serde(default)]
+    owner: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct CreateKeyResponse {
     id: String,
-    status: String,
-    status_changed_at: Option<i64>,
-    last_used_at: Option<i64>,
-    deleted_at: Option<i64>,
-    quota_limit: Option<i64>,
-    quota_remaining: Option<i64>,
-    quota_synced_at: Option<i64>,
-    total_requests: i64,
-    success_count: i64,
-    error_count: i64,
-    quota_exhausted_count: i64,
+}
+
+const API_KEYS_BATCH_LIMIT: usize = 1000;
+
+#[derive(Debug, Deserialize)]
+struct BatchCreateKeysRequest {
+    api_keys: Vec<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct BatchCreateKeysSummary {
+    created: u64,
+    undeleted: u64,
+    existed: u64,
+    duplicate_in_input: u64,
+    failed: u64,
+    ignored_empty: u64,
 }
 
 #[derive(Debug, Serialize)]
-struct ApiKeySecretView {
+struct BatchCreateKeysResult {
     api_key: String,
+    status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
-struct RequestLogView {
-    id: i64,
-    key_id: String,
-    auth_token_id: Option<String>,
-    method: String,
-    path: String,
-    query: Option<String>,
-    http_status: Option<i64>,
-    mcp_status: Option<i64>,
-    result_status: String,
-    created_at: i64,
-    error_message: Option<String>,
-    request_body: Option<String>,
-    response_body: Option<String>,
-    forwarded_headers: Vec<String>,
-    dropped_headers: Vec<String>,
+struct BatchCreateKeysResponse {
+    summary: BatchCreateKeysSummary,
+    results: Vec<BatchCreateKeysResult>,
+}
+
+/// Admin: add a key to the shared pool, optionally handing its management to `owner`.
+/// Non-admin users are refused before the key is looked at, so the answer never reveals
+/// whether a secret is already pooled.
+#[utoipa::path(
+    post,
+    path = "/api/keys",
+    tag = "keys",
+    summary = "Add or restore a key, optionally owned by a forward-auth user (`owner`).",
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn create_api_key(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(payload): Json<CreateKeyRequest>,
+) -> Result<(StatusCode, Json<CreateKeyResponse>), StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let api_key = payload.api_key.trim();
+    if api_key.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let owner = payload
+        .owner
+        .as_deref()
+        .map(str::trim)
+        .filter(|owner| !owner.is_empty());
+
+    let id = state
+        .proxy
+        .add_or_undelete_key(api_key)
+        .await
+        .map_err(|err| {
+            tracing::error!("create api key error: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if let Some(owner) = owner {
+        state
+            .proxy
+            .set_api_key_owner(&id, Some(owner))
+            .await
+            .map_err(|err| {
+                tracing::error!("set api key owner error: {err}");
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+    }
+    Ok((StatusCode::CREATED, Json(CreateKeyResponse { id })))
+}
+
+/// Shortest secret fragment `/api/keys/lookup` accepts, so it cannot enumerate the pool.
+const KEY_LOOKUP_MIN_CHARS: usize = 12;
+
+#[derive(Debug, Deserialize)]
+struct KeyLookupRequest {
+    secret: String,
 }
 
 #[derive(Debug, Serialize)]
-#[serde(rename_all = "camelCase")]
-struct JobLogView {
-    id: i64,
-    job_type: String,
-    key_id: Option<String>,
+struct KeyLookupMatchView {
+    id: String,
     status: String,
-    attempt: i64,
-    message: Option<String>,
-    started_at: i64,
-    finished_at: Option<i64>,
+    exact: bool,
 }
 
 #[derive(Debug, Serialize)]
-struct SummaryView {
-    total_requests: i64,
-    success_count: i64,
-    error_count: i64,
-    quota_exhausted_count: i64,
-    active_keys: i64,
-    exhausted_keys: i64,
-    last_activity: Option<i64>,
-    total_quota_limit: i64,
-    total_quota_remaining: i64,
+struct KeyLookupResponse {
+    matches: Vec<KeyLookupMatchView>,
 }
 
-#[derive(Debug, Serialize)]
-#[serde(rename_all = "camelCase")]
-struct PublicMetricsView {
-    monthly_success: i64,
-    daily_success: i64,
+#[utoipa::path(
+    post,
+    path = "/api/keys/lookup",
+    tag = "keys",
+    summary = "Find the key a pasted secret belongs to.",
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn lookup_api_keys(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(payload): Json<KeyLookupRequest>,
+) -> Result<Json<KeyLookupResponse>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if payload.secret.trim().chars().count() < KEY_LOOKUP_MIN_CHARS {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    match state.proxy.lookup_api_keys_by_secret(&payload.secret).await {
+        Ok(matches) => Ok(Json(KeyLookupResponse {
+            matches: matches
+                .into_iter()
+                .map(|m| KeyLookupMatchView {
+                    id: m.key_id,
+                    status: m.status,
+                    exact: m.exact,
+                })
+                .collect(),
+        })),
+        Err(err) => {
+            tracing::error!("key lookup error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Shortest token id fragment `/api/tokens/lookup` accepts, so it cannot list every token.
+const TOKEN_LOOKUP_MIN_ID_CHARS: usize = 2;
+
+#[derive(Debug, Deserialize)]
+struct TokenLookupQuery {
+    prefix: String,
 }
 
-// ---- Access Token views ----
 #[derive(Debug, Serialize)]
-struct AuthTokenView {
+struct TokenLookupMatchView {
     id: String,
-    enabled: bool,
     note: Option<String>,
     group: Option<String>,
-    total_requests: i64,
-    created_at: i64,
-    last_used_at: Option<i64>,
-    quota_state: String,
-    quota_hourly_used: i64,
-    quota_hourly_limit: i64,
-    quota_daily_used: i64,
-    quota_daily_limit: i64,
-    quota_monthly_used: i64,
-    quota_monthly_limit: i64,
-    quota_hourly_reset_at: Option<i64>,
-    quota_daily_reset_at: Option<i64>,
-    quota_monthly_reset_at: Option<i64>,
+    enabled: bool,
+    deleted: bool,
+    secret_match: Option<&'static str>,
 }
 
-impl From<AuthToken> for AuthTokenView {
-    fn from(t: AuthToken) -> Self {
-        let (
-            quota_state,
-            quota_hourly_used,
-            quota_hourly_limit,
-            quota_daily_used,
-            quota_daily_limit,
-            quota_monthly_used,
-            quota_monthly_limit,
-        ) = if let Some(quota) = t.quota {
-            (
-                quota.state_key().to_string(),
-                quota.hourly_used,
-                quota.hourly_limit,
-                quota.daily_used,
-                quota.daily_limit,
-                quota.monthly_used,
-                quota.monthly_limit,
-            )
-        } else {
-            (
-                "normal".to_string(),
-                0,
-                effective_token_hourly_limit(),
-                0,
-                effective_token_daily_limit(),
-                0,
-                effective_token_monthly_limit(),
-            )
-        };
-        Self {
-            id: t.id,
-            enabled: t.enabled,
-            note: t.note,
-            group: t.group_name,
-            total_requests: t.total_requests,
-            created_at: t.created_at,
-            last_used_at: t.last_used_at,
-            quota_state,
-            quota_hourly_used,
-            quota_hourly_limit,
-            quota_daily_used,
-            quota_daily_limit,
-            quota_monthly_used,
-            quota_monthly_limit,
-            quota_hourly_reset_at: t.quota_hourly_reset_at,
-            quota_daily_reset_at: t.quota_daily_reset_at,
-            quota_monthly_reset_at: t.quota_monthly_reset_at,
+#[derive(Debug, Serialize)]
+struct TokenLookupResponse {
+    matches: Vec<TokenLookupMatchView>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/tokens/lookup",
+    tag = "tokens",
+    summary = "Find the tokens a partial token value may belong to.",
+    params(("prefix" = Option<String>, Query)),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn lookup_tokens(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(q): Query<TokenLookupQuery>,
+) -> Result<Json<TokenLookupResponse>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let prefix = q.prefix.trim();
+    let id_part = prefix.strip_prefix("th-").unwrap_or(prefix);
+    let id_part = id_part.split('-').next().unwrap_or_default();
+    if id_part.chars().count() < TOKEN_LOOKUP_MIN_ID_CHARS {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    match state.proxy.lookup_access_tokens_by_prefix(prefix).await {
+        Ok(matches) => Ok(Json(TokenLookupResponse {
+            matches: matches
+                .into_iter()
+                .map(|m| TokenLookupMatchView {
+                    id: m.token_id,
+                    note: m.note,
+                    group: m.group,
+                    enabled: m.enabled,
+                    deleted: m.deleted,
+                    secret_match: m.secret_match,
+                })
+                .collect(),
+        })),
+        Err(err) => {
+            tracing::error!("token lookup error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
 }
 
-#[derive(Debug, Serialize)]
-struct AuthTokenSecretView {
-    token: String,
-}
+#[utoipa::path(
+    post,
+    path = "/api/keys/batch",
+    tag = "keys",
+    summary = "Add or restore many keys.",
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn create_api_keys_batch(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(payload): Json<BatchCreateKeysRequest>,
+) -> Result<Response<Body>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
 
-// ---- Token Detail views ----
-#[derive(Debug, Serialize)]
-struct TokenSummaryView {
-    total_requests: i64,
-    success_count: i64,
-    error_count: i64,
-    quota_exhausted_count: i64,
-    last_activity: Option<i64>,
-}
+    let mut summary = BatchCreateKeysSummary::default();
+    let mut trimmed = Vec::<String>::with_capacity(payload.api_keys.len());
+    for api_key in payload.api_keys {
+        let api_key = api_key.trim();
+        if api_key.is_empty() {
+            summary.ignored_empty += 1;
+            continue;
+        }
+        trimmed.push(api_key.to_string());
+    }
 
-impl From<TokenSummary> for TokenSummaryView {
-    fn from(s: TokenSummary) -> Self {
-        Self {
-            total_requests: s.total_requests,
-            success_count: s.success_count,
-            error_count: s.error_count,
-            quota_exhausted_count: s.quota_exhausted_count,
-            last_activity: s.last_activity,
+    if trimmed.len() > API_KEYS_BATCH_LIMIT {
+        let body = Json(json!({
+            "error": "too_many_items",
+            "detail": format!("api_keys exceeds limit (max {})", API_KEYS_BATCH_LIMIT),
+        }));
+        return Ok((StatusCode::BAD_REQUEST, body).into_response());
+    }
+
+    let mut results = Vec::with_capacity(trimmed.len());
+    let mut seen = HashSet::<String>::new();
+
+    for api_key in trimmed {
+        if !seen.insert(api_key.clone()) {
+            summary.duplicate_in_input += 1;
+            results.push(BatchCreateKeysResult {
+                api_key,
+                status: "duplicate_in_input".to_string(),
+                id: None,
+                error: None,
+            });
+            continue;
+        }
+
+        match state.proxy.add_or_undelete_key_with_status(&api_key).await {
+            Ok((id, status)) => {
+                match status.as_str() {
+                    "created" => summary.created += 1,
+                    "undeleted" => summary.undeleted += 1,
+                    "existed" => summary.existed += 1,
+                    _ => {}
+                }
+                results.push(BatchCreateKeysResult {
+                    api_key,
+                    status: status.as_str().to_string(),
+                    id: Some(id),
+                    error: None,
+                });
+            }
+            Err(err) => {
+                summary.failed += 1;
+                results.push(BatchCreateKeysResult {
+                    api_key,
+                    status: "failed".to_string(),
+                    id: None,
+                    error: Some(err.to_string()),
+                });
+            }
         }
     }
+
+    Ok((
+        StatusCode::OK,
+        Json(BatchCreateKeysResponse { summary, results }),
+    )
+        .into_response())
 }
 
-#[derive(Debug, Serialize)]
-struct TokenLogView {
-    id: i64,
-    method: String,
-    path: String,
-    query: Option<String>,
-    http_status: Option<i64>,
-    mcp_status: Option<i64>,
-    result_status: String,
-    error_message: Option<String>,
-    created_at: i64,
+#[utoipa::path(
+    delete,
+    path = "/api/keys/{id}",
+    tag = "keys",
+    summary = "Soft-delete a key.",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn delete_api_key(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<StatusCode, StatusCode> {
+    let scope = manage_scope(&state, &headers)?;
+    ensure_key_in_scope(&state, &scope, &id).await?;
+
+    match state.proxy.soft_delete_key_by_id(&id).await {
+        Ok(()) => Ok(StatusCode::NO_CONTENT),
+        Err(err) => {
+            tracing::error!("delete api key error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
 }
 
-impl From<TokenLogRecord> for TokenLogView {
-    fn from(r: TokenLogRecord) -> Self {
-        Self {
-            id: r.id,
-            method: r.method,
-            path: r.path,
-            query: r.query,
-            http_status: r.http_status,
-            mcp_status: r.mcp_status,
-            result_status: r.result_status,
-            error_message: r.error_message,
-            created_at: r.created_at,
+#[utoipa::path(
+    post,
+    path = "/api/keys/{id}/restore",
+    tag = "keys",
+    summary = "Undo a key's soft delete.",
+    params(("id" = String, Path)),
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn restore_api_key(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<StatusCode, StatusCode> {
+    let scope = manage_scope(&state, &headers)?;
+    ensure_key_in_scope(&state, &scope, &id).await?;
+
+    match state.proxy.restore_key_by_id(&id).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            tracing::error!("restore api key error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
 }
 
 #[derive(Debug, Deserialize)]
-struct CreateTokenRequest {
-    note: Option<String>,
+struct UpdateKeyStatus {
+    status: String,
 }
 
-#[derive(Debug, Deserialize)]
-struct LogsQuery {
-    page: Option<i64>,
-    per_page: Option<i64>,
-    result: Option<String>,
+#[utoipa::path(
+    patch,
+    path = "/api/keys/{id}/status",
+    tag = "keys",
+    summary = "Enable or disable a key.",
+    params(("id" = String, Path)),
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn update_api_key_status(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<UpdateKeyStatus>,
+) -> Result<StatusCode, StatusCode> {
+    let scope = manage_scope(&state, &headers)?;
+    ensure_key_in_scope(&state, &scope, &id).await?;
+
+    let status = payload.status.trim().to_ascii_lowercase();
+    match status.as_str() {
+        "disabled" => match state.proxy.disable_key_by_id(&id).await {
+            Ok(()) => Ok(StatusCode::NO_CONTENT),
+            Err(err) => {
+                tracing::error!("disable api key error: {err}");
+                Err(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        },
+        "active" => match state.proxy.enable_key_by_id(&id).await {
+            Ok(()) => Ok(StatusCode::NO_CONTENT),
+            Err(err) => {
+                tracing::error!("enable api key error: {err}");
+                Err(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        },
+        _ => Err(StatusCode::BAD_REQUEST),
+    }
 }
 
-#[derive(Debug, Deserialize)]
-struct KeyMetricsQuery {
-    period: Option<String>,
-    since: Option<i64>,
+#[derive(Debug, Deserialize, Serialize)]
+struct KeyTagsPayload {
+    tags: Vec<String>,
 }
 
-async fn get_key_metrics(
+#[derive(Debug, Serialize)]
+struct KeyTagCountView {
+    tag: String,
+    keys: i64,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/keys/tags",
+    tag = "keys",
+    summary = "Tags in use with their key counts.",
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn list_key_tags(
     State(state): State<Arc<AppState>>,
     headers: HeaderMap,
+) -> Result<Json<Vec<KeyTagCountView>>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    match state.proxy.key_tag_counts().await {
+        Ok(tags) => Ok(Json(
+            tags.into_iter()
+                .map(|t| KeyTagCountView {
+                    tag: t.tag,
+                    keys: t.keys,
+                })
+                .collect(),
+        )),
+        Err(err) => {
+            tracing::error!("list key tags error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/keys/{id}/tags",
+    tag = "keys",
+    summary = "Tags of a key.",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_api_key_tags(
+    State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
-    Query(q): Query<KeyMetricsQuery>,
-) -> Result<Json<SummaryView>, StatusCode> {
+    headers: HeaderMap,
+) -> Result<Json<KeyTagsPayload>, StatusCode> {
     if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
         return Err(StatusCode::FORBIDDEN);
     }
-    let since = if let Some(since) = q.since {
-        since
-    } else {
-        // fallback by period
-        let now = chrono::Local::now();
-        let local_midnight_ts = |date: chrono::NaiveDate| -> i64 {
-            let naive = date.and_hms_opt(0, 0, 0).expect("valid midnight");
-            match chrono::Local.from_local_datetime(&naive) {
-                chrono::LocalResult::Single(dt) => dt.with_timezone(&Utc).timestamp(),
-                chrono::LocalResult::Ambiguous(dt, _) => dt.with_timezone(&Utc).timestamp(),
-                chrono::LocalResult::None => now.with_timezone(&Utc).timestamp(),
-            }
-        };
-        match q.period.as_deref() {
-            Some("day") => local_midnight_ts(now.date_naive()),
-            Some("week") => {
-                let weekday = now.weekday().num_days_from_monday() as i64;
-                let start = (now - chrono::Duration::days(weekday)).date_naive();
-                local_midnight_ts(start)
-            }
-            _ => {
-                // month default
-                let first =
-                    chrono::NaiveDate::from_ymd_opt(now.year(), now.month(), 1).expect("valid");
-                local_midnight_ts(first)
-            }
+    match state.proxy.api_key_tags(&id).await {
+        Ok(tags) => Ok(Json(KeyTagsPayload { tags })),
+        Err(err) => {
+            tracing::error!("get api key tags error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
-    };
-
-    state
-        .proxy
-        .key_summary_since(&id, since)
-        .await
-        .map(|s| Json(s.into()))
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
-}
-
-#[derive(Debug, Deserialize)]
-struct KeyLogsQuery {
-    limit: Option<usize>,
-    since: Option<i64>,
+    }
 }
 
-async fn get_key_logs(
+#[utoipa::path(
+    put,
+    path = "/api/keys/{id}/tags",
+    tag = "keys",
+    summary = "Replace the tags of a key.",
+    params(("id" = String, Path)),
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn put_api_key_tags(
     State(state): State<Arc<AppState>>,
-    headers: HeaderMap,
     Path(id): Path<String>,
-    Query(q): Query<KeyLogsQuery>,
-) -> Result<Json<Vec<RequestLogView>>, StatusCode> {
+    headers: HeaderMap,
+    Json(payload): Json<KeyTagsPayload>,
+) -> Result<StatusCode, StatusCode> {
     if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
         return Err(StatusCode::FORBIDDEN);
     }
-    let limit = q.limit.unwrap_or(DEFAULT_LOG_LIMIT).clamp(1, 500);
-    state
-        .proxy
-        .key_recent_logs(&id, limit, q.since)
-        .await
-        .map(|logs| Json(logs.into_iter().map(RequestLogView::from).collect()))
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+    match state.proxy.set_api_key_tags(&id, &payload.tags).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            tracing::error!("set api key tags error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
 }
 
-// ---- Token detail endpoints ----
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct KeyStatusChangeView {
+    id: i64,
+    from_status: Option<String>,
+    to_status: String,
+    reason: String,
+    detail: Option<String>,
+    created_at: i64,
+}
 
 #[derive(Debug, Deserialize)]
-struct TokenMetricsQuery {
-    period: Option<String>,
-    since: Option<String>,
-    until: Option<String>,
+struct KeyStatusHistoryQuery {
+    limit: Option<i64>,
 }
 
-async fn get_token_metrics(
+#[utoipa::path(
+    get,
+    path = "/api/keys/{id}/status-history",
+    tag = "keys",
+    summary = "Status changes of a key.",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_api_key_status_history(
     State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
+    Query(q): Query<KeyStatusHistoryQuery>,
     headers: HeaderMap,
-    Query(q): Query<TokenMetricsQuery>,
-) -> Result<Json<TokenSummaryView>, StatusCode> {
+) -> Result<Json<Vec<KeyStatusChangeView>>, StatusCode> {
     if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
         return Err(StatusCode::FORBIDDEN);
     }
-    let since = q
-        .since
-        .as_deref()
-        .and_then(parse_iso_timestamp)
-        .unwrap_or_else(|| default_since(q.period.as_deref()));
-    let until = q
-        .until
-        .as_deref()
-        .and_then(parse_iso_timestamp)
-        .unwrap_or_else(|| default_until(q.period.as_deref(), since));
-
-    state
+    match state
         .proxy
-        .token_summary_since(&id, since, Some(until))
+        .key_status_history(&id, q.limit.unwrap_or(100))
         .await
-        .map(|s| Json(s.into()))
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+    {
+        Ok(changes) => Ok(Json(
+            changes
+                .into_iter()
+                .map(|c| KeyStatusChangeView {
+                    id: c.id,
+                    from_status: c.from_status,
+                    to_status: c.to_status,
+                    reason: c.reason,
+                    detail: c.detail,
+                    created_at: c.created_at,
+                })
+                .collect(),
+        )),
+        Err(err) => {
+            tracing::error!("key status history error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
 }
 
-#[derive(Debug, Deserialize)]
-struct TokenLogsQuery {
-    limit: Option<usize>,
-    before: Option<i64>,
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WebhookDeliveryView {
+    id: i64,
+    event: String,
+    url: String,
+    payload: Value,
+    status: String,
+    attempts: i64,
+    http_status: Option<i64>,
+    last_error: Option<String>,
+    created_at: i64,
+    updated_at: i64,
+}
+
+impl From<WebhookDelivery> for WebhookDeliveryView {
+    fn from(d: WebhookDelivery) -> Self {
+        Self {
+            id: d.id,
+            event: d.event,
+            url: d.url,
+            payload: serde_json::from_str(&d.payload).unwrap_or(Value::String(d.payload)),
+            status: d.status,
+            attempts: d.attempts,
+            http_status: d.http_status,
+            last_error: d.last_error,
+            created_at: d.created_at,
+            updated_at: d.updated_at,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
-struct TokenHourlyQuery {
-    hours: Option<i64>,
+struct WebhookDeliveriesQuery {
+    limit: Option<i64>,
 }
 
-async fn get_token_logs(
+#[utoipa::path(
+    get,
+    path = "/api/webhooks/deliveries",
+    tag = "admin",
+    summary = "Webhook delivery attempts.",
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn list_webhook_deliveries(
+    State(state): State<Arc<AppState>>,
+    Query(q): Query<WebhookDeliveriesQuery>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<WebhookDeliveryView>>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    match state.proxy.webhook_deliveries(q.limit.unwrap_or(50)).await {
+        Ok(deliveries) => Ok(Json(
+            deliveries
+                .into_iter()
+                .map(WebhookDeliveryView::from)
+                .collect(),
+        )),
+        Err(err) => {
+            tracing::error!("webhook deliveries error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct KeyErrorDigestView {
+    status_code: Option<i64>,
+    tavily_status_code: Option<i64>,
+    result_status: String,
+    message: Option<String>,
+    count: i64,
+    first_seen: i64,
+    last_seen: i64,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/keys/{id}/errors",
+    tag = "keys",
+    summary = "Recent distinct errors of a key.",
+    params(("id" = String, Path), ("limit" = Option<String>, Query)),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_api_key_errors(
     State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
+    Query(q): Query<KeyStatusHistoryQuery>,
     headers: HeaderMap,
-    Query(q): Query<TokenLogsQuery>,
-) -> Result<Json<Vec<TokenLogView>>, StatusCode> {
+) -> Result<Json<Vec<KeyErrorDigestView>>, StatusCode> {
     if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
         return Err(StatusCode::FORBIDDEN);
     }
-    let limit = q.limit.unwrap_or(DEFAULT_LOG_LIMIT).clamp(1, 500);
-    state
+    match state
         .proxy
-        .token_recent_logs(&id, limit, q.before)
+        .key_error_digest(&id, q.limit.unwrap_or(20))
         .await
-        .map(|logs| {
-            let mapped: Vec<TokenLogView> = logs
+    {
+        Ok(errors) => Ok(Json(
+            errors
                 .into_iter()
-                .map(TokenLogView::from)
-                .map(|mut v| {
-                    if let Some(err) = v.error_message.as_ref() {
-                        v.error_message = Some(redact_sensitive(err));
-                    }
-                    v
+                .map(|e| KeyErrorDigestView {
+                    status_code: e.status_code,
+                    tavily_status_code: e.tavily_status_code,
+                    result_status: e.result_status,
+                    message: e.message,
+                    count: e.count,
+                    first_seen: e.first_seen,
+                    last_seen: e.last_seen,
                 })
-                .collect();
-            Json(mapped)
-        })
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+                .collect(),
+        )),
+        Err(err) => {
+            tracing::error!("key error digest error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
 }
 
-#[derive(Debug, Deserialize)]
-struct TokenLogsPageQuery {
-    page: Option<usize>,
-    per_page: Option<usize>,
-    since: Option<String>,
-    until: Option<String>,
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct KeySpendForecastView {
+    key_id: String,
+    quota_limit: i64,
+    quota_remaining: i64,
+    daily_burn: f64,
+    projected_month_usage: i64,
+    projected_overage: i64,
 }
 
 #[derive(Debug, Serialize)]
-struct TokenLogsPageView {
-    items: Vec<TokenLogView>,
-    page: usize,
-    per_page: usize,
-    total: i64,
+#[serde(rename_all = "camelCase")]
+struct KeysForecastResponse {
+    generated_at: i64,
+    keys: Vec<KeySpendForecastView>,
+    pool: PoolDepletionView,
 }
 
 #[derive(Debug, Serialize)]
-struct TokenHourlyBucketView {
-    bucket_start: i64,
-    success_count: i64,
-    system_failure_count: i64,
-    external_failure_count: i64,
+#[serde(rename_all = "camelCase")]
+struct KeyExhaustionView {
+    key_id: String,
+    status: String,
+    quota_remaining: Option<i64>,
+    requests_per_hour: f64,
+    exhausts_at: Option<i64>,
 }
 
 #[derive(Debug, Serialize)]
-struct TokenUsageBucketView {
-    bucket_start: i64,
-    success_count: i64,
-    system_failure_count: i64,
-    external_failure_count: i64,
+#[serde(rename_all = "camelCase")]
+struct PoolDepletionView {
+    window_secs: i64,
+    quota_remaining: i64,
+    requests_per_hour: f64,
+    depletes_at: Option<i64>,
+    unknown_quota_keys: i64,
+    keys: Vec<KeyExhaustionView>,
 }
 
-#[derive(Debug, Deserialize)]
-struct TokenLeaderboardQuery {
-    period: Option<String>,
-    focus: Option<String>,
+impl From<PoolDepletionForecast> for PoolDepletionView {
+    fn from(f: PoolDepletionForecast) -> Self {
+        Self {
+            window_secs: f.window_secs,
+            quota_remaining: f.quota_remaining,
+            requests_per_hour: f.requests_per_hour,
+            depletes_at: f.depletes_at,
+            unknown_quota_keys: f.unknown_quota_keys,
+            keys: f
+                .keys
+                .into_iter()
+                .map(|k| KeyExhaustionView {
+                    key_id: k.key_id,
+                    status: k.status,
+                    quota_remaining: k.quota_remaining,
+                    requests_per_hour: k.requests_per_hour,
+                    exhausts_at: k.exhausts_at,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/keys/forecast",
+    tag = "keys",
+    summary = "Projected month-end usage per key and key/pool exhaustion times.",
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_api_keys_forecast(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Json<KeysForecastResponse>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let forecasts = match state.proxy.key_spend_forecasts().await {
+        Ok(forecasts) => state
+            .proxy
+            .key_exhaustion_forecast()
+            .await
+            .map(|pool| (forecasts, pool)),
+        Err(err) => Err(err),
+    };
+    match forecasts {
+        Ok((forecasts, pool)) => Ok(Json(KeysForecastResponse {
+            generated_at: Utc::now().timestamp(),
+            keys: forecasts
+                .into_iter()
+                .map(|f| KeySpendForecastView {
+                    key_id: f.key_id,
+                    quota_limit: f.quota_limit,
+                    quota_remaining: f.quota_remaining,
+                    daily_burn: f.daily_burn,
+                    projected_month_usage: f.projected_month_usage,
+                    projected_overage: f.projected_overage,
+                })
+                .collect(),
+            pool: pool.into(),
+        })),
+        Err(err) => {
+            tracing::error!("key spend forecast error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
-struct TokenLeaderboardItemView {
+struct DrainKeyResponse {
     id: String,
-    enabled: bool,
-    note: Option<String>,
-    group: Option<String>,
-    total_requests: i64,
-    last_used_at: Option<i64>,
-    quota_state: String,
-    // Business quota windows (tools/call)
-    quota_hourly_used: i64,
-    quota_hourly_limit: i64,
-    quota_daily_used: i64,
-    quota_daily_limit: i64,
-    // Hourly raw request limiter (any authenticated request)
-    hourly_any_used: i64,
-    hourly_any_limit: i64,
-    today_total: i64,
-    today_errors: i64,
-    today_other: i64,
-    month_total: i64,
-    month_errors: i64,
-    month_other: i64,
-    all_total: i64,
-    all_errors: i64,
-    all_other: i64,
+    status: String,
 }
 
-async fn get_token_logs_page(
+#[utoipa::path(
+    post,
+    path = "/api/keys/{id}/drain",
+    tag = "keys",
+    summary = "Stop leasing a key, then disable it.",
+    params(("id" = String, Path)),
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn drain_api_key(
     State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
     headers: HeaderMap,
-    Query(q): Query<TokenLogsPageQuery>,
-) -> Result<Json<TokenLogsPageView>, StatusCode> {
+) -> Result<Json<DrainKeyResponse>, StatusCode> {
     if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
         return Err(StatusCode::FORBIDDEN);
     }
-    let page = q.page.unwrap_or(1).max(1);
-    let per_page = q.per_page.unwrap_or(20).clamp(1, 200);
-    let since = q
-        .since
-        .as_deref()
-        .and_then(parse_iso_timestamp)
-        .unwrap_or_else(|| default_since(Some("month")));
-    let until = q
-        .until
-        .as_deref()
-        .and_then(parse_iso_timestamp)
-        .unwrap_or_else(|| default_until(Some("month"), since));
-    if until <= since {
-        return Err(StatusCode::BAD_REQUEST);
+
+    match state.proxy.drain_key_by_id(&id).await {
+        Ok(Some(status)) => Ok(Json(DrainKeyResponse { id, status })),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            tracing::error!("drain api key error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
     }
-    state
-        .proxy
-        .token_logs_page(&id, page, per_page, since, Some(until))
-        .await
-        .map(|(items, total)| {
-            let mapped: Vec<TokenLogView> = items
-                .into_iter()
-                .map(TokenLogView::from)
-                .map(|mut v| {
-                    if let Some(err) = v.error_message.as_ref() {
-                        v.error_message = Some(redact_sensitive(err));
-                    }
-                    v
-                })
-                .collect();
-            Json(TokenLogsPageView {
-                items: mapped,
-                page,
-                per_page,
-                total,
-            })
-        })
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
 }
 
-async fn get_token_hourly_breakdown(
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct QuotaDriftView {
+    token_id: String,
+    monthly_before: i64,
+    monthly_after: i64,
+    minute_buckets_before: i64,
+    minute_buckets_after: i64,
+    hour_buckets_before: i64,
+    hour_buckets_after: i64,
+}
+
+impl From<QuotaDrift> for QuotaDriftView {
+    fn from(d: QuotaDrift) -> Self {
+        Self {
+            token_id: d.token_id,
+            monthly_before: d.monthly_before,
+            monthly_after: d.monthly_after,
+            minute_buckets_before: d.minute_buckets_before,
+            minute_buckets_after: d.minute_buckets_after,
+            hour_buckets_before: d.hour_buckets_before,
+            hour_buckets_after: d.hour_buckets_after,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct QuotaReconcileView {
+    job_id: i64,
+    tokens_corrected: usize,
+    drifts: Vec<QuotaDriftView>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/admin/reconcile-quota",
+    tag = "admin",
+    summary = "Rebuild token quota counters.",
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn post_reconcile_quota(
     State(state): State<Arc<AppState>>,
-    Path(id): Path<String>,
     headers: HeaderMap,
-    Query(q): Query<TokenHourlyQuery>,
-) -> Result<Json<Vec<TokenHourlyBucketView>>, StatusCode> {
+) -> Result<Json<QuotaReconcileView>, StatusCode> {
     if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
         return Err(StatusCode::FORBIDDEN);
     }
-    let hours = q.hours.unwrap_or(25);
-    state
+    let job_id = state
         .proxy
-        .token_hourly_breakdown(&id, hours)
+        .scheduled_job_start("quota_reconcile/manual", None, 1)
         .await
-        .map(|buckets| {
-            Json(
-                buckets
-                    .into_iter()
-                    .map(
-                        |TokenHourlyBucket {
-                             bucket_start,
-                             success_count,
-                             system_failure_count,
-                             external_failure_count,
-                         }| TokenHourlyBucketView {
-                            bucket_start,
-                            success_count,
-                            system_failure_count,
-                            external_failure_count,
-                        },
-                    )
-                    .collect(),
-            )
-        })
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    match state.proxy.reconcile_token_quota().await {
+        Ok(drifts) => {
+            let msg = quota_drift_summary(&drifts);
+            let _ = state
+                .proxy
+                .scheduled_job_finish(job_id, "success", Some(&msg))
+                .await;
+            Ok(Json(QuotaReconcileView {
+                job_id,
+                tokens_corrected: drifts.len(),
+                drifts: drifts.into_iter().map(QuotaDriftView::from).collect(),
+            }))
+        }
+        Err(err) => {
+            tracing::error!("reconcile quota error: {err}");
+            let _ = state
+                .proxy
+                .scheduled_job_finish(job_id, "error", Some(&err.to_string()))
+                .await;
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DatabaseBackupView {
+    job_id: i64,
+    file: String,
+    bytes: i64,
+    created_at: i64,
+    removed: usize,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/admin/backup",
+    tag = "admin",
+    summary = "Snapshot the database into the backup directory.",
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn post_database_backup(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Json<DatabaseBackupView>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let job_id = state
+        .proxy
+        .scheduled_job_start("database_backup/manual", None, 1)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    match state
+        .proxy
+        .backup_database(effective_backup_keep() as usize)
+        .await
+    {
+        Ok(backup) => {
+            let msg = backup_job_summary(&backup);
+            let _ = state
+                .proxy
+                .scheduled_job_finish(job_id, "success", Some(&msg))
+                .await;
+            Ok(Json(DatabaseBackupView {
+                job_id,
+                file: backup.file,
+                bytes: backup.bytes,
+                created_at: backup.created_at,
+                removed: backup.removed,
+            }))
+        }
+        Err(err) => {
+            tracing::error!("database backup error: {err}");
+            let _ = state
+                .proxy
+                .scheduled_job_finish(job_id, "error", Some(&err.to_string()))
+                .await;
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
-struct UsageSeriesQuery {
-    since: Option<String>,
-    until: Option<String>,
-    bucket_secs: Option<i64>,
+struct DatabaseRestoreRequest {
+    file: String,
 }
 
-async fn get_token_usage_series(
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DatabaseRestoreView {
+    file: String,
+    schema_version: i64,
+    duration_ms: i64,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/admin/restore",
+    tag = "debug",
+    summary = "Restore a database snapshot (DEV_OPEN_ADMIN only).",
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn post_database_restore(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<DatabaseRestoreRequest>,
+) -> Result<Json<DatabaseRestoreView>, (StatusCode, String)> {
+    if !state.dev_open_admin {
+        return Err((StatusCode::NOT_FOUND, String::new()));
+    }
+    let file = payload.file.trim();
+    let Some(path) = state.proxy.backup_path(file) else {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("invalid backup file name '{file}'"),
+        ));
+    };
+    if !path.is_file() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!("backup file '{file}' not found"),
+        ));
+    }
+    match state.proxy.restore_database(file).await {
+        Ok(restore) => {
+            tracing::warn!("database restored from backup {}", restore.file);
+            Ok(Json(DatabaseRestoreView {
+                file: restore.file,
+                schema_version: restore.schema_version,
+                duration_ms: restore.duration_ms,
+            }))
+        }
+        Err(ProxyError::Other(message)) => Err((StatusCode::UNPROCESSABLE_ENTITY, message)),
+        Err(err) => {
+            tracing::error!("database restore error: {err}");
+            Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RuntimeConfigView {
+    changed: usize,
+    loaded_at: i64,
+    token_hourly_limit: i64,
+    token_daily_limit: i64,
+    token_monthly_limit: i64,
+    token_hourly_request_limit: i64,
+    request_logs_retention_days: i64,
+    request_log_bodies: bool,
+    header_policy_source: Option<String>,
+    outcome_rules_source: Option<String>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/admin/reload-config",
+    tag = "admin",
+    summary = "Reload runtime settings.",
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn post_reload_config(
     State(state): State<Arc<AppState>>,
-    Path(id): Path<String>,
     headers: HeaderMap,
-    Query(q): Query<UsageSeriesQuery>,
-) -> Result<Json<Vec<TokenUsageBucketView>>, StatusCode> {
+) -> Result<Json<RuntimeConfigView>, StatusCode> {
     if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
         return Err(StatusCode::FORBIDDEN);
     }
-    let now = Utc::now().timestamp();
-    let until = q
-        .until
-        .as_deref()
-        .and_then(parse_iso_timestamp)
-        .unwrap_or(now);
-    let default_since = until - ChronoDuration::hours(25).num_seconds();
-    let since = q
-        .since
-        .as_deref()
-        .and_then(parse_iso_timestamp)
-        .unwrap_or(default_since);
-    if until <= since {
-        return Err(StatusCode::BAD_REQUEST);
+    let changed = reload_runtime_config(&state, "api").await.map_err(|err| {
+        tracing::error!("config reload error: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let config = state.proxy.runtime_config();
+    Ok(Json(RuntimeConfigView {
+        changed,
+        loaded_at: config.loaded_at,
+        token_hourly_limit: config.token_hourly_limit,
+        token_daily_limit: config.token_daily_limit,
+        token_monthly_limit: config.token_monthly_limit,
+        token_hourly_request_limit: config.token_hourly_request_limit,
+        request_logs_retention_days: config.request_logs_retention_days,
+        request_log_bodies: config.request_log_bodies,
+        header_policy_source: config.header_policy.source.clone(),
+        outcome_rules_source: config.outcome_rules.source.clone(),
+    }))
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MaintenanceView {
+    enabled: bool,
+    since: Option<i64>,
+    until: Option<i64>,
+    message: Option<String>,
+    retry_after_secs: Option<i64>,
+}
+
+impl From<Option<MaintenanceMode>> for MaintenanceView {
+    fn from(mode: Option<MaintenanceMode>) -> Self {
+        let retry_after_secs = mode
+            .as_ref()
+            .map(|mode| mode.retry_after_secs(Utc::now().timestamp()));
+        Self {
+            enabled: mode.is_some(),
+            since: mode.as_ref().map(|mode| mode.since),
+            until: mode.as_ref().and_then(|mode| mode.until),
+            message: mode.and_then(|mode| mode.message),
+            retry_after_secs,
+        }
     }
-    let bucket_secs = q
-        .bucket_secs
-        .unwrap_or(ChronoDuration::hours(1).num_seconds());
-    state
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MaintenanceRequest {
+    enabled: bool,
+    duration_secs: Option<i64>,
+    message: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/maintenance",
+    tag = "admin",
+    summary = "Maintenance mode state.",
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_maintenance(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Json<MaintenanceView>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(Json(MaintenanceView::from(state.proxy.maintenance())))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/admin/maintenance",
+    tag = "admin",
+    summary = "Turn the maintenance mode on or off.",
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn post_maintenance(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(payload): Json<MaintenanceRequest>,
+) -> Result<Json<MaintenanceView>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    match state
         .proxy
-        .token_usage_series(&id, since, until, bucket_secs)
+        .set_maintenance(payload.enabled, payload.duration_secs, payload.message)
         .await
-        .map(|series| {
-            Json(
-                series
-                    .into_iter()
-                    .map(
-                        |TokenUsageBucket {
-                             bucket_start,
-                             success_count,
-                             system_failure_count,
-                             external_failure_count,
-                         }| TokenUsageBucketView {
-                            bucket_start,
-                            success_count,
-                            system_failure_count,
-                            external_failure_count,
-                        },
-                    )
-                    .collect(),
-            )
-        })
-        .map_err(|err| match err {
-            ProxyError::Other(_) => StatusCode::BAD_REQUEST,
-            _ => StatusCode::INTERNAL_SERVER_ERROR,
-        })
+    {
+        Ok(mode) => {
+            match &mode {
+                Some(mode) => tracing::warn!(
+                    until = ?mode.until,
+                    "maintenance mode on: /mcp requests are refused"
+                ),
+                None => tracing::info!("maintenance mode off"),
+            }
+            Ok(Json(MaintenanceView::from(mode)))
+        }
+        Err(err) => {
+            tracing::error!("set maintenance error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
 }
 
-async fn get_token_leaderboard(
+#[utoipa::path(
+    get,
+    path = "/api/keys/{id}/secret",
+    tag = "keys",
+    summary = "Reveal the real Tavily key.",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_api_key_secret(
     State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
     headers: HeaderMap,
-    Query(q): Query<TokenLeaderboardQuery>,
-) -> Result<Json<Vec<TokenLeaderboardItemView>>, StatusCode> {
+) -> Result<Json<ApiKeySecretView>, StatusCode> {
     if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
         return Err(StatusCode::FORBIDDEN);
     }
 
-    let now = Utc::now();
-    let day_since = start_of_day_dt(now).timestamp();
-    let month_since = start_of_month_dt(now).timestamp();
+    match state.proxy.get_api_key_secret(&id).await {
+        Ok(Some(secret)) => Ok(Json(ApiKeySecretView { api_key: secret })),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            tracing::error!("fetch api key secret error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
 
-    let period = match q.period.as_deref() {
-        Some("day") | None => "day",
-        Some("month") => "month",
-        Some("all") => "all",
-        _ => return Err(StatusCode::BAD_REQUEST),
-    };
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PaginatedLogsView {
+    items: Vec<RequestLogView>,
+    total: i64,
+    page: i64,
+    per_page: i64,
+}
 
-    let focus = match q.focus.as_deref() {
-        Some("usage") | None => "usage",
-        Some("errors") => "errors",
-        Some("other") => "other",
-        _ => return Err(StatusCode::BAD_REQUEST),
-    };
+/// Keyset page returned when a `cursor` query parameter is present (an empty `cursor`
+/// starts from the newest row). `nextCursor` is null on the last page.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CursorPageView<T> {
+    items: Vec<T>,
+    next_cursor: Option<String>,
+    per_page: i64,
+}
+
+/// `Some(None)` = cursor mode from the start, `Some(Some(c))` = continue after `c`,
+/// `None` = legacy page/offset mode. Malformed cursors are rejected.
+fn parse_cursor_param(raw: Option<&str>) -> Result<Option<Option<LogCursor>>, StatusCode> {
+    match raw.map(str::trim) {
+        None => Ok(None),
+        Some("") => Ok(Some(None)),
+        Some(value) => LogCursor::decode(value)
+            .map(|c| Some(Some(c)))
+            .ok_or(StatusCode::BAD_REQUEST),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/logs",
+    tag = "logs",
+    summary = "Recent request logs.",
+    params(
+        ("cursor" = Option<String>, Query),
+        ("page" = Option<String>, Query),
+        ("per_page" = Option<String>, Query),
+        ("result" = Option<String>, Query),
+    ),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn list_logs(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(params): Query<LogsQuery>,
+) -> Result<Response<Body>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let page = params.page.unwrap_or(1).max(1);
+    let per_page = params.per_page.unwrap_or(20).clamp(1, 200);
+    let cursor = parse_cursor_param(params.cursor.as_deref())?;
+
+    // Optional result_status filter: normalize to known values.
+    let result_status: Option<&str> = match params.result.as_deref().map(str::trim) {
+        Some(v) if v.eq_ignore_ascii_case("success") => Some("success"),
+        Some(v) if v.eq_ignore_ascii_case("error") => Some("error"),
+        Some(v) if v.eq_ignore_ascii_case("quota_exhausted") || v.eq_ignore_ascii_case("quota") => {
+            Some("quota_exhausted")
+        }
+        _ => None,
+    };
+
+    if let Some(cursor) = cursor {
+        return state
+            .proxy
+            .request_logs_after(result_status, cursor, per_page)
+            .await
+            .map(|page| {
+                Json(CursorPageView {
+                    items: page.items.into_iter().map(RequestLogView::from).collect(),
+                    next_cursor: page.next_cursor.map(|c| c.encode()),
+                    per_page,
+                })
+                .into_response()
+            })
+            .map_err(|err| {
+                tracing::error!("list logs error: {err}");
+                StatusCode::INTERNAL_SERVER_ERROR
+            });
+    }
+
+    // Deprecated: OFFSET paging slows down on deep pages; kept for existing clients.
+    state
+        .proxy
+        .recent_request_logs_page(result_status, page, per_page)
+        .await
+        .map(|(logs, total)| {
+            let view_items = logs.into_iter().map(RequestLogView::from).collect();
+            Json(PaginatedLogsView {
+                items: view_items,
+                total,
+                page,
+                per_page,
+            })
+            .into_response()
+        })
+        .map_err(|err| {
+            tracing::error!("list logs error: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+/// Column order of `GET /api/logs/export?format=csv`.
+const LOG_EXPORT_CSV_COLUMNS: &[&str] = &[
+    "id",
+    "public_id",
+    "created_at",
+    "key_id",
+    "auth_token_id",
+    "method",
+    "path",
+    "query",
+    "http_status",
+    "mcp_status",
+    "result_status",
+    "error_message",
+    "attempt",
+    "timeout_ms",
+    "latency_ms",
+    "cache_hits",
+    "response_summary",
+    "request_body_sha256",
+    "request_body_len",
+    "response_body_sha256",
+    "response_body_len",
+    "forwarded_headers",
+    "dropped_headers",
+    "request_body",
+    "response_body",
+];
+
+fn csv_field(value: &Value) -> String {
+    let raw = match value {
+        Value::Null => return String::new(),
+        Value::String(s) => s.clone(),
+        Value::Array(items) => items
+            .iter()
+            .map(|item| {
+                item.as_str()
+                    .map(str::to_string)
+                    .unwrap_or_else(|| item.to_string())
+            })
+            .collect::<Vec<_>>()
+            .join(";"),
+        other => other.to_string(),
+    };
+    if raw.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", raw.replace('"', "\"\""))
+    } else {
+        raw
+    }
+}
+
+fn log_export_line(view: &RequestLogView, csv: bool) -> Result<String, serde_json::Error> {
+    let value = serde_json::to_value(view)?;
+    if !csv {
+        return Ok(format!("{value}\n"));
+    }
+    let mut line = LOG_EXPORT_CSV_COLUMNS
+        .iter()
+        .map(|column| csv_field(value.get(*column).unwrap_or(&Value::Null)))
+        .collect::<Vec<_>>()
+        .join(",");
+    line.push_str("\r\n");
+    Ok(line)
+}
+
+/// Streams request logs (bodies included) as CSV or JSON lines so they can be archived
+/// before the retention GC removes them.
+#[utoipa::path(
+    get,
+    path = "/api/logs/export",
+    tag = "logs",
+    summary = "Stream request logs as CSV or JSON lines.",
+    params(
+        ("format" = Option<String>, Query),
+        ("since" = Option<String>, Query),
+        ("until" = Option<String>, Query),
+    ),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn export_logs(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(params): Query<LogsExportQuery>,
+) -> Result<Response<Body>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let csv = match params.format.as_deref().map(str::trim) {
+        None | Some("") | Some("jsonl") => false,
+        Some("csv") => true,
+        Some(_) => return Err(StatusCode::BAD_REQUEST),
+    };
+    let parse_bound = |raw: Option<&str>, default: i64| match raw.map(str::trim) {
+        None | Some("") => Ok(default),
+        Some(value) => parse_iso_timestamp(value).ok_or(StatusCode::BAD_REQUEST),
+    };
+    let since = parse_bound(params.since.as_deref(), 0)?;
+    let until = parse_bound(params.until.as_deref(), Utc::now().timestamp() + 1)?;
+    if since >= until {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let mut records = state.proxy.export_request_logs(since, until);
+    let body = stream! {
+        if csv {
+            let mut header = LOG_EXPORT_CSV_COLUMNS.join(",");
+            header.push_str("\r\n");
+            yield Ok::<_, std::io::Error>(bytes::Bytes::from(header));
+        }
+        while let Some(item) = records.next().await {
+            let line = item
+                .map_err(|err| err.to_string())
+                .and_then(|record| {
+                    log_export_line(&RequestLogView::from(record), csv).map_err(|err| err.to_string())
+                });
+            match line {
+                Ok(line) => yield Ok(bytes::Bytes::from(line)),
+                Err(err) => {
+                    tracing::error!("export logs error: {err}");
+                    yield Err(std::io::Error::other(err));
+                    break;
+                }
+            }
+        }
+    };
+
+    let (content_type, extension) = if csv {
+        ("text/csv; charset=utf-8", "csv")
+    } else {
+        ("application/x-ndjson", "jsonl")
+    };
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, content_type)
+        .header(
+            "content-disposition",
+            format!("attachment; filename=\"request-logs-{since}-{until}.{extension}\""),
+        )
+        .body(Body::from_stream(body))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/logs/{id}",
+    tag = "logs",
+    summary = "One request log with bodies.",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_log_detail(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<RequestLogView>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    // Accepts the internal integer id or the UUIDv7 `public_id`.
+    let lookup = if let Ok(id) = id.parse::<i64>() {
+        state.proxy.request_log(id).await
+    } else if is_uuid(&id) {
+        state.proxy.request_log_by_public_id(&id).await
+    } else {
+        return Err(StatusCode::BAD_REQUEST);
+    };
+    let record = match lookup {
+        Ok(Some(record)) => record,
+        Ok(None) => return Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            tracing::error!("get log detail error: {err}");
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let annotations = match state
+        .proxy
+        .log_annotations(LogKind::Request, record.id)
+        .await
+    {
+        Ok(annotations) => annotations,
+        Err(err) => {
+            tracing::error!("get log annotations error: {err}");
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let bodies_intact = record.bodies_intact();
+    let mut view = RequestLogView::from(record);
+    view.bodies_intact = bodies_intact;
+    view.annotations = Some(
+        annotations
+            .into_iter()
+            .map(LogAnnotationView::from)
+            .collect(),
+    );
+    Ok(Json(view))
+}
+
+/// Longest admin note accepted on a log row.
+const LOG_ANNOTATION_MAX_CHARS: usize = 500;
+
+#[derive(Debug, Deserialize)]
+struct LogAnnotationRequest {
+    note: String,
+}
+
+#[derive(Debug, Serialize)]
+struct LogAnnotationView {
+    id: i64,
+    note: String,
+    author: Option<String>,
+    created_at: i64,
+}
+
+impl From<LogAnnotation> for LogAnnotationView {
+    fn from(a: LogAnnotation) -> Self {
+        Self {
+            id: a.id,
+            note: a.note,
+            author: a.author,
+            created_at: a.created_at,
+        }
+    }
+}
+
+async fn annotate_log(
+    state: &AppState,
+    headers: &HeaderMap,
+    kind: LogKind,
+    log_id: i64,
+    note: &str,
+) -> Result<(StatusCode, Json<LogAnnotationView>), StatusCode> {
+    let note = note.trim();
+    if note.is_empty() || note.chars().count() > LOG_ANNOTATION_MAX_CHARS {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let author = state.forward_auth.user_value(headers);
+    match state.proxy.annotate_log(kind, log_id, note, author).await {
+        Ok(Some(annotation)) => Ok((StatusCode::CREATED, Json(annotation.into()))),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            tracing::error!("annotate log error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/logs/{id}/annotations",
+    tag = "logs",
+    summary = "Annotate a request log.",
+    params(("id" = String, Path)),
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn post_request_log_annotation(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<LogAnnotationRequest>,
+) -> Result<(StatusCode, Json<LogAnnotationView>), StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let log_id = if let Ok(id) = id.parse::<i64>() {
+        id
+    } else if is_uuid(&id) {
+        match state.proxy.request_log_by_public_id(&id).await {
+            Ok(Some(record)) => record.id,
+            Ok(None) => return Err(StatusCode::NOT_FOUND),
+            Err(err) => {
+                tracing::error!("annotate log lookup error: {err}");
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        }
+    } else {
+        return Err(StatusCode::BAD_REQUEST);
+    };
+    annotate_log(&state, &headers, LogKind::Request, log_id, &payload.note).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/tokens/{id}/logs/{log_id}/annotations",
+    tag = "logs",
+    summary = "Annotate a token log.",
+    params(("id" = String, Path), ("log_id" = String, Path)),
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn post_token_log_annotation(
+    State(state): State<Arc<AppState>>,
+    Path((token_id, log_id)): Path<(String, i64)>,
+    headers: HeaderMap,
+    Json(payload): Json<LogAnnotationRequest>,
+) -> Result<(StatusCode, Json<LogAnnotationView>), StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    match state.proxy.token_log(&token_id, log_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            tracing::error!("annotate token log lookup error: {err}");
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+    annotate_log(&state, &headers, LogKind::Token, log_id, &payload.note).await
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/log-annotations/{id}",
+    tag = "logs",
+    summary = "Remove a log annotation.",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn delete_log_annotation(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+) -> Result<StatusCode, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    match state.proxy.delete_log_annotation(id).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            tracing::error!("delete log annotation error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct LogsByHashQuery {
+    limit: Option<i64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/logs/by-hash/{sha256}",
+    tag = "logs",
+    summary = "Logs with this body digest.",
+    params(("sha256" = String, Path)),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn list_logs_by_body_hash(
+    State(state): State<Arc<AppState>>,
+    Path(sha256): Path<String>,
+    Query(q): Query<LogsByHashQuery>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<RequestLogView>>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    match state
+        .proxy
+        .request_logs_by_body_hash(&sha256, q.limit.unwrap_or(50))
+        .await
+    {
+        Ok(records) => Ok(Json(
+            records.into_iter().map(RequestLogView::from).collect(),
+        )),
+        Err(err) => {
+            tracing::error!("list logs by body hash error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReplicationSnapshotView {
+    watermark: i64,
+    tables: HashMap<String, Vec<ReplicationRow>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReplicationChangeView {
+    id: i64,
+    table: String,
+    key: Vec<String>,
+    row: Option<ReplicationRow>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReplicationChangesView {
+    since_id: i64,
+    next_since_id: i64,
+    high_watermark: i64,
+    has_more: bool,
+    changes: Vec<ReplicationChangeView>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ReplicationStatusView {
+    role: &'static str,
+    primary_url: Option<String>,
+    cursor: Option<i64>,
+    primary_watermark: Option<i64>,
+    lag: Option<i64>,
+    synced_at: Option<i64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/replication/snapshot",
+    tag = "replication",
+    summary = "Snapshot for a warm standby.",
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_replication_snapshot(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Json<ReplicationSnapshotView>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    match state.proxy.replication_snapshot().await {
+        Ok(snapshot) => Ok(Json(ReplicationSnapshotView {
+            watermark: snapshot.watermark,
+            tables: snapshot.tables.into_iter().collect(),
+        })),
+        Err(err) => {
+            tracing::error!("replication snapshot error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/replication/changes",
+    tag = "replication",
+    summary = "Changes for a warm standby.",
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_replication_changes(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(q): Query<ExportChangesQuery>,
+) -> Result<Json<ReplicationChangesView>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let since_id = q.since_id.unwrap_or(0).max(0);
+    let limit = q.limit.unwrap_or(1000);
+
+    match state.proxy.replication_changes(since_id, limit).await {
+        Ok(page) => Ok(Json(ReplicationChangesView {
+            since_id,
+            next_since_id: page.next_since_id,
+            high_watermark: page.high_watermark,
+            has_more: page.next_since_id < page.high_watermark,
+            changes: page
+                .changes
+                .into_iter()
+                .map(|c| ReplicationChangeView {
+                    id: c.id,
+                    table: c.table,
+                    key: c.key,
+                    row: c.row,
+                })
+                .collect(),
+        })),
+        Err(err) => {
+            tracing::error!("replication changes error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/replication/status",
+    tag = "replication",
+    summary = "Standby replication progress.",
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_replication_status(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Json<ReplicationStatusView>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let primary_url = effective_replication_primary_url();
+    let progress = state.proxy.replication_progress().await.map_err(|err| {
+        tracing::error!("replication status error: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(ReplicationStatusView {
+        role: if primary_url.is_some() {
+            "standby"
+        } else {
+            "primary"
+        },
+        primary_url,
+        cursor: progress.as_ref().map(|p| p.cursor),
+        primary_watermark: progress.as_ref().map(|p| p.primary_watermark),
+        lag: progress
+            .as_ref()
+            .map(|p| (p.primary_watermark - p.cursor).max(0)),
+        synced_at: progress.and_then(|p| p.synced_at),
+    }))
+}
+
+// ----- Access token management handlers -----
+
+#[derive(Debug, Deserialize)]
+struct ListTokensQuery {
+    page: Option<i64>,
+    per_page: Option<i64>,
+    group: Option<String>,
+    no_group: Option<bool>,
+    include_deleted: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ListTokensResponse {
+    items: Vec<AuthTokenView>,
+    total: i64,
+    page: i64,
+    per_page: i64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TokenGroupView {
+    name: String,
+    token_count: i64,
+    latest_created_at: i64,
+    throttle: Option<GroupThrottleView>,
+    response_headers: ResponseHeaders,
+    note: Option<String>,
+    hourly_limit: Option<i64>,
+    daily_limit: Option<i64>,
+    monthly_limit: Option<i64>,
+    usage: GroupUsageView,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GroupUsageView {
+    hourly_used: i64,
+    daily_used: i64,
+    monthly_used: i64,
+}
+
+impl From<GroupQuotaUsage> for GroupUsageView {
+    fn from(usage: GroupQuotaUsage) -> Self {
+        Self {
+            hourly_used: usage.hourly_used,
+            daily_used: usage.daily_used,
+            monthly_used: usage.monthly_used,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GroupThrottleView {
+    active: bool,
+    factor_percent: i64,
+    requests: i64,
+    external_failures: i64,
+    started_at: i64,
+    expires_at: i64,
+    lifted_at: Option<i64>,
+}
+
+impl GroupThrottleView {
+    fn new(throttle: &GroupThrottle, now: i64) -> Self {
+        Self {
+            active: throttle.is_active(now),
+            factor_percent: throttle.factor_percent,
+            requests: throttle.requests,
+            external_failures: throttle.external_failures,
+            started_at: throttle.started_at,
+            expires_at: throttle.expires_at,
+            lifted_at: throttle.lifted_at,
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/tokens",
+    tag = "tokens",
+    summary = "List tokens, paginated.",
+    params(
+        ("page" = Option<String>, Query),
+        ("per_page" = Option<String>, Query),
+        ("group" = Option<String>, Query),
+        ("no_group" = Option<String>, Query),
+        ("include_deleted" = Option<String>, Query),
+    ),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn list_tokens(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(q): Query<ListTokensQuery>,
+) -> Result<Response<Body>, StatusCode> {
+    let scope = manage_scope(&state, &headers)?;
+    // The data version does not track ownership, so scoped listings are not cached.
+    let etag = match scope {
+        ManageScope::All => admin_list_etag(&state, "tokens").await,
+        ManageScope::Owner(_) => None,
+    };
+    if let Some(resp) = not_modified(&headers, etag.as_ref()) {
+        return Ok(resp);
+    }
+    let page = q.page.unwrap_or(1).max(1);
+    let per_page = q.per_page.unwrap_or(10).clamp(1, 200);
+    let group = q
+        .group
+        .as_deref()
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_owned);
+    let no_group = q.no_group.unwrap_or(false);
+    let include_deleted = q.include_deleted.unwrap_or(false);
+
+    let listed = if let Some(owner) = scope.owner() {
+        let owned_items = match state.proxy.owned_access_token_ids(owner).await {
+            Ok(owned) => state
+                .proxy
+                .list_access_tokens_with_deleted(include_deleted)
+                .await
+                .map(|items| (owned, items)),
+            Err(err) => Err(err),
+        };
+        match owned_items {
+            Ok((owned, items)) => {
+                let filtered: Vec<AuthToken> = items
+                    .into_iter()
+                    .filter(|t| owned.contains(&t.id))
+                    .filter(|t| {
+                        let token_group = t
+                            .group_name
+                            .as_deref()
+                            .map(str::trim)
+                            .filter(|g| !g.is_empty());
+                        if no_group {
+                            token_group.is_none()
+                        } else {
+                            group.is_none() || token_group == group.as_deref()
+                        }
+                    })
+                    .collect();
+                let total = filtered.len() as i64;
+                let start = ((page - 1) * per_page).max(0) as usize;
+                let end = start.saturating_add(per_page as usize).min(total as usize);
+                let slice = if start >= total as usize {
+                    Vec::new()
+                } else {
+                    filtered[start..end].to_vec()
+                };
+                Ok(Json(ListTokensResponse {
+                    items: slice.into_iter().map(AuthTokenView::from).collect(),
+                    total,
+                    page,
+                    per_page,
+                }))
+            }
+            Err(err) => {
+                tracing::error!("list tokens (owner filter) error: {err}");
+                Err(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    } else if no_group {
+        match state
+            .proxy
+            .list_access_tokens_with_deleted(include_deleted)
+            .await
+        {
+            Ok(items) => {
+                let filtered: Vec<AuthToken> = items
+                    .into_iter()
+                    .filter(|t| {
+                        t.group_name
+                            .as_deref()
+                            .map(str::trim)
+                            .map(|g| g.is_empty())
+                            .unwrap_or(true)
+                    })
+                    .collect();
+                let total = filtered.len() as i64;
+                let start = ((page - 1) * per_page).max(0) as usize;
+                let end = start.saturating_add(per_page as usize).min(total as usize);
+                let slice = if start >= total as usize {
+                    Vec::new()
+                } else {
+                    filtered[start..end].to_vec()
+                };
+                Ok(Json(ListTokensResponse {
+                    items: slice.into_iter().map(AuthTokenView::from).collect(),
+                    total,
+                    page,
+                    per_page,
+                }))
+            }
+            Err(err) => {
+                tracing::error!("list tokens (no_group filter) error: {err}");
+                Err(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    } else if let Some(group) = group {
+        match state
+            .proxy
+            .list_access_tokens_with_deleted(include_deleted)
+            .await
+        {
+            Ok(items) => {
+                let filtered: Vec<AuthToken> = items
+                    .into_iter()
+                    .filter(|t| t.group_name.as_deref() == Some(group.as_str()))
+                    .collect();
+                let total = filtered.len() as i64;
+                let start = ((page - 1) * per_page).max(0) as usize;
+                let end = start.saturating_add(per_page as usize).min(total as usize);
+                let slice = if start >= total as usize {
+                    Vec::new()
+                } else {
+                    filtered[start..end].to_vec()
+                };
+                Ok(Json(ListTokensResponse {
+                    items: slice.into_iter().map(AuthTokenView::from).collect(),
+                    total,
+                    page,
+                    per_page,
+                }))
+            }
+            Err(err) => {
+                tracing::error!("list tokens (group filter) error: {err}");
+                Err(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    } else {
+        match state
+            .proxy
+            .list_access_tokens_paged(page, per_page, include_deleted)
+            .await
+        {
+            Ok((items, total)) => Ok(Json(ListTokensResponse {
+                items: items.into_iter().map(AuthTokenView::from).collect(),
+                total,
+                page,
+                per_page,
+            })),
+            Err(err) => {
+                tracing::error!("list tokens error: {err}");
+                Err(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    };
+    listed.map(|json| with_etag(json, etag))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/tokens/groups",
+    tag = "tokens",
+    summary = "Token groups with usage.",
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+#[axum::debug_handler]
+async fn list_token_groups(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<TokenGroupView>>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    token_group_views(&state).await.map(Json)
+}
+
+/// Groups with their members, limits and usage; tokens without a group are listed under "".
+async fn token_group_views(state: &AppState) -> Result<Vec<TokenGroupView>, StatusCode> {
+    let defined = match state.proxy.token_groups().await {
+        Ok(groups) => groups,
+        Err(err) => {
+            tracing::error!("list token groups error: {err}");
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let mut usage = match state.proxy.token_group_usage(None).await {
+        Ok(usage) => usage,
+        Err(err) => {
+            tracing::error!("token group usage error: {err}");
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let throttles = match state.proxy.group_throttles().await {
+        Ok(throttles) => throttles,
+        Err(err) => {
+            tracing::error!("list group throttles error: {err}");
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let mut group_headers = match state.proxy.group_response_headers().await {
+        Ok(headers) => headers,
+        Err(err) => {
+            tracing::error!("list group response headers error: {err}");
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let now = Utc::now().timestamp();
+
+    match state.proxy.list_access_tokens().await {
+        Ok(tokens) => {
+            let mut groups: HashMap<String, TokenGroupView> = HashMap::new();
+            for t in tokens {
+                let raw = t.group_name.as_deref().map(str::trim).unwrap_or("");
+                let key = raw.to_owned();
+                let entry = groups.entry(key.clone()).or_insert(TokenGroupView {
+                    name: key.clone(),
+                    token_count: 0,
+                    latest_created_at: t.created_at,
+                    throttle: throttles
+                        .iter()
+                        .find(|g| !key.is_empty() && g.group_name == key)
+                        .map(|g| GroupThrottleView::new(g, now)),
+                    response_headers: if key.is_empty() {
+                        ResponseHeaders::new()
+                    } else {
+                        group_headers.remove(&key).unwrap_or_default()
+                    },
+                    note: None,
+                    hourly_limit: None,
+                    daily_limit: None,
+                    monthly_limit: None,
+                    usage: GroupUsageView::default(),
+                });
+                entry.token_count += 1;
+                if t.created_at > entry.latest_created_at {
+                    entry.latest_created_at = t.created_at;
+                }
+            }
+            for group in defined {
+                let entry = groups
+                    .entry(group.name.clone())
+                    .or_insert_with(|| TokenGroupView {
+                        name: group.name.clone(),
+                        token_count: 0,
+                        latest_created_at: group.created_at,
+                        throttle: throttles
+                            .iter()
+                            .find(|g| g.group_name == group.name)
+                            .map(|g| GroupThrottleView::new(g, now)),
+                        response_headers: group_headers.remove(&group.name).unwrap_or_default(),
+                        note: None,
+                        hourly_limit: None,
+                        daily_limit: None,
+                        monthly_limit: None,
+                        usage: GroupUsageView::default(),
+                    });
+                entry.note = group.note;
+                entry.hourly_limit = group.hourly_limit;
+                entry.daily_limit = group.daily_limit;
+                entry.monthly_limit = group.monthly_limit;
+                entry.usage = usage.remove(&group.name).unwrap_or_default().into();
+            }
+            let mut out: Vec<TokenGroupView> = groups.into_values().collect();
+            out.sort_by(|a, b| {
+                b.latest_created_at
+                    .cmp(&a.latest_created_at)
+                    .then_with(|| a.name.cmp(&b.name))
+            });
+            Ok(out)
+        }
+        Err(err) => {
+            tracing::error!("list token groups error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DemoDataView {
+    api_keys: usize,
+    tokens: usize,
+    request_logs: usize,
+    token_logs: usize,
+}
+
+/// Dev only: fills the database with demo keys, tokens and usage. Hidden unless the server
+/// runs with `--dev-open-admin`, even for real admins.
+#[utoipa::path(
+    post,
+    path = "/api/dev/seed-demo-data",
+    tag = "debug",
+    summary = "Seed demo data (DEV_OPEN_ADMIN only).",
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+#[axum::debug_handler]
+async fn seed_demo_data(
+    State(state): State<Arc<AppState>>,
+) -> Result<(StatusCode, Json<DemoDataView>), StatusCode> {
+    if !state.dev_open_admin {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    match state.proxy.seed_demo_data().await {
+        Ok(summary) => Ok((
+            StatusCode::CREATED,
+            Json(DemoDataView {
+                api_keys: summary.api_keys,
+                tokens: summary.tokens,
+                request_logs: summary.request_logs,
+                token_logs: summary.token_logs,
+            }),
+        )),
+        Err(err) => {
+            tracing::error!("seed demo data error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/tokens/groups/{name}/throttle",
+    tag = "tokens",
+    summary = "Lift a group throttle.",
+    params(("name" = String, Path)),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+#[axum::debug_handler]
+async fn lift_token_group_throttle(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    match state.proxy.lift_group_throttle(&name).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            tracing::error!("lift group throttle error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/tokens/groups/{name}",
+    tag = "tokens",
+    summary = "One token group.",
+    params(("name" = String, Path)),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+#[axum::debug_handler]
+async fn get_token_group(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> Result<Json<TokenGroupView>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    token_group_views(&state)
+        .await?
+        .into_iter()
+        .find(|g| g.name == name)
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Billing period of a group usage export, as inclusive `YYYY-MM` months (UTC). Both
+/// default to the current month and `to` defaults to `from`.
+#[derive(Debug, Deserialize)]
+struct GroupUsageExportQuery {
+    from: Option<String>,
+    to: Option<String>,
+}
+
+/// Longest billing period a single group usage export may cover.
+const GROUP_USAGE_EXPORT_MAX_MONTHS: i32 = 24;
+
+/// Column order of `GET /api/tokens/groups/:name/usage/export`.
+const GROUP_USAGE_CSV_COLUMNS: &[&str] = &[
+    "month",
+    "token_id",
+    "note",
+    "success_count",
+    "error_count",
+    "quota_exhausted_count",
+];
+
+fn parse_billing_month(raw: &str) -> Option<DateTime<Utc>> {
+    let date = NaiveDate::parse_from_str(&format!("{}-01", raw.trim()), "%Y-%m-%d").ok()?;
+    Some(Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0)?))
+}
+
+/// Per-token monthly success and error counts of a group's members, for invoicing teams.
+/// Counts come from the hourly usage rollups, so the current hour may not be included yet.
+#[utoipa::path(
+    get,
+    path = "/api/tokens/groups/{name}/usage/export",
+    tag = "tokens",
+    summary = "Per-token monthly usage of a group as CSV.",
+    params(
+        ("name" = String, Path),
+        ("from" = Option<String>, Query),
+        ("to" = Option<String>, Query),
+    ),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn export_token_group_usage(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+    Query(params): Query<GroupUsageExportQuery>,
+) -> Result<Response<Body>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let name = name.trim();
+    if name.is_empty()
+        || !token_group_views(&state)
+            .await?
+            .iter()
+            .any(|g| g.name == name)
+    {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let month = |raw: Option<&str>, default: DateTime<Utc>| match raw.map(str::trim) {
+        None | Some("") => Ok(default),
+        Some(raw) => parse_billing_month(raw).ok_or(StatusCode::BAD_REQUEST),
+    };
+    let from = month(params.from.as_deref(), start_of_month_dt(Utc::now()))?;
+    let to = month(params.to.as_deref(), from)?;
+    let months = (to.year() - from.year()) * 12 + to.month() as i32 - from.month() as i32;
+    if !(0..GROUP_USAGE_EXPORT_MAX_MONTHS).contains(&months) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let until = default_until(None, to.timestamp());
+
+    let rows = state
+        .proxy
+        .group_token_monthly_usage(name, from.timestamp(), until)
+        .await
+        .map_err(|err| {
+            tracing::error!("export group usage error: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    let mut body = GROUP_USAGE_CSV_COLUMNS.join(",");
+    body.push_str("\r\n");
+    for row in rows {
+        let fields = [
+            Value::String(row.month),
+            Value::String(row.token_id),
+            row.note.map(Value::String).unwrap_or(Value::Null),
+            Value::from(row.success_count),
+            Value::from(row.error_count),
+            Value::from(row.quota_exhausted_count),
+        ];
+        body.push_str(&fields.iter().map(csv_field).collect::<Vec<_>>().join(","));
+        body.push_str("\r\n");
+    }
+
+    let file_group: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "text/csv; charset=utf-8")
+        .header(
+            "content-disposition",
+            format!(
+                "attachment; filename=\"{file_group}-usage-{}-{}.csv\"",
+                from.format("%Y-%m"),
+                to.format("%Y-%m")
+            ),
+        )
+        .body(Body::from(body))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UpsertTokenGroup {
+    note: Option<String>,
+    hourly_limit: Option<i64>,
+    daily_limit: Option<i64>,
+    monthly_limit: Option<i64>,
+}
+
+/// Create a group or replace its note and limits; omitted limits become unlimited.
+#[utoipa::path(
+    put,
+    path = "/api/tokens/groups/{name}",
+    tag = "tokens",
+    summary = "Update a token group.",
+    params(("name" = String, Path)),
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+#[axum::debug_handler]
+async fn put_token_group(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+    Json(payload): Json<UpsertTokenGroup>,
+) -> Result<(StatusCode, Json<TokenGroupView>), StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let name = name.trim();
+    let limits = [
+        payload.hourly_limit,
+        payload.daily_limit,
+        payload.monthly_limit,
+    ];
+    if name.is_empty() || limits.iter().flatten().any(|limit| *limit <= 0) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let settings = TokenGroupSettings {
+        note: payload
+            .note
+            .map(|note| note.trim().to_string())
+            .filter(|note| !note.is_empty()),
+        hourly_limit: payload.hourly_limit,
+        daily_limit: payload.daily_limit,
+        monthly_limit: payload.monthly_limit,
+    };
+    let created = match state.proxy.upsert_token_group(name, &settings).await {
+        Ok(created) => created,
+        Err(err) => {
+            tracing::error!("upsert token group error: {err}");
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let view = token_group_views(&state)
+        .await?
+        .into_iter()
+        .find(|g| g.name == name)
+        .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
+    let status = if created {
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    };
+    Ok((status, Json(view)))
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UsageAlertView {
+    id: i64,
+    scope: &'static str,
+    subject: String,
+    percent: i64,
+    month_start: i64,
+    monthly_used: i64,
+    monthly_limit: i64,
+    created_at: i64,
+}
+
+impl From<UsageAlert> for UsageAlertView {
+    fn from(alert: UsageAlert) -> Self {
+        Self {
+            id: alert.id,
+            scope: alert.scope.as_str(),
+            subject: alert.subject,
+            percent: alert.percent,
+            month_start: alert.month_start,
+            monthly_used: alert.monthly_used,
+            monthly_limit: alert.monthly_limit,
+            created_at: alert.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UsageAlertThresholdView {
+    scope: &'static str,
+    subject: String,
+    percent: i64,
+    webhook: bool,
+    created_at: i64,
+    updated_at: i64,
+}
+
+impl From<UsageAlertThreshold> for UsageAlertThresholdView {
+    fn from(t: UsageAlertThreshold) -> Self {
+        Self {
+            scope: t.scope.as_str(),
+            subject: t.subject,
+            percent: t.percent,
+            webhook: t.webhook,
+            created_at: t.created_at,
+            updated_at: t.updated_at,
+        }
+    }
+}
+
+/// Usage alerts whose threshold is still crossed.
+#[utoipa::path(
+    get,
+    path = "/api/alerts",
+    tag = "alerts",
+    summary = "Usage alerts.",
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn list_usage_alerts(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<UsageAlertView>>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    state
+        .proxy
+        .active_usage_alerts()
+        .await
+        .map(|alerts| Json(alerts.into_iter().map(UsageAlertView::from).collect()))
+        .map_err(|err| {
+            tracing::error!("list usage alerts error: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/alerts/thresholds",
+    tag = "alerts",
+    summary = "Usage alert thresholds.",
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn list_usage_alert_thresholds(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<UsageAlertThresholdView>>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    state
+        .proxy
+        .usage_alert_thresholds()
+        .await
+        .map(|items| {
+            Json(
+                items
+                    .into_iter()
+                    .map(UsageAlertThresholdView::from)
+                    .collect(),
+            )
+        })
+        .map_err(|err| {
+            tracing::error!("list usage alert thresholds error: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+#[derive(Debug, Deserialize)]
+struct UpsertUsageAlertThreshold {
+    percent: i64,
+    webhook: Option<bool>,
+}
+
+/// Set the monthly usage alert threshold (1-100 %) of a token or group.
+#[utoipa::path(
+    put,
+    path = "/api/alerts/thresholds/{scope}/{subject}",
+    tag = "alerts",
+    summary = "Set a usage alert threshold.",
+    params(("scope" = String, Path), ("subject" = String, Path)),
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn put_usage_alert_threshold(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path((scope, subject)): Path<(String, String)>,
+    Json(payload): Json<UpsertUsageAlertThreshold>,
+) -> Result<StatusCode, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let scope = AlertScope::parse(&scope).ok_or(StatusCode::NOT_FOUND)?;
+    if !(1..=100).contains(&payload.percent) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    match state
+        .proxy
+        .set_usage_alert_threshold(
+            scope,
+            &subject,
+            payload.percent,
+            payload.webhook.unwrap_or(true),
+        )
+        .await
+    {
+        Ok(Some(true)) => Ok(StatusCode::CREATED),
+        Ok(Some(false)) => Ok(StatusCode::NO_CONTENT),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            tracing::error!("set usage alert threshold error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/alerts/thresholds/{scope}/{subject}",
+    tag = "alerts",
+    summary = "Remove a usage alert threshold.",
+    params(("scope" = String, Path), ("subject" = String, Path)),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn delete_usage_alert_threshold(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path((scope, subject)): Path<(String, String)>,
+) -> Result<StatusCode, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let scope = AlertScope::parse(&scope).ok_or(StatusCode::NOT_FOUND)?;
+    match state
+        .proxy
+        .delete_usage_alert_threshold(scope, &subject)
+        .await
+    {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            tracing::error!("delete usage alert threshold error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Delete a group; its tokens stay but are ungrouped.
+#[utoipa::path(
+    delete,
+    path = "/api/tokens/groups/{name}",
+    tag = "tokens",
+    summary = "Delete a token group's settings.",
+    params(("name" = String, Path)),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+#[axum::debug_handler]
+async fn delete_token_group(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    match state.proxy.delete_token_group(&name).await {
+        Ok(Some(_)) => Ok(StatusCode::NO_CONTENT),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            tracing::error!("delete token group error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct UpdateTokenGroup {
+    group: Option<String>,
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/tokens/{id}/group",
+    tag = "tokens",
+    summary = "Move a token to a group.",
+    params(("id" = String, Path)),
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+#[axum::debug_handler]
+async fn put_token_group_membership(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    Json(payload): Json<UpdateTokenGroup>,
+) -> Result<StatusCode, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    match state
+        .proxy
+        .set_access_token_group(&id, payload.group.as_deref())
+        .await
+    {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            tracing::error!("set token group error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct UpdateResponseHeaders {
+    headers: ResponseHeaders,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TokenResponseHeadersView {
+    group: Option<String>,
+    group_headers: ResponseHeaders,
+    token_headers: ResponseHeaders,
+    /// What `/mcp` responses carry: group headers overridden by the token's own.
+    effective: ResponseHeaders,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/tokens/{id}/response-headers",
+    tag = "tokens",
+    summary = "A token's response headers.",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_token_response_headers(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<TokenResponseHeadersView>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    match state.proxy.token_response_headers(&id).await {
+        Ok(Some(configured)) => Ok(Json(TokenResponseHeadersView {
+            effective: configured.effective(),
+            group: configured.group_name,
+            group_headers: configured.group,
+            token_headers: configured.token,
+        })),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            tracing::error!("get token response headers error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/tokens/{id}/response-headers",
+    tag = "tokens",
+    summary = "Set a token's response headers.",
+    params(("id" = String, Path)),
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn put_token_response_headers(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<UpdateResponseHeaders>,
+) -> Result<StatusCode, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let configured = normalize_response_headers(&payload.headers).map_err(|err| {
+        tracing::warn!("update token response headers rejected: {err}");
+        StatusCode::BAD_REQUEST
+    })?;
+    match state
+        .proxy
+        .set_access_token_response_headers(&id, &configured)
+        .await
+    {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            tracing::error!("update token response headers error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/tokens/groups/{name}/response-headers",
+    tag = "tokens",
+    summary = "Set a group's response headers.",
+    params(("name" = String, Path)),
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn put_token_group_response_headers(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+    Json(payload): Json<UpdateResponseHeaders>,
+) -> Result<StatusCode, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if name.trim().is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let configured = normalize_response_headers(&payload.headers).map_err(|err| {
+        tracing::warn!("update group response headers rejected: {err}");
+        StatusCode::BAD_REQUEST
+    })?;
+    match state
+        .proxy
+        .set_group_response_headers(&name, &configured)
+        .await
+    {
+        Ok(()) => Ok(StatusCode::NO_CONTENT),
+        Err(err) => {
+            tracing::error!("update group response headers error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Appends the token's configured static headers to an `/mcp` response. Lookup failures only
+/// cost the headers, never the response.
+async fn apply_token_response_headers(
+    state: &AppState,
+    token_id: &str,
+    response: &mut Response<Body>,
+) {
+    let configured = match state.proxy.token_response_headers(token_id).await {
+        Ok(Some(configured)) => configured.effective(),
+        Ok(None) => return,
+        Err(err) => {
+            tracing::error!("token response headers lookup failed: {err}");
+            return;
+        }
+    };
+    for (name, value) in configured {
+        if let (Ok(name), Ok(value)) = (
+            axum::http::HeaderName::from_bytes(name.as_bytes()),
+            axum::http::HeaderValue::from_str(&value),
+        ) {
+            response.headers_mut().insert(name, value);
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/tokens",
+    tag = "tokens",
+    summary = "Create a token.",
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+#[axum::debug_handler]
+async fn create_token(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(payload): Json<CreateTokenRequest>,
+) -> Result<(StatusCode, Json<AuthTokenSecretView>), StatusCode> {
+    let scope = manage_scope(&state, &headers)?;
+    let secret = state
+        .proxy
+        .create_access_token(payload.note.as_deref())
+        .await
+        .map_err(|err| {
+            tracing::error!("create token error: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if let Some(owner) = scope.owner() {
+        state
+            .proxy
+            .set_access_token_owner(&secret.id, Some(owner))
+            .await
+            .map_err(|err| {
+                tracing::error!("set token owner error: {err}");
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+    }
+    Ok((
+        StatusCode::CREATED,
+        Json(AuthTokenSecretView {
+            token: secret.token,
+        }),
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateTokenInviteRequest {
+    note: Option<String>,
+    ttl_secs: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TokenInviteView {
+    id: String,
+    claim_url: String,
+    expires_at: i64,
+}
+
+/// Absolute claim URL on the host the admin reached, honouring a TLS-terminating proxy.
+fn claim_url(headers: &HeaderMap, code: &str) -> String {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(',').next())
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+    };
+    let host = header("x-forwarded-host")
+        .or_else(|| header("host"))
+        .unwrap_or("localhost");
+    let scheme = header("x-forwarded-proto").unwrap_or("http");
+    format!("{scheme}://{host}/claim/{code}")
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/tokens/invite",
+    tag = "tokens",
+    summary = "Create an unclaimed token with a one-time claim URL.",
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn create_token_invite(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(payload): Json<CreateTokenInviteRequest>,
+) -> Result<(StatusCode, Json<TokenInviteView>), StatusCode> {
+    let scope = manage_scope(&state, &headers)?;
+    let invite = match state
+        .proxy
+        .create_token_invite(
+            payload.note.as_deref(),
+            payload.ttl_secs.unwrap_or(TOKEN_INVITE_DEFAULT_TTL_SECS),
+        )
+        .await
+    {
+        Ok(invite) => invite,
+        Err(ProxyError::Other(_)) => return Err(StatusCode::BAD_REQUEST),
+        Err(err) => {
+            tracing::error!("create token invite error: {err}");
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    if let Some(owner) = scope.owner() {
+        state
+            .proxy
+            .set_access_token_owner(&invite.id, Some(owner))
+            .await
+            .map_err(|err| {
+                tracing::error!("set token owner error: {err}");
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+    }
+    Ok((
+        StatusCode::CREATED,
+        Json(TokenInviteView {
+            claim_url: claim_url(&headers, &invite.code),
+            id: invite.id,
+            expires_at: invite.expires_at,
+        }),
+    ))
+}
+
+/// Public: reveal an invited token's secret to whoever holds the claim URL, once.
+#[utoipa::path(
+    get,
+    path = "/claim/{code}",
+    tag = "tokens",
+    summary = "Claim an invited token; reveals its secret once.",
+    params(("code" = String, Path)),
+    responses((status = 200, description = "OK"))
+)]
+async fn claim_token(
+    State(state): State<Arc<AppState>>,
+    Path(code): Path<String>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+) -> Result<Response<Body>, StatusCode> {
+    let from = state
+        .trusted_proxies
+        .client_ip(&headers, peer.map(|info| info.0));
+    let outcome = state
+        .proxy
+        .claim_access_token(&code, from.as_deref())
+        .await
+        .map_err(|err| {
+            tracing::error!("claim token error: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    let (status, payload) = match outcome {
+        TokenClaimOutcome::Claimed(secret) => (
+            StatusCode::OK,
+            json!({ "id": secret.id, "token": secret.token }),
+        ),
+        TokenClaimOutcome::AlreadyClaimed => (
+            StatusCode::GONE,
+            json!({ "error": "already_claimed", "message": "this claim link was already used" }),
+        ),
+        TokenClaimOutcome::Expired => (
+            StatusCode::GONE,
+            json!({ "error": "expired", "message": "this claim link has expired" }),
+        ),
+        TokenClaimOutcome::Unknown => return Err(StatusCode::NOT_FOUND),
+    };
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json; charset=utf-8")
+        .header(axum::http::header::CACHE_CONTROL, "no-store")
+        .body(Body::from(payload.to_string()))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/tokens/{id}",
+    tag = "tokens",
+    summary = "Delete a token.",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn delete_token(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<StatusCode, StatusCode> {
+    let scope = manage_scope(&state, &headers)?;
+    ensure_token_in_scope(&state, &scope, &id).await?;
+    state
+        .proxy
+        .delete_access_token(&id)
+        .await
+        .map(|_| StatusCode::NO_CONTENT)
+        .map_err(|err| {
+            tracing::error!("delete token error: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/tokens/{id}/restore",
+    tag = "tokens",
+    summary = "Undo a token's soft delete; the token comes back enabled.",
+    params(("id" = String, Path)),
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn restore_token(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<StatusCode, StatusCode> {
+    let scope = manage_scope(&state, &headers)?;
+    ensure_token_in_scope(&state, &scope, &id).await?;
+    match state.proxy.restore_access_token(&id).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            tracing::error!("restore token error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct UpdateTokenStatus {
+    enabled: bool,
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/tokens/{id}/status",
+    tag = "tokens",
+    summary = "Enable or disable a token.",
+    params(("id" = String, Path)),
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn update_token_status(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<UpdateTokenStatus>,
+) -> Result<StatusCode, StatusCode> {
+    let scope = manage_scope(&state, &headers)?;
+    ensure_token_in_scope(&state, &scope, &id).await?;
+    state
+        .proxy
+        .set_access_token_enabled(&id, payload.enabled)
+        .await
+        .map(|_| StatusCode::NO_CONTENT)
+        .map_err(|err| {
+            tracing::error!("update token status error: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct QuarantinedTokenView {
+    id: String,
+    note: Option<String>,
+    group: Option<String>,
+    quarantined_at: i64,
+    reason: Option<String>,
+    last_used_at: Option<i64>,
+}
+
+impl From<QuarantinedToken> for QuarantinedTokenView {
+    fn from(t: QuarantinedToken) -> Self {
+        Self {
+            id: t.id,
+            note: t.note,
+            group: t.group_name,
+            quarantined_at: t.quarantined_at,
+            reason: t.reason,
+            last_used_at: t.last_used_at,
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/tokens/quarantine",
+    tag = "tokens",
+    summary = "Quarantined tokens.",
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn list_quarantined_tokens(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<QuarantinedTokenView>>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    state
+        .proxy
+        .list_quarantined_tokens()
+        .await
+        .map(|items| Json(items.into_iter().map(Into::into).collect()))
+        .map_err(|err| {
+            tracing::error!("list quarantined tokens error: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum QuarantineAction {
+    Release,
+    Disable,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResolveQuarantineRequest {
+    action: QuarantineAction,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/tokens/{id}/quarantine",
+    tag = "tokens",
+    summary = "Release or revoke a quarantined token.",
+    params(("id" = String, Path)),
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn resolve_token_quarantine(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<ResolveQuarantineRequest>,
+) -> Result<StatusCode, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let disable = matches!(payload.action, QuarantineAction::Disable);
+    match state.proxy.resolve_token_quarantine(&id, disable).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            tracing::error!("resolve token quarantine error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct UpdateTokenPriority {
+    priority: String,
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/tokens/{id}/priority",
+    tag = "tokens",
+    summary = "Set a token's priority.",
+    params(("id" = String, Path)),
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn update_token_priority(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<UpdateTokenPriority>,
+) -> Result<StatusCode, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let Some(priority) = TokenPriority::parse(&payload.priority) else {
+        return Err(StatusCode::BAD_REQUEST);
+    };
+    match state.proxy.set_access_token_priority(&id, priority).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            tracing::error!("update token priority error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct UpdateTokenUpstream {
+    upstream: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UpdateTokenAllowedTools {
+    tools: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize)]
+struct TokenAllowedToolsView {
+    allowed_tools: Option<Vec<String>>,
+}
+
+/// `tools: null` lifts the restriction; a list limits the token to those tools.
+#[utoipa::path(
+    patch,
+    path = "/api/tokens/{id}/allowed-tools",
+    tag = "tokens",
+    summary = "Restrict a token to some tools.",
+    params(("id" = String, Path)),
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn update_token_allowed_tools(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<UpdateTokenAllowedTools>,
+) -> Result<Json<TokenAllowedToolsView>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    match state
+        .proxy
+        .set_access_token_allowed_tools(&id, payload.tools.as_deref())
+        .await
+    {
+        Ok(Some(allowed_tools)) => Ok(Json(TokenAllowedToolsView { allowed_tools })),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            tracing::error!("update token allowed tools error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/tokens/{id}/upstream",
+    tag = "tokens",
+    summary = "Set a token's upstream override.",
+    params(("id" = String, Path)),
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn update_token_upstream(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<UpdateTokenUpstream>,
+) -> Result<StatusCode, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let upstream = payload
+        .upstream
+        .as_deref()
+        .map(str::trim)
+        .filter(|name| !name.is_empty());
+    if let Some(name) = upstream
+        && !state
+            .proxy
+            .upstream_override_names()
+            .iter()
+            .any(|n| n == name)
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    match state
+        .proxy
+        .set_access_token_upstream_override(&id, upstream)
+        .await
+    {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            tracing::error!("update token upstream error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// `tag: null` clears the preference; `fallback` (default true) lets the rest of the pool
+/// serve the token while no key with the tag is usable.
+#[derive(Debug, Deserialize)]
+struct UpdateTokenKeyTag {
+    tag: Option<String>,
+    fallback: Option<bool>,
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/tokens/{id}/key-tag",
+    tag = "tokens",
+    summary = "Set the key tag a token prefers.",
+    params(("id" = String, Path)),
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn update_token_key_tag(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<UpdateTokenKeyTag>,
+) -> Result<StatusCode, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let tag = payload
+        .tag
+        .as_deref()
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty());
+    match state
+        .proxy
+        .set_access_token_key_tag(&id, tag, payload.fallback.unwrap_or(true))
+        .await
+    {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(ProxyError::Other(_)) => Err(StatusCode::BAD_REQUEST),
+        Err(err) => {
+            tracing::error!("update token key tag error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// `extend_days` pushes the expiry out from the later of now and the current expiry;
+/// otherwise `expires_at` (ISO timestamp) replaces it and null clears it.
+#[derive(Debug, Deserialize)]
+struct UpdateTokenExpiry {
+    expires_at: Option<String>,
+    extend_days: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+struct TokenExpiryView {
+    expires_at: Option<i64>,
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/tokens/{id}/expiry",
+    tag = "tokens",
+    summary = "Set or extend a token's expiry.",
+    params(("id" = String, Path)),
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn update_token_expiry(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<UpdateTokenExpiry>,
+) -> Result<Json<TokenExpiryView>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let result = match (payload.expires_at, payload.extend_days) {
+        (None, Some(days)) if days > 0 => state
+            .proxy
+            .extend_access_token_expiry(&id, days.saturating_mul(24 * 3600))
+            .await
+            .map(|expires_at| expires_at.map(Some)),
+        (raw, None) => {
+            let expires_at = match raw.as_deref().map(str::trim) {
+                Some(raw) => Some(parse_iso_timestamp(raw).ok_or(StatusCode::BAD_REQUEST)?),
+                None => None,
+            };
+            state
+                .proxy
+                .set_access_token_expiry(&id, expires_at)
+                .await
+                .map(|found| found.then_some(expires_at))
+        }
+        _ => return Err(StatusCode::BAD_REQUEST),
+    };
+    match result {
+        Ok(Some(expires_at)) => Ok(Json(TokenExpiryView { expires_at })),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            tracing::error!("update token expiry error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct UpdateTokenTier {
+    tier: String,
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/tokens/{id}/tier",
+    tag = "tokens",
+    summary = "Set a token's tier.",
+    params(("id" = String, Path)),
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn update_token_tier(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<UpdateTokenTier>,
+) -> Result<StatusCode, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let tier = payload.tier.trim();
+    if !state
+        .proxy
+        .token_tier_names()
+        .iter()
+        .any(|name| name == tier)
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    match state.proxy.set_access_token_tier(&id, tier).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            tracing::error!("update token tier error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TierChangesQuery {
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TokenTierChangeView {
+    id: i64,
+    from_tier: String,
+    to_tier: String,
+    rule: String,
+    reason: Option<String>,
+    changed_at: i64,
+}
+
+impl From<TokenTierChange> for TokenTierChangeView {
+    fn from(c: TokenTierChange) -> Self {
+        Self {
+            id: c.id,
+            from_tier: c
+                .from_tier
+                .unwrap_or_else(|| TOKEN_TIER_DEFAULT.to_string()),
+            to_tier: c.to_tier.unwrap_or_else(|| TOKEN_TIER_DEFAULT.to_string()),
+            rule: c.rule,
+            reason: c.reason,
+            changed_at: c.changed_at,
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/tokens/{id}/tier-changes",
+    tag = "tokens",
+    summary = "Tier changes of a token.",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn list_token_tier_changes(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Query(q): Query<TierChangesQuery>,
+) -> Result<Json<Vec<TokenTierChangeView>>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    state
+        .proxy
+        .token_tier_changes(&id, q.limit.unwrap_or(50))
+        .await
+        .map(|changes| Json(changes.into_iter().map(Into::into).collect()))
+        .map_err(|err| {
+            tracing::error!("list token tier changes error: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct McpSessionView {
+    session_id: String,
+    key_id: String,
+    created_at: i64,
+    last_seen_at: i64,
+    request_count: i64,
+    error_count: i64,
+}
+
+impl From<McpSession> for McpSessionView {
+    fn from(s: McpSession) -> Self {
+        Self {
+            session_id: s.session_id,
+            key_id: s.key_id,
+            created_at: s.created_at,
+            last_seen_at: s.last_seen_at,
+            request_count: s.request_count,
+            error_count: s.error_count,
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/tokens/{id}/sessions",
+    tag = "tokens",
+    summary = "MCP sessions opened by a token.",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn list_token_sessions(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Query(q): Query<TierChangesQuery>,
+) -> Result<Json<Vec<McpSessionView>>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    state
+        .proxy
+        .token_mcp_sessions(&id, q.limit.unwrap_or(50))
+        .await
+        .map(|sessions| Json(sessions.into_iter().map(Into::into).collect()))
+        .map_err(|err| {
+            tracing::error!("list token sessions error: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+#[derive(Debug, Deserialize)]
+struct UpdateTokenNote {
+    note: String,
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/tokens/{id}/note",
+    tag = "tokens",
+    summary = "Set a token's note.",
+    params(("id" = String, Path)),
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn update_token_note(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<UpdateTokenNote>,
+) -> Result<StatusCode, StatusCode> {
+    let scope = manage_scope(&state, &headers)?;
+    ensure_token_in_scope(&state, &scope, &id).await?;
+    state
+        .proxy
+        .update_access_token_note(&id, payload.note.trim())
+        .await
+        .map(|_| StatusCode::NO_CONTENT)
+        .map_err(|err| {
+            tracing::error!("update token note error: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/tokens/{id}/secret",
+    tag = "tokens",
+    summary = "Reveal a token's full value.",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_token_secret(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<AuthTokenSecretView>, StatusCode> {
+    let scope = manage_scope(&state, &headers)?;
+    ensure_token_in_scope(&state, &scope, &id).await?;
+    match state.proxy.get_access_token_secret(&id).await {
+        Ok(Some(secret)) => Ok(Json(AuthTokenSecretView {
+            token: secret.token,
+        })),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            tracing::error!("get token secret error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/tokens/{id}/secret/rotate",
+    tag = "tokens",
+    summary = "Rotate a token's secret.",
+    params(("id" = String, Path)),
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+#[axum::debug_handler]
+async fn rotate_token_secret(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<AuthTokenSecretView>, StatusCode> {
+    let scope = manage_scope(&state, &headers)?;
+    ensure_token_in_scope(&state, &scope, &id).await?;
+    state
+        .proxy
+        .rotate_access_token_secret(&id)
+        .await
+        .map(|secret| {
+            Json(AuthTokenSecretView {
+                token: secret.token,
+            })
+        })
+        .map_err(|err| {
+            tracing::error!("rotate token secret error: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+#[derive(Debug, Deserialize)]
+struct BatchCreateTokenRequest {
+    group: String,
+    count: usize,
+    note: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct BatchCreateTokenResponse {
+    tokens: Vec<String>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/tokens/batch",
+    tag = "tokens",
+    summary = "Create many tokens.",
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+#[axum::debug_handler]
+async fn create_tokens_batch(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(payload): Json<BatchCreateTokenRequest>,
+) -> Result<Json<BatchCreateTokenResponse>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let group = payload.group.trim();
+    if group.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let count = payload.count.clamp(1, 1000);
+    state
+        .proxy
+        .create_access_tokens_batch(group, count, payload.note.as_deref())
+        .await
+        .map(|secrets| {
+            Json(BatchCreateTokenResponse {
+                tokens: secrets.into_iter().map(|s| s.token).collect(),
+            })
+        })
+        .map_err(|err| {
+            tracing::error!("batch create tokens error: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+/// Most token ids accepted by one `POST /api/tokens/bulk` call.
+const BULK_TOKENS_MAX_IDS: usize = 1000;
+
+#[derive(Debug, Deserialize)]
+struct BulkTokensRequest {
+    ids: Vec<String>,
+    /// `enable`, `disable`, `delete`, `rotate` or `move_group`.
+    action: String,
+    /// Target of `move_group`; null or empty removes the tokens from their group.
+    group: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct BulkTokensResponse {
+    action: String,
+    updated: usize,
+    ids: Vec<String>,
+    /// New full tokens (`th-<id>-<secret>`), only for `rotate`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tokens: Vec<String>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/tokens/bulk",
+    tag = "tokens",
+    summary = "Change many tokens at once.",
+    request_body(content = Option<Object>, content_type = "application/json"),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn bulk_tokens(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(payload): Json<BulkTokensRequest>,
+) -> Result<Response<Body>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let op = match payload.action.as_str() {
+        "enable" => BulkTokenOperation::Enable,
+        "disable" => BulkTokenOperation::Disable,
+        "delete" => BulkTokenOperation::Delete,
+        "rotate" => BulkTokenOperation::Rotate,
+        "move_group" => BulkTokenOperation::MoveGroup(payload.group),
+        _ => return Err(StatusCode::BAD_REQUEST),
+    };
+    let ids: Vec<String> = payload
+        .ids
+        .iter()
+        .map(|id| id.trim().to_string())
+        .filter(|id| !id.is_empty())
+        .collect();
+    if ids.is_empty() || ids.len() > BULK_TOKENS_MAX_IDS {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let result = state
+        .proxy
+        .bulk_token_operation(&ids, op)
+        .await
+        .map_err(|err| {
+            tracing::error!("bulk token operation error: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if !result.missing.is_empty() {
+        let payload = json!({ "error": "unknown_tokens", "missing": result.missing });
+        return Ok((StatusCode::NOT_FOUND, Json(payload)).into_response());
+    }
+    Ok(Json(BulkTokensResponse {
+        action: payload.action,
+        updated: result.updated.len(),
+        ids: result.updated,
+        tokens: result.rotated.into_iter().map(|s| s.token).collect(),
+    })
+    .into_response())
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn serve(
+    addr: SocketAddr,
+    proxy: TavilyProxy,
+    static_dir: Option<PathBuf>,
+    forward_auth: ForwardAuthConfig,
+    dev_open_admin: bool,
+    usage_base: String,
+    rate_limit: RateLimitConfig,
+    trusted_proxies: TrustedProxies,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let state = Arc::new(AppState {
+        proxy,
+        static_dir: static_dir.clone(),
+        forward_auth,
+        dev_open_admin,
+        usage_base: usage_base.clone(),
+        access_log: AccessLog::from_env(),
+        cors: CorsPolicy::from_env(),
+        public_quota: Arc::new(PublicIpQuota::from_env()),
+        rate_limiter: ClientRateLimiter::new(rate_limit).map(Arc::new),
+        trusted_proxies: Arc::new(trusted_proxies),
+    });
+
+    if let Some(h) = state.forward_auth.user_header() {
+        tracing::info!(
+            "Forward-Auth: header='{}' admin_value='{}'",
+            h,
+            state.forward_auth.admin_value().unwrap_or("<none>")
+        );
+    } else {
+        tracing::info!(
+            "Forward-Auth: disabled (no user header), admin_override={} dev_open_admin={}",
+            state.forward_auth.admin_override_name().unwrap_or("<none>"),
+            state.dev_open_admin
+        );
+    }
+
+    let router = build_router(state.clone());
+
+    // Settings come from env/CLI; diff them against the last recorded values so restarts
+    // with edited configuration show up in the audit trail.
+    match state
+        .proxy
+        .record_config_changes("startup", &effective_runtime_settings())
+        .await
+    {
+        Ok(0) => {}
+        Ok(changed) => tracing::info!("Config audit: recorded {changed} changed setting(s)"),
+        Err(err) => tracing::error!("config audit error: {err}"),
+    }
+
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    let bound_addr = listener.local_addr()?;
+    let versions = detect_versions(state.static_dir.as_deref());
+    tracing::info!(
+        "Build: backend={} frontend={} git_sha={} built_at={} features=[{}]",
+        versions.backend,
+        versions.frontend,
+        versions.git_sha,
+        versions.build_timestamp.as_deref().unwrap_or("unknown"),
+        versions.features.join(",")
+    );
+    tracing::info!("Tavily proxy listening on http://{bound_addr}");
+    for (prefix, url) in state.proxy.upstream_routes() {
+        tracing::info!("Upstream route: {prefix}/* -> {url}");
+    }
+
+    // Spawn background schedulers
+    spawn_quota_sync_scheduler(state.clone());
+    spawn_token_usage_rollup_scheduler(state.clone());
+    spawn_token_quota_snapshot_scheduler(state.clone());
+    spawn_auth_token_logs_gc_scheduler(state.clone());
+    spawn_request_logs_gc_scheduler(state.clone());
+    spawn_quota_reconcile_scheduler(state.clone());
+    spawn_wal_checkpoint_scheduler(state.clone());
+    let backup_interval_secs = effective_backup_interval_secs();
+    if backup_interval_secs > 0 {
+        tracing::info!(
+            "Database backups every {backup_interval_secs}s into {}",
+            state.proxy.backup_dir().display()
+        );
+        spawn_database_backup_scheduler(state.clone(), backup_interval_secs as u64);
+    }
+    if let Some(primary) = effective_replication_primary_url() {
+        tracing::info!("Replication: warm standby following {primary}");
+        spawn_replication_follower(state.clone(), primary);
+    }
+    spawn_key_error_guard_scheduler(state.clone());
+    spawn_group_error_budget_scheduler(state.clone());
+    spawn_token_tier_policy_scheduler(state.clone());
+    spawn_token_expiry_scheduler(state.clone());
+    spawn_availability_report_scheduler(state.clone());
+    spawn_upstream_health_scheduler(state.clone());
+    #[cfg(unix)]
+    spawn_config_reload_on_sighup(state.clone());
+    if effective_request_analytics_enabled() {
+        spawn_request_analytics_scheduler(state.clone());
+    }
+
+    axum::serve(
+        listener,
+        router.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown_signal())
+    .await?;
+    tracing::info!("Server shut down gracefully.");
+    Ok(())
+}
+
+// ---- OpenAPI contract ----
+
+/// The OpenAPI document served at `/api/openapi.json`: the operations declared by the
+/// handlers registered in [`api_routes`], plus `/mcp` (routed for every method, so described
+/// here) and the security schemes, whose admin header name depends on the ForwardAuth setup.
+fn openapi_document(forward_auth: &ForwardAuthConfig) -> Value {
+    let mut doc = serde_json::to_value(api_routes().into_openapi()).unwrap_or_else(|_| json!({}));
+    doc["info"] = json!({
+        "title": "Tavily Hikari",
+        "version": env!("CARGO_PKG_VERSION"),
+    });
+    let mcp_responses = json!({
+        "200": { "description": "OK" },
+        "401": { "description": "Missing or invalid token" },
+    });
+    doc["paths"]["/mcp"] = json!({
+        "post": {
+            "tags": ["mcp"],
+            "summary": "MCP JSON-RPC through the key pool.",
+            "operationId": "mcp_post",
+            "responses": mcp_responses,
+            "security": [{ "hikariToken": [] }],
+        },
+        "get": {
+            "tags": ["mcp"],
+            "summary": "MCP event stream or WebSocket upgrade.",
+            "operationId": "mcp_get",
+            "responses": mcp_responses,
+            "security": [{ "hikariToken": [] }],
+        },
+    });
+
+    let admin_header = forward_auth
+        .user_header()
+        .map(|name| name.as_str().to_string())
+        .unwrap_or_else(|| "Remote-Email".to_string());
+    doc["components"]["securitySchemes"] = json!({
+        "forwardAuth": {
+            "type": "apiKey",
+            "in": "header",
+            "name": admin_header,
+            "description": "Set by the ForwardAuth proxy; must equal FORWARD_AUTH_ADMIN_VALUE.",
+        },
+        "hikariToken": {
+            "type": "http",
+            "scheme": "bearer",
+            "description": "Hikari access token th-<id>-<secret>.",
+        },
+    });
+    doc
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/openapi.json",
+    tag = "system",
+    summary = "This OpenAPI document.",
+    responses((status = 200, description = "OK"))
+)]
+async fn get_openapi_spec(State(state): State<Arc<AppState>>) -> Json<Value> {
+    Json(openapi_document(&state.forward_auth))
+}
+
+const SWAGGER_UI_HTML: &str = r##"<!doctype html>
+<html lang="en">
+  <head>
+    <meta charset="utf-8" />
+    <title>Tavily Hikari API</title>
+    <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css" />
+  </head>
+  <body>
+    <div id="swagger-ui"></div>
+    <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+    <script>
+      window.ui = SwaggerUIBundle({ url: "/api/openapi.json", dom_id: "#swagger-ui" });
+    </script>
+  </body>
+</html>
+"##;
+
+async fn serve_swagger_ui() -> axum::response::Html<&'static str> {
+    axum::response::Html(SWAGGER_UI_HTML)
+}
+
+/// Builds the full HTTP application (API routes, MCP proxy, static assets and fallback)
+/// without binding a listener or starting background schedulers.
+pub fn app_router(
+    proxy: TavilyProxy,
+    static_dir: Option<PathBuf>,
+    forward_auth: ForwardAuthConfig,
+    dev_open_admin: bool,
+    usage_base: String,
+) -> Router {
+    build_router(Arc::new(AppState {
+        proxy,
+        static_dir,
+        forward_auth,
+        dev_open_admin,
+        usage_base,
+        access_log: AccessLog::from_env(),
+        cors: CorsPolicy::from_env(),
+        public_quota: Arc::new(PublicIpQuota::from_env()),
+        rate_limiter: ClientRateLimiter::new(RateLimitConfig::from_env()).map(Arc::new),
+        trusted_proxies: Arc::new(TrustedProxies::from_env()),
+    }))
+}
+
+/// Every documented route: `/health`, `/api/*`, the `/v1/*` REST passthrough and `/claim/*`.
+/// Paths and methods come from each handler's `#[utoipa::path]`, so the router and
+/// `/api/openapi.json` are built from the same source.
+fn api_routes() -> OpenApiRouter<Arc<AppState>> {
+    OpenApiRouter::new()
+        .routes(routes!(health_check))
+        .routes(routes!(get_openapi_spec))
+        .routes(routes!(debug_headers))
+        .routes(routes!(debug_is_admin))
+        .routes(routes!(get_forward_auth_debug))
+        .routes(routes!(get_admin_debug))
+        .routes(routes!(get_self_metrics))
+        .routes(routes!(get_scheduler_stats))
+        .routes(routes!(get_db_stats))
+        .routes(routes!(get_db_health))
+        .routes(routes!(get_response_cache, flush_response_cache))
+        .routes(routes!(sse_public))
+        .routes(routes!(get_public_logs))
+        .routes(routes!(get_public_usage_series))
+        .routes(routes!(get_public_quota))
+        .routes(routes!(get_token_metrics_public))
+        .routes(routes!(sse_dashboard))
+        .routes(routes!(get_versions))
+        .routes(routes!(get_profile))
+        .routes(routes!(tavily_http_search))
+        .routes(routes!(tavily_http_extract))
+        .routes(routes!(tavily_http_crawl))
+        .routes(routes!(tavily_http_map))
+        .routes(routes!(tavily_http_usage))
+        .routes(routes!(tavily_rest_passthrough))
+        .routes(routes!(fetch_summary))
+        .routes(routes!(get_public_metrics))
+        .routes(routes!(list_keys))
+        .routes(routes!(create_api_key))
+        .routes(routes!(create_api_keys_batch))
+        .routes(routes!(get_api_keys_forecast))
+        .routes(routes!(lookup_api_keys))
+        .routes(routes!(list_key_tags))
+        .routes(routes!(get_api_key_detail))
+        .routes(routes!(post_sync_key_usage))
+        .routes(routes!(post_verify_key))
+        .routes(routes!(get_api_key_secret))
+        .routes(routes!(delete_api_key))
+        .routes(routes!(restore_api_key))
+        .routes(routes!(update_api_key_status))
+        .routes(routes!(drain_api_key))
+        .routes(routes!(get_api_key_status_history))
+        .routes(routes!(get_api_key_errors))
+        .routes(routes!(get_api_key_tags, put_api_key_tags))
+        .routes(routes!(list_jobs))
+        .routes(routes!(get_availability_report))
+        .routes(routes!(get_latency_metrics))
+        .routes(routes!(pause_job))
+        .routes(routes!(resume_job))
+        .routes(routes!(seed_demo_data))
+        .routes(routes!(post_reconcile_quota))
+        .routes(routes!(post_reload_config))
+        .routes(routes!(get_maintenance, post_maintenance))
+        .routes(routes!(post_database_backup))
+        .routes(routes!(post_database_restore))
+        .routes(routes!(list_config_history))
+        .routes(routes!(get_header_policy))
+        .routes(routes!(
+            get_upstream_header_rules,
+            put_upstream_header_rules
+        ))
+        .routes(routes!(get_upstream_health))
+        .routes(routes!(list_webhook_deliveries))
+        .routes(routes!(get_export_changes))
+        .routes(routes!(get_replication_snapshot))
+        .routes(routes!(get_replication_changes))
+        .routes(routes!(get_replication_status))
+        .routes(routes!(get_analytics_queries))
+        .routes(routes!(get_analytics_tools))
+        .routes(routes!(list_logs))
+        .routes(routes!(export_logs))
+        .routes(routes!(get_log_detail))
+        .routes(routes!(post_request_log_annotation))
+        .routes(routes!(delete_log_annotation))
+        .routes(routes!(list_logs_by_body_hash))
+        // Key details
+        .routes(routes!(get_key_metrics))
+        .routes(routes!(get_key_logs))
+        .routes(routes!(sse_key))
+        // Token details
+        .routes(routes!(get_token_detail))
+        .routes(routes!(get_token_metrics))
+        .routes(routes!(get_token_usage_series))
+        .routes(routes!(get_token_quota_burndown))
+        .routes(routes!(get_token_hourly_breakdown))
+        .routes(routes!(get_token_leaderboard))
+        .routes(routes!(lookup_tokens))
+        .routes(routes!(get_token_tools))
+        .routes(routes!(get_token_logs))
+        .routes(routes!(get_token_logs_page))
+        .routes(routes!(get_token_log_detail))
+        .routes(routes!(post_token_log_annotation))
+        .routes(routes!(sse_token))
+        // Access token management (admin only)
+        .routes(routes!(list_tokens))
+        .routes(routes!(create_token))
+        .routes(routes!(create_token_invite))
+        .routes(routes!(list_token_groups))
+        .routes(routes!(
+            get_token_group,
+            put_token_group,
+            delete_token_group
+        ))
+        .routes(routes!(export_token_group_usage))
+        .routes(routes!(lift_token_group_throttle))
+        .routes(routes!(put_token_group_response_headers))
+        .routes(routes!(
+            get_token_response_headers,
+            put_token_response_headers
+        ))
+        .routes(routes!(list_quarantined_tokens))
+        .routes(routes!(list_usage_alerts))
+        .routes(routes!(list_usage_alert_thresholds))
+        .routes(routes!(
+            put_usage_alert_threshold,
+            delete_usage_alert_threshold
+        ))
+        .routes(routes!(resolve_token_quarantine))
+        .routes(routes!(create_tokens_batch))
+        .routes(routes!(bulk_tokens))
+        .routes(routes!(delete_token))
+        .routes(routes!(restore_token))
+        .routes(routes!(update_token_status))
+        .routes(routes!(put_token_group_membership))
+        .routes(routes!(update_token_note))
+        .routes(routes!(update_token_priority))
+        .routes(routes!(update_token_upstream))
+        .routes(routes!(update_token_key_tag))
+        .routes(routes!(update_token_allowed_tools))
+        .routes(routes!(update_token_expiry))
+        .routes(routes!(update_token_tier))
+        .routes(routes!(list_token_tier_changes))
+        .routes(routes!(list_token_sessions))
+        .routes(routes!(get_token_secret))
+        .routes(routes!(rotate_token_secret))
+        .routes(routes!(claim_token))
+}
+
+fn build_router(state: Arc<AppState>) -> Router {
+    let (mut router, _) = api_routes().split_for_parts();
+
+    if effective_swagger_ui_enabled() {
+        router = router.route("/api/docs", get(serve_swagger_ui));
+    }
+
+    if let Some(dir) = state.static_dir.as_ref() {
+        if dir.is_dir() {
+            let index_file = dir.join("index.html");
+            if index_file.exists() {
+                router = router.nest_service("/assets", ServeDir::new(dir.join("assets")));
+                router = router.route("/", get(serve_index));
+                router = router.route("/admin", get(serve_admin_index));
+                router = router.route("/admin/", get(serve_admin_index));
+                router =
+                    router.route_service("/favicon.svg", ServeFile::new(dir.join("favicon.svg")));
+            } else {
+                tracing::warn!(
+                    "static index.html not found at {} — skip serving SPA",
+                    index_file.display()
+                );
+            }
+        } else {
+            tracing::warn!("static dir '{}' is not a directory", dir.display());
+        }
+    }
+
+    router = router
+        .route("/mcp", any(proxy_handler))
+        .route("/mcp/*path", any(proxy_handler));
+
+    // 404 landing page that updates URL back to original via history API
+    router = router.route("/__404", get(not_found_landing));
+
+    // Fallback: if UA/Accept 支持 HTML 则重定向到 __404；否则返回纯 404
+    async fn supports_html(headers: &HeaderMap) -> bool {
+        let accept = headers
+            .get(axum::http::header::ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        if accept.contains("text/html") {
+            return true;
+        }
+        let ua = headers
+            .get(axum::http::header::USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        ua.contains("mozilla/")
+    }
+
+    router = router.fallback(|req: Request<Body>| async move {
+        let headers = req.headers().clone();
+        if supports_html(&headers).await {
+            // 302 for GET/HEAD; 303 for others
+            let uri = req.uri();
+            let pq = uri
+                .path_and_query()
+                .map(|v| v.as_str())
+                .unwrap_or(uri.path());
+            let target = format!("/__404?path={}", urlencoding::encode(pq));
+            match *req.method() {
+                Method::GET | Method::HEAD => Redirect::temporary(&target).into_response(),
+                _ => Redirect::to(&target).into_response(), // 303 See Other
+            }
+        } else {
+            (StatusCode::NOT_FOUND, Body::empty()).into_response()
+        }
+    });
+
+    router = router.layer(middleware::from_fn_with_state(
+        state.clone(),
+        public_quota_middleware,
+    ));
+
+    router = router.layer(middleware::from_fn(request_span_middleware));
+
+    if state.rate_limiter.is_some() {
+        router = router.layer(middleware::from_fn_with_state(
+            state.clone(),
+            rate_limit_middleware,
+        ));
+    }
+
+    if state.cors.is_some() {
+        router = router.layer(middleware::from_fn_with_state(
+            state.clone(),
+            cors_middleware,
+        ));
+    }
+
+    if state.access_log.is_some() {
+        router = router.layer(middleware::from_fn_with_state(
+            state.clone(),
+            access_log_middleware,
+        ));
+    }
+
+    router.with_state(state)
+}
+
+/// Destination of the structured access log (`ACCESS_LOG`).
+#[derive(Debug)]
+enum AccessLog {
+    Stdout,
+    File(StdMutex<RotatingLogFile>),
+}
+
+impl AccessLog {
+    fn from_env() -> Option<Arc<Self>> {
+        let target = effective_access_log_target()?;
+        if target.eq_ignore_ascii_case("stdout") || target == "-" {
+            return Some(Arc::new(Self::Stdout));
+        }
+        match RotatingLogFile::open(
+            PathBuf::from(&target),
+            effective_access_log_max_bytes() as u64,
+            effective_access_log_max_files() as usize,
+        ) {
+            Ok(file) => Some(Arc::new(Self::File(StdMutex::new(file)))),
+            Err(err) => {
+                tracing::warn!("access log disabled, cannot open '{target}': {err}");
+                None
+            }
+        }
+    }
+
+    fn write(&self, line: &str) {
+        match self {
+            Self::Stdout => println!("{line}"),
+            Self::File(file) => {
+                let mut file = file.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                if let Err(err) = file.write_line(line) {
+                    tracing::error!("access log write error: {err}");
+                }
+            }
+        }
+    }
+}
+
+/// Append-only log file rotated by size into `<path>.1` .. `<path>.<max_files>`.
+#[derive(Debug)]
+struct RotatingLogFile {
+    path: PathBuf,
+    file: fs::File,
+    written: u64,
+    max_bytes: u64,
+    max_files: usize,
+}
+
+impl RotatingLogFile {
+    fn open(path: PathBuf, max_bytes: u64, max_files: usize) -> std::io::Result<Self> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            path,
+            file,
+            written,
+            max_bytes,
+            max_files: max_files.max(1),
+        })
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{index}"));
+        PathBuf::from(name)
+    }
+
+    fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.written > 0 && self.written + len > self.max_bytes {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.file.write_all(b"\n")?;
+        self.written += len;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        for index in (1..self.max_files).rev() {
+            let from = self.rotated_path(index);
+            if from.exists() {
+                fs::rename(&from, self.rotated_path(index + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated_path(1))?;
+        self.file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+/// Reverse proxies allowed to report the client address (`--trusted-proxies`).
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    nets: Vec<IpNet>,
+}
+
+impl TrustedProxies {
+    /// Parses a comma-separated list of IP addresses and CIDR ranges.
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let mut nets = Vec::new();
+        for entry in raw.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let net = entry
+                .parse::<IpNet>()
+                .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|_| format!("invalid trusted proxy '{entry}'"))?;
+            nets.push(net);
+        }
+        Ok(Self { nets })
+    }
+
+    pub fn from_env() -> Self {
+        Self::parse(&effective_trusted_proxies()).unwrap_or_else(|err| {
+            tracing::warn!("TRUSTED_PROXIES ignored: {err}");
+            Self::default()
+        })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.nets.iter().any(|net| net.contains(&ip))
+    }
+
+    /// Client address of a request. Forwarding headers only count when the TCP peer is a
+    /// trusted proxy; `X-Forwarded-For` is then walked from the right and the first hop that
+    /// is not itself trusted wins, so a client cannot pick its own address by prepending hops.
+    fn client_ip(&self, headers: &HeaderMap, peer: Option<SocketAddr>) -> Option<String> {
+        let peer = peer?.ip().to_canonical();
+        if !self.contains(peer) {
+            return Some(peer.to_string());
+        }
+        let hops: Vec<&str> = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|hop| !hop.is_empty())
+            .collect();
+        if hops.is_empty() {
+            let real_ip = headers
+                .get("x-real-ip")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse::<IpAddr>().ok());
+            return Some(real_ip.unwrap_or(peer).to_canonical().to_string());
+        }
+        let mut client = peer;
+        for hop in hops.into_iter().rev() {
+            // A garbled hop was not written by a proxy we trust; stop at the last good one.
+            let Ok(ip) = hop.parse::<IpAddr>() else {
+                break;
+            };
+            client = ip.to_canonical();
+            if !self.contains(client) {
+                break;
+            }
+        }
+        Some(client.to_string())
+    }
+}
+
+fn hash_client_ip(ip: &str) -> String {
+    let digest = Sha256::digest(ip.as_bytes());
+    digest[..8].iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Browser access policy for the public endpoints (`CORS_ALLOWED_*`). Admin and MCP routes
+/// never get CORS headers.
+#[derive(Debug)]
+struct CorsPolicy {
+    origins: Vec<String>,
+    any_origin: bool,
+    methods: axum::http::HeaderValue,
+    headers: axum::http::HeaderValue,
+    max_age: axum::http::HeaderValue,
+}
+
+impl CorsPolicy {
+    fn from_env() -> Option<Arc<Self>> {
+        let origins = effective_cors_allowed_origins();
+        if origins.is_empty() {
+            return None;
+        }
+        let header = |name: &str, raw: String| match axum::http::HeaderValue::from_str(&raw) {
+            Ok(value) => Some(value),
+            Err(_) => {
+                tracing::warn!("CORS disabled, invalid {name}: '{raw}'");
+                None
+            }
+        };
+        Some(Arc::new(Self {
+            any_origin: origins.iter().any(|o| o == "*"),
+            methods: header("CORS_ALLOWED_METHODS", effective_cors_allowed_methods())?,
+            headers: header("CORS_ALLOWED_HEADERS", effective_cors_allowed_headers())?,
+            max_age: header(
+                "CORS_MAX_AGE_SECS",
+                effective_cors_max_age_secs().to_string(),
+            )?,
+            origins,
+        }))
+    }
+
+    fn applies_to(path: &str) -> bool {
+        path.starts_with("/api/public/") || path.starts_with("/api/token/")
+    }
+
+    /// `Access-Control-Allow-Origin` value for a request origin, if it is allowed.
+    fn allow_origin(&self, origin: &str) -> Option<axum::http::HeaderValue> {
+        if self.any_origin {
+            return Some(axum::http::HeaderValue::from_static("*"));
+        }
+        self.origins
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(origin))
+            .then(|| axum::http::HeaderValue::from_str(origin).ok())
+            .flatten()
+    }
+}
+
+/// Answers CORS preflights and tags responses of the public endpoints for allowed origins.
+/// Requests from other origins pass through untouched, so browsers block them as before.
+async fn cors_middleware(
+    State(state): State<Arc<AppState>>,
+    req: Request<Body>,
+    next: Next,
+) -> Response<Body> {
+    let Some(cors) = state.cors.clone() else {
+        return next.run(req).await;
+    };
+    if !CorsPolicy::applies_to(req.uri().path()) {
+        return next.run(req).await;
+    }
+    let Some(allow_origin) = req
+        .headers()
+        .get(axum::http::header::ORIGIN)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|origin| cors.allow_origin(origin))
+    else {
+        return next.run(req).await;
+    };
+
+    let preflight = req.method() == Method::OPTIONS
+        && req
+            .headers()
+            .contains_key(axum::http::header::ACCESS_CONTROL_REQUEST_METHOD);
+    let mut response = if preflight {
+        let mut response = StatusCode::NO_CONTENT.into_response();
+        let headers = response.headers_mut();
+        headers.insert(
+            axum::http::header::ACCESS_CONTROL_ALLOW_METHODS,
+            cors.methods.clone(),
+        );
+        headers.insert(
+            axum::http::header::ACCESS_CONTROL_ALLOW_HEADERS,
+            cors.headers.clone(),
+        );
+        headers.insert(
+            axum::http::header::ACCESS_CONTROL_MAX_AGE,
+            cors.max_age.clone(),
+        );
+        response
+    } else {
+        next.run(req).await
+    };
+    let headers = response.headers_mut();
+    headers.insert(
+        axum::http::header::ACCESS_CONTROL_ALLOW_ORIGIN,
+        allow_origin,
+    );
+    if !cors.any_origin {
+        headers.append(
+            axum::http::header::VARY,
+            axum::http::HeaderValue::from_static("origin"),
+        );
+    }
+    response
+}
+
+/// Upper bound on distinct IPs counted per window. When a newcomer arrives at the cap, the
+/// least recently seen [`PUBLIC_QUOTA_EVICT_BATCH`] entries are dropped, so a scan over many
+/// addresses cannot grow the map without bound or push real clients into a shared bucket.
+const PUBLIC_QUOTA_MAX_TRACKED_IPS: usize = 50_000;
+const PUBLIC_QUOTA_EVICT_BATCH: usize = PUBLIC_QUOTA_MAX_TRACKED_IPS / 16;
+const PUBLIC_QUOTA_WINDOW_SECS: i64 = 3600;
+const PUBLIC_QUOTA_TOP_CLIENTS: usize = 10;
+
+/// Soft per-IP request quota for the unauthenticated public endpoints
+/// (`PUBLIC_IP_HOURLY_LIMIT`), counted in memory over fixed clock-hour windows.
+#[derive(Debug)]
+struct PublicIpQuota {
+    limit: i64,
+    window: StdMutex<PublicIpWindow>,
+}
+
+#[derive(Debug, Default)]
+struct PublicIpWindow {
+    start: i64,
+    counts: HashMap<String, PublicIpCount>,
+    /// Bumped on every request; orders entries by recency for eviction.
+    seq: u64,
+    requests: i64,
+    rejected: i64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PublicIpCount {
+    requests: i64,
+    last_seen: u64,
+}
+
+impl PublicIpQuota {
+    fn new(limit: i64) -> Self {
+        Self {
+            limit,
+            window: StdMutex::new(PublicIpWindow::default()),
+        }
+    }
+
+    fn from_env() -> Self {
+        Self::new(effective_public_ip_hourly_limit())
+    }
+
+    fn applies_to(path: &str) -> bool {
+        path == "/api/public/metrics" || path == "/api/public/events" || path.starts_with("/claim/")
+    }
+
+    /// Count one request from `ip`; `false` once the IP is over the limit for this hour.
+    fn admit(&self, ip: &str, now: i64) -> bool {
+        let mut window = self.window.lock().unwrap_or_else(|e| e.into_inner());
+        window.roll(now);
+        if window.counts.len() >= PUBLIC_QUOTA_MAX_TRACKED_IPS && !window.counts.contains_key(ip) {
+            window.evict_oldest(PUBLIC_QUOTA_EVICT_BATCH);
+        }
+        window.seq += 1;
+        let seq = window.seq;
+        let entry = window
+            .counts
+            .entry(ip.to_string())
+            .or_insert(PublicIpCount {
+                requests: 0,
+                last_seen: seq,
+            });
+        entry.requests += 1;
+        entry.last_seen = seq;
+        let count = entry.requests;
+        let over = self.limit > 0 && count > self.limit;
+        window.requests += 1;
+        if over {
+            window.rejected += 1;
+        }
+        !over
+    }
+
+    fn snapshot(&self, now: i64) -> PublicQuotaView {
+        let mut window = self.window.lock().unwrap_or_else(|e| e.into_inner());
+        window.roll(now);
+        let mut top: Vec<(&String, i64)> = window
+            .counts
+            .iter()
+            .map(|(ip, count)| (ip, count.requests))
+            .collect();
+        top.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        PublicQuotaView {
+            hourly_limit: self.limit,
+            window_start: window.start,
+            tracked_ips: window.counts.len() as i64,
+            window_requests: window.requests,
+            window_rejected: window.rejected,
+            top_clients: top
+                .into_iter()
+                .take(PUBLIC_QUOTA_TOP_CLIENTS)
+                .map(|(ip, requests)| PublicQuotaClientView {
+                    // Same hashing as the access log: enough to spot a scraper, not to name it.
+                    client: hash_client_ip(ip),
+                    requests,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl PublicIpWindow {
+    /// Drops the `batch` least recently seen IPs.
+    fn evict_oldest(&mut self, batch: usize) {
+        let mut seen: Vec<u64> = self.counts.values().map(|count| count.last_seen).collect();
+        if seen.is_empty() || batch == 0 {
+            return;
+        }
+        let cut = batch.min(seen.len()) - 1;
+        let (_, threshold, _) = seen.select_nth_unstable(cut);
+        let threshold = *threshold;
+        self.counts.retain(|_, count| count.last_seen > threshold);
+    }
+
+    fn roll(&mut self, now: i64) {
+        let start = now - now.rem_euclid(PUBLIC_QUOTA_WINDOW_SECS);
+        if start != self.start {
+            *self = Self {
+                start,
+                ..Self::default()
+            };
+        }
+    }
+}
+
+/// Enforces [`PublicIpQuota`] on the public metrics endpoints.
+async fn public_quota_middleware(
+    State(state): State<Arc<AppState>>,
+    req: Request<Body>,
+    next: Next,
+) -> Response<Body> {
+    if !PublicIpQuota::applies_to(req.uri().path()) {
+        return next.run(req).await;
+    }
+    let peer = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|info| info.0);
+    let ip = state
+        .trusted_proxies
+        .client_ip(req.headers(), peer)
+        .unwrap_or_else(|| "unknown".to_string());
+    let now = Utc::now().timestamp();
+    if state.public_quota.admit(&ip, now) {
+        return next.run(req).await;
+    }
+    let retry_after = PUBLIC_QUOTA_WINDOW_SECS - now.rem_euclid(PUBLIC_QUOTA_WINDOW_SECS);
+    let payload = json!({
+        "error": "rate_limited",
+        "message": "too many requests from this address; retry next hour",
+    });
+    Response::builder()
+        .status(StatusCode::TOO_MANY_REQUESTS)
+        .header(CONTENT_TYPE, "application/json; charset=utf-8")
+        .header(axum::http::header::RETRY_AFTER, retry_after.to_string())
+        .body(Body::from(payload.to_string()))
+        .unwrap_or_else(|_| StatusCode::TOO_MANY_REQUESTS.into_response())
+}
+
+/// Per-client token buckets for `/api/*` (`--rate-limit-rps` / `--rate-limit-burst`).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RateLimitConfig {
+    /// Tokens refilled per second; 0 disables the limiter.
+    pub requests_per_sec: f64,
+    /// Bucket size; 0 means twice the per-second rate, at least 1.
+    pub burst: u32,
+}
+
+impl RateLimitConfig {
+    pub fn from_env() -> Self {
+        Self {
+            requests_per_sec: effective_rate_limit_rps(),
+            burst: effective_rate_limit_burst(),
+        }
+    }
+
+    fn capacity(&self) -> f64 {
+        if self.burst > 0 {
+            f64::from(self.burst)
+        } else {
+            (self.requests_per_sec * 2.0).ceil().max(1.0)
+        }
+    }
+}
+
+/// Clients tracked before idle (full) buckets are dropped.
+const RATE_LIMIT_MAX_TRACKED_CLIENTS: usize = 10_000;
+
+#[derive(Debug)]
+struct ClientRateLimiter {
+    rate: f64,
+    capacity: f64,
+    buckets: StdMutex<HashMap<String, RateBucket>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct RateBucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl ClientRateLimiter {
+    fn new(config: RateLimitConfig) -> Option<Self> {
+        (config.requests_per_sec > 0.0).then(|| Self {
+            rate: config.requests_per_sec,
+            capacity: config.capacity(),
+            buckets: StdMutex::new(HashMap::new()),
+        })
+    }
+
+    fn applies_to(path: &str) -> bool {
+        path.starts_with("/api/") && !path.starts_with("/api/tavily/")
+    }
+
+    /// Take one token from `client`'s bucket; `Err` carries the seconds until one is available.
+    fn acquire(&self, client: &str, now: Instant) -> Result<(), u64> {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if buckets.len() >= RATE_LIMIT_MAX_TRACKED_CLIENTS && !buckets.contains_key(client) {
+            let (rate, capacity) = (self.rate, self.capacity);
+            buckets.retain(|_, bucket| {
+                let idle = now.saturating_duration_since(bucket.refilled).as_secs_f64();
+                bucket.tokens + idle * rate < capacity
+            });
+        }
+        let bucket = buckets.entry(client.to_string()).or_insert(RateBucket {
+            tokens: self.capacity,
+            refilled: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.capacity);
+        bucket.refilled = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - bucket.tokens) / self.rate).ceil().max(1.0) as u64)
+        }
+    }
+}
+
+/// Applies [`ClientRateLimiter`] to admin and public API calls, keyed by the forward-auth
+/// user when the header is present and by client IP otherwise.
+async fn rate_limit_middleware(
+    State(state): State<Arc<AppState>>,
+    req: Request<Body>,
+    next: Next,
+) -> Response<Body> {
+    let Some(limiter) = state.rate_limiter.as_ref() else {
+        return next.run(req).await;
+    };
+    if !ClientRateLimiter::applies_to(req.uri().path()) {
+        return next.run(req).await;
+    }
+    let client = match state.forward_auth.user_value(req.headers()) {
+        Some(user) => format!("user:{user}"),
+        None => {
+            let peer = req
+                .extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|info| info.0);
+            let ip = state
+                .trusted_proxies
+                .client_ip(req.headers(), peer)
+                .unwrap_or_else(|| "unknown".to_string());
+            format!("ip:{ip}")
+        }
+    };
+    match limiter.acquire(&client, Instant::now()) {
+        Ok(()) => next.run(req).await,
+        Err(retry_after) => {
+            let payload = json!({
+                "error": "rate_limited",
+                "message": "too many requests; slow down",
+            });
+            Response::builder()
+                .status(StatusCode::TOO_MANY_REQUESTS)
+                .header(CONTENT_TYPE, "application/json; charset=utf-8")
+                .header(axum::http::header::RETRY_AFTER, retry_after.to_string())
+                .body(Body::from(payload.to_string()))
+                .unwrap_or_else(|_| StatusCode::TOO_MANY_REQUESTS.into_response())
+        }
+    }
+}
+
+fn is_proxied_path(path: &str) -> bool {
+    path == "/mcp"
+        || path.starts_with("/mcp/")
+        || path.starts_with("/api/tavily/")
+        || path.starts_with("/v1/")
+}
+
+/// Opens one `proxy_request` span per proxied call. The proxy fills in `key_id`, `token_id`
+/// and `outcome` while forwarding; status and latency are recorded once headers are ready.
+async fn request_span_middleware(req: Request<Body>, next: Next) -> Response<Body> {
+    if !is_proxied_path(req.uri().path()) {
+        return next.run(req).await;
+    }
+    let span = tracing::info_span!(
+        "proxy_request",
+        method = %req.method(),
+        path = req.uri().path(),
+        token_id = tracing::field::Empty,
+        key_id = tracing::field::Empty,
+        outcome = tracing::field::Empty,
+        status = tracing::field::Empty,
+        latency_ms = tracing::field::Empty,
+    );
+    let started = Instant::now();
+    let response = next.run(req).instrument(span.clone()).await;
+    let status = response.status().as_u16();
+    let latency_ms = started.elapsed().as_millis() as u64;
+    span.record("status", status);
+    span.record("latency_ms", latency_ms);
+    span.in_scope(|| tracing::info!(status, latency_ms, "request completed"));
+    response
+}
+
+/// Emits one JSON line per HTTP request. Query strings are left out since they may carry
+/// tokens; latency is measured until response headers are ready.
+async fn access_log_middleware(
+    State(state): State<Arc<AppState>>,
+    req: Request<Body>,
+    next: Next,
+) -> Response<Body> {
+    let Some(log) = state.access_log.clone() else {
+        return next.run(req).await;
+    };
+    let started = Instant::now();
+    let method = req.method().to_string();
+    let path = req.uri().path().to_string();
+    let peer = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|info| info.0);
+    let client_ip_hash = state
+        .trusted_proxies
+        .client_ip(req.headers(), peer)
+        .map(|ip| hash_client_ip(&ip));
+    let admin = if state.forward_auth.is_request_admin(req.headers()) {
+        state
+            .forward_auth
+            .user_value(req.headers())
+            .map(str::to_string)
+    } else {
+        None
+    };
+
+    let response = next.run(req).await;
+
+    let line = json!({
+        "ts": Utc::now().to_rfc3339(),
+        "method": method,
+        "path": path,
+        "status": response.status().as_u16(),
+        "latency_ms": started.elapsed().as_millis() as u64,
+        "client_ip_hash": client_ip_hash,
+        "admin": admin,
+    });
+    log.write(&line.to_string());
+    response
+}
+
+async fn wait_for_ctrl_c() -> &'static str {
+    match signal::ctrl_c().await {
+        Ok(()) => "ctrl_c",
+        Err(err) => {
+            tracing::error!("Failed to listen for Ctrl+C: {err}");
+            "ctrl_c_error"
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn wait_for_sigterm() -> &'static str {
+    match unix_signal(SignalKind::terminate()) {
+        Ok(mut sigterm) => {
+            sigterm.recv().await;
+            "sigterm"
+        }
+        Err(err) => {
+            tracing::error!("Failed to listen for SIGTERM: {err}");
+            wait_for_ctrl_c().await
+        }
+    }
+}
+
+async fn shutdown_signal() {
+    let signal = {
+        #[cfg(unix)]
+        {
+            tokio::select! {
+                reason = wait_for_ctrl_c() => reason,
+                reason = wait_for_sigterm() => reason,
+            }
+        }
+
+        #[cfg(not(unix))]
+        {
+            wait_for_ctrl_c().await
+        }
+    };
+
+    tracing::info!(
+        "Shutdown signal ({signal}) received, waiting for in-flight requests to finish..."
+    );
+}
+
+const BODY_LIMIT: usize = 16 * 1024 * 1024; // 16 MiB 默认限制
+const DEFAULT_LOG_LIMIT: usize = 200;
+
+#[derive(Debug, Serialize)]
+struct ApiKeyView {
+    id: String,
+    status: String,
+    status_changed_at: Option<i64>,
+    last_used_at: Option<i64>,
+    deleted_at: Option<i64>,
+    quota_limit: Option<i64>,
+    quota_remaining: Option<i64>,
+    quota_synced_at: Option<i64>,
+    quota_next_sync_at: Option<i64>,
+    total_requests: i64,
+    success_count: i64,
+    error_count: i64,
+    quota_exhausted_count: i64,
+    projected_month_usage: Option<i64>,
+    projected_overage: Option<i64>,
+    exhausts_at: Option<i64>,
+    in_flight: i64,
+    cooldown_until: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+struct ApiKeySecretView {
+    api_key: String,
+}
+
+#[derive(Debug, Serialize)]
+struct RequestLogView {
+    id: i64,
+    public_id: Option<String>,
+    key_id: String,
+    auth_token_id: Option<String>,
+    method: String,
+    path: String,
+    query: Option<String>,
+    http_status: Option<i64>,
+    mcp_status: Option<i64>,
+    result_status: String,
+    created_at: i64,
+    error_message: Option<String>,
+    request_body: Option<String>,
+    response_body: Option<String>,
+    forwarded_headers: Vec<String>,
+    dropped_headers: Vec<String>,
+    timeout_ms: Option<i64>,
+    latency_ms: Option<i64>,
+    attempt: i64,
+    response_summary: Option<String>,
+    cache_hits: i64,
+    request_body_sha256: Option<String>,
+    request_body_len: Option<i64>,
+    response_body_sha256: Option<String>,
+    response_body_len: Option<i64>,
+    /// Set on the detail endpoint only, where the bodies are loaded and re-hashed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bodies_intact: Option<bool>,
+    /// Admin notes; detail endpoint only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    annotations: Option<Vec<LogAnnotationView>>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JobLogView {
+    id: i64,
+    public_id: Option<String>,
+    job_type: String,
+    key_id: Option<String>,
+    status: String,
+    attempt: i64,
+    message: Option<String>,
+    started_at: i64,
+    finished_at: Option<i64>,
+}
+
+impl From<JobLog> for JobLogView {
+    fn from(j: JobLog) -> Self {
+        Self {
+            id: j.id,
+            public_id: j.public_id,
+            job_type: j.job_type,
+            key_id: j.key_id,
+            status: j.status,
+            attempt: j.attempt,
+            message: j.message,
+            started_at: j.started_at,
+            finished_at: j.finished_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct SummaryView {
+    total_requests: i64,
+    success_count: i64,
+    error_count: i64,
+    quota_exhausted_count: i64,
+    active_keys: i64,
+    exhausted_keys: i64,
+    last_activity: Option<i64>,
+    total_quota_limit: i64,
+    total_quota_remaining: i64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PublicMetricsView {
+    monthly_success: i64,
+    daily_success: i64,
+}
+
+// ---- Access Token views ----
+#[derive(Debug, Serialize)]
+struct AuthTokenView {
+    id: String,
+    enabled: bool,
+    note: Option<String>,
+    group: Option<String>,
+    total_requests: i64,
+    created_at: i64,
+    last_used_at: Option<i64>,
+    priority: String,
+    upstream_override: Option<String>,
+    key_tag: Option<String>,
+    key_tag_fallback: bool,
+    allowed_tools: Option<Vec<String>>,
+    tier: String,
+    expires_at: Option<i64>,
+    quota_state: String,
+    quota_hourly_used: i64,
+    quota_hourly_limit: i64,
+    quota_daily_used: i64,
+    quota_daily_limit: i64,
+    quota_monthly_used: i64,
+    quota_monthly_limit: i64,
+    quota_hourly_reset_at: Option<i64>,
+    quota_daily_reset_at: Option<i64>,
+    quota_monthly_reset_at: Option<i64>,
+    /// Only set on soft-deleted tokens, listed with `include_deleted=true`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deleted_at: Option<i64>,
+    /// Only populated by the token detail endpoint.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sla: Option<TokenSlaView>,
+    /// Only populated by the token detail endpoint, for tokens created as invites.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    claim: Option<TokenClaimView>,
+}
+
+#[derive(Debug, Serialize)]
+struct TokenClaimView {
+    /// `pending`, `claimed` or `expired`.
+    state: &'static str,
+    expires_at: Option<i64>,
+    claimed_at: Option<i64>,
+    claimed_from: Option<String>,
+}
+
+impl TokenClaimView {
+    fn new(claim: TokenClaim, now: i64) -> Self {
+        let state = if claim.claimed_at.is_some() {
+            "claimed"
+        } else if claim.expires_at.is_some_and(|at| at <= now) {
+            "expired"
+        } else {
+            "pending"
+        };
+        Self {
+            state,
+            expires_at: claim.expires_at,
+            claimed_at: claim.claimed_at,
+            claimed_from: claim.claimed_from,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct TokenSlaView {
+    window_days: i64,
+    success_count: i64,
+    system_failure_count: i64,
+    external_failure_count: i64,
+    quota_exhausted_count: i64,
+    success_rate: Option<f64>,
+    p95_latency_ms: Option<i64>,
+}
+
+impl From<TokenSla> for TokenSlaView {
+    fn from(s: TokenSla) -> Self {
+        Self {
+            window_days: s.window_days,
+            success_count: s.success_count,
+            system_failure_count: s.system_failure_count,
+            external_failure_count: s.external_failure_count,
+            quota_exhausted_count: s.quota_exhausted_count,
+            success_rate: s.success_rate,
+            p95_latency_ms: s.p95_latency_ms,
+        }
+    }
+}
+
+impl From<AuthToken> for AuthTokenView {
+    fn from(t: AuthToken) -> Self {
+        let (
+            quota_state,
+            quota_hourly_used,
+            quota_hourly_limit,
+            quota_daily_used,
+            quota_daily_limit,
+            quota_monthly_used,
+            quota_monthly_limit,
+        ) = if let Some(quota) = t.quota {
+            (
+                quota.state_key().to_string(),
+                quota.hourly_used,
+                quota.hourly_limit,
+                quota.daily_used,
+                quota.daily_limit,
+                quota.monthly_used,
+                quota.monthly_limit,
+            )
+        } else {
+            (
+                "normal".to_string(),
+                0,
+                effective_token_hourly_limit(),
+                0,
+                effective_token_daily_limit(),
+                0,
+                effective_token_monthly_limit(),
+            )
+        };
+        Self {
+            id: t.id,
+            enabled: t.enabled,
+            note: t.note,
+            group: t.group_name,
+            total_requests: t.total_requests,
+            created_at: t.created_at,
+            last_used_at: t.last_used_at,
+            priority: t.priority.as_str().to_string(),
+            upstream_override: t.upstream_override,
+            key_tag: t.key_tag,
+            key_tag_fallback: t.key_tag_fallback,
+            allowed_tools: t.allowed_tools,
+            expires_at: t.expires_at,
+            tier: t.tier.unwrap_or_else(|| TOKEN_TIER_DEFAULT.to_string()),
+            quota_state,
+            quota_hourly_used,
+            quota_hourly_limit,
+            quota_daily_used,
+            quota_daily_limit,
+            quota_monthly_used,
+            quota_monthly_limit,
+            quota_hourly_reset_at: t.quota_hourly_reset_at,
+            quota_daily_reset_at: t.quota_daily_reset_at,
+            quota_monthly_reset_at: t.quota_monthly_reset_at,
+            deleted_at: t.deleted_at,
+            sla: None,
+            claim: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct AuthTokenSecretView {
+    token: String,
+}
+
+// ---- Token Detail views ----
+#[derive(Debug, Serialize)]
+struct TokenSummaryView {
+    total_requests: i64,
+    success_count: i64,
+    error_count: i64,
+    quota_exhausted_count: i64,
+    last_activity: Option<i64>,
+}
+
+impl From<TokenSummary> for TokenSummaryView {
+    fn from(s: TokenSummary) -> Self {
+        Self {
+            total_requests: s.total_requests,
+            success_count: s.success_count,
+            error_count: s.error_count,
+            quota_exhausted_count: s.quota_exhausted_count,
+            last_activity: s.last_activity,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct TokenLogView {
+    id: i64,
+    public_id: Option<String>,
+    method: String,
+    path: String,
+    query: Option<String>,
+    http_status: Option<i64>,
+    mcp_status: Option<i64>,
+    result_status: String,
+    error_message: Option<String>,
+    created_at: i64,
+    /// Admin notes; detail endpoint only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    annotations: Option<Vec<LogAnnotationView>>,
+}
+
+impl From<TokenLogRecord> for TokenLogView {
+    fn from(r: TokenLogRecord) -> Self {
+        Self {
+            id: r.id,
+            public_id: r.public_id,
+            method: r.method,
+            path: r.path,
+            query: r.query,
+            http_status: r.http_status,
+            mcp_status: r.mcp_status,
+            result_status: r.result_status,
+            error_message: r.error_message,
+            created_at: r.created_at,
+            annotations: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateTokenRequest {
+    note: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LogsQuery {
+    page: Option<i64>,
+    per_page: Option<i64>,
+    result: Option<String>,
+    cursor: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LogsExportQuery {
+    format: Option<String>,
+    since: Option<String>,
+    until: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct KeyMetricsQuery {
+    period: Option<String>,
+    since: Option<i64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/keys/{id}/metrics",
+    tag = "keys",
+    summary = "Usage metrics of a key.",
+    params(
+        ("id" = String, Path),
+        ("period" = Option<String>, Query),
+        ("since" = Option<String>, Query),
+    ),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_key_metrics(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    Query(q): Query<KeyMetricsQuery>,
+) -> Result<Json<SummaryView>, StatusCode> {
+    let scope = manage_scope(&state, &headers)?;
+    ensure_key_in_scope(&state, &scope, &id).await?;
+    let since = if let Some(since) = q.since {
+        since
+    } else {
+        // fallback by period
+        let now = chrono::Local::now();
+        let local_midnight_ts = |date: chrono::NaiveDate| -> i64 {
+            let naive = date.and_hms_opt(0, 0, 0).expect("valid midnight");
+            match chrono::Local.from_local_datetime(&naive) {
+                chrono::LocalResult::Single(dt) => dt.with_timezone(&Utc).timestamp(),
+                chrono::LocalResult::Ambiguous(dt, _) => dt.with_timezone(&Utc).timestamp(),
+                chrono::LocalResult::None => now.with_timezone(&Utc).timestamp(),
+            }
+        };
+        match q.period.as_deref() {
+            Some("day") => local_midnight_ts(now.date_naive()),
+            Some("week") => {
+                let weekday = now.weekday().num_days_from_monday() as i64;
+                let start = (now - chrono::Duration::days(weekday)).date_naive();
+                local_midnight_ts(start)
+            }
+            _ => {
+                // month default
+                let first =
+                    chrono::NaiveDate::from_ymd_opt(now.year(), now.month(), 1).expect("valid");
+                local_midnight_ts(first)
+            }
+        }
+    };
+
+    state
+        .proxy
+        .key_summary_since(&id, since)
+        .await
+        .map(|s| Json(s.into()))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+#[derive(Debug, Deserialize)]
+struct KeyLogsQuery {
+    limit: Option<usize>,
+    since: Option<i64>,
+    cursor: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/keys/{id}/logs",
+    tag = "logs",
+    summary = "Request logs of a key.",
+    params(
+        ("id" = String, Path),
+        ("cursor" = Option<String>, Query),
+        ("limit" = Option<String>, Query),
+        ("since" = Option<String>, Query),
+    ),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_key_logs(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    Query(q): Query<KeyLogsQuery>,
+) -> Result<Response<Body>, StatusCode> {
+    let scope = manage_scope(&state, &headers)?;
+    ensure_key_in_scope(&state, &scope, &id).await?;
+    let limit = q.limit.unwrap_or(DEFAULT_LOG_LIMIT).clamp(1, 500);
+    if let Some(cursor) = parse_cursor_param(q.cursor.as_deref())? {
+        return state
+            .proxy
+            .key_logs_after(&id, q.since, cursor, limit as i64)
+            .await
+            .map(|page| {
+                Json(CursorPageView {
+                    items: page.items.into_iter().map(RequestLogView::from).collect(),
+                    next_cursor: page.next_cursor.map(|c| c.encode()),
+                    per_page: limit as i64,
+                })
+                .into_response()
+            })
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    state
+        .proxy
+        .key_recent_logs(&id, limit, q.since)
+        .await
+        .map(|logs| {
+            Json(
+                logs.into_iter()
+                    .map(RequestLogView::from)
+                    .collect::<Vec<_>>(),
+            )
+            .into_response()
+        })
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+// ---- Token detail endpoints ----
+
+#[derive(Debug, Deserialize)]
+struct TokenMetricsQuery {
+    period: Option<String>,
+    since: Option<String>,
+    until: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/tokens/{id}/metrics",
+    tag = "tokens",
+    summary = "Usage metrics of a token.",
+    params(
+        ("id" = String, Path),
+        ("period" = Option<String>, Query),
+        ("since" = Option<String>, Query),
+        ("until" = Option<String>, Query),
+    ),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_token_metrics(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Query(q): Query<TokenMetricsQuery>,
+) -> Result<Json<TokenSummaryView>, StatusCode> {
+    let scope = manage_scope(&state, &headers)?;
+    ensure_token_in_scope(&state, &scope, &id).await?;
+    let since = q
+        .since
+        .as_deref()
+        .and_then(parse_iso_timestamp)
+        .unwrap_or_else(|| default_since(q.period.as_deref()));
+    let until = q
+        .until
+        .as_deref()
+        .and_then(parse_iso_timestamp)
+        .unwrap_or_else(|| default_until(q.period.as_deref(), since));
+
+    state
+        .proxy
+        .token_summary_since(&id, since, Some(until))
+        .await
+        .map(|s| Json(s.into()))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenLogsQuery {
+    limit: Option<usize>,
+    before: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenHourlyQuery {
+    hours: Option<i64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/tokens/{id}/logs/{log_id}",
+    tag = "logs",
+    summary = "One token log with annotations.",
+    params(("id" = String, Path), ("log_id" = String, Path)),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_token_log_detail(
+    State(state): State<Arc<AppState>>,
+    Path((token_id, log_id)): Path<(String, i64)>,
+    headers: HeaderMap,
+) -> Result<Json<TokenLogView>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let record = match state.proxy.token_log(&token_id, log_id).await {
+        Ok(Some(record)) => record,
+        Ok(None) => return Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            tracing::error!("get token log detail error: {err}");
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let annotations = match state.proxy.log_annotations(LogKind::Token, log_id).await {
+        Ok(annotations) => annotations,
+        Err(err) => {
+            tracing::error!("get token log annotations error: {err}");
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let mut view = TokenLogView::from(record);
+    if let Some(err) = view.error_message.as_ref() {
+        view.error_message = Some(redact_sensitive(err));
+    }
+    view.annotations = Some(
+        annotations
+            .into_iter()
+            .map(LogAnnotationView::from)
+            .collect(),
+    );
+    Ok(Json(view))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/tokens/{id}/logs",
+    tag = "logs",
+    summary = "Recent logs of a token.",
+    params(
+        ("id" = String, Path),
+        ("limit" = Option<String>, Query),
+        ("before" = Option<String>, Query),
+    ),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_token_logs(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Query(q): Query<TokenLogsQuery>,
+) -> Result<Json<Vec<TokenLogView>>, StatusCode> {
+    let scope = manage_scope(&state, &headers)?;
+    ensure_token_in_scope(&state, &scope, &id).await?;
+    let limit = q.limit.unwrap_or(DEFAULT_LOG_LIMIT).clamp(1, 500);
+    state
+        .proxy
+        .token_recent_logs(&id, limit, q.before)
+        .await
+        .map(|logs| {
+            let mapped: Vec<TokenLogView> = logs
+                .into_iter()
+                .map(TokenLogView::from)
+                .map(|mut v| {
+                    if let Some(err) = v.error_message.as_ref() {
+                        v.error_message = Some(redact_sensitive(err));
+                    }
+                    v
+                })
+                .collect();
+            Json(mapped)
+        })
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenLogsPageQuery {
+    page: Option<usize>,
+    per_page: Option<usize>,
+    since: Option<String>,
+    until: Option<String>,
+    cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct TokenLogsPageView {
+    items: Vec<TokenLogView>,
+    page: usize,
+    per_page: usize,
+    total: i64,
+}
+
+#[derive(Debug, Serialize)]
+struct TokenHourlyBucketView {
+    bucket_start: i64,
+    success_count: i64,
+    system_failure_count: i64,
+    external_failure_count: i64,
+}
+
+#[derive(Debug, Serialize)]
+struct TokenUsageBucketView {
+    bucket_start: i64,
+    success_count: i64,
+    system_failure_count: i64,
+    external_failure_count: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenLeaderboardQuery {
+    period: Option<String>,
+    focus: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct TokenLeaderboardItemView {
+    id: String,
+    enabled: bool,
+    note: Option<String>,
+    group: Option<String>,
+    total_requests: i64,
+    last_used_at: Option<i64>,
+    quota_state: String,
+    // Business quota windows (tools/call)
+    quota_hourly_used: i64,
+    quota_hourly_limit: i64,
+    quota_daily_used: i64,
+    quota_daily_limit: i64,
+    // Hourly raw request limiter (any authenticated request)
+    hourly_any_used: i64,
+    hourly_any_limit: i64,
+    today_total: i64,
+    today_errors: i64,
+    today_other: i64,
+    month_total: i64,
+    month_errors: i64,
+    month_other: i64,
+    all_total: i64,
+    all_errors: i64,
+    all_other: i64,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/tokens/{id}/logs/page",
+    tag = "logs",
+    summary = "Paged logs of a token.",
+    params(
+        ("id" = String, Path),
+        ("cursor" = Option<String>, Query),
+        ("page" = Option<String>, Query),
+        ("per_page" = Option<String>, Query),
+        ("since" = Option<String>, Query),
+        ("until" = Option<String>, Query),
+    ),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_token_logs_page(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Query(q): Query<TokenLogsPageQuery>,
+) -> Result<Response<Body>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let page = q.page.unwrap_or(1).max(1);
+    let per_page = q.per_page.unwrap_or(20).clamp(1, 200);
+    let since = q
+        .since
+        .as_deref()
+        .and_then(parse_iso_timestamp)
+        .unwrap_or_else(|| default_since(Some("month")));
+    let until = q
+        .until
+        .as_deref()
+        .and_then(parse_iso_timestamp)
+        .unwrap_or_else(|| default_until(Some("month"), since));
+    if until <= since {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let redacted = |items: Vec<TokenLogRecord>| -> Vec<TokenLogView> {
+        items
+            .into_iter()
+            .map(TokenLogView::from)
+            .map(|mut v| {
+                if let Some(err) = v.error_message.as_ref() {
+                    v.error_message = Some(redact_sensitive(err));
+                }
+                v
+            })
+            .collect()
+    };
+    if let Some(cursor) = parse_cursor_param(q.cursor.as_deref())? {
+        return state
+            .proxy
+            .token_logs_after(&id, since, Some(until), cursor, per_page as i64)
+            .await
+            .map(|page| {
+                Json(CursorPageView {
+                    items: redacted(page.items),
+                    next_cursor: page.next_cursor.map(|c| c.encode()),
+                    per_page: per_page as i64,
+                })
+                .into_response()
+            })
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    // Deprecated: OFFSET paging; prefer `cursor`.
+    state
+        .proxy
+        .token_logs_page(&id, page, per_page, since, Some(until))
+        .await
+        .map(|(items, total)| {
+            Json(TokenLogsPageView {
+                items: redacted(items),
+                page,
+                per_page,
+                total,
+            })
+            .into_response()
+        })
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/tokens/{id}/metrics/hourly",
+    tag = "tokens",
+    summary = "Hourly breakdown of a token.",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_token_hourly_breakdown(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Query(q): Query<TokenHourlyQuery>,
+) -> Result<Json<Vec<TokenHourlyBucketView>>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let hours = q.hours.unwrap_or(25);
+    state
+        .proxy
+        .token_hourly_breakdown(&id, hours)
+        .await
+        .map(|buckets| {
+            Json(
+                buckets
+                    .into_iter()
+                    .map(
+                        |TokenHourlyBucket {
+                             bucket_start,
+                             success_count,
+                             system_failure_count,
+                             external_failure_count,
+                         }| TokenHourlyBucketView {
+                            bucket_start,
+                            success_count,
+                            system_failure_count,
+                            external_failure_count,
+                        },
+                    )
+                    .collect(),
+            )
+        })
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+#[derive(Debug, Deserialize)]
+struct UsageSeriesQuery {
+    since: Option<String>,
+    until: Option<String>,
+    bucket_secs: Option<i64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/tokens/{id}/metrics/usage-series",
+    tag = "tokens",
+    summary = "Usage time series of a token.",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_token_usage_series(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Query(q): Query<UsageSeriesQuery>,
+) -> Result<Json<Vec<TokenUsageBucketView>>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    token_usage_series_view(&state, &id, &q).await
+}
+
+async fn token_usage_series_view(
+    state: &AppState,
+    id: &str,
+    q: &UsageSeriesQuery,
+) -> Result<Json<Vec<TokenUsageBucketView>>, StatusCode> {
+    let now = Utc::now().timestamp();
+    let until = q
+        .until
+        .as_deref()
+        .and_then(parse_iso_timestamp)
+        .unwrap_or(now);
+    let default_since = until - ChronoDuration::hours(25).num_seconds();
+    let since = q
+        .since
+        .as_deref()
+        .and_then(parse_iso_timestamp)
+        .unwrap_or(default_since);
+    if until <= since {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let bucket_secs = q
+        .bucket_secs
+        .unwrap_or(ChronoDuration::hours(1).num_seconds());
+    state
+        .proxy
+        .token_usage_series(id, since, until, bucket_secs)
+        .await
+        .map(|series| {
+            Json(
+                series
+                    .into_iter()
+                    .map(
+                        |TokenUsageBucket {
+                             bucket_start,
+                             success_count,
+                             system_failure_count,
+                             external_failure_count,
+                         }| TokenUsageBucketView {
+                            bucket_start,
+                            success_count,
+                            system_failure_count,
+                            external_failure_count,
+                        },
+                    )
+                    .collect(),
+            )
+        })
+        .map_err(|err| match err {
+            ProxyError::Other(_) => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        })
+}
+
+#[derive(Debug, Deserialize)]
+struct BurndownQuery {
+    since: Option<String>,
+    until: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AvailabilityDayView {
+    day_start: i64,
+    total_minutes: i64,
+    available_minutes: i64,
+    no_active_key_minutes: i64,
+    low_success_minutes: i64,
+    requests: i64,
+    successes: i64,
+    threshold_percent: i64,
+    computed_at: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct LatencyQuery {
+    window: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LatencyPercentilesView {
+    count: i64,
+    p50_ms: Option<i64>,
+    p95_ms: Option<i64>,
+    p99_ms: Option<i64>,
+}
+
+impl From<LatencyPercentiles> for LatencyPercentilesView {
+    fn from(p: LatencyPercentiles) -> Self {
+        Self {
+            count: p.count,
+            p50_ms: p.p50_ms,
+            p95_ms: p.p95_ms,
+            p99_ms: p.p99_ms,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct KeyLatencyView {
+    key_id: String,
+    #[serde(flatten)]
+    latency: LatencyPercentilesView,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LatencyReportView {
+    window_secs: i64,
+    since: i64,
+    overall: LatencyPercentilesView,
+    keys: Vec<KeyLatencyView>,
+}
+
+/// Parses a `<n>m`, `<n>h` or `<n>d` window into seconds.
+fn parse_window_secs(raw: &str) -> Option<i64> {
+    let raw = raw.trim();
+    let unit = match raw.chars().last()? {
+        'm' => 60,
+        'h' => 3600,
+        'd' => 24 * 3600,
+        _ => return None,
+    };
+    let count: i64 = raw[..raw.len() - 1].parse().ok()?;
+    (count > 0).then(|| count.checked_mul(unit)).flatten()
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/metrics/latency",
+    tag = "analytics",
+    summary = "Upstream latency percentiles.",
+    params(("window" = Option<String>, Query)),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_latency_metrics(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(q): Query<LatencyQuery>,
+) -> Result<Json<LatencyReportView>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let window_secs =
+        parse_window_secs(q.window.as_deref().unwrap_or("24h")).ok_or(StatusCode::BAD_REQUEST)?;
+    let since = Utc::now().timestamp().saturating_sub(window_secs);
+    match state.proxy.latency_report(since).await {
+        Ok(report) => Ok(Json(LatencyReportView {
+            window_secs,
+            since: report.since,
+            overall: report.overall.into(),
+            keys: report
+                .keys
+                .into_iter()
+                .map(|key| KeyLatencyView {
+                    key_id: key.key_id,
+                    latency: key.latency.into(),
+                })
+                .collect(),
+        })),
+        Err(err) => {
+            tracing::error!("get_latency_metrics error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AvailabilityReportView {
+    since: i64,
+    until: i64,
+    total_minutes: i64,
+    available_minutes: i64,
+    /// Available share of the reported minutes in percent; `None` when no day is reported.
+    availability_percent: Option<f64>,
+    days: Vec<AvailabilityDayView>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/reports/availability",
+    tag = "jobs",
+    summary = "Daily availability report.",
+    params(("since" = Option<String>, Query), ("until" = Option<String>, Query)),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_availability_report(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(q): Query<BurndownQuery>,
+) -> Result<Json<AvailabilityReportView>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let now = Utc::now().timestamp();
+    let until = q
+        .until
+        .as_deref()
+        .and_then(parse_iso_timestamp)
+        .unwrap_or(now + 1);
+    let since = q
+        .since
+        .as_deref()
+        .and_then(parse_iso_timestamp)
+        .unwrap_or(until - 30 * 24 * 3600);
+    if until <= since {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    match state.proxy.availability_report(since, until).await {
+        Ok(days) => {
+            let total_minutes: i64 = days.iter().map(|d| d.total_minutes).sum();
+            let available_minutes: i64 = days.iter().map(|d| d.available_minutes).sum();
+            Ok(Json(AvailabilityReportView {
+                since,
+                until,
+                total_minutes,
+                available_minutes,
+                availability_percent: (total_minutes > 0)
+                    .then(|| available_minutes as f64 * 100.0 / total_minutes as f64),
+                days: days
+                    .into_iter()
+                    .map(|d| AvailabilityDayView {
+                        day_start: d.day_start,
+                        total_minutes: d.total_minutes,
+                        available_minutes: d.available_minutes,
+                        no_active_key_minutes: d.no_active_key_minutes,
+                        low_success_minutes: d.low_success_minutes,
+                        requests: d.requests,
+                        successes: d.successes,
+                        threshold_percent: d.threshold_percent,
+                        computed_at: d.computed_at,
+                    })
+                    .collect(),
+            }))
+        }
+        Err(err) => {
+            tracing::error!("get_availability_report error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct TokenQuotaSnapshotView {
+    snapshot_at: i64,
+    monthly_used: i64,
+    monthly_limit: i64,
+    monthly_remaining: i64,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/tokens/{id}/metrics/burndown",
+    tag = "tokens",
+    summary = "Monthly quota burndown of a token.",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_token_quota_burndown(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Query(q): Query<BurndownQuery>,
+) -> Result<Json<Vec<TokenQuotaSnapshotView>>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let now = Utc::now();
+    let until = q
+        .until
+        .as_deref()
+        .and_then(parse_iso_timestamp)
+        .unwrap_or(now.timestamp() + 1);
+    // Default to the current month, which is the window the monthly quota burns down in.
+    let since = q
+        .since
+        .as_deref()
+        .and_then(parse_iso_timestamp)
+        .unwrap_or_else(|| start_of_month_dt(now).timestamp());
+    if until <= since {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    match state.proxy.token_quota_burndown(&id, since, until).await {
+        Ok(snapshots) => Ok(Json(
+            snapshots
+                .into_iter()
+                .map(|s| TokenQuotaSnapshotView {
+                    snapshot_at: s.snapshot_at,
+                    monthly_used: s.monthly_used,
+                    monthly_limit: s.monthly_limit,
+                    monthly_remaining: s.monthly_remaining,
+                })
+                .collect(),
+        )),
+        Err(err) => {
+            tracing::error!("get_token_quota_burndown error: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/tokens/leaderboard",
+    tag = "tokens",
+    summary = "Busiest tokens.",
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_token_leaderboard(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(q): Query<TokenLeaderboardQuery>,
+) -> Result<Json<Vec<TokenLeaderboardItemView>>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let now = Utc::now();
+    let day_since = start_of_day_dt(now).timestamp();
+    let month_since = start_of_month_dt(now).timestamp();
+
+    let period = match q.period.as_deref() {
+        Some("day") | None => "day",
+        Some("month") => "month",
+        Some("all") => "all",
+        _ => return Err(StatusCode::BAD_REQUEST),
+    };
+
+    let focus = match q.focus.as_deref() {
+        Some("usage") | None => "usage",
+        Some("errors") => "errors",
+        Some("other") => "other",
+        _ => return Err(StatusCode::BAD_REQUEST),
+    };
+
+    let tokens = state
+        .proxy
+        .list_access_tokens()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let token_ids: Vec<String> = tokens.iter().map(|t| t.id.clone()).collect();
+    let hourly_any_map = state
+        .proxy
+        .token_hourly_any_snapshot(&token_ids)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let mut items: Vec<TokenLeaderboardItemView> = Vec::with_capacity(tokens.len());
+
+    for token in tokens {
+        // summaries
+        let today = state
+            .proxy
+            .token_summary_since(&token.id, day_since, None)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let month = state
+            .proxy
+            .token_summary_since(&token.id, month_since, None)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let all = state
+            .proxy
+            .token_summary_since(&token.id, 0, None)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+        let other_today = (today.total_requests - today.success_count - today.error_count).max(0);
+        let other_month = (month.total_requests - month.success_count - month.error_count).max(0);
+        let other_all = (all.total_requests - all.success_count - all.error_count).max(0);
+
+        // quota snapshot
+        let quota_verdict = match token.quota {
+            Some(ref v) => Some(v.clone()),
+            None => state
+                .proxy
+                .token_quota_snapshot(&token.id)
+                .await
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+                .clone(),
+        };
+        let (hour_used, hour_limit, day_used, day_limit) = quota_verdict
+            .as_ref()
+            .map(|q| (q.hourly_used, q.hourly_limit, q.daily_used, q.daily_limit))
+            .unwrap_or((
+                0,
+                effective_token_hourly_limit(),
+                0,
+                effective_token_daily_limit(),
+            ));
+        let quota_state = quota_verdict
+            .as_ref()
+            .and_then(|q| q.exceeded_window)
+            .map(|w| w.as_str().to_string())
+            .unwrap_or_else(|| "normal".to_string());
+
+        let (hourly_any_used, hourly_any_limit) = hourly_any_map
+            .get(&token.id)
+            .map(|v| (v.hourly_used, v.hourly_limit))
+            .unwrap_or((0, effective_token_hourly_request_limit()));
+
+        let item = TokenLeaderboardItemView {
+            id: token.id.clone(),
+            enabled: token.enabled,
+            note: token.note.clone(),
+            group: token.group_name.clone(),
+            total_requests: all.total_requests,
+            last_used_at: all.last_activity,
+            quota_state,
+            quota_hourly_used: hour_used,
+            quota_hourly_limit: hour_limit,
+            quota_daily_used: day_used,
+            quota_daily_limit: day_limit,
+            hourly_any_used,
+            hourly_any_limit,
+            today_total: today.total_requests,
+            today_errors: today.error_count,
+            today_other: other_today,
+            month_total: month.total_requests,
+            month_errors: month.error_count,
+            month_other: other_month,
+            all_total: all.total_requests,
+            all_errors: all.error_count,
+            all_other: other_all,
+        };
+        items.push(item);
+    }
+
+    let metric = |it: &TokenLeaderboardItemView, p: &str, f: &str| -> i64 {
+        match (p, f) {
+            ("day", "usage") => it.today_total,
+            ("day", "errors") => it.today_errors,
+            ("day", "other") => it.today_other,
+            ("month", "usage") => it.month_total,
+            ("month", "errors") => it.month_errors,
+            ("month", "other") => it.month_other,
+            ("all", "usage") => it.all_total,
+            ("all", "errors") => it.all_errors,
+            ("all", "other") => it.all_other,
+            _ => 0,
+        }
+    };
+
+    items.sort_by(|a, b| {
+        metric(b, period, focus)
+            .cmp(&metric(a, period, focus))
+            .then_with(|| b.total_requests.cmp(&a.total_requests))
+    });
+
+    items.truncate(50);
+
+    Ok(Json(items))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/tokens/{id}",
+    tag = "tokens",
+    summary = "One token.",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn get_token_detail(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<AuthTokenView>, StatusCode> {
+    let scope = manage_scope(&state, &headers)?;
+    ensure_token_in_scope(&state, &scope, &id).await?;
+    let tokens = state
+        .proxy
+        .list_access_tokens()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let Some(token) = tokens.into_iter().find(|t| t.id == id) else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    let sla = state
+        .proxy
+        .token_sla(&id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let claim = state
+        .proxy
+        .token_claim(&id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let mut view = AuthTokenView::from(token);
+    view.sla = Some(sla.into());
+    view.claim = claim.map(|claim| TokenClaimView::new(claim, Utc::now().timestamp()));
+    Ok(Json(view))
+}
+
+#[derive(Debug, Serialize)]
+struct TokenSnapshot {
+    summary: TokenSummaryView,
+    logs: Vec<TokenLogView>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/tokens/{id}/events",
+    tag = "logs",
+    summary = "SSE live tail of a token's logs.",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn sse_token(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Sse<impl futures_util::Stream<Item = Result<Event, axum::http::Error>>>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let state = state.clone();
+    let stream = stream! {
+        let mut changes = state.proxy.subscribe_changes();
+        let mut last_log_id: Option<i64> = None;
+        if let Some(event) = build_token_snapshot_event(&state, &id).await { yield Ok(event); }
+        if let Ok(logs) = state.proxy.token_recent_logs(&id, 1, None).await {
+            last_log_id = logs.first().map(|l| l.id);
+        }
+        loop {
+            match state.proxy.token_recent_logs(&id, 1, None).await {
+                Ok(logs) => {
+                    let latest = logs.first().map(|l| l.id);
+                    if latest != last_log_id {
+                        if let Some(event) = build_token_snapshot_event(&state, &id).await { yield Ok(event); }
+                        last_log_id = latest;
+                    } else {
+                        let keep = Event::default().event("ping").data("{}");
+                        yield Ok(keep);
+                    }
+                }
+                Err(_) => {
+                    let keep = Event::default().event("ping").data("{}");
+                    yield Ok(keep);
+                }
+            }
+            wait_for_data_change(&mut changes).await;
+        }
+    };
+    Ok(Sse::new(stream).keep_alive(KeepAlive::new().interval(Duration::from_secs(15)).text("")))
+}
+
+#[derive(Debug, Deserialize)]
+struct KeyEventsQuery {
+    after: Option<i64>,
+}
+
+/// Rows pushed per `log` burst before yielding to the next poll.
+const KEY_EVENTS_BATCH: usize = 100;
+
+/// Live tail of one key's request logs: a `snapshot` of recent rows on connect, then one
+/// `log` event per new row. Event ids are log ids, so `Last-Event-ID` (or `?after=`)
+/// resumes without gaps.
+#[utoipa::path(
+    get,
+    path = "/api/keys/{id}/events",
+    tag = "logs",
+    summary = "SSE live tail of a key's logs.",
+    params(("id" = String, Path)),
+    responses((status = 200, description = "OK"), (status = 403, description = "Not an admin")),
+    security(("forwardAuth" = []))
+)]
+async fn sse_key(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Query(q): Query<KeyEventsQuery>,
+    headers: HeaderMap,
+) -> Result<Sse<impl futures_util::Stream<Item = Result<Event, axum::http::Error>>>, StatusCode> {
+    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let resume_from = headers
+        .get("last-event-id")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<i64>().ok())
+        .or(q.after);
+    let state = state.clone();
+    let stream = stream! {
+        let mut changes = state.proxy.subscribe_changes();
+        let mut last_log_id = match resume_from {
+            Some(after) => after,
+            None => {
+                let recent = state
+                    .proxy
+                    .key_recent_logs(&id, DEFAULT_LOG_LIMIT, None)
+                    .await
+                    .unwrap_or_default();
+                let last = recent.iter().map(|l| l.id).max().unwrap_or(0);
+                let logs: Vec<RequestLogView> =
+                    recent.into_iter().map(RequestLogView::from).collect();
+                if let Ok(json) = serde_json::to_string(&logs) {
+                    yield Ok(Event::default().event("snapshot").id(last.to_string()).data(json));
+                }
+                last
+            }
+        };
+        loop {
+            match state.proxy.key_logs_after_id(&id, last_log_id, KEY_EVENTS_BATCH).await {
+                Ok(logs) if !logs.is_empty() => {
+                    for log in logs {
+                        last_log_id = log.id;
+                        if let Ok(json) = serde_json::to_string(&RequestLogView::from(log)) {
+                            yield Ok(Event::default().event("log").id(last_log_id.to_string()).data(json));
+                        }
+                    }
+                    continue;
+                }
+                _ => {
+                    let keep = Event::default().event("ping").data("{}");
+                    yield Ok(keep);
+                }
+            }
+            wait_for_data_change(&mut changes).await;
+        }
+    };
+    Ok(Sse::new(stream).keep_alive(KeepAlive::new().interval(Duration::from_secs(15)).text("")))
+}
+
+async fn build_token_snapshot_event(state: &Arc<AppState>, id: &str) -> Option<Event> {
+    let now = Utc::now();
+    let month_start = Utc
+        .with_ymd_and_hms(now.year(), now.month(), 1, 0, 0, 0)
+        .single()?
+        .timestamp();
+    let summary = state
+        .proxy
+        .token_summary_since(id, month_start, None)
+        .await
+        .ok()?;
+    let logs = state
+        .proxy
+        .token_recent_logs(id, DEFAULT_LOG_LIMIT, None)
+        .await
+        .ok()?;
+    let payload = TokenSnapshot {
+        summary: summary.into(),
+        logs: logs
+            .into_iter()
+            .map(TokenLogView::from)
+            .map(|mut v| {
+                if let Some(err) = v.error_message.as_ref() {
+                    v.error_message = Some(redact_sensitive(err));
+                }
+                v
+            })
+            .collect(),
+    };
+    let json = serde_json::to_string(&payload).ok()?;
+    Some(Event::default().event("snapshot").data(json))
+}
+
+fn extract_token_from_query(raw_query: Option<&str>) -> (Option<String>, Option<String>) {
+    let Some(raw) = raw_query else {
+        return (None, None);
+    };
+
+    if raw.is_empty() {
+        return (None, None);
+    }
+
+    let mut token: Option<String> = None;
+    let mut serializer = form_urlencoded::Serializer::new(String::new());
+
+    for (key, value) in form_urlencoded::parse(raw.as_bytes()) {
+        if key.eq_ignore_ascii_case("tavilyApiKey") {
+            // Capture the first non-empty token value and strip it from the forwarded query.
+            if token.is_none() {
+                let trimmed = value.trim();
+                if !trimmed.is_empty() {
+                    token = Some(trimmed.to_string());
+                }
+            }
+            continue;
+        }
+
+        serializer.append_pair(&key, &value);
+    }
+
+    let serialized = serializer.finish();
+    let query = if serialized.is_empty() {
+        None
+    } else {
+        Some(serialized)
+    };
+
+    (query, token)
+}
+
+fn mcp_request_counts_toward_business_quota(path: &str, body: &[u8]) -> bool {
+    // Only apply special handling for /mcp traffic. Other endpoints (such as
+    // /api/tavily/*) are always treated as business-costful.
+    if !path.starts_with("/mcp") {
+        return true;
+    }
+
+    // Non-business whitelist: only the following methods are treated as "no business cost".
+    // Everything else is considered business-costful and will consume business quota.
+    match serde_json::from_slice::<Value>(body) {
+        Ok(Value::Object(map)) => {
+            let method = map.get("method").and_then(|v| v.as_str()).unwrap_or("");
+            let is_non_business = matches!(
+                method,
+                "tools/list"
+                    | "resources/list"
+                    | "resources/templates/list"
+                    | "resources/read"
+                    | "prompts/list"
+                    | "prompts/get"
+            ) || method.starts_with("notifications/");
+            // Return semantics: true = count towards business quota; false = only hourly-any limiter.
+            !is_non_business
+        }
+        // 对于无法解析或缺少 method 的请求，保守起见视为“有业务成本”。
+        _ => true,
+    }
+}
+
+/// Why an `/mcp` body was rejected locally, as a JSON-RPC error.
+#[derive(Debug)]
+struct JsonRpcEnvelopeError {
+    code: i64,
+    message: &'static str,
+    /// The request's `id` when it could be read, so clients can correlate the error.
+    id: Value,
+}
+
+impl JsonRpcEnvelopeError {
+    fn invalid(message: &'static str, id: Value) -> Self {
+        Self {
+            code: JSONRPC_INVALID_REQUEST,
+            message,
+            id,
+        }
+    }
+}
+
+const JSONRPC_PARSE_ERROR: i64 = -32700;
+const JSONRPC_INVALID_REQUEST: i64 = -32600;
+/// Implementation-defined server error: the token may not call the requested tool.
+const JSONRPC_TOOL_NOT_ALLOWED: i64 = -32003;
+
+/// Cheap structural check of an `/mcp` POST body so payloads that can only fail upstream are
+/// rejected before a key is leased. Messages may be requests, notifications or responses to
+/// server-initiated requests.
+fn validate_jsonrpc_envelope(
+    body: &[u8],
+    mode: JsonRpcValidation,
+) -> Result<(), JsonRpcEnvelopeError> {
+    if mode == JsonRpcValidation::Off {
+        return Ok(());
+    }
+    let value = serde_json::from_slice::<Value>(body).map_err(|_| JsonRpcEnvelopeError {
+        code: JSONRPC_PARSE_ERROR,
+        message: "Parse error: body is not valid JSON",
+        id: Value::Null,
+    })?;
+    let messages = match &value {
+        Value::Array(_) if mode == JsonRpcValidation::Strict => {
+            return Err(JsonRpcEnvelopeError::invalid(
+                "Invalid Request: batch requests are not supported",
+                Value::Null,
+            ));
+        }
+        Value::Array(items) if items.is_empty() => {
+            return Err(JsonRpcEnvelopeError::invalid(
+                "Invalid Request: empty batch",
+                Value::Null,
+            ));
+        }
+        Value::Array(items) => items.iter().collect::<Vec<_>>(),
+        other => vec![other],
+    };
+
+    for message in messages {
+        let Value::Object(map) = message else {
+            return Err(JsonRpcEnvelopeError::invalid(
+                "Invalid Request: message must be an object",
+                Value::Null,
+            ));
+        };
+        let id = map
+            .get("id")
+            .filter(|id| id.is_string() || id.is_number())
+            .cloned()
+            .unwrap_or(Value::Null);
+        if map.get("jsonrpc").and_then(Value::as_str) != Some("2.0") {
+            return Err(JsonRpcEnvelopeError::invalid(
+                "Invalid Request: jsonrpc must be \"2.0\"",
+                id,
+            ));
+        }
+        let is_response = map.contains_key("result") || map.contains_key("error");
+        match map.get("method") {
+            Some(Value::String(method)) => {
+                if mode == JsonRpcValidation::Strict && (method.is_empty() || is_response) {
+                    return Err(JsonRpcEnvelopeError::invalid(
+                        "Invalid Request: malformed method",
+                        id,
+                    ));
+                }
+            }
+            Some(_) => {
+                return Err(JsonRpcEnvelopeError::invalid(
+                    "Invalid Request: method must be a string",
+                    id,
+                ));
+            }
+            None if is_response => {}
+            None => {
+                return Err(JsonRpcEnvelopeError::invalid(
+                    "Invalid Request: missing method",
+                    id,
+                ));
+            }
+        }
+        if mode == JsonRpcValidation::Strict {
+            if map
+                .get("id")
+                .is_some_and(|id| !id.is_string() && !id.is_number())
+            {
+                return Err(JsonRpcEnvelopeError::invalid(
+                    "Invalid Request: id must be a string or number",
+                    Value::Null,
+                ));
+            }
+            if map
+                .get("params")
+                .is_some_and(|params| !params.is_object() && !params.is_array())
+            {
+                return Err(JsonRpcEnvelopeError::invalid(
+                    "Invalid Request: params must be an object or array",
+                    id,
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Body of a proxied request, see [`read_proxy_body`].
+enum ProxyBody {
+    Buffered(bytes::Bytes),
+    /// First bytes of a body too large to buffer, and a stream of the rest.
+    Streamed {
+        head: bytes::Bytes,
+        rest: ProxyBodyStream,
+    },
+}
+
+/// Read a request body of at most [`BODY_LIMIT`] bytes. With `stream_above` set, a body
+/// longer than that is not buffered: only its first `stream_above` bytes are read, and the
+/// rest is handed back unread (still capped at [`BODY_LIMIT`]).
+async fn read_proxy_body(body: Body, stream_above: usize) -> Result<ProxyBody, StatusCode> {
+    let mut chunks = body.into_data_stream();
+    let mut head = bytes::BytesMut::new();
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk.map_err(|_| StatusCode::BAD_REQUEST)?;
+        if stream_above > 0 && head.len() + chunk.len() > stream_above {
+            let split = stream_above - head.len();
+            head.extend_from_slice(&chunk[..split]);
+            let mut total = stream_above;
+            let rest = futures_util::stream::once(async move { Ok(chunk.slice(split..)) })
+                .chain(chunks.map(|chunk| chunk.map_err(std::io::Error::other)))
+                .map(move |chunk| {
+                    let chunk = chunk?;
+                    total += chunk.len();
+                    if total > BODY_LIMIT {
+                        return Err(std::io::Error::other("request body exceeds the size limit"));
+                    }
+                    Ok(chunk)
+                });
+            return Ok(ProxyBody::Streamed {
+                head: head.freeze(),
+                rest: Box::pin(rest),
+            });
+        }
+        if head.len() + chunk.len() > BODY_LIMIT {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        head.extend_from_slice(&chunk);
+    }
+    Ok(ProxyBody::Buffered(head.freeze()))
+}
+
+/// JSON-RPC envelope of a streamed `/mcp` body, read from its head: the top-level `jsonrpc`,
+/// `id` and `method` members and `params.name`, serialized as a small message the usual
+/// checks can parse. `None` when the head is not a single message or `method` (and, for
+/// `tools/call`, the tool name) is not within it, since quota and tool policies could not
+/// be applied.
+fn jsonrpc_head_envelope(head: &[u8]) -> Option<bytes::Bytes> {
+    let mut envelope = serde_json::Map::new();
+    let mut tool = None;
+    let mut pos = skip_json_ws(head, 0);
+    if head.get(pos) != Some(&b'{') {
+        return None;
+    }
+    pos += 1;
+    // Each pass reads one member; running into the end of the head stops the scan.
+    while let Some((key, value_start)) = json_member_start(head, &mut pos) {
+        match key.as_str() {
+            "jsonrpc" | "id" | "method" => {
+                let Some(end) = json_value_end(head, value_start) else {
+                    break;
+                };
+                let value = serde_json::from_slice::<Value>(&head[value_start..end]).ok()?;
+                envelope.insert(key, value);
+                pos = end;
+            }
+            "params" if head[value_start] == b'{' => {
+                let mut params_pos = value_start + 1;
+                while let Some((name, start)) = json_member_start(head, &mut params_pos) {
+                    let Some(end) = json_value_end(head, start) else {
+                        break;
+                    };
+                    if name == "name" {
+                        tool = serde_json::from_slice::<Value>(&head[start..end]).ok();
+                    }
+                    params_pos = end;
+                }
+                match json_value_end(head, value_start) {
+                    Some(end) => pos = end,
+                    None => break,
+                }
+            }
+            _ => match json_value_end(head, value_start) {
+                Some(end) => pos = end,
+                None => break,
+            },
+        }
+    }
+
+    let method = envelope.get("method")?.as_str()?;
+    if method == "tools/call" && !tool.as_ref().is_some_and(Value::is_string) {
+        return None;
+    }
+    if let Some(tool) = tool {
+        envelope.insert("params".to_string(), json!({ "name": tool }));
+    }
+    serde_json::to_vec(&Value::Object(envelope))
+        .ok()
+        .map(bytes::Bytes::from)
+}
+
+fn skip_json_ws(bytes: &[u8], mut pos: usize) -> usize {
+    while bytes.get(pos).is_some_and(u8::is_ascii_whitespace) {
+        pos += 1;
+    }
+    pos
+}
+
+/// Key and value offset of the next member of the object being scanned at `pos` (just past
+/// `{` or a previous value). `None` at the end of the object or of the input.
+fn json_member_start(bytes: &[u8], pos: &mut usize) -> Option<(String, usize)> {
+    let mut at = skip_json_ws(bytes, *pos);
+    if bytes.get(at) == Some(&b',') {
+        at = skip_json_ws(bytes, at + 1);
+    }
+    if bytes.get(at) != Some(&b'"') {
+        return None;
+    }
+    let key_end = json_value_end(bytes, at)?;
+    let key = serde_json::from_slice::<String>(&bytes[at..key_end]).ok()?;
+    let colon = skip_json_ws(bytes, key_end);
+    if bytes.get(colon) != Some(&b':') {
+        return None;
+    }
+    let value_start = skip_json_ws(bytes, colon + 1);
+    bytes.get(value_start)?;
+    *pos = value_start;
+    Some((key, value_start))
+}
+
+/// Offset just past the JSON value starting at `start`, or `None` when the input ends first.
+fn json_value_end(bytes: &[u8], start: usize) -> Option<usize> {
+    match *bytes.get(start)? {
+        b'"' => {
+            let mut at = start + 1;
+            loop {
+                match *bytes.get(at)? {
+                    b'\\' => at += 2,
+                    b'"' => return Some(at + 1),
+                    _ => at += 1,
+                }
+            }
+        }
+        b'{' | b'[' => {
+            let mut depth = 0usize;
+            let mut at = start;
+            loop {
+                match *bytes.get(at)? {
+                    b'"' => {
+                        at = json_value_end(bytes, at)?;
+                        continue;
+                    }
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some(at + 1);
+                        }
+                    }
+                    _ => {}
+                }
+                at += 1;
+            }
+        }
+        _ => {
+            // Scalars only end at a delimiter, so a number cut off by the head is not read.
+            let mut at = start;
+            while !matches!(*bytes.get(at)?, b',' | b'}' | b']') && !bytes[at].is_ascii_whitespace()
+            {
+                at += 1;
+            }
+            Some(at)
+        }
+    }
+}
+
+async fn proxy_handler(
+    State(state): State<Arc<AppState>>,
+    req: Request<Body>,
+) -> Result<Response<Body>, StatusCode> {
+    let maintenance = state.proxy.maintenance();
+    if req.method() == Method::GET && is_websocket_upgrade(req.headers()) {
+        if let Some(mode) = maintenance {
+            return maintenance_response(&mode, Value::Null);
+        }
+        return websocket_proxy_handler(state, req).await;
+    }
+    let (parts, body) = req.into_parts();
+    let method = parts.method.clone();
+    let path = parts.uri.path().to_owned();
+    let (query, query_token) = extract_token_from_query(parts.uri.query());
+
+    if method == Method::GET && accepts_event_stream(&parts.headers) {
+        let response = Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .body(Body::empty())
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        return Ok(response);
+    }
+
+    // Require Authorization: Bearer th-<id>-<secret>
+    let auth_bearer = parts
+        .headers
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .map(|s| s.trim().to_string());
+
+    let header_token = auth_bearer
+        .as_deref()
+        .and_then(|raw| raw.strip_prefix("Bearer "))
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_string());
+
+    let token = if let Some(t) = header_token {
+        t
+    } else if let Some(t) = query_token {
+        t
+    } else if state.dev_open_admin {
+        "th-dev-override".to_string()
+    } else {
+        return Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header(CONTENT_TYPE, "application/json; charset=utf-8")
+            .body(Body::from("{\"error\":\"missing token\"}"))
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR);
+    };
+
+    let valid = if state.dev_open_admin {
+        true
+    } else {
+        state
+            .proxy
+            .validate_access_token(&token)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    };
+    if !valid {
+        return Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header(CONTENT_TYPE, "application/json; charset=utf-8")
+            .body(Body::from("{\"error\":\"invalid or disabled token\"}"))
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    let mut headers = clone_headers(&parts.headers);
+    // prevent leaking our Authorization to upstream
+    headers.remove(axum::http::header::AUTHORIZATION);
+    let stream_above = if method == Method::POST && path.starts_with("/mcp") {
+        effective_mcp_stream_body_bytes()
+    } else {
+        0
+    };
+    // `inspected` is what the checks below read: the whole body, or the JSON-RPC envelope
+    // of a streamed one.
+    let (body_bytes, inspected, upload) = match read_proxy_body(body, stream_above).await? {
+        ProxyBody::Buffered(bytes) => (bytes.clone(), bytes, None),
+        ProxyBody::Streamed { head, rest } => {
+            let Some(envelope) = jsonrpc_head_envelope(&head) else {
+                let payload = json!({
+                    "jsonrpc": "2.0",
+                    "id": Value::Null,
+                    "error": {
+                        "code": JSONRPC_INVALID_REQUEST,
+                        "message": "Invalid Request: a streamed body must be one message with method and params.name before other params",
+                    },
+                });
+                return Response::builder()
+                    .status(StatusCode::PAYLOAD_TOO_LARGE)
+                    .header(CONTENT_TYPE, "application/json; charset=utf-8")
+                    .body(Body::from(payload.to_string()))
+                    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR);
+            };
+            let upload = RequestUpload::new(envelope.clone(), rest);
+            (head, envelope, Some(upload))
+        }
+    };
+
+    if let Some(mode) = maintenance {
+        return maintenance_response(&mode, jsonrpc_request_id(&inspected));
+    }
+
+    let billable_flag = mcp_request_counts_toward_business_quota(&path, &inspected);
+
+    let auth_token_id = if state.dev_open_admin {
+        Some("dev".to_string())
+    } else {
+        token
+            .strip_prefix("th-")
+            .and_then(|rest| rest.split_once('-').map(|(id, _)| id))
+            .map(|s| s.to_string())
+    };
+
+    let mut proxy_request = ProxyRequest {
+        method: method.clone(),
+        path: path.clone(),
+        query,
+        headers,
+        body: body_bytes,
+        auth_token_id,
+        upload,
+        buffer_reply: false,
+    };
+
+    let token_id = if state.dev_open_admin {
+        Some("dev".to_string())
+    } else {
+        token
+            .strip_prefix("th-")
+            .and_then(|rest| rest.split('-').next())
+            .map(|s| s.to_string())
+    };
+
+    let tool = request_tool_name(&path, &inspected);
+
+    if let Some(resp) = quarantine_gate(
+        &state,
+        token_id.as_deref(),
+        &method,
+        &path,
+        parts.uri.query(),
+        tool.as_deref(),
+    )
+    .await?
+    {
+        return Ok(resp);
+    }
+
+    if method == Method::POST
+        && path.starts_with("/mcp")
+        && let Err(err) = validate_jsonrpc_envelope(&inspected, effective_mcp_jsonrpc_validation())
+    {
+        // Rejected locally: no key lease, no upstream round trip, no business quota.
+        if let Some(tid) = token_id.as_deref() {
+            let _ = state
+                .proxy
+                .record_token_attempt(
+                    tid,
+                    &method,
+                    &path,
+                    parts.uri.query(),
+                    tool.as_deref(),
+                    Some(StatusCode::BAD_REQUEST.as_u16() as i64),
+                    None,
+                    false,
+                    "error",
+                    Some(err.message),
+                )
+                .await;
+        }
+        let payload = json!({
+            "jsonrpc": "2.0",
+            "id": err.id,
+            "error": { "code": err.code, "message": err.message },
+        });
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .header(CONTENT_TYPE, "application/json; charset=utf-8")
+            .body(Body::from(payload.to_string()))
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    if let Some(resp) = tool_policy_gate(
+        &state,
+        token_id.as_deref(),
+        &method,
+        &path,
+        parts.uri.query(),
+        tool.as_deref(),
+        path.starts_with("/mcp")
+            .then(|| jsonrpc_request_id(&inspected)),
+    )
+    .await?
+    {
+        return Ok(resp);
+    }
+
+    // A repeat of a recent tool call is answered before any quota is charged.
+    let dedup = match token_id.as_deref() {
+        Some(tid)
+            if method == Method::POST
+                && path.starts_with("/mcp")
+                && proxy_request.upload.is_none() =>
+        {
+            let idempotency_key = parts
+                .headers
+                .get(IDEMPOTENCY_KEY_HEADER)
+                .and_then(|v| v.to_str().ok());
+            state
+                .proxy
+                .begin_mcp_dedup(tid, idempotency_key, &inspected)
+                .await
+        }
+        _ => None,
+    };
+    let dedup_lead = match dedup {
+        Some(DedupTicket::Replay(resp)) => {
+            if let Some(tid) = token_id.as_deref() {
+                let _ = state
+                    .proxy
+                    .record_token_attempt(
+                        tid,
+                        &method,
+                        &path,
+                        parts.uri.query(),
+                        tool.as_deref(),
+                        Some(resp.status.as_u16() as i64),
+                        None,
+                        false,
+                        "success",
+                        Some("replayed from the dedup window"),
+                    )
+                    .await;
+            }
+            let mut response = build_response(resp);
+            if let Some(tid) = token_id.as_deref() {
+                apply_token_response_headers(&state, tid, &mut response).await;
+            }
+            return Ok(response);
+        }
+        Some(DedupTicket::Lead(lead)) => {
+            proxy_request.buffer_reply = true;
+            Some(lead)
+        }
+        None => None,
+    };
+
+    let mut _quota_verdict: Option<TokenQuotaVerdict> = None;
+    if let Some(tid) = token_id.as_deref() {
+        // 1) 全量“任意请求”小时限频：所有通过鉴权的请求都会计入。
+        if !state.dev_open_admin {
+            match state.proxy.check_token_hourly_requests(tid).await {
+                Ok(verdict) => {
+                    if !verdict.allowed {
+                        let message = build_request_limit_error_message(&verdict);
+                        let _ = state
+                            .proxy
+                            .record_token_attempt(
+                                tid,
+                                &method,
+                                &path,
+                                parts.uri.query(),
+                                tool.as_deref(),
+                                Some(StatusCode::TOO_MANY_REQUESTS.as_u16() as i64),
+                                None,
+                                false,
+                                "quota_exhausted",
+                                Some(&message),
+                            )
+                            .await;
+                        return mcp_error_response(
+                            McpFailure::RateLimited,
+                            jsonrpc_request_id(&inspected),
+                            &message,
+                            request_limit_payload(&verdict),
+                        );
+                    }
+                }
+                Err(err) => {
+                    tracing::error!("hourly request limit check failed: {err}");
+                    return Err(StatusCode::INTERNAL_SERVER_ERROR);
+                }
+            }
+        }
+
+        // 2) 业务配额（小时 / 日 / 月）只对 MCP 工具调用生效。
+        if billable_flag {
+            match state.proxy.check_token_quota(tid).await {
+                Ok(verdict) => {
+                    if !state.dev_open_admin && !verdict.allowed {
+                        let message = build_quota_error_message(&verdict);
+                        let _ = state
+                            .proxy
+                            .record_token_attempt(
+                                tid,
+                                &method,
+                                &path,
+                                parts.uri.query(),
+                                tool.as_deref(),
+                                Some(StatusCode::TOO_MANY_REQUESTS.as_u16() as i64),
+                                None,
+                                true,
+                                "quota_exhausted",
+                                Some(&message),
+                            )
+                            .await;
+                        return mcp_error_response(
+                            McpFailure::QuotaExceeded,
+                            jsonrpc_request_id(&inspected),
+                            &message,
+                            quota_exceeded_payload(&verdict),
+                        );
+                    }
+                    _quota_verdict = Some(verdict);
+                }
+                Err(err) => {
+                    tracing::error!("quota check failed: {err}");
+                    return Err(StatusCode::INTERNAL_SERVER_ERROR);
+                }
+            }
+        }
+    }
+
+    match state.proxy.proxy_request(proxy_request).await {
+        Ok(UpstreamResponse::Stream(upstream)) => {
+            let mut response = build_stream_response(
+                &state,
+                token_id.clone(),
+                &method,
+                &path,
+                parts.uri.query(),
+                tool.clone(),
+                billable_flag,
+                upstream,
+            );
+            if let Some(tid) = token_id.as_deref() {
+                apply_token_response_headers(&state, tid, &mut response).await;
+            }
+            Ok(response)
+        }
+        Ok(UpstreamResponse::Buffered(resp)) => {
+            if let Some(lead) = dedup_lead {
+                lead.complete(&resp);
+            }
+            if let Some(tid) = token_id.as_deref() {
+                // 尝试从 Tavily JSON 回复中解析结构化状态码
+                let mut tavily_code: Option<i64> = None;
+                let mut result_status = "success";
+                #[allow(clippy::collapsible_if)]
+                {
+                    if let Ok(text) = std::str::from_utf8(&resp.body) {
+                        if let Ok(value) = serde_json::from_str::<serde_json::Value>(text) {
+                            if let Some(sc) = value
+                                .get("result")
+                                .and_then(|v| v.get("structuredContent"))
+                                .and_then(|v| v.get("status"))
+                                .and_then(|v| v.as_i64())
+                            {
+                                tavily_code = Some(sc);
+                                result_status = state.proxy.outcome_rules().classify_code(sc);
+                            } else if value
+                                .get("result")
+                                .and_then(|v| v.get("structuredContent"))
+                                .and_then(|v| v.get("isError"))
+                                .and_then(|v| v.as_bool())
+                                .unwrap_or(false)
+                            {
+                                result_status = "error";
+                            }
+                        }
+                    }
+                }
+
+                if result_status == "success" && !resp.status.is_success() {
+                    result_status = "error";
+                }
+
+                let http_code = resp.status.as_u16() as i64;
+                let _ = state
+                    .proxy
+                    .record_token_attempt(
+                        tid,
+                        &method,
+                        &path,
+                        parts.uri.query(),
+                        tool.as_deref(),
+                        Some(http_code),
+                        tavily_code,
+                        billable_flag,
+                        result_status,
+                        None,
+                    )
+                    .await;
+            }
+            let mut response = build_response(resp);
+            if let Some(tid) = token_id.as_deref() {
+                apply_token_response_headers(&state, tid, &mut response).await;
+            }
+            Ok(response)
+        }
+        Err(err) => {
+            tracing::error!("proxy error: {err}");
+            if let Some(tid) = token_id.as_deref() {
+                let err_str = err.to_string();
+                let _ = state
+                    .proxy
+                    .record_token_attempt(
+                        tid,
+                        &method,
+                        &path,
+                        parts.uri.query(),
+                        tool.as_deref(),
+                        None,
+                        None,
+                        billable_flag,
+                        "error",
+                        Some(err_str.as_str()),
+                    )
+                    .await;
+            }
+            let failure = McpFailure::from_proxy_error(&err);
+            let (message, data) = match err {
+                ProxyError::Overloaded { priority } => (
+                    overloaded_message(priority),
+                    json!({ "priority": priority }),
+                ),
+                _ => (failure.default_message().to_string(), json!({})),
+            };
+            mcp_error_response(failure, jsonrpc_request_id(&inspected), &message, data)
+        }
+    }
+}
+
+/// Proxy-side failure classes of `/mcp` requests. Clients receive them as JSON-RPC errors
+/// whose `error.data.code` names the class, so they can tell a local limit from an upstream
+/// outage without parsing messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum McpFailure {
+    NoKeys,
+    KeysSaturated,
+    Overloaded,
+    Maintenance,
+    QuotaExceeded,
+    RateLimited,
+    UpstreamTimeout,
+    UpstreamUnavailable,
+    Internal,
+}
+
+impl McpFailure {
+    fn from_proxy_error(err: &ProxyError) -> Self {
+        match err {
+            ProxyError::NoAvailableKeys => Self::NoKeys,
+            ProxyError::KeysSaturated => Self::KeysSaturated,
+            ProxyError::Overloaded { .. } => Self::Overloaded,
+            ProxyError::Http(err) if err.is_timeout() => Self::UpstreamTimeout,
+            ProxyError::Http(_) => Self::UpstreamUnavailable,
+            ProxyError::Database(_)
+            | ProxyError::InvalidEndpoint { .. }
+            | ProxyError::QuotaDataMissing { .. }
+            | ProxyError::UsageHttp { .. }
+            | ProxyError::SelfCheck(_)
+            | ProxyError::QuotaBackend(_)
+            | ProxyError::Other(_) => Self::Internal,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::NoKeys => "NO_KEYS",
+            Self::KeysSaturated => "KEYS_SATURATED",
+            Self::Overloaded => "OVERLOADED",
+            Self::Maintenance => "MAINTENANCE",
+            Self::QuotaExceeded => "QUOTA_EXCEEDED",
+            Self::RateLimited => "RATE_LIMITED",
+            Self::UpstreamTimeout => "UPSTREAM_TIMEOUT",
+            Self::UpstreamUnavailable => "UPSTREAM_UNAVAILABLE",
+            Self::Internal => "INTERNAL_ERROR",
+        }
+    }
+
+    /// JSON-RPC error code, from the implementation-defined server error range.
+    fn jsonrpc_code(self) -> i64 {
+        match self {
+            Self::NoKeys => -32010,
+            Self::KeysSaturated => -32011,
+            Self::Overloaded => -32012,
+            Self::Maintenance => -32013,
+            Self::QuotaExceeded => -32020,
+            Self::RateLimited => -32021,
+            Self::UpstreamTimeout => -32030,
+            Self::UpstreamUnavailable => -32031,
+            Self::Internal => -32603,
+        }
+    }
+
+    fn status(self) -> StatusCode {
+        match self {
+            Self::NoKeys | Self::KeysSaturated | Self::Maintenance => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            Self::Overloaded | Self::QuotaExceeded | Self::RateLimited => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+            Self::UpstreamTimeout => StatusCode::GATEWAY_TIMEOUT,
+            Self::UpstreamUnavailable => StatusCode::BAD_GATEWAY,
+            Self::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Short waits help only when capacity frees up by itself.
+    fn retry_after_secs(self) -> Option<u64> {
+        matches!(self, Self::KeysSaturated | Self::Overloaded).then_some(1)
+    }
+
+    fn default_message(self) -> &'static str {
+        match self {
+            Self::NoKeys => {
+                "no Tavily API key is available; ask the administrator to add or re-enable keys"
+            }
+            Self::KeysSaturated => "every Tavily API key is busy; retry shortly",
+            Self::Overloaded => "proxy is at capacity; retry later",
+            Self::Maintenance => "proxy is down for maintenance; retry later",
+            Self::QuotaExceeded => "token quota exceeded",
+            Self::RateLimited => "token request limit reached",
+            Self::UpstreamTimeout => "Tavily did not respond in time; retry later",
+            Self::UpstreamUnavailable => "Tavily could not be reached; retry later",
+            Self::Internal => "internal proxy error",
+        }
+    }
+}
+
+/// JSON-RPC error reply for a failed `/mcp` request. `data` carries details for the failure
+/// class and gains the tavily-hikari `code`.
+fn mcp_error_response(
+    failure: McpFailure,
+    id: Value,
+    message: &str,
+    data: Value,
+) -> Result<Response<Body>, StatusCode> {
+    let payload = mcp_error_payload(failure, id, message, data);
+    let mut builder = Response::builder()
+        .status(failure.status())
+        .header(CONTENT_TYPE, "application/json; charset=utf-8");
+    if let Some(secs) = failure.retry_after_secs() {
+        builder = builder.header(axum::http::header::RETRY_AFTER, secs.to_string());
+    }
+    builder
+        .body(Body::from(payload.to_string()))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// JSON-RPC error message of [`mcp_error_response`], also sent as a WebSocket frame.
+fn mcp_error_payload(failure: McpFailure, id: Value, message: &str, mut data: Value) -> Value {
+    if let Value::Object(map) = &mut data {
+        map.insert("code".to_string(), json!(failure.as_str()));
+    }
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": {
+            "code": failure.jsonrpc_code(),
+            "message": message,
+            "data": data,
+        },
+    })
+}
+
+/// 503 refusing an `/mcp` request during maintenance, with `Retry-After` pointing at the
+/// planned end.
+fn maintenance_response(mode: &MaintenanceMode, id: Value) -> Result<Response<Body>, StatusCode> {
+    let retry_after = mode.retry_after_secs(Utc::now().timestamp());
+    let failure = McpFailure::Maintenance;
+    let mut response = mcp_error_response(
+        failure,
+        id,
+        mode.message
+            .as_deref()
+            .unwrap_or_else(|| failure.default_message()),
+        json!({
+            "retryAfterSecs": retry_after,
+            "until": mode.until,
+        }),
+    )?;
+    response.headers_mut().insert(
+        axum::http::header::RETRY_AFTER,
+        axum::http::HeaderValue::from(retry_after),
+    );
+    Ok(response)
+}
+
+/// Reject requests from quarantined tokens with 429 while still logging the attempt.
+async fn quarantine_gate(
+    state: &AppState,
+    token_id: Option<&str>,
+    method: &Method,
+    path: &str,
+    query: Option<&str>,
+    tool: Option<&str>,
+) -> Result<Option<Response<Body>>, StatusCode> {
+    let Some(tid) = token_id else {
+        return Ok(None);
+    };
+    if state.dev_open_admin {
+        return Ok(None);
+    }
+    let reason = match state.proxy.token_quarantine_reason(tid).await {
+        Ok(Some(reason)) => reason,
+        Ok(None) => return Ok(None),
+        Err(err) => {
+            tracing::error!("token quarantine check failed: {err}");
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    let message = "token is quarantined pending review; please contact the administrator";
+    let _ = state
+        .proxy
+        .record_token_attempt(
+            tid,
+            method,
+            path,
+            query,
+            tool,
+            Some(StatusCode::TOO_MANY_REQUESTS.as_u16() as i64),
+            None,
+            false,
+            "error",
+            Some(message),
+        )
+        .await;
+    let payload = json!({
+        "error": "token_quarantined",
+        "message": message,
+        "reason": reason,
+    });
+    let resp = Response::builder()
+        .status(StatusCode::TOO_MANY_REQUESTS)
+        .header(CONTENT_TYPE, "application/json; charset=utf-8")
+        .body(Body::from(payload.to_string()))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Some(resp))
+}
+
+/// JSON-RPC `id` of a request body, `null` when absent or unreadable.
+fn jsonrpc_request_id(body: &[u8]) -> Value {
+    serde_json::from_slice::<Value>(body)
+        .ok()
+        .and_then(|value| value.get("id").cloned())
+        .unwrap_or(Value::Null)
+}
+
+/// Reject tool calls outside the token's allowed tools with HTTP 403. MCP requests (those
+/// passing `jsonrpc_id`) get a JSON-RPC error carrying the request id.
+async fn tool_policy_gate(
+    state: &AppState,
+    token_id: Option<&str>,
+    method: &Method,
+    path: &str,
+    query: Option<&str>,
+    tool: Option<&str>,
+    jsonrpc_id: Option<Value>,
+) -> Result<Option<Response<Body>>, StatusCode> {
+    let (Some(tid), Some(tool_name)) = (token_id, tool) else {
+        return Ok(None);
+    };
+    if state.dev_open_admin {
+        return Ok(None);
+    }
+    match state.proxy.token_tool_allowed(tid, tool).await {
+        Ok(true) => return Ok(None),
+        Ok(false) => {}
+        Err(err) => {
+            tracing::error!("token tool policy check failed: {err}");
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+
+    let message = format!("tool '{tool_name}' is not allowed for this token");
+    let _ = state
+        .proxy
+        .record_token_attempt(
+            tid,
+            method,
+            path,
+            query,
+            tool,
+            Some(StatusCode::FORBIDDEN.as_u16() as i64),
+            None,
+            false,
+            "error",
+            Some(&message),
+        )
+        .await;
+    let payload = match jsonrpc_id {
+        Some(id) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": {
+                "code": JSONRPC_TOOL_NOT_ALLOWED,
+                "message": message,
+                "data": { "tool": tool_name },
+            },
+        }),
+        None => json!({
+            "error": "tool_not_allowed",
+            "message": message,
+            "tool": tool_name,
+        }),
+    };
+    let resp = Response::builder()
+        .status(StatusCode::FORBIDDEN)
+        .header(CONTENT_TYPE, "application/json; charset=utf-8")
+        .body(Body::from(payload.to_string()))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Some(resp))
+}
+
+fn clone_headers(headers: &HeaderMap) -> ReqHeaderMap {
+    let mut map = ReqHeaderMap::new();
+    for (name, value) in headers.iter() {
+        if let Ok(cloned) = ReqHeaderValue::from_bytes(value.as_bytes()) {
+            map.insert(name.clone(), cloned);
+        }
+    }
+    map
+}
+
+/// Whether a request asks to switch to the WebSocket protocol.
+fn is_websocket_upgrade(headers: &HeaderMap) -> bool {
+    let upgrade = headers
+        .get(axum::http::header::UPGRADE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.trim().eq_ignore_ascii_case("websocket"));
+    let connection = headers
+        .get(axum::http::header::CONNECTION)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| {
+            v.split(',')
+                .any(|part| part.trim().eq_ignore_ascii_case("upgrade"))
+        });
+    upgrade && connection
+}
+
+fn websocket_error(status: StatusCode, error: &str) -> Result<Response<Body>, StatusCode> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json; charset=utf-8")
+        .body(Body::from(json!({ "error": error }).to_string()))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// `/mcp` opened as a WebSocket: authenticate the token, connect the WebSocket upstream with
+/// a leased key and relay frames both ways. Each JSON-RPC reply is recorded as a token attempt.
+async fn websocket_proxy_handler(
+    state: Arc<AppState>,
+    req: Request<Body>,
+) -> Result<Response<Body>, StatusCode> {
+    if !state.proxy.ws_enabled() {
+        return websocket_error(StatusCode::NOT_IMPLEMENTED, "websocket transport disabled");
+    }
+    let (mut parts, _) = req.into_parts();
+    let Ok(ws) = WebSocketUpgrade::from_request_parts(&mut parts, &state).await else {
+        return websocket_error(StatusCode::BAD_REQUEST, "invalid websocket handshake");
+    };
+    let headers = &parts.headers;
+    let protocols = headers
+        .get(axum::http::header::SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+
+    let header_token = headers
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|raw| raw.trim().strip_prefix("Bearer "))
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .map(str::to_string);
+    let (_, query_token) = extract_token_from_query(parts.uri.query());
+    let token = match header_token.or(query_token) {
+        Some(token) => token,
+        None if state.dev_open_admin => "th-dev-override".to_string(),
+        None => return websocket_error(StatusCode::UNAUTHORIZED, "missing token"),
+    };
+    let valid = state.dev_open_admin
+        || state
+            .proxy
+            .validate_access_token(&token)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if !valid {
+        return websocket_error(StatusCode::UNAUTHORIZED, "invalid or disabled token");
+    }
+    let token_id = if state.dev_open_admin {
+        Some("dev".to_string())
+    } else {
+        token
+            .strip_prefix("th-")
+            .and_then(|rest| rest.split('-').next())
+            .map(|s| s.to_string())
+    };
+
+    let method = parts.method.clone();
+    let path = parts.uri.path().to_owned();
+    if let Some(resp) =
+        quarantine_gate(&state, token_id.as_deref(), &method, &path, None, None).await?
+    {
+        return Ok(resp);
+    }
+    // Per-tool restrictions are not enforced mid-session, so such tokens are refused up front.
+    if let Some(tid) = token_id.as_deref()
+        && !state.dev_open_admin
+        && state
+            .proxy
+            .token_has_tool_policy(tid)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    {
+        return websocket_error(
+            StatusCode::FORBIDDEN,
+            "tool-restricted tokens cannot use the websocket transport",
+        );
+    }
+
+    // The upgrade counts as one request, as on HTTP. Tool calls are charged per message
+    // below, so a token already over its business quota is refused from a snapshot here.
+    if let Some(tid) = token_id.as_deref()
+        && !state.dev_open_admin
+    {
+        match ws_message_gate(&state, tid, &path, None).await {
+            WsGate::Pass => {}
+            WsGate::Refuse(failure, message, data) => {
+                return mcp_error_response(failure, Value::Null, &message, data);
+            }
+        }
+        let snapshot = state.proxy.token_quota_snapshot(tid).await.map_err(|err| {
+            tracing::error!("quota snapshot failed for websocket: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+        if let Some(verdict) = snapshot.filter(|verdict| !verdict.allowed) {
+            let message = build_quota_error_message(&verdict);
+            return mcp_error_response(
+                McpFailure::QuotaExceeded,
+                Value::Null,
+                &message,
+                quota_exceeded_payload(&verdict),
+            );
+        }
+    }
+
+    let upstream = match state
+        .proxy
+        .connect_ws_upstream(token_id.as_deref(), &path, protocols.as_deref())
+        .await
+    {
+        Ok(upstream) => upstream,
+        Err(err) => {
+            tracing::warn!("websocket upstream connect failed: {err}");
+            return websocket_error(StatusCode::BAD_GATEWAY, "websocket upstream unavailable");
+        }
+    };
+    let mut ws = ws
+        .max_message_size(WEBSOCKET_MAX_MESSAGE_BYTES)
+        .max_frame_size(WEBSOCKET_MAX_MESSAGE_BYTES);
+    if let Some(protocol) = upstream.protocol.clone() {
+        ws = ws.protocols([protocol]);
+    }
+
+    // Whichever of the two upgrade callbacks runs takes the upstream connection.
+    let upstream = Arc::new(StdMutex::new(Some(upstream)));
+    let on_failed_upgrade = {
+        let upstream = upstream.clone();
+        let proxy = state.proxy.clone();
+        move |err: axum::Error| {
+            tracing::warn!("websocket client upgrade failed: {err}");
+            let upstream = upstream.lock().unwrap_or_else(|e| e.into_inner()).take();
+            if let Some(upstream) = upstream {
+                tokio::spawn(async move {
+                    let _ = proxy.release_ws_upstream(upstream).await;
+                });
+            }
+        }
+    };
+    let proxy = state.proxy.clone();
+    let gate_state = state.clone();
+    let on_upgrade = move |client: WebSocket| async move {
+        let upstream = upstream.lock().unwrap_or_else(|e| e.into_inner()).take();
+        let Some(upstream) = upstream else {
+            return;
+        };
+        let recorder = proxy.clone();
+        let result = proxy
+            .run_ws_session(
+                upstream,
+                client,
+                token_id.as_deref(),
+                &path,
+                |message: Vec<u8>| {
+                    let state = gate_state.clone();
+                    let token_id = token_id.clone();
+                    let path = path.clone();
+                    async move {
+                        let tid = token_id.filter(|_| !state.dev_open_admin)?;
+                        match ws_message_gate(&state, &tid, &path, Some(&message)).await {
+                            WsGate::Pass => None,
+                            WsGate::Refuse(failure, text, data) => {
+                                let payload = mcp_error_payload(
+                                    failure,
+                                    jsonrpc_request_id(&message),
+                                    &text,
+                                    data,
+                                );
+                                Some(payload.to_string().into_bytes())
+                            }
+                        }
+                    }
+                },
+                |exchange: WsExchange| {
+                    let recorder = recorder.clone();
+                    let token_id = token_id.clone();
+                    let path = path.clone();
+                    async move {
+                        let Some(tid) = token_id.as_deref() else {
+                            return;
+                        };
+                        let billable =
+                            mcp_request_counts_toward_business_quota(&path, &exchange.request);
+                        let tool = request_tool_name(&path, &exchange.request);
+                        let _ = recorder
+                            .record_token_attempt(
+                                tid,
+                                &Method::GET,
+                                &path,
+                                None,
+                                tool.as_deref(),
+                                Some(StatusCode::SWITCHING_PROTOCOLS.as_u16() as i64),
+                                exchange.tavily_status_code,
+                                billable,
+                                exchange.outcome,
+                                None,
+                            )
+                            .await;
+                    }
+                },
+            )
+            .await;
+        if let Err(err) = result {
+            tracing::warn!("websocket session failed: {err}");
+        }
+    };
+    Ok(ws
+        .on_failed_upgrade(on_failed_upgrade)
+        .on_upgrade(on_upgrade))
+}
+
+/// Verdict of [`ws_message_gate`]: a refusal carries the JSON-RPC failure, message and data.
+enum WsGate {
+    Pass,
+    Refuse(McpFailure, String, Value),
+}
+
+/// The hourly request limit and business quota of `proxy_handler`, applied to the WebSocket
+/// upgrade (`message` is `None`) and to each client message carrying a JSON-RPC method.
+/// Refusals are recorded as token attempts like their HTTP counterparts.
+async fn ws_message_gate(
+    state: &AppState,
+    token_id: &str,
+    path: &str,
+    message: Option<&[u8]>,
+) -> WsGate {
+    let is_request = message.is_none_or(|message| {
+        serde_json::from_slice::<Value>(message)
+            .ok()
+            .is_some_and(|value| value.get("method").is_some())
+    });
+    if !is_request {
+        return WsGate::Pass;
+    }
+    let tool = message.and_then(|message| request_tool_name(path, message));
+    let record = |billable: bool, message: String| async move {
+        let _ = state
+            .proxy
+            .record_token_attempt(
+                token_id,
+                &Method::GET,
+                path,
+                None,
+                tool.as_deref(),
+                Some(StatusCode::TOO_MANY_REQUESTS.as_u16() as i64),
+                None,
+                billable,
+                "quota_exhausted",
+                Some(&message),
+            )
+            .await;
+    };
+    let internal = || {
+        WsGate::Refuse(
+            McpFailure::Internal,
+            McpFailure::Internal.default_message().to_string(),
+            json!({}),
+        )
+    };
+
+    match state.proxy.check_token_hourly_requests(token_id).await {
+        Ok(verdict) if !verdict.allowed => {
+            let text = build_request_limit_error_message(&verdict);
+            record(false, text.clone()).await;
+            return WsGate::Refuse(
+                McpFailure::RateLimited,
+                text,
+                request_limit_payload(&verdict),
+            );
+        }
+        Ok(_) => {}
+        Err(err) => {
+            tracing::error!("hourly request limit check failed for websocket: {err}");
+            return internal();
+        }
+    }
+
+    let Some(message) = message else {
+        return WsGate::Pass;
+    };
+    if !mcp_request_counts_toward_business_quota(path, message) {
+        return WsGate::Pass;
+    }
+    match state.proxy.check_token_quota(token_id).await {
+        Ok(verdict) if !verdict.allowed => {
+            let text = build_quota_error_message(&verdict);
+            record(true, text.clone()).await;
+            WsGate::Refuse(
+                McpFailure::QuotaExceeded,
+                text,
+                quota_exceeded_payload(&verdict),
+            )
+        }
+        Ok(_) => WsGate::Pass,
+        Err(err) => {
+            tracing::error!("quota check failed for websocket: {err}");
+            internal()
+        }
+    }
+}
+
+fn accepts_event_stream(headers: &HeaderMap) -> bool {
+    headers
+        .get(axum::http::header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .map(|raw| {
+            raw.split(',')
+                .any(|v| v.trim().eq_ignore_ascii_case("text/event-stream"))
+        })
+        .unwrap_or(false)
+}
+
+fn build_response(resp: ProxyResponse) -> Response<Body> {
+    let mut builder = Response::builder().status(resp.status);
+    if let Some(headers) = builder.headers_mut() {
+        copy_upstream_headers(headers, &resp.headers);
+        headers.insert(CONTENT_LENGTH, value_from_len(resp.body.len()));
+    }
+    builder
+        .body(Body::from(resp.body))
+        .unwrap_or_else(|_| Response::builder().status(500).body(Body::empty()).unwrap())
+}
+
+/// Pass a streamed upstream reply through chunk by chunk. The token attempt is recorded once
+/// the proxy has analyzed the whole stream; the body ends only after that, so clients that
+/// read to the end observe up-to-date token logs.
+#[allow(clippy::too_many_arguments)]
+fn build_stream_response(
+    state: &Arc<AppState>,
+    token_id: Option<String>,
+    method: &Method,
+    path: &str,
+    query: Option<&str>,
+    tool: Option<String>,
+    billable: bool,
+    upstream: ProxyStream,
+) -> Response<Body> {
+    let ProxyStream {
+        status,
+        headers: upstream_headers,
+        body: mut upstream_body,
+        outcome,
+    } = upstream;
+    let (recorded_tx, recorded_rx) = tokio::sync::oneshot::channel::<()>();
+    let proxy = state.proxy.clone();
+    let method = method.clone();
+    let path = path.to_string();
+    let query = query.map(str::to_string);
+    tokio::spawn(async move {
+        let Ok(analysis) = outcome.await else {
+            return;
+        };
+        if let Some(tid) = token_id.as_deref() {
+            let result_status = match analysis.status {
+                "quota_exhausted" => "quota_exhausted",
+                "error" => "error",
+                _ => "success",
+            };
+            let _ = proxy
+                .record_token_attempt(
+                    tid,
+                    &method,
+                    &path,
+                    query.as_deref(),
+                    tool.as_deref(),
+                    Some(status.as_u16() as i64),
+                    analysis.tavily_status_code,
+                    billable,
+                    result_status,
+                    None,
+                )
+                .await;
+        }
+        let _ = recorded_tx.send(());
+    });
+    let body = stream! {
+        while let Some(chunk) = upstream_body.next().await {
+            yield chunk;
+        }
+        let _ = recorded_rx.await;
+    };
+
+    let mut builder = Response::builder().status(status);
+    if let Some(headers) = builder.headers_mut() {
+        copy_upstream_headers(headers, &upstream_headers);
+    }
+    builder
+        .body(Body::from_stream(body))
+        .unwrap_or_else(|_| Response::builder().status(500).body(Body::empty()).unwrap())
+}
+
+fn copy_upstream_headers(target: &mut HeaderMap, upstream: &ReqHeaderMap) {
+    for (name, value) in upstream.iter() {
+        if name == TRANSFER_ENCODING || name == CONNECTION || name == CONTENT_LENGTH {
+            continue;
+        }
+        target.append(name.clone(), value.clone());
+    }
+}
+
+fn value_from_len(len: usize) -> axum::http::HeaderValue {
+    axum::http::HeaderValue::from_str(len.to_string().as_str())
+        .unwrap_or_else(|_| axum::http::HeaderValue::from_static("0"))
+}
+
+fn overloaded_message(priority: &str) -> String {
+    format!("proxy is at capacity for {priority} priority requests; retry later")
+}
+
+fn overloaded_response(priority: &str) -> Result<Response<Body>, StatusCode> {
+    let payload = json!({
+        "error": "overloaded",
+        "message": overloaded_message(priority),
+        "priority": priority,
+    });
+    Response::builder()
+        .status(StatusCode::TOO_MANY_REQUESTS)
+        .header(CONTENT_TYPE, "application/json; charset=utf-8")
+        .header(axum::http::header::RETRY_AFTER, "1")
+        .body(Body::from(payload.to_string()))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+fn request_limit_payload(verdict: &TokenHourlyRequestVerdict) -> Value {
+    json!({
+        "window": "hour",
+        "hourlyAny": {
+            "limit": verdict.hourly_limit,
+            "used": verdict.hourly_used,
+        },
+    })
+}
+
+/// Limits and usage of the window a token ran out of, plus the group's when the group
+/// limit was hit.
+fn quota_exceeded_payload(verdict: &TokenQuotaVerdict) -> Value {
+    let mut payload = json!({
+        "window": verdict.window_name(),
+        "hourly": {
+            "limit": verdict.hourly_limit,
+            "used": verdict.hourly_used,
+        },
+        "daily": {
+            "limit": verdict.daily_limit,
+            "used": verdict.daily_used,
+        },
+        "monthly": {
+            "limit": verdict.monthly_limit,
+            "used": verdict.monthly_used,
+        },
+    });
+    // Group limits are shared by all member tokens; unset limits are reported as null.
+    if let Some(group) = verdict
+        .group
+        .as_ref()
+        .filter(|g| g.exceeded_window.is_some())
+    {
+        payload["group"] = json!({
+            "name": group.group_name,
+            "window": group.exceeded_window.map(|w| w.as_str()),
+            "hourly": { "limit": group.hourly_limit, "used": group.usage.hourly_used },
+            "daily": { "limit": group.daily_limit, "used": group.usage.daily_used },
+            "monthly": { "limit": group.monthly_limit, "used": group.usage.monthly_used },
+        });
+    }
+    payload
+}
+
+fn build_request_limit_error_message(verdict: &TokenHourlyRequestVerdict) -> String {
+    match verdict.group_throttle_percent {
+        Some(percent) => format!(
+            "token hourly request limit exceeded (limit {}, used {}; token group throttled to {percent}% after upstream failures)",
+            verdict.hourly_limit, verdict.hourly_used
+        ),
+        None => format!(
+            "token hourly request limit exceeded (limit {}, used {})",
+            verdict.hourly_limit, verdict.hourly_used
+        ),
+    }
+}
+
+fn build_quota_error_message(verdict: &TokenQuotaVerdict) -> String {
+    if let Some(group) = &verdict.group
+        && let (Some(window), Some((limit, used))) = (group.exceeded_window, group.exceeded_stats())
+    {
+        return format!(
+            "token group '{}' quota exceeded on {} window (limit {limit}, used {used})",
+            group.group_name,
+            window.as_str()
+        );
+    }
+    let (limit, used) = quota_window_stats(verdict);
+    let window = verdict.window_name().unwrap_or("unknown");
+    format!("token quota exceeded on {window} window (limit {limit}, used {used})")
+}
+
+fn quota_window_stats(verdict: &TokenQuotaVerdict) -> (i64, i64) {
+    match verdict.exceeded_window.unwrap_or(QuotaWindow::Hour) {
+        QuotaWindow::Hour => (verdict.hourly_limit, verdict.hourly_used),
+        QuotaWindow::Day => (verdict.daily_limit, verdict.daily_used),
+        QuotaWindow::Month => (verdict.monthly_limit, verdict.monthly_used),
+    }
+}
+
+impl From<ApiKeyMetrics> for ApiKeyView {
+    fn from(metrics: ApiKeyMetrics) -> Self {
+        Self {
+            id: metrics.id,
+            status: metrics.status,
+            status_changed_at: metrics.status_changed_at,
+            last_used_at: metrics.last_used_at,
+            deleted_at: metrics.deleted_at,
+            quota_limit: metrics.quota_limit,
+            quota_remaining: metrics.quota_remaining,
+            quota_synced_at: metrics.quota_synced_at,
+            quota_next_sync_at: metrics.quota_next_sync_at,
+            total_requests: metrics.total_requests,
+            success_count: metrics.success_count,
+            error_count: metrics.error_count,
+            quota_exhausted_count: metrics.quota_exhausted_count,
+            projected_month_usage: metrics.projected_month_usage,
+            projected_overage: metrics.projected_overage,
+            exhausts_at: metrics.exhausts_at,
+            in_flight: metrics.in_flight,
+            cooldown_until: metrics.cooldown_until,
+        }
+    }
+}
+
+fn decode_body(bytes: &[u8]) -> Option<String> {
+    if bytes.is_empty() {
+        None
+    } else {
+        Some(String::from_utf8_lossy(bytes).into_owned())
+    }
+}
+
+impl From<RequestLogRecord> for RequestLogView {
+    fn from(record: RequestLogRecord) -> Self {
+        Self {
+            id: record.id,
+            public_id: record.public_id,
+            key_id: record.key_id,
+            auth_token_id: record.auth_token_id,
+            method: record.method,
+            path: record.path,
+            query: record.query,
+            http_status: record.status_code,
+            mcp_status: record.tavily_status_code,
+            result_status: record.result_status,
+            created_at: record.created_at,
+            error_message: record.error_message,
+            request_body: decode_body(&record.request_body),
+            response_body: decode_body(&record.response_body),
+            forwarded_headers: record.forwarded_headers,
+            dropped_headers: record.dropped_headers,
+            timeout_ms: record.timeout_ms,
+            latency_ms: record.latency_ms,
+            attempt: record.attempt,
+            response_summary: record.response_summary,
+            cache_hits: record.cache_hits,
+            request_body_sha256: record.request_body_sha256,
+            request_body_len: record.request_body_len,
+            response_body_sha256: record.response_body_sha256,
+            response_body_len: record.response_body_len,
+            bodies_intact: None,
+            annotations: None,
+        }
+    }
+}
+
+impl From<ProxySummary> for SummaryView {
+    fn from(summary: ProxySummary) -> Self {
+        Self {
+            total_requests: summary.total_requests,
+            success_count: summary.success_count,
+            error_count: summary.error_count,
+            quota_exhausted_count: summary.quota_exhausted_count,
+            active_keys: summary.active_keys,
+            exhausted_keys: summary.exhausted_keys,
+            last_activity: summary.last_activity,
+            total_quota_limit: summary.total_quota_limit,
+            total_quota_remaining: summary.total_quota_remaining,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DEFAULT_UPSTREAM;
+    use axum::Router;
+    use axum::extract::{Json, Query};
+    use axum::http::Method;
+    use axum::routing::{any, post};
+    use nanoid::nanoid;
+    use reqwest::Client;
+    use sqlx::Row;
+    use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions};
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+    use tokio::net::TcpListener;
+
+    fn temp_db_path(prefix: &str) -> PathBuf {
+        let file = format!("{}-{}.db", prefix, nanoid!(8));
+        std::env::temp_dir().join(file)
+    }
+
+    async fn spawn_mock_upstream(expected_api_key: String) -> SocketAddr {
+        let app = Router::new().route(
+            "/mcp",
+            any({
+                move |Query(params): Query<HashMap<String, String>>| {
+                    let expected_api_key = expected_api_key.clone();
+                    async move {
+                        let received = params.get("tavilyApiKey").cloned();
+                        if received.as_deref() != Some(expected_api_key.as_str()) {
+                            return (
+                                StatusCode::UNAUTHORIZED,
+                                Body::from("missing or incorrect tavilyApiKey"),
+                            );
+                        }
+                        (StatusCode::OK, Body::from("{\"ok\":true}"))
+                    }
+                }
+            }),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app.into_make_service())
+                .await
+                .unwrap();
+        });
+        addr
+    }
+
+    async fn spawn_http_search_mock_asserting_api_key(expected_api_key: String) -> SocketAddr {
+        let app = Router::new().route(
+            "/search",
+            post({
+                move |Json(body): Json<Value>| {
+                    let expected_api_key = expected_api_key.clone();
+                    async move {
+                        let api_key = body.get("api_key").and_then(|v| v.as_str()).unwrap_or("");
+                        assert_eq!(
+                            api_key, expected_api_key,
+                            "upstream api_key should use Tavily key from pool"
+                        );
+                        assert!(
+                            !api_key.starts_with("th-"),
+                            "upstream api_key must not be Hikari token"
+                        );
+                        (
+                            StatusCode::OK,
+                            Json(serde_json::json!({
+                                "status": 200,
+                                "results": [],
+                            })),
+                        )
+                    }
+                }
+            }),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app.into_make_service())
+                .await
+                .unwrap();
+        });
+        addr
+    }
+
+    async fn spawn_http_extract_mock_asserting_api_key(expected_api_key: String) -> SocketAddr {
+        let app = Router::new().route(
+            "/extract",
+            post({
+                move |Json(body): Json<Value>| {
+                    let expected_api_key = expected_api_key.clone();
+                    async move {
+                        let api_key = body.get("api_key").and_then(|v| v.as_str()).unwrap_or("");
+                        assert_eq!(
+                            api_key, expected_api_key,
+                            "upstream api_key for /extract should use Tavily key from pool"
+                        );
+                        assert!(
+                            !api_key.starts_with("th-"),
+                            "upstream /extract api_key must not be Hikari token"
+                        );
+                        (
+                            StatusCode::OK,
+                            Json(serde_json::json!({
+                                "status": 200,
+                                "results": [],
+                            })),
+                        )
+                    }
+                }
+            }),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app.into_make_service())
+                .await
+                .unwrap();
+        });
+        addr
+    }
+
+    async fn spawn_http_crawl_mock_asserting_api_key(expected_api_key: String) -> SocketAddr {
+        let app = Router::new().route(
+            "/crawl",
+            post({
+                move |Json(body): Json<Value>| {
+                    let expected_api_key = expected_api_key.clone();
+                    async move {
+                        let api_key = body.get("api_key").and_then(|v| v.as_str()).unwrap_or("");
+                        assert_eq!(
+                            api_key, expected_api_key,
+                            "upstream api_key for /crawl should use Tavily key from pool"
+                        );
+                        assert!(
+                            !api_key.starts_with("th-"),
+                            "upstream /crawl api_key must not be Hikari token"
+                        );
+                        (
+                            StatusCode::OK,
+                            Json(serde_json::json!({
+                                "status": 200,
+                                "results": [],
+                            })),
+                        )
+                    }
+                }
+            }),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app.into_make_service())
+                .await
+                .unwrap();
+        });
+        addr
+    }
+
+    async fn spawn_http_map_mock_asserting_api_key(expected_api_key: String) -> SocketAddr {
+        let app = Router::new().route(
+            "/map",
+            post({
+                move |Json(body): Json<Value>| {
+                    let expected_api_key = expected_api_key.clone();
+                    async move {
+                        let api_key = body.get("api_key").and_then(|v| v.as_str()).unwrap_or("");
+                        assert_eq!(
+                            api_key, expected_api_key,
+                            "upstream api_key for /map should use Tavily key from pool"
+                        );
+                        assert!(
+                            !api_key.starts_with("th-"),
+                            "upstream /map api_key must not be Hikari token"
+                        );
+                        (
+                            StatusCode::OK,
+                            Json(serde_json::json!({
+                                "status": 200,
+                                "results": [],
+                            })),
+                        )
+                    }
+                }
+            }),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app.into_make_service())
+                .await
+                .unwrap();
+        });
+        addr
+    }
+
+    async fn spawn_proxy_server(proxy: TavilyProxy, usage_base: String) -> SocketAddr {
+        spawn_proxy_server_with_dev(proxy, usage_base, false).await
+    }
+
+    async fn spawn_proxy_server_with_dev(
+        proxy: TavilyProxy,
+        usage_base: String,
+        dev_open_admin: bool,
+    ) -> SocketAddr {
+        let state = Arc::new(AppState {
+            proxy,
+            static_dir: None,
+            forward_auth: ForwardAuthConfig::new(None, None, None, None),
+            dev_open_admin,
+            usage_base,
+            access_log: None,
+            cors: None,
+            public_quota: Arc::new(PublicIpQuota::new(0)),
+            rate_limiter: None,
+            trusted_proxies: Arc::new(TrustedProxies::default()),
+        });
+
+        let app = Router::new()
+            .route("/mcp", any(proxy_handler))
+            .route("/mcp/*path", any(proxy_handler))
+            .route("/api/tavily/search", post(tavily_http_search))
+            .route("/api/tavily/extract", post(tavily_http_extract))
+            .route("/api/tavily/crawl", post(tavily_http_crawl))
+            .route("/api/tavily/map", post(tavily_http_map))
+            .route("/api/tavily/usage", get(tavily_http_usage))
+            .with_state(state);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app.into_make_service())
+                .await
+                .unwrap();
+        });
+        addr
+    }
+
+    async fn spawn_keys_admin_server(
+        proxy: TavilyProxy,
+        forward_auth: ForwardAuthConfig,
+        dev_open_admin: bool,
+    ) -> SocketAddr {
+        let state = Arc::new(AppState {
+            proxy,
+            static_dir: None,
+            forward_auth,
+            dev_open_admin,
+            usage_base: "http://127.0.0.1:58088".to_string(),
+            access_log: None,
+            cors: None,
+            public_quota: Arc::new(PublicIpQuota::new(0)),
+            rate_limiter: None,
+            trusted_proxies: Arc::new(TrustedProxies::default()),
+        });
+
+        let app = Router::new()
+            .route("/api/keys/batch", post(create_api_keys_batch))
+            .with_state(state);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app.into_make_service())
+                .await
+                .unwrap();
+        });
+        addr
+    }
+
+    /// Minimal WebSocket MCP upstream: checks the injected key, accepts the `mcp` subprotocol
+    /// and answers every JSON-RPC request with a result carrying the same id.
+    async fn spawn_ws_mcp_upstream(expected_api_key: &'static str) -> SocketAddr {
+        use futures_util::SinkExt;
+        use tokio_tungstenite::tungstenite::{
+            Message,
+            handshake::server::{Request as WsRequest, Response as WsResponse},
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let check = |request: &WsRequest, mut response: WsResponse| {
+                        let query = request.uri().query().unwrap_or_default();
+                        assert!(query.contains(&format!("tavilyApiKey={expected_api_key}")));
+                        let offered = request
+                            .headers()
+                            .get("sec-websocket-protocol")
+                            .and_then(|v| v.to_str().ok())
+                            .is_some_and(|v| v.split(',').any(|p| p.trim() == "mcp"));
+                        if offered {
+                            response
+                                .headers_mut()
+                                .insert("sec-websocket-protocol", "mcp".parse().unwrap());
+                        }
+                        Ok(response)
+                    };
+                    let Ok(mut socket) = tokio_tungstenite::accept_hdr_async(socket, check).await
+                    else {
+                        return;
+                    };
+                    while let Some(Ok(message)) = socket.next().await {
+                        let Message::Text(text) = message else {
+                            continue;
+                        };
+                        let request: Value = serde_json::from_str(&text).unwrap();
+                        let response = json!({
+                            "jsonrpc": "2.0",
+                            "id": request["id"],
+                            "result": { "content": [{ "type": "text", "text": "ok" }] },
+                        });
+                        if socket
+                            .send(Message::Text(response.to_string()))
+                            .await
+                            .is_err()
+                        {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    /// Client handshake for `/mcp` on `addr`, authenticated with `token`.
+    fn ws_mcp_request(
+        addr: SocketAddr,
+        token: &str,
+    ) -> tokio_tungstenite::tungstenite::handshake::client::Request {
+        use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+        let mut request = format!("ws://{addr}/mcp").into_client_request().unwrap();
+        let headers = request.headers_mut();
+        headers.insert("authorization", format!("Bearer {token}").parse().unwrap());
+        headers.insert("sec-websocket-protocol", "mcp".parse().unwrap());
+        request
+    }
+
+    #[tokio::test]
+    async fn mcp_websocket_relays_messages_and_logs_each_reply() {
+        use futures_util::SinkExt;
+        use tokio_tungstenite::tungstenite::{
+            Message,
+            protocol::{CloseFrame, frame::coding::CloseCode},
+        };
+
+        let db_path = temp_db_path("mcp-websocket");
+        let db_str = db_path.to_string_lossy().to_string();
+        let expected_api_key = "tvly-websocket-key";
+        let upstream_addr = spawn_ws_mcp_upstream(expected_api_key).await;
+        let proxy = TavilyProxy::with_endpoint(vec![expected_api_key], DEFAULT_UPSTREAM, &db_str)
+            .await
+            .expect("proxy created")
+            .with_ws_upstream(Some(&format!("ws://{upstream_addr}/ws")));
+        let access_token = proxy
+            .create_access_token(Some("websocket"))
+            .await
+            .expect("token");
+        let pool = proxy.key_store.pool.clone();
+        let proxy_addr = spawn_proxy_server(proxy, DEFAULT_UPSTREAM.to_string()).await;
+
+        let (mut socket, response) =
+            tokio_tungstenite::connect_async(ws_mcp_request(proxy_addr, &access_token.token))
+                .await
+                .expect("websocket handshake");
+        assert_eq!(response.status(), StatusCode::SWITCHING_PROTOCOLS);
+        // The subprotocol the upstream accepted is echoed to the client.
+        assert_eq!(
+            response
+                .headers()
+                .get("sec-websocket-protocol")
+                .and_then(|v| v.to_str().ok()),
+            Some("mcp")
+        );
+
+        // Pings are answered by the proxy itself.
+        socket.send(Message::Ping(b"alive".to_vec())).await.unwrap();
+        let pong = socket.next().await.expect("pong").unwrap();
+        assert_eq!(pong, Message::Pong(b"alive".to_vec()));
+
+        let call = r#"{"jsonrpc":"2.0","id":7,"method":"tools/call","params":{"name":"tavily-search","arguments":{"query":"ws"}}}"#;
+        socket.send(Message::Text(call.to_string())).await.unwrap();
+        let Message::Text(reply) = socket.next().await.expect("reply").unwrap() else {
+            panic!("expected a text reply");
+        };
+        let reply: Value = serde_json::from_str(&reply).unwrap();
+        assert_eq!(reply["id"], 7);
+
+        socket
+            .send(Message::Close(Some(CloseFrame {
+                code: CloseCode::Normal,
+                reason: "".into(),
+            })))
+            .await
+            .unwrap();
+        let close = socket.next().await.expect("close").unwrap();
+        assert!(matches!(close, Message::Close(_)), "{close:?}");
+
+        let mut token_log = None;
+        for _ in 0..50 {
+            token_log = sqlx::query_as::<_, (String, Option<String>, i64)>(
+                "SELECT result_status, tool, counts_business_quota FROM auth_token_logs WHERE token_id = ?",
+            )
+            .bind(&access_token.id)
+            .fetch_optional(&pool)
+            .await
+            .expect("token log");
+            if token_log.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(
+            token_log,
+            Some(("success".to_string(), Some("search".to_string()), 1))
+        );
+        let (outcome, tool): (String, Option<String>) =
+            sqlx::query_as("SELECT result_status, tool FROM request_logs")
+                .fetch_one(&pool)
+                .await
+                .expect("request log");
+        assert_eq!(
+            (outcome.as_str(), tool.as_deref()),
+            ("success", Some("search"))
+        );
+
+        let _ = std::fs::remove_file(db_path);
+    }
+
+    #[tokio::test]
+    async fn mcp_websocket_enforces_token_quota() {
+        use futures_util::SinkExt;
+        use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+
+        let _guard = crate::tests::env_lock().lock_owned().await;
+        unsafe {
+            std::env::set_var("TOKEN_HOURLY_LIMIT", "1");
+        }
+        let db_path = temp_db_path("mcp-websocket-quota");
+        let db_str = db_path.to_string_lossy().to_string();
+        let expected_api_key = "tvly-websocket-quota-key";
+        let http_upstream = spawn_mock_upstream(expected_api_key.to_string()).await;
+        let ws_upstream = spawn_ws_mcp_upstream(expected_api_key).await;
+        let proxy = TavilyProxy::with_endpoint(
+            vec![expected_api_key],
+            &format!("http://{http_upstream}"),
+            &db_str,
+        )
+        .await
+        .expect("proxy created")
+        .with_ws_upstream(Some(&format!("ws://{ws_upstream}/ws")));
+        let access_token = proxy
+            .create_access_token(Some("websocket-quota"))
+            .await
+            .expect("token");
+        let pool = proxy.key_store.pool.clone();
+        let proxy_addr = spawn_proxy_server(proxy, DEFAULT_UPSTREAM.to_string()).await;
+
+        // Spend the whole hourly business quota over HTTP.
+        let call = |id: i64| {
+            json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "method": "tools/call",
+                "params": { "name": "tavily-search", "arguments": { "query": "ws" } },
+            })
+        };
+        let resp = Client::new()
+            .post(format!("http://{proxy_addr}/mcp"))
+            .bearer_auth(&access_token.token)
+            .json(&call(1))
+            .send()
+            .await
+            .expect("http tool call");
+        assert!(resp.status().is_success(), "{}", resp.status());
+
+        let (mut socket, _) =
+            tokio_tungstenite::connect_async(ws_mcp_request(proxy_addr, &access_token.token))
+                .await
+                .expect("websocket handshake");
+        socket
+            .send(Message::Text(call(7).to_string()))
+            .await
+            .unwrap();
+        let Message::Text(reply) = socket.next().await.expect("refusal").unwrap() else {
+            panic!("expected a text refusal");
+        };
+        let reply: Value = serde_json::from_str(&reply).unwrap();
+        assert_eq!(reply["id"], 7);
+        assert_eq!(
+            reply["error"]["code"],
+            McpFailure::QuotaExceeded.jsonrpc_code()
+        );
+        let Message::Close(Some(close)) = socket.next().await.expect("close").unwrap() else {
+            panic!("expected a close frame with a code");
+        };
+        assert_eq!(u16::from(close.code), 1008);
+
+        // Only the HTTP call reached an upstream.
+        let (attempts,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM request_logs")
+            .fetch_one(&pool)
+            .await
+            .expect("request logs");
+        assert_eq!(attempts, 1);
+
+        // Now over quota, the token cannot open another session.
+        let refused =
+            tokio_tungstenite::connect_async(ws_mcp_request(proxy_addr, &access_token.token))
+                .await
+                .expect_err("handshake refused");
+        let WsError::Http(response) = refused else {
+            panic!("expected an HTTP refusal, got {refused:?}");
+        };
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        unsafe {
+            std::env::remove_var("TOKEN_HOURLY_LIMIT");
+        }
+        let _ = std::fs::remove_file(db_path);
+    }
+
+    #[tokio::test]
+    async fn tavily_http_search_returns_401_without_token() {
+        let db_path = temp_db_path("http-search-401-missing");
+        let db_str = db_path.to_string_lossy().to_string();
+
+        let expected_api_key = "tvly-http-search-any-limit-key";
+        let proxy = TavilyProxy::with_endpoint(
+            vec![expected_api_key.to_string()],
+            DEFAULT_UPSTREAM,
+            &db_str,
+        )
+        .await
+        .expect("proxy created");
+
+        let upstream_addr =
+            spawn_http_search_mock_asserting_api_key(expected_api_key.to_string()).await;
+        let usage_base = format!("http://{}", upstream_addr);
+        let proxy_addr = spawn_proxy_server(proxy, usage_base).await;
+
+        let client = Client::new();
+        let url = format!("http://{}/api/tavily/search", proxy_addr);
+        let resp = client
+            .post(url)
+            .json(&serde_json::json!({ "query": "test" }))
+            .send()
+            .await
+            .expect("request to proxy succeeds");
+
+        assert_eq!(resp.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+        let body: serde_json::Value = resp.json().await.expect("parse json body");
+        assert_eq!(
+            body.get("error"),
+            Some(&serde_json::Value::String("missing token".into()))
+        );
+
+        let _ = std::fs::remove_file(db_path);
+    }
+
+    #[tokio::test]
+    async fn tavily_http_search_dev_open_admin_does_not_fail_foreign_key() {
+        let db_path = temp_db_path("http-search-dev-open-admin-fk");
+        let db_str = db_path.to_string_lossy().to_string();
+
+        let expected_api_key = "tvly-http-search-dev-open-admin-key";
+        let proxy = TavilyProxy::with_endpoint(
+            vec![expected_api_key.to_string()],
+            DEFAULT_UPSTREAM,
+            &db_str,
+        )
+        .await
+        .expect("proxy created");
+
+        let upstream_addr =
+            spawn_http_search_mock_asserting_api_key(expected_api_key.to_string()).await;
+        let usage_base = format!("http://{}", upstream_addr);
+        let proxy_addr = spawn_proxy_server_with_dev(proxy, usage_base, true).await;
+
+        let client = Client::new();
+        let url = format!("http://{}/api/tavily/search", proxy_addr);
+        let resp = client
+            .post(url)
+            .json(&serde_json::json!({ "query": "dev-open-admin fk" }))
+            .send()
+            .await
+            .expect("request to proxy succeeds");
+
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+
+        let body: serde_json::Value = resp.json().await.expect("parse json body");
+        assert_eq!(body.get("status").and_then(|v| v.as_i64()), Some(200));
+
+        let _ = std::fs::remove_file(db_path);
+    }
+
+    #[tokio::test]
+    async fn version_endpoint_reports_build_metadata() {
+        let versions = detect_versions(None);
+        assert_eq!(versions.git_sha, env!("APP_GIT_SHA"));
+        assert!(!versions.git_sha.is_empty());
+        let built = versions
+            .build_timestamp
+            .as_deref()
+            .expect("build.rs embeds a build timestamp");
+        DateTime::parse_from_rfc3339(built).expect("rfc3339 build timestamp");
+        let expected_features: Vec<_> = env!("APP_CARGO_FEATURES")
+            .split(',')
+            .filter(|f| !f.is_empty())
+            .collect();
+        assert_eq!(versions.features, expected_features);
+
+        let app = crate::test_util::TestApp::spawn(Default::default(), &["tvly-version-key"])
+            .await
+            .expect("test app");
+        let body: serde_json::Value = app
+            .client()
+            .get(app.url("/api/version"))
+            .send()
+            .await
+            .expect("version request")
+            .json()
+            .await
+            .expect("version json");
+        assert_eq!(body["gitSha"], versions.git_sha);
+        assert_eq!(body["buildTimestamp"], built);
+        assert_eq!(body["features"], serde_json::json!(expected_features));
+        assert!(body["backend"].is_string());
+    }
+
+    #[tokio::test]
+    async fn api_keys_batch_returns_403_for_non_admin() {
+        let db_path = temp_db_path("keys-batch-403-non-admin");
+        let db_str = db_path.to_string_lossy().to_string();
+
+        let proxy = TavilyProxy::with_endpoint(Vec::<String>::new(), DEFAULT_UPSTREAM, &db_str)
+            .await
+            .expect("proxy created");
+
+        let forward_auth = ForwardAuthConfig::new(
+            Some(HeaderName::from_static("x-forward-user")),
+            Some("admin".to_string()),
+            None,
+            None,
+        );
+        let addr = spawn_keys_admin_server(proxy, forward_auth, false).await;
+
+        let client = Client::new();
+        let url = format!("http://{}/api/keys/batch", addr);
+        let resp = client
+            .post(url)
+            .json(&serde_json::json!({ "api_keys": ["k1"] }))
+            .send()
+            .await
+            .expect("request succeeds");
+
+        assert_eq!(resp.status(), reqwest::StatusCode::FORBIDDEN);
+
+        let _ = std::fs::remove_file(db_path);
+    }
+
+    #[tokio::test]
+    async fn api_keys_batch_rejects_over_limit() {
+        let db_path = temp_db_path("keys-batch-400-over-limit");
+        let db_str = db_path.to_string_lossy().to_string();
+
+        let proxy = TavilyProxy::with_endpoint(Vec::<String>::new(), DEFAULT_UPSTREAM, &db_str)
+            .await
+            .expect("proxy created");
+
+        let forward_auth = ForwardAuthConfig::new(
+            Some(HeaderName::from_static("x-forward-user")),
+            Some("admin".to_string()),
+            None,
+            None,
+        );
+        let addr = spawn_keys_admin_server(proxy, forward_auth, false).await;
+
+        let api_keys: Vec<String> = (0..=API_KEYS_BATCH_LIMIT)
+            .map(|i| format!("tvly-{i}"))
+            .collect();
+
+        let client = Client::new();
+        let url = format!("http://{}/api/keys/batch", addr);
+        let resp = client
+            .post(url)
+            .header("x-forward-user", "admin")
+            .json(&serde_json::json!({ "api_keys": api_keys }))
+            .send()
+            .await
+            .expect("request succeeds");
+
+        assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+
+        let body: serde_json::Value = resp.json().await.expect("parse json body");
+        assert_eq!(
+            body.get("error"),
+            Some(&serde_json::Value::String("too_many_items".into()))
+        );
+
+        let _ = std::fs::remove_file(db_path);
+    }
+
+    #[tokio::test]
+    async fn api_keys_batch_reports_statuses_and_is_partial_success() {
+        let db_path = temp_db_path("keys-batch-mixed");
+        let db_str = db_path.to_string_lossy().to_string();
+
+        let proxy = TavilyProxy::with_endpoint(Vec::<String>::new(), DEFAULT_UPSTREAM, &db_str)
+            .await
+            .expect("proxy created");
+
+        // Pre-create: one active existing key, and one soft-deleted key.
+        let _existing_id = proxy
+            .add_or_undelete_key("tvly-existing")
+            .await
+            .expect("existing key created");
+        let deleted_id = proxy
+            .add_or_undelete_key("tvly-deleted")
+            .await
+            .expect("deleted key created");
+        proxy
+            .soft_delete_key_by_id(&deleted_id)
+            .await
+            .expect("key soft deleted");
+
+        // Create a trigger that forces a deterministic failure for one key.
+        let options = SqliteConnectOptions::new()
+            .filename(&db_str)
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .busy_timeout(Duration::from_secs(5));
+        let pool = SqlitePoolOptions::new()
+            .min_connections(1)
+            .max_connections(1)
+            .connect_with(options)
+            .await
+            .expect("open db pool");
+        sqlx::query(
+            r#"
+            CREATE TRIGGER fail_insert_api_key
+            BEFORE INSERT ON api_keys
+            WHEN NEW.api_key = 'tvly-fail'
+            BEGIN
+                SELECT RAISE(ABORT, 'boom');
+            END;
+            "#,
+        )
+        .execute(&pool)
+        .await
+        .expect("create trigger");
+
+        let forward_auth = ForwardAuthConfig::new(
+            Some(HeaderName::from_static("x-forward-user")),
+            Some("admin".to_string()),
+            None,
+            None,
+        );
+        let addr = spawn_keys_admin_server(proxy, forward_auth, false).await;
+
+        let input = vec![
+            "  tvly-new  ".to_string(),
+            "tvly-fail".to_string(),
+            "tvly-new-2".to_string(),
+            "tvly-existing".to_string(),
+            "tvly-deleted".to_string(),
+            "tvly-existing".to_string(),
+            "tvly-new-2".to_string(),
+            "".to_string(),
+            "   ".to_string(),
+        ];
+
+        let client = Client::new();
+        let url = format!("http://{}/api/keys/batch", addr);
+        let resp = client
+            .post(url)
+            .header("x-forward-user", "admin")
+            .json(&serde_json::json!({ "api_keys": input }))
+            .send()
+            .await
+            .expect("request succeeds");
+
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+
+        let body: serde_json::Value = resp.json().await.expect("parse json body");
+        let summary = body.get("summary").expect("summary exists");
+        assert_eq!(summary.get("created").and_then(|v| v.as_u64()), Some(2));
+        assert_eq!(summary.get("undeleted").and_then(|v| v.as_u64()), Some(1));
+        assert_eq!(summary.get("existed").and_then(|v| v.as_u64()), Some(1));
+        assert_eq!(
+            summary.get("duplicate_in_input").and_then(|v| v.as_u64()),
+            Some(2)
+        );
+        assert_eq!(summary.get("failed").and_then(|v| v.as_u64()), Some(1));
+        assert_eq!(
+            summary.get("ignored_empty").and_then(|v| v.as_u64()),
+            Some(2)
+        );
+
+        let results = body
+            .get("results")
+            .and_then(|v| v.as_array())
+            .expect("results array");
+        assert_eq!(results.len(), 7, "empty items are ignored in results");
+
+        let statuses: Vec<(&str, &str)> = results
+            .iter()
+            .map(|r| {
+                (
+                    r.get("api_key").and_then(|v| v.as_str()).unwrap_or(""),
+                    r.get("status").and_then(|v| v.as_str()).unwrap_or(""),
+                )
+            })
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                ("tvly-new", "created"),
+                ("tvly-fail", "failed"),
+                ("tvly-new-2", "created"),
+                ("tvly-existing", "existed"),
+                ("tvly-deleted", "undeleted"),
+                ("tvly-existing", "duplicate_in_input"),
+                ("tvly-new-2", "duplicate_in_input"),
+            ]
+        );
+
+        // id is present only when we hit the DB successfully.
+        for (idx, expected_has_id) in [
+            (0, true),
+            (1, false),
+            (2, true),
+            (3, true),
+            (4, true),
+            (5, false),
+            (6, false),
+        ] {
+            let has_id = results[idx].get("id").and_then(|v| v.as_str()).is_some();
+            assert_eq!(has_id, expected_has_id, "result[{idx}] id presence");
+        }
+
+        // error is required for failed.
+        let err = results[1]
+            .get("error")
+            .and_then(|v| v.as_str())
+            .unwrap_or("");
+        assert!(
+            err.contains("boom"),
+            "failed error should include trigger message"
+        );
+
+        // DB side effects: soft-deleted key should be restored, failed key should not exist.
+        let deleted_at: Option<i64> =
+            sqlx::query_scalar("SELECT deleted_at FROM api_keys WHERE api_key = ?")
+                .bind("tvly-deleted")
+                .fetch_one(&pool)
+                .await
+                .expect("tvly-deleted exists");
+        assert!(deleted_at.is_none(), "tvly-deleted should be undeleted");
+
+        let fail_row: Option<String> =
+            sqlx::query_scalar("SELECT id FROM api_keys WHERE api_key = ?")
+                .bind("tvly-fail")
+                .fetch_optional(&pool)
+                .await
+                .expect("query fail key");
+        assert!(fail_row.is_none(), "tvly-fail should not be inserted");
+
+        let _ = std::fs::remove_file(db_path);
+    }
+
+    #[tokio::test]
+    async fn tavily_http_search_returns_401_for_invalid_token() {
+        let db_path = temp_db_path("http-search-401-invalid");
+        let db_str = db_path.to_string_lossy().to_string();
+
+        let proxy = TavilyProxy::with_endpoint(Vec::<String>::new(), DEFAULT_UPSTREAM, &db_str)
+            .await
+            .expect("proxy created");
+
+        let usage_base = "http://127.0.0.1:58088".to_string();
+        let proxy_addr = spawn_proxy_server(proxy, usage_base).await;
+
+        let client = Client::new();
+        let url = format!("http://{}/api/tavily/search", proxy_addr);
+        let resp = client
+            .post(url)
+            .header("Authorization", "Bearer th-invalid-token")
+            .json(&serde_json::json!({ "query": "test" }))
+            .send()
+            .await
+            .expect("request to proxy succeeds");
+
+        assert_eq!(resp.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+        let body: serde_json::Value = resp.json().await.expect("parse json body");
+        assert_eq!(
+            body.get("error"),
+            Some(&serde_json::Value::String(
+                "invalid or disabled token".into()
+            ))
+        );
+
+        let _ = std::fs::remove_file(db_path);
+    }
+
+    #[tokio::test]
+    async fn tavily_http_search_returns_429_when_quota_exhausted_and_logs_token_attempt() {
+        let db_path = temp_db_path("http-search-429-quota");
+        let db_str = db_path.to_string_lossy().to_string();
+
+        let proxy = TavilyProxy::with_endpoint(Vec::<String>::new(), DEFAULT_UPSTREAM, &db_str)
+            .await
+            .expect("proxy created");
+        let token = proxy
+            .create_access_token(Some("quota-test"))
+            .await
+            .expect("create token");
+
+        // Pre-saturate hourly quota so that the next check in handler will block.
+        let hourly_limit = effective_token_hourly_limit();
+        for _ in 0..hourly_limit {
+            let verdict = proxy
+                .check_token_quota(&token.id)
+                .await
+                .expect("quota check ok");
+            assert!(
+                verdict.allowed,
+                "should be allowed within limit during warmup"
+            );
+        }
+
+        let usage_base = "http://127.0.0.1:58088".to_string();
+        let proxy_addr = spawn_proxy_server(proxy.clone(), usage_base).await;
+
+        let client = Client::new();
+        let url = format!("http://{}/api/tavily/search", proxy_addr);
+        let resp = client
+            .post(url)
+            .header("Authorization", format!("Bearer {}", token.token))
+            .json(&serde_json::json!({ "query": "test quota" }))
+            .send()
+            .await
+            .expect("request to proxy succeeds");
+
+        assert_eq!(resp.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+
+        let body: serde_json::Value = resp.json().await.expect("parse json body");
+        assert_eq!(
+            body.get("error"),
+            Some(&serde_json::Value::String("quota_exhausted".into()))
+        );
+
+        // Verify token logs contain a quota_exhausted entry with HTTP 429.
+        let options = SqliteConnectOptions::new()
+            .filename(&db_str)
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .busy_timeout(Duration::from_secs(5));
+        let pool = SqlitePoolOptions::new()
+            .min_connections(1)
+            .max_connections(5)
+            .connect_with(options)
+            .await
+            .expect("connect to sqlite");
+
+        let row = sqlx::query(
+            r#"
+            SELECT http_status, mcp_status, result_status
+            FROM auth_token_logs
+            WHERE token_id = ?
+            ORDER BY id DESC
+            LIMIT 1
+            "#,
+        )
+        .bind(&token.id)
+        .fetch_one(&pool)
+        .await
+        .expect("token log row exists");
+
+        let http_status: Option<i64> = row.try_get("http_status").unwrap();
+        let mcp_status: Option<i64> = row.try_get("mcp_status").unwrap();
+        let result_status: String = row.try_get("result_status").unwrap();
+
+        assert_eq!(http_status, Some(429));
+        assert_eq!(mcp_status, None);
+        assert_eq!(result_status, "quota_exhausted");
+
+        let _ = std::fs::remove_file(db_path);
+    }
+
+    #[tokio::test]
+    async fn tavily_http_search_hourly_any_limit_429_is_non_billable_and_excluded_from_rollup() {
+        let _guard = crate::tests::env_lock().lock_owned().await;
+        let db_path = temp_db_path("http-search-hourly-any-nonbillable");
+        let db_str = db_path.to_string_lossy().to_string();
+
+        // Preserve any existing env value to avoid cross-test leakage.
+        let previous_limit = std::env::var("TOKEN_HOURLY_REQUEST_LIMIT").ok();
+        unsafe {
+            std::env::set_var("TOKEN_HOURLY_REQUEST_LIMIT", "1");
+        }
+
+        let expected_api_key = "tvly-http-search-hourly-any-key";
+        let proxy = TavilyProxy::with_endpoint(
+            vec![expected_api_key.to_string()],
+            DEFAULT_UPSTREAM,
+            &db_str,
+        )
+        .await
+        .expect("proxy created");
+
+        let access_token = proxy
+            .create_access_token(Some("hourly-any-e2e"))
+            .await
+            .expect("create token");
+
+        let upstream_addr =
+            spawn_http_search_mock_asserting_api_key(expected_api_key.to_string()).await;
+        let usage_base = format!("http://{}", upstream_addr);
+        let proxy_addr = spawn_proxy_server(proxy.clone(), usage_base).await;
+
+        let client = Client::new();
+        let url = format!("http://{}/api/tavily/search", proxy_addr);
+
+        // 1st request should pass and hit mock upstream.
+        let first = client
+            .post(url.clone())
+            .json(&serde_json::json!({
+                "api_key": access_token.token,
+                "query": "hourly-any smoke"
+            }))
+            .send()
+            .await
+            .expect("first request succeeds");
+        assert!(
+            first.status().is_success(),
+            "first request should be allowed, got {}",
+            first.status()
+        );
+
+        // 2nd request should be blocked by hourly-any limiter before upstream.
+        let second = client
+            .post(url)
+            .json(&serde_json::json!({
+                "api_key": access_token.token,
+                "query": "hourly-any blocked"
+            }))
+            .send()
+            .await
+            .expect("second request succeeds");
+        assert_eq!(
+            second.status(),
+            reqwest::StatusCode::TOO_MANY_REQUESTS,
+            "expected hourly-any 429 on second request"
+        );
+
+        // Inspect latest auth_token_logs row for hourly-any 429.
+        let options = SqliteConnectOptions::new()
+            .filename(&db_str)
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .busy_timeout(Duration::from_secs(5));
+        let pool = SqlitePoolOptions::new()
+            .min_connections(1)
+            .max_connections(5)
+            .connect_with(options)
+            .await
+            .expect("connect to sqlite");
+
+        let row = sqlx::query(
+            r#"
+            SELECT http_status, counts_business_quota
+            FROM auth_token_logs
+            WHERE token_id = ?
+            ORDER BY id DESC
+            LIMIT 1
+            "#,
+        )
+        .bind(&access_token.id)
+        .fetch_one(&pool)
+        .await
+        .expect("token log row exists");
+
+        let http_status: Option<i64> = row.try_get("http_status").unwrap();
+        let counts_business_quota: i64 = row.try_get("counts_business_quota").unwrap();
+        assert_eq!(
+            http_status,
+            Some(StatusCode::TOO_MANY_REQUESTS.as_u16() as i64),
+            "latest log should be hourly-any 429"
+        );
+        assert_eq!(
+            counts_business_quota, 0,
+            "hourly-any limiter blocks should be non-billable"
+        );
+
+        // Roll up and verify billable totals only include the first request.
+        let _ = proxy
+            .rollup_token_usage_stats()
+            .await
+            .expect("rollup token usage stats");
+        let summary = proxy
+            .token_summary_since(&access_token.id, 0, None)
+            .await
+            .expect("summary since");
+
+        assert_eq!(
+            summary.total_requests, 1,
+            "billable totals should count only successful first request"
+        );
+        assert_eq!(summary.success_count, 1);
+        assert_eq!(
+            summary.quota_exhausted_count, 0,
+            "hourly-any 429 should not be included in billable totals"
+        );
+
+        unsafe {
+            if let Some(prev) = previous_limit {
+                std::env::set_var("TOKEN_HOURLY_REQUEST_LIMIT", prev);
+            } else {
+                std::env::remove_var("TOKEN_HOURLY_REQUEST_LIMIT");
+            }
+        }
+
+        let _ = std::fs::remove_file(db_path);
+    }
+
+    #[tokio::test]
+    async fn tavily_http_search_replaces_body_api_key_with_tavily_key() {
+        let db_path = temp_db_path("http-search-replace-key");
+        let db_str = db_path.to_string_lossy().to_string();
+
+        let expected_api_key = "tvly-http-search-key";
+        let proxy = TavilyProxy::with_endpoint(
+            vec![expected_api_key.to_string()],
+            DEFAULT_UPSTREAM,
+            &db_str,
+        )
+        .await
+        .expect("proxy created");
+
+        let access_token = proxy
+            .create_access_token(Some("http-search"))
+            .await
+            .expect("create token");
+
+        let upstream_addr =
+            spawn_http_search_mock_asserting_api_key(expected_api_key.to_string()).await;
+        let usage_base = format!("http://{}", upstream_addr);
+
+        let proxy_addr = spawn_proxy_server(proxy.clone(), usage_base).await;
+
+        let client = Client::new();
+        let url = format!("http://{}/api/tavily/search", proxy_addr);
+        let resp = client
+            .post(url)
+            .json(&serde_json::json!({
+                "api_key": access_token.token,
+                "query": "hello world"
+            }))
+            .send()
+            .await
+            .expect("request to proxy succeeds");
+
+        assert!(resp.status().is_success());
+        let body: serde_json::Value = resp.json().await.expect("parse json body");
+        assert_eq!(body.get("status").and_then(|v| v.as_i64()), Some(200));
+
+        // Verify request_logs entry has success status, structured status, and redacted bodies.
+        let options = SqliteConnectOptions::new()
+            .filename(&db_str)
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .busy_timeout(Duration::from_secs(5));
+        let pool = SqlitePoolOptions::new()
+            .min_connections(1)
+            .max_connections(5)
+            .connect_with(options)
+            .await
+            .expect("connect to sqlite");
+
+        let row = sqlx::query(
+            r#"
+            SELECT request_body, response_body, result_status, tavily_status_code
+            FROM request_logs
+            ORDER BY id DESC
+            LIMIT 1
+            "#,
+        )
+        .fetch_one(&pool)
+        .await
+        .expect("request log row exists");
+
+        let request_body: Vec<u8> = row.try_get("request_body").unwrap();
+        let response_body: Vec<u8> = row.try_get("response_body").unwrap();
+        let result_status: String = row.try_get("result_status").unwrap();
+        let tavily_status_code: Option<i64> = row.try_get("tavily_status_code").unwrap();
+
+        let req_text = String::from_utf8_lossy(&request_body);
+        let resp_text = String::from_utf8_lossy(&response_body);
+
+        assert_eq!(result_status, "success");
+        assert_eq!(tavily_status_code, Some(200));
+        assert!(
+            !req_text.contains(expected_api_key)
+                && !req_text.contains(&access_token.token)
+                && !resp_text.contains(expected_api_key)
+                && !resp_text.contains(&access_token.token),
+            "request/response logs must not contain raw api_key secrets",
+        );
+        assert!(
+            req_text.contains("***redacted***") || !req_text.contains("api_key"),
+            "api_key fields in request logs should be redacted",
+        );
+
+        let _ = std::fs::remove_file(db_path);
+    }
+
+    #[tokio::test]
+    async fn tavily_http_usage_returns_daily_and_monthly_counts() {
+        let db_path = temp_db_path("http-usage-view");
+        let db_str = db_path.to_string_lossy().to_string();
+
+        let expected_api_key = "tvly-http-usage-key";
+        let proxy = TavilyProxy::with_endpoint(
+            vec![expected_api_key.to_string()],
+            DEFAULT_UPSTREAM,
+            &db_str,
+        )
+        .await
+        .expect("proxy created");
+
+        let access_token = proxy
+            .create_access_token(Some("http-usage"))
+            .await
+            .expect("create token");
+
+        let upstream_addr =
+            spawn_http_search_mock_asserting_api_key(expected_api_key.to_string()).await;
+        let usage_base = format!("http://{}", upstream_addr);
+
+        let proxy_addr = spawn_proxy_server(proxy.clone(), usage_base).await;
+
+        // One successful /search call to generate request_logs + token_logs.
+        let client = Client::new();
+        let search_url = format!("http://{}/api/tavily/search", proxy_addr);
+        let _ = client
+            .post(search_url)
+            .json(&serde_json::json!({
+                "api_key": access_token.token,
+                "query": "usage metrics test"
+            }))
+            .send()
+            .await
+            .expect("request to proxy succeeds");
+
+        // Manually record one quota_exhausted attempt for this token so that monthly_quota_exhausted > 0.
+        let method = Method::GET;
+        proxy
+            .record_token_attempt(
+                &access_token.id,
+                &method,
+                "/api/tavily/search",
+                None,
+                None,
+                Some(StatusCode::TOO_MANY_REQUESTS.as_u16() as i64),
+                None,
+                true,
+                "quota_exhausted",
+                Some("test quota exhaustion"),
+            )
+            .await
+            .expect("record token attempt");
+
+        // Roll up auth_token_logs into token_usage_stats for the usage summary.
+        let _ = proxy
+            .rollup_token_usage_stats()
+            .await
+            .expect("rollup token usage stats");
+
+        // Query /api/tavily/usage.
+        let usage_url = format!("http://{}/api/tavily/usage", proxy_addr);
+        let resp = client
+            .get(usage_url)
+            .header("Authorization", format!("Bearer {}", access_token.token))
+            .send()
+            .await
+            .expect("request to /api/tavily/usage succeeds");
+        let status = resp.status();
+        let text = resp.text().await.expect("read usage body");
+
+        assert!(
+            status.is_success(),
+            "expected success from /api/tavily/usage, got status={} body={}",
+            status,
+            text
+        );
+        let body: serde_json::Value =
+            serde_json::from_str(&text).expect("parse json body from /api/tavily/usage");
+
+        assert_eq!(
+            body.get("tokenId").and_then(|v| v.as_str()),
+            Some(access_token.id.as_str())
+        );
+        let daily_success = body
+            .get("dailySuccess")
+            .and_then(|v| v.as_i64())
+            .unwrap_or(-1);
+        let daily_error = body
+            .get("dailyError")
+            .and_then(|v| v.as_i64())
+            .unwrap_or(-1);
+        let monthly_success = body
+            .get("monthlySuccess")
+            .and_then(|v| v.as_i64())
+            .unwrap_or(-1);
+        let monthly_quota_exhausted = body
+            .get("monthlyQuotaExhausted")
+            .and_then(|v| v.as_i64())
+            .unwrap_or(-1);
+
+        assert!(
+            daily_success >= 1,
+            "daily_success should be at least 1, got {daily_success}"
+        );
+        assert_eq!(daily_error, 0, "no error requests expected in this test");
+        assert!(
+            monthly_success >= daily_success,
+            "monthly_success should be >= daily_success"
+        );
+        assert!(
+            monthly_quota_exhausted >= 1,
+            "expected at least one quota_exhausted event, got {monthly_quota_exhausted}"
+        );
+
+        let _ = std::fs::remove_file(db_path);
+    }
+
+    #[tokio::test]
+    async fn tavily_http_extract_replaces_body_api_key_with_tavily_key() {
+        let db_path = temp_db_path("http-extract-replace-key");
+        let db_str = db_path.to_string_lossy().to_string();
+
+        let expected_api_key = "tvly-http-extract-key";
+        let proxy = TavilyProxy::with_endpoint(
+            vec![expected_api_key.to_string()],
+            DEFAULT_UPSTREAM,
+            &db_str,
+        )
+        .await
+        .expect("proxy created");
+
+        let access_token = proxy
+            .create_access_token(Some("http-extract"))
+            .await
+            .expect("create token");
+
+        let upstream_addr =
+            spawn_http_extract_mock_asserting_api_key(expected_api_key.to_string()).await;
+        let usage_base = format!("http://{}", upstream_addr);
+
+        let proxy_addr = spawn_proxy_server(proxy.clone(), usage_base).await;
+
+        let client = Client::new();
+        let url = format!("http://{}/api/tavily/extract", proxy_addr);
+        let resp = client
+            .post(url)
+            .json(&serde_json::json!({
+                "api_key": access_token.token,
+                "urls": ["https://example.com"]
+            }))
+            .send()
+            .await
+            .expect("request to proxy succeeds");
+
+        assert!(resp.status().is_success());
+        let body: serde_json::Value = resp.json().await.expect("parse json body");
+        assert_eq!(body.get("status").and_then(|v| v.as_i64()), Some(200));
+
+        let _ = std::fs::remove_file(db_path);
+    }
+
+    #[tokio::test]
+    async fn tavily_http_crawl_replaces_body_api_key_with_tavily_key() {
+        let db_path = temp_db_path("http-crawl-replace-key");
+        let db_str = db_path.to_string_lossy().to_string();
+
+        let expected_api_key = "tvly-http-crawl-key";
+        let proxy = TavilyProxy::with_endpoint(
+            vec![expected_api_key.to_string()],
+            DEFAULT_UPSTREAM,
+            &db_str,
+        )
+        .await
+        .expect("proxy created");
+
+        let access_token = proxy
+            .create_access_token(Some("http-crawl"))
+            .await
+            .expect("create token");
+
+        let upstream_addr =
+            spawn_http_crawl_mock_asserting_api_key(expected_api_key.to_string()).await;
+        let usage_base = format!("http://{}", upstream_addr);
+
+        let proxy_addr = spawn_proxy_server(proxy.clone(), usage_base).await;
+
+        let client = Client::new();
+        let url = format!("http://{}/api/tavily/crawl", proxy_addr);
+        let resp = client
+            .post(url)
+            .json(&serde_json::json!({
+                "api_key": access_token.token,
+                "urls": ["https://example.com/page"]
+            }))
+            .send()
+            .await
+            .expect("request to proxy succeeds");
+
+        assert!(resp.status().is_success());
+        let body: serde_json::Value = resp.json().await.expect("parse json body");
+        assert_eq!(body.get("status").and_then(|v| v.as_i64()), Some(200));
+
+        let _ = std::fs::remove_file(db_path);
+    }
+
+    #[tokio::test]
+    async fn tavily_http_map_replaces_body_api_key_with_tavily_key() {
+        let db_path = temp_db_path("http-map-replace-key");
+        let db_str = db_path.to_string_lossy().to_string();
+
+        let expected_api_key = "tvly-http-map-key";
+        let proxy = TavilyProxy::with_endpoint(
+            vec![expected_api_key.to_string()],
+            DEFAULT_UPSTREAM,
+            &db_str,
+        )
+        .await
+        .expect("proxy created");
+
+        let access_token = proxy
+            .create_access_token(Some("http-map"))
+            .await
+            .expect("create token");
+
+        let upstream_addr =
+            spawn_http_map_mock_asserting_api_key(expected_api_key.to_string()).await;
+        let usage_base = format!("http://{}", upstream_addr);
+
+        let proxy_addr = spawn_proxy_server(proxy.clone(), usage_base).await;
+
+        let client = Client::new();
+        let url = format!("http://{}/api/tavily/map", proxy_addr);
+        let resp = client
+            .post(url)
+            .json(&serde_json::json!({
+                "api_key": access_token.token,
+                "url": "https://example.com"
+            }))
+            .send()
+            .await
+            .expect("request to proxy succeeds");
+
+        assert!(resp.status().is_success());
+        let body: serde_json::Value = resp.json().await.expect("parse json body");
+        assert_eq!(body.get("status").and_then(|v| v.as_i64()), Some(200));
+
+        let _ = std::fs::remove_file(db_path);
+    }
+
+    #[tokio::test]
+    async fn mcp_accepts_token_from_query_param() {
+        let db_path = temp_db_path("e2e-query-token");
+        let db_str = db_path.to_string_lossy().to_string();
+
+        let expected_api_key = "tvly-e2e-upstream-key";
+        let upstream_addr = spawn_mock_upstream(expected_api_key.to_string()).await;
+        let upstream = format!("http://{}", upstream_addr);
+
+        let proxy =
+            TavilyProxy::with_endpoint(vec![expected_api_key.to_string()], &upstream, &db_str)
+                .await
+                .expect("proxy created");
+
+        let access_token = proxy
+            .create_access_token(Some("e2e-query-param"))
+            .await
+            .expect("create access token");
+
+        let proxy_addr =
+            spawn_proxy_server(proxy.clone(), "https://api.tavily.com".to_string()).await;
+
+        let client = Client::new();
+        let url = format!(
+            "http://{}/mcp?tavilyApiKey={}",
+            proxy_addr, access_token.token
+        );
+        let resp = client
+            .post(url)
+            .json(&serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "ping" }))
+            .send()
+            .await
+            .expect("request to proxy succeeds");
+
+        assert!(
+            resp.status().is_success(),
+            "expected success from /mcp using query param token, got {}",
+            resp.status()
+        );
+
+        let _ = std::fs::remove_file(db_path);
+    }
+
+    #[tokio::test]
+    async fn mcp_non_tool_calls_are_ignored_by_business_quota() {
+        let _guard = crate::tests::env_lock().lock_owned().await;
+        let db_path = temp_db_path("mcp-non-tool-ignored");
+        let db_str = db_path.to_string_lossy().to_string();
+
+        // Tighten business hourly quota to 1 so that the token is quickly exhausted
+        // for TokenQuota, while the per-hour raw request limiter still uses default.
+        unsafe {
+            std::env::set_var("TOKEN_HOURLY_LIMIT", "1");
+        }
+
+        let expected_api_key = "tvly-mcp-non-tool-key";
+        let upstream_addr = spawn_mock_upstream(expected_api_key.to_string()).await;
+        let upstream = format!("http://{}", upstream_addr);
+
+        let proxy =
+            TavilyProxy::with_endpoint(vec![expected_api_key.to_string()], &upstream, &db_str)
+                .await
+                .expect("proxy created");
+
+        let access_token = proxy
+            .create_access_token(Some("mcp-non-tool"))
+            .await
+            .expect("create access token");
+
+        // Pre-exhaust business quota for this token.
+        let hourly_limit = effective_token_hourly_limit();
+        for _ in 0..=hourly_limit {
+            let _ = proxy
+                .check_token_quota(&access_token.id)
+                .await
+                .expect("quota check ok");
+        }
+
+        let proxy_addr =
+            spawn_proxy_server(proxy.clone(), "https://api.tavily.com".to_string()).await;
+
+        let client = Client::new();
+        let url = format!(
+            "http://{}/mcp?tavilyApiKey={}",
+            proxy_addr, access_token.token
+        );
+        // MCP 非工具调用：tools/list 应当被业务配额忽略，但仍经过“任意请求”限频。
+        let resp = client
+            .post(url)
+            .json(&serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "tools/list" }))
+            .send()
+            .await
+            .expect("request to proxy succeeds");
+
+        assert!(
+            resp.status().is_success(),
+            "non-tool MCP call (tools/list) should not be blocked by business quota, got {}",
+            resp.status()
+        );
 
-    let tokens = state
-        .proxy
-        .list_access_tokens()
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        // Verify that the most recent auth_token_logs entry is not billable.
+        let options = SqliteConnectOptions::new()
+            .filename(&db_str)
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .busy_timeout(Duration::from_secs(5));
+        let pool = SqlitePoolOptions::new()
+            .min_connections(1)
+            .max_connections(5)
+            .connect_with(options)
+            .await
+            .expect("connect to sqlite");
 
-    let token_ids: Vec<String> = tokens.iter().map(|t| t.id.clone()).collect();
-    let hourly_any_map = state
-        .proxy
-        .token_hourly_any_snapshot(&token_ids)
+        let row = sqlx::query(
+            r#"
+            SELECT counts_business_quota
+            FROM auth_token_logs
+            WHERE token_id = ?
+            ORDER BY id DESC
+            LIMIT 1
+            "#,
+        )
+        .bind(&access_token.id)
+        .fetch_one(&pool)
         .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .expect("token log row exists");
 
-    let mut items: Vec<TokenLeaderboardItemView> = Vec::with_capacity(tokens.len());
+        let counts_business_quota: i64 = row.try_get("counts_business_quota").unwrap();
+        assert_eq!(counts_business_quota, 0);
 
-    for token in tokens {
-        // summaries
-        let today = state
-            .proxy
-            .token_summary_since(&token.id, day_since, None)
-            .await
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-        let month = state
-            .proxy
-            .token_summary_since(&token.id, month_since, None)
-            .await
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-        let all = state
-            .proxy
-            .token_summary_since(&token.id, 0, None)
-            .await
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        unsafe {
+            std::env::remove_var("TOKEN_HOURLY_LIMIT");
+        }
+        let _ = std::fs::remove_file(db_path);
+    }
 
-        let other_today = (today.total_requests - today.success_count - today.error_count).max(0);
-        let other_month = (month.total_requests - month.success_count - month.error_count).max(0);
-        let other_all = (all.total_requests - all.success_count - all.error_count).max(0);
+    #[tokio::test]
+    async fn mcp_tools_list_does_not_increment_billable_totals_after_rollup() {
+        let db_path = temp_db_path("mcp-nonbillable-rollup");
+        let db_str = db_path.to_string_lossy().to_string();
 
-        // quota snapshot
-        let quota_verdict = match token.quota {
-            Some(ref v) => Some(v.clone()),
-            None => state
-                .proxy
-                .token_quota_snapshot(&token.id)
+        let expected_api_key = "tvly-mcp-nonbillable-key";
+        let upstream_addr = spawn_mock_upstream(expected_api_key.to_string()).await;
+        let upstream = format!("http://{}", upstream_addr);
+
+        let proxy =
+            TavilyProxy::with_endpoint(vec![expected_api_key.to_string()], &upstream, &db_str)
                 .await
-                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-                .clone(),
-        };
-        let (hour_used, hour_limit, day_used, day_limit) = quota_verdict
-            .as_ref()
-            .map(|q| (q.hourly_used, q.hourly_limit, q.daily_used, q.daily_limit))
-            .unwrap_or((
-                0,
-                effective_token_hourly_limit(),
-                0,
-                effective_token_daily_limit(),
-            ));
-        let quota_state = quota_verdict
-            .as_ref()
-            .and_then(|q| q.exceeded_window)
-            .map(|w| w.as_str().to_string())
-            .unwrap_or_else(|| "normal".to_string());
+                .expect("proxy created");
 
-        let (hourly_any_used, hourly_any_limit) = hourly_any_map
-            .get(&token.id)
-            .map(|v| (v.hourly_used, v.hourly_limit))
-            .unwrap_or((0, effective_token_hourly_request_limit()));
+        let access_token = proxy
+            .create_access_token(Some("mcp-nonbillable-rollup"))
+            .await
+            .expect("create access token");
 
-        let item = TokenLeaderboardItemView {
-            id: token.id.clone(),
-            enabled: token.enabled,
-            note: token.note.clone(),
-            group: token.group_name.clone(),
-            total_requests: all.total_requests,
-            last_used_at: all.last_activity,
-            quota_state,
-            quota_hourly_used: hour_used,
-            quota_hourly_limit: hour_limit,
-            quota_daily_used: day_used,
-            quota_daily_limit: day_limit,
-            hourly_any_used,
-            hourly_any_limit,
-            today_total: today.total_requests,
-            today_errors: today.error_count,
-            today_other: other_today,
-            month_total: month.total_requests,
-            month_errors: month.error_count,
-            month_other: other_month,
-            all_total: all.total_requests,
-            all_errors: all.error_count,
-            all_other: other_all,
-        };
-        items.push(item);
-    }
+        let proxy_addr = spawn_proxy_server(proxy.clone(), upstream.clone()).await;
 
-    let metric = |it: &TokenLeaderboardItemView, p: &str, f: &str| -> i64 {
-        match (p, f) {
-            ("day", "usage") => it.today_total,
-            ("day", "errors") => it.today_errors,
-            ("day", "other") => it.today_other,
-            ("month", "usage") => it.month_total,
-            ("month", "errors") => it.month_errors,
-            ("month", "other") => it.month_other,
-            ("all", "usage") => it.all_total,
-            ("all", "errors") => it.all_errors,
-            ("all", "other") => it.all_other,
-            _ => 0,
-        }
-    };
+        let client = Client::new();
+        let url = format!(
+            "http://{}/mcp?tavilyApiKey={}",
+            proxy_addr, access_token.token
+        );
+        let resp = client
+            .post(url)
+            .json(&serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "tools/list" }))
+            .send()
+            .await
+            .expect("request to proxy succeeds");
 
-    items.sort_by(|a, b| {
-        metric(b, period, focus)
-            .cmp(&metric(a, period, focus))
-            .then_with(|| b.total_requests.cmp(&a.total_requests))
-    });
+        assert!(
+            resp.status().is_success(),
+            "expected success from /mcp tools/list, got {}",
+            resp.status()
+        );
 
-    items.truncate(50);
+        let _ = proxy
+            .rollup_token_usage_stats()
+            .await
+            .expect("rollup token usage stats");
 
-    Ok(Json(items))
-}
+        let summary = proxy
+            .token_summary_since(&access_token.id, 0, None)
+            .await
+            .expect("summary since");
 
-async fn get_token_detail(
-    State(state): State<Arc<AppState>>,
-    Path(id): Path<String>,
-    headers: HeaderMap,
-) -> Result<Json<AuthTokenView>, StatusCode> {
-    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
-        return Err(StatusCode::FORBIDDEN);
-    }
-    let tokens = state
-        .proxy
-        .list_access_tokens()
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    match tokens.into_iter().find(|t| t.id == id) {
-        Some(t) => Ok(Json(t.into())),
-        None => Err(StatusCode::NOT_FOUND),
+        assert_eq!(
+            summary.total_requests, 0,
+            "non-billable MCP tools/list should not affect billable totals"
+        );
+        assert_eq!(summary.success_count, 0);
+        assert_eq!(summary.quota_exhausted_count, 0);
+
+        let _ = std::fs::remove_file(db_path);
     }
-}
 
-#[derive(Debug, Serialize)]
-struct TokenSnapshot {
-    summary: TokenSummaryView,
-    logs: Vec<TokenLogView>,
-}
+    #[tokio::test]
+    async fn mcp_initialize_is_served_from_cache_for_new_sessions() {
+        let db_path = temp_db_path("mcp-initialize-cache");
+        let db_str = db_path.to_string_lossy().to_string();
 
-async fn sse_token(
-    State(state): State<Arc<AppState>>,
-    Path(id): Path<String>,
-    headers: HeaderMap,
-) -> Result<Sse<impl futures_util::Stream<Item = Result<Event, axum::http::Error>>>, StatusCode> {
-    if !state.dev_open_admin && !state.forward_auth.is_request_admin(&headers) {
-        return Err(StatusCode::FORBIDDEN);
-    }
-    let state = state.clone();
-    let stream = stream! {
-        let mut last_log_id: Option<i64> = None;
-        if let Some(event) = build_token_snapshot_event(&state, &id).await { yield Ok(event); }
-        if let Ok(logs) = state.proxy.token_recent_logs(&id, 1, None).await {
-            last_log_id = logs.first().map(|l| l.id);
+        let hits = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let app = Router::new().route(
+            "/mcp",
+            any({
+                let hits = hits.clone();
+                move |Json(body): Json<serde_json::Value>| {
+                    let hits = hits.clone();
+                    async move {
+                        hits.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                        Json(serde_json::json!({
+                            "jsonrpc": "2.0",
+                            "id": body["id"],
+                            "result": {
+                                "protocolVersion": body["params"]["protocolVersion"],
+                                "serverInfo": { "name": "mock" },
+                            },
+                        }))
+                    }
+                }
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app.into_make_service())
+                .await
+                .unwrap();
+        });
+        let upstream = format!("http://{}", upstream_addr);
+
+        let proxy = TavilyProxy::with_endpoint(vec!["tvly-init-cache"], &upstream, &db_str)
+            .await
+            .expect("proxy created");
+        let access_token = proxy
+            .create_access_token(Some("initialize-cache"))
+            .await
+            .expect("create access token");
+        let proxy_addr = spawn_proxy_server(proxy, upstream.clone()).await;
+
+        let client = Client::new();
+        let url = format!("http://{}/mcp", proxy_addr);
+        for (id, version) in [(1, "2025-03-26"), (2, "2025-03-26"), (3, "2024-11-05")] {
+            let resp = client
+                .post(&url)
+                .bearer_auth(&access_token.token)
+                .json(&serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "method": "initialize",
+                    "params": { "protocolVersion": version },
+                }))
+                .send()
+                .await
+                .expect("request to proxy succeeds");
+            assert!(resp.status().is_success());
+            let body: serde_json::Value = resp.json().await.expect("json body");
+            assert_eq!(body["id"], id, "response id must match the request id");
+            assert_eq!(body["result"]["protocolVersion"], version);
         }
-        loop {
-            match state.proxy.token_recent_logs(&id, 1, None).await {
-                Ok(logs) => {
-                    let latest = logs.first().map(|l| l.id);
-                    if latest != last_log_id {
-                        if let Some(event) = build_token_snapshot_event(&state, &id).await { yield Ok(event); }
-                        last_log_id = latest;
-                    } else {
-                        let keep = Event::default().event("ping").data("{}");
-                        yield Ok(keep);
+
+        assert_eq!(
+            hits.load(std::sync::atomic::Ordering::SeqCst),
+            2,
+            "repeat initialize for the same protocol version should hit the cache"
+        );
+
+        let _ = std::fs::remove_file(db_path);
+    }
+
+    #[tokio::test]
+    async fn mcp_sessions_stay_on_the_key_that_opened_them() {
+        let db_path = temp_db_path("mcp-session-affinity");
+        let db_str = db_path.to_string_lossy().to_string();
+
+        let served_by = Arc::new(std::sync::Mutex::new(Vec::<String>::new()));
+        let app = Router::new().route(
+            "/mcp",
+            any({
+                let served_by = served_by.clone();
+                move |Query(query): Query<HashMap<String, String>>,
+                      Json(body): Json<serde_json::Value>| {
+                    let served_by = served_by.clone();
+                    async move {
+                        let key = query.get("tavilyApiKey").cloned().unwrap_or_default();
+                        served_by.lock().unwrap().push(key);
+                        let reply = Json(serde_json::json!({
+                            "jsonrpc": "2.0",
+                            "id": body["id"],
+                            "result": {},
+                        }));
+                        if body["method"] == "initialize" {
+                            ([("mcp-session-id", "sess-1")], reply).into_response()
+                        } else {
+                            reply.into_response()
+                        }
                     }
                 }
-                Err(_) => {
-                    let keep = Event::default().event("ping").data("{}");
-                    yield Ok(keep);
-                }
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app.into_make_service())
+                .await
+                .unwrap();
+        });
+        let upstream = format!("http://{}", upstream_addr);
+
+        let proxy = TavilyProxy::with_endpoint(
+            vec!["tvly-session-a", "tvly-session-b"],
+            &upstream,
+            &db_str,
+        )
+        .await
+        .expect("proxy created");
+        let access_token = proxy
+            .create_access_token(Some("sessions"))
+            .await
+            .expect("create access token");
+        let proxy_addr = spawn_proxy_server(proxy.clone(), upstream.clone()).await;
+
+        let client = Client::new();
+        let url = format!("http://{}/mcp", proxy_addr);
+        let call = |id: i64, method: &str, session: Option<&str>| {
+            let mut request = client
+                .post(&url)
+                .bearer_auth(&access_token.token)
+                .json(&serde_json::json!({ "jsonrpc": "2.0", "id": id, "method": method }));
+            if let Some(session) = session {
+                request = request.header("Mcp-Session-Id", session);
             }
-            tokio::time::sleep(Duration::from_secs(2)).await;
+            request.send()
+        };
+        let resp = call(1, "initialize", None).await.expect("initialize");
+        assert_eq!(resp.headers()["mcp-session-id"], "sess-1");
+        let opened_with = served_by.lock().unwrap()[0].clone();
+
+        // Without the session, the token would be re-pinned to the least recently used key.
+        proxy.unpin_token_affinity(&access_token.id).await;
+        for id in 2..=4 {
+            let resp = call(id, "tools/list", Some("sess-1")).await.expect("call");
+            assert!(resp.status().is_success());
         }
-    };
-    Ok(Sse::new(stream).keep_alive(KeepAlive::new().interval(Duration::from_secs(15)).text("")))
-}
+        // Unknown session ids are scheduled normally and never registered.
+        call(5, "tools/list", Some("sess-unknown"))
+            .await
+            .expect("call");
 
-async fn build_token_snapshot_event(state: &Arc<AppState>, id: &str) -> Option<Event> {
-    let now = Utc::now();
-    let month_start = Utc
-        .with_ymd_and_hms(now.year(), now.month(), 1, 0, 0, 0)
-        .single()?
-        .timestamp();
-    let summary = state
-        .proxy
-        .token_summary_since(id, month_start, None)
-        .await
-        .ok()?;
-    let logs = state
-        .proxy
-        .token_recent_logs(id, DEFAULT_LOG_LIMIT, None)
-        .await
-        .ok()?;
-    let payload = TokenSnapshot {
-        summary: summary.into(),
-        logs: logs
-            .into_iter()
-            .map(TokenLogView::from)
-            .map(|mut v| {
-                if let Some(err) = v.error_message.as_ref() {
-                    v.error_message = Some(redact_sensitive(err));
-                }
-                v
-            })
-            .collect(),
-    };
-    let json = serde_json::to_string(&payload).ok()?;
-    Some(Event::default().event("snapshot").data(json))
-}
+        let served_by = served_by.lock().unwrap().clone();
+        assert!(
+            served_by[..4].iter().all(|key| *key == opened_with),
+            "session requests must use the key that opened the session: {served_by:?}"
+        );
+        assert_eq!(proxy.key_acquisition_snapshot().await.session_hits, 3);
 
-fn extract_token_from_query(raw_query: Option<&str>) -> (Option<String>, Option<String>) {
-    let Some(raw) = raw_query else {
-        return (None, None);
-    };
+        let sessions = proxy
+            .token_mcp_sessions(&access_token.id, 50)
+            .await
+            .expect("sessions");
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].session_id, "sess-1");
+        assert_eq!(sessions[0].request_count, 4);
+        assert_eq!(sessions[0].error_count, 0);
+        assert_eq!(
+            proxy
+                .get_api_key_secret(&sessions[0].key_id)
+                .await
+                .expect("key secret"),
+            Some(opened_with)
+        );
 
-    if raw.is_empty() {
-        return (None, None);
+        let _ = std::fs::remove_file(db_path);
     }
 
-    let mut token: Option<String> = None;
-    let mut serializer = form_urlencoded::Serializer::new(String::new());
-
-    for (key, value) in form_urlencoded::parse(raw.as_bytes()) {
-        if key.eq_ignore_ascii_case("tavilyApiKey") {
-            // Capture the first non-empty token value and strip it from the forwarded query.
-            if token.is_none() {
-                let trimmed = value.trim();
-                if !trimmed.is_empty() {
-                    token = Some(trimmed.to_string());
-                }
-            }
-            continue;
+    #[tokio::test]
+    async fn mcp_proxy_failures_are_reported_as_jsonrpc_errors() {
+        let app = crate::test_util::TestApp::spawn(Default::default(), &["tvly-only-key"])
+            .await
+            .expect("test app");
+        let token = app.create_token().await.expect("token");
+        for key in app.proxy.list_api_key_metrics().await.expect("keys") {
+            app.proxy.disable_key_by_id(&key.id).await.expect("disable");
         }
 
-        serializer.append_pair(&key, &value);
+        let resp = app
+            .call_tool(&token, 7, "tavily-search", json!({ "query": "no keys" }))
+            .await
+            .expect("tool call");
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body: Value = resp.json().await.expect("json-rpc error");
+        assert_eq!(body["jsonrpc"], "2.0");
+        assert_eq!(body["id"], 7);
+        assert_eq!(
+            body["error"]["code"],
+            McpFailure::NoKeys.jsonrpc_code(),
+            "{body}"
+        );
+        assert_eq!(body["error"]["data"]["code"], "NO_KEYS");
+        assert!(
+            body["error"]["message"]
+                .as_str()
+                .is_some_and(|m| !m.is_empty())
+        );
     }
 
-    let serialized = serializer.finish();
-    let query = if serialized.is_empty() {
-        None
-    } else {
-        Some(serialized)
-    };
+    #[tokio::test]
+    async fn mcp_rejects_invalid_token_in_query_param() {
+        let db_path = temp_db_path("e2e-query-token-invalid");
+        let db_str = db_path.to_string_lossy().to_string();
 
-    (query, token)
-}
+        let expected_api_key = "tvly-e2e-upstream-key-invalid";
+        let upstream_addr = spawn_mock_upstream(expected_api_key.to_string()).await;
+        let upstream = format!("http://{}", upstream_addr);
 
-fn mcp_request_counts_toward_business_quota(path: &str, body: &[u8]) -> bool {
-    // Only apply special handling for /mcp traffic. Other endpoints (such as
-    // /api/tavily/*) are always treated as business-costful.
-    if !path.starts_with("/mcp") {
-        return true;
+        let proxy =
+            TavilyProxy::with_endpoint(vec![expected_api_key.to_string()], &upstream, &db_str)
+                .await
+                .expect("proxy created");
+
+        let proxy_addr =
+            spawn_proxy_server(proxy.clone(), "https://api.tavily.com".to_string()).await;
+
+        let client = Client::new();
+        let url = format!(
+            "http://{}/mcp?tavilyApiKey={}",
+            proxy_addr, "th-invalid-unknown"
+        );
+        let resp = client
+            .post(url)
+            .body("{}")
+            .send()
+            .await
+            .expect("request to proxy succeeds");
+
+        assert_eq!(
+            resp.status(),
+            reqwest::StatusCode::UNAUTHORIZED,
+            "expected 401 for invalid query param token"
+        );
+
+        let _ = std::fs::remove_file(db_path);
     }
 
-    // Non-business whitelist: only the following methods are treated as "no business cost".
-    // Everything else is considered business-costful and will consume business quota.
-    match serde_json::from_slice::<Value>(body) {
-        Ok(Value::Object(map)) => {
-            let method = map.get("method").and_then(|v| v.as_str()).unwrap_or("");
-            let is_non_business = matches!(
-                method,
-                "tools/list"
-                    | "resources/list"
-                    | "resources/templates/list"
-                    | "resources/read"
-                    | "prompts/list"
-                    | "prompts/get"
-            ) || method.starts_with("notifications/");
-            // Return semantics: true = count towards business quota; false = only hourly-any limiter.
-            !is_non_business
-        }
-        // 对于无法解析或缺少 method 的请求，保守起见视为“有业务成本”。
-        _ => true,
+    #[test]
+    fn extract_token_from_query_none_or_empty() {
+        let (q, t) = extract_token_from_query(None);
+        assert_eq!(q, None);
+        assert_eq!(t, None);
+
+        let (q, t) = extract_token_from_query(Some(""));
+        assert_eq!(q, None);
+        assert_eq!(t, None);
     }
-}
 
-async fn proxy_handler(
-    State(state): State<Arc<AppState>>,
-    req: Request<Body>,
-) -> Result<Response<Body>, StatusCode> {
-    let (parts, body) = req.into_parts();
-    let method = parts.method.clone();
-    let path = parts.uri.path().to_owned();
-    let (query, query_token) = extract_token_from_query(parts.uri.query());
+    #[test]
+    fn extract_token_from_query_single_param_case_insensitive() {
+        let (q, t) = extract_token_from_query(Some("TavilyApiKey=th-abc-xyz"));
+        assert_eq!(q, None, "no other params → query should be None");
+        assert_eq!(t.as_deref(), Some("th-abc-xyz"));
+    }
 
-    if method == Method::GET && accepts_event_stream(&parts.headers) {
-        let response = Response::builder()
-            .status(StatusCode::METHOD_NOT_ALLOWED)
-            .body(Body::empty())
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-        return Ok(response);
+    #[test]
+    fn extract_token_from_query_strips_param_and_preserves_others() {
+        let (q, t) = extract_token_from_query(Some("foo=1&tavilyApiKey=th-abc-xyz&bar=2"));
+        assert_eq!(t.as_deref(), Some("th-abc-xyz"));
+        // Order should be preserved for non-auth params.
+        assert_eq!(q.as_deref(), Some("foo=1&bar=2"));
     }
 
-    // Require Authorization: Bearer th-<id>-<secret>
-    let auth_bearer = parts
-        .headers
-        .get(axum::http::header::AUTHORIZATION)
-        .and_then(|v| v.to_str().ok())
-        .map(|s| s.trim().to_string());
+    #[test]
+    fn extract_token_from_query_uses_first_non_empty_token() {
+        let (q, t) =
+            extract_token_from_query(Some("tavilyApiKey=&tavilyApiKey=th-abc-xyz&foo=bar"));
+        assert_eq!(t.as_deref(), Some("th-abc-xyz"));
+        assert_eq!(q.as_deref(), Some("foo=bar"));
+    }
 
-    let header_token = auth_bearer
-        .as_deref()
-        .and_then(|raw| raw.strip_prefix("Bearer "))
-        .map(str::trim)
-        .filter(|t| !t.is_empty())
-        .map(|t| t.to_string());
+    #[test]
+    fn extract_token_from_query_ignores_additional_token_params() {
+        let (q, t) = extract_token_from_query(Some("tavilyApiKey=th-1&tavilyApiKey=th-2&foo=bar"));
+        assert_eq!(t.as_deref(), Some("th-1"));
+        assert_eq!(q.as_deref(), Some("foo=bar"));
+    }
+
+    #[tokio::test]
+    async fn test_util_harness_drives_full_app_against_sse_mock_upstream() {
+        use crate::test_util::{MockUpstreamConfig, TestApp};
+
+        let api_key = "tvly-test-util-key";
+        let app = TestApp::spawn(
+            MockUpstreamConfig::default()
+                .expect_api_key(api_key)
+                .with_sse()
+                .with_latency(Duration::from_millis(20))
+                .exhaust_after(1),
+            &[api_key],
+        )
+        .await
+        .expect("test app spawned");
+        let token = app.create_token().await.expect("token created");
+
+        let resp = app
+            .mcp(
+                &token,
+                1,
+                "initialize",
+                serde_json::json!({ "protocolVersion": "2025-03-26" }),
+            )
+            .await
+            .expect("initialize");
+        assert!(resp.status().is_success());
+        let body = resp.text().await.expect("initialize body");
+        assert!(
+            body.contains("mock-tavily"),
+            "SSE frame should be passed through: {body}"
+        );
+
+        let first = app
+            .call_tool(
+                &token,
+                2,
+                "tavily-search",
+                serde_json::json!({ "query": "rust" }),
+            )
+            .await
+            .expect("first tool call");
+        assert!(first.status().is_success());
+        let _ = first.text().await;
+
+        let second = app
+            .call_tool(
+                &token,
+                3,
+                "tavily-search",
+                serde_json::json!({ "query": "rust" }),
+            )
+            .await
+            .expect("second tool call");
+        let _ = second.text().await;
+        assert_eq!(app.upstream.tool_calls(), 2);
+
+        let metrics = app.proxy.list_api_key_metrics().await.expect("metrics");
+        let key = metrics.first().expect("key metrics");
+        assert_eq!(
+            key.status, "exhausted",
+            "injected 432 should exhaust the key"
+        );
+        assert_eq!(key.quota_exhausted_count, 1);
+    }
+
+    #[tokio::test]
+    async fn repeated_tool_calls_are_replayed_within_the_dedup_window() {
+        use crate::test_util::{MockToolReply, MockUpstreamConfig, TestApp};
 
-    let token = if let Some(t) = header_token {
-        t
-    } else if let Some(t) = query_token {
-        t
-    } else if state.dev_open_admin {
-        "th-dev-override".to_string()
-    } else {
-        return Response::builder()
-            .status(StatusCode::UNAUTHORIZED)
-            .header(CONTENT_TYPE, "application/json; charset=utf-8")
-            .body(Body::from("{\"error\":\"missing token\"}"))
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR);
-    };
+        let _guard = crate::tests::env_lock().lock_owned().await;
+        unsafe {
+            std::env::set_var("MCP_DEDUP_WINDOW_SECS", "60");
+        }
+        let app = TestApp::spawn(
+            MockUpstreamConfig::default()
+                .with_sse()
+                .script([MockToolReply::Error("upstream exploded".to_string())]),
+            &["tvly-dedup"],
+        )
+        .await
+        .expect("test app spawned");
+        unsafe {
+            std::env::remove_var("MCP_DEDUP_WINDOW_SECS");
+        }
+        let token = app.create_token().await.expect("token created");
+        let token_id = token.split('-').nth(1).expect("token id").to_string();
 
-    let valid = if state.dev_open_admin {
-        true
-    } else {
-        state
+        let mut bodies = Vec::new();
+        for id in 1..=3 {
+            let resp = app
+                .call_tool(&token, id, "tavily-search", json!({ "query": "dedup" }))
+                .await
+                .expect("tool call");
+            assert!(resp.status().is_success());
+            bodies.push(resp.text().await.expect("tool call body"));
+        }
+        // A failed call is not remembered, so its retry goes upstream.
+        assert!(bodies[0].contains("upstream exploded"));
+        assert!(bodies[1].contains("mock result"));
+        let replay: Value = serde_json::from_str(&bodies[2]).expect("replay is plain JSON");
+        assert_eq!(replay["id"], 3);
+        assert!(replay["result"].to_string().contains("mock result"));
+
+        for (id, query) in [(4, "first"), (5, "second")] {
+            let resp = app
+                .client()
+                .post(app.url("/mcp"))
+                .bearer_auth(&token)
+                .header("accept", "application/json, text/event-stream")
+                .header("idempotency-key", "retry-1")
+                .json(&json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "method": "tools/call",
+                    "params": { "name": "tavily-search", "arguments": { "query": query } },
+                }))
+                .send()
+                .await
+                .expect("tool call with idempotency key");
+            assert!(resp.status().is_success());
+        }
+
+        assert_eq!(app.upstream.tool_call_keys().len(), 3);
+        let quota = app
             .proxy
-            .validate_access_token(&token)
+            .token_quota_status(&token_id)
             .await
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-    };
-    if !valid {
-        return Response::builder()
-            .status(StatusCode::UNAUTHORIZED)
-            .header(CONTENT_TYPE, "application/json; charset=utf-8")
-            .body(Body::from("{\"error\":\"invalid or disabled token\"}"))
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR);
+            .expect("quota status")
+            .expect("token has quota usage");
+        assert_eq!(quota.verdict.hourly_used, 3, "replays spend no quota");
     }
 
-    let mut headers = clone_headers(&parts.headers);
-    // prevent leaking our Authorization to upstream
-    headers.remove(axum::http::header::AUTHORIZATION);
-    let body_bytes = body::to_bytes(body, BODY_LIMIT)
+    #[tokio::test]
+    async fn mock_upstream_replies_rotate_keys_after_quota_exhaustion() {
+        use crate::test_util::{MockToolReply, MockUpstreamConfig, TestApp};
+
+        let app = TestApp::spawn(
+            MockUpstreamConfig::default().with_sse().script([
+                MockToolReply::Error("upstream exploded".to_string()),
+                MockToolReply::QuotaExhausted,
+                MockToolReply::Success,
+                MockToolReply::Success,
+            ]),
+            &["tvly-rotate-a", "tvly-rotate-b"],
+        )
         .await
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
+        .expect("test app spawned");
+        let token = app.create_token().await.expect("token created");
 
-    let billable_flag = mcp_request_counts_toward_business_quota(&path, &body_bytes);
+        let mut bodies = Vec::new();
+        for id in 1..=4 {
+            let resp = app
+                .call_tool(&token, id, "tavily-search", json!({ "query": "rotate" }))
+                .await
+                .expect("tool call");
+            assert!(resp.status().is_success(), "SSE replies are relayed as 200");
+            bodies.push(resp.text().await.expect("tool call body"));
+            if id == 1 {
+                // A plain JSON-RPC error says nothing about quota and leaves every key usable.
+                let metrics = app.proxy.list_api_key_metrics().await.expect("metrics");
+                assert!(metrics.iter().all(|key| key.status == "active"));
+            }
+        }
+        assert!(bodies[0].contains("upstream exploded"));
+        assert!(bodies[1].contains("432"));
+        assert!(bodies[2].contains("mock result"));
 
-    let auth_token_id = if state.dev_open_admin {
-        Some("dev".to_string())
-    } else {
-        token
-            .strip_prefix("th-")
-            .and_then(|rest| rest.split_once('-').map(|(id, _)| id))
-            .map(|s| s.to_string())
-    };
+        let keys = app.upstream.tool_call_keys();
+        assert_eq!(keys.len(), 4);
+        let exhausted = keys[1].clone().expect("exhausted call carried a key");
+        assert!(
+            keys[2..].iter().all(|key| key.as_ref() != Some(&exhausted)),
+            "calls after exhaustion must avoid {exhausted}: {keys:?}"
+        );
 
-    let proxy_request = ProxyRequest {
-        method: method.clone(),
-        path: path.clone(),
-        query,
-        headers,
-        body: body_bytes.clone(),
-        auth_token_id,
-    };
+        let metrics = app.proxy.list_api_key_metrics().await.expect("metrics");
+        let mut statuses: Vec<_> = metrics
+            .iter()
+            .map(|key| (key.status.as_str(), key.quota_exhausted_count))
+            .collect();
+        statuses.sort();
+        assert_eq!(statuses, vec![("active", 0), ("exhausted", 1)]);
+    }
 
-    let token_id = if state.dev_open_admin {
-        Some("dev".to_string())
-    } else {
-        token
-            .strip_prefix("th-")
-            .and_then(|rest| rest.split('-').next())
-            .map(|s| s.to_string())
-    };
+    #[tokio::test]
+    async fn mock_upstream_http_432_exhausts_key_until_a_fallback_succeeds() {
+        use crate::test_util::{MockToolReply, MockUpstreamConfig, TestApp};
 
-    let mut _quota_verdict: Option<TokenQuotaVerdict> = None;
-    if let Some(tid) = token_id.as_deref() {
-        // 1) 全量“任意请求”小时限频：所有通过鉴权的请求都会计入。
-        if !state.dev_open_admin {
-            match state.proxy.check_token_hourly_requests(tid).await {
-                Ok(verdict) => {
-                    if !verdict.allowed {
-                        let message = build_request_limit_error_message(&verdict);
-                        let _ = state
-                            .proxy
-                            .record_token_attempt(
-                                tid,
-                                &method,
-                                &path,
-                                parts.uri.query(),
-                                Some(StatusCode::TOO_MANY_REQUESTS.as_u16() as i64),
-                                None,
-                                false,
-                                "quota_exhausted",
-                                Some(&message),
-                            )
-                            .await;
-                        let response = request_limit_exceeded_response(&verdict)?;
-                        return Ok(response);
-                    }
-                }
-                Err(err) => {
-                    eprintln!("hourly request limit check failed: {err}");
-                    return Err(StatusCode::INTERNAL_SERVER_ERROR);
-                }
-            }
-        }
+        let app = TestApp::spawn(
+            MockUpstreamConfig::default().script([MockToolReply::Status(432)]),
+            &["tvly-http-432"],
+        )
+        .await
+        .expect("test app spawned");
+        let token = app.create_token().await.expect("token created");
 
-        // 2) 业务配额（小时 / 日 / 月）只对 MCP 工具调用生效。
-        if billable_flag {
-            match state.proxy.check_token_quota(tid).await {
-                Ok(verdict) => {
-                    if !state.dev_open_admin && !verdict.allowed {
-                        let message = build_quota_error_message(&verdict);
-                        let _ = state
-                            .proxy
-                            .record_token_attempt(
-                                tid,
-                                &method,
-                                &path,
-                                parts.uri.query(),
-                                Some(StatusCode::TOO_MANY_REQUESTS.as_u16() as i64),
-                                None,
-                                true,
-                                "quota_exhausted",
-                                Some(&message),
-                            )
-                            .await;
-                        let response = quota_exceeded_response(&verdict)?;
-                        return Ok(response);
-                    }
-                    _quota_verdict = Some(verdict);
-                }
-                Err(err) => {
-                    eprintln!("quota check failed: {err}");
-                    return Err(StatusCode::INTERNAL_SERVER_ERROR);
-                }
-            }
-        }
+        let resp = app
+            .call_tool(&token, 1, "tavily-search", json!({ "query": "quota" }))
+            .await
+            .expect("tool call");
+        assert_eq!(resp.status().as_u16(), 432);
+        let metrics = app.proxy.list_api_key_metrics().await.expect("metrics");
+        assert_eq!(metrics[0].status, "exhausted");
+        assert_eq!(metrics[0].quota_exhausted_count, 1);
+
+        // With no active key left the exhausted one is leased as a fallback; a successful
+        // reply brings it back.
+        let resp = app
+            .call_tool(&token, 2, "tavily-search", json!({ "query": "quota" }))
+            .await
+            .expect("tool call");
+        assert!(resp.status().is_success());
+        assert_eq!(app.upstream.tool_calls(), 2);
+        let metrics = app.proxy.list_api_key_metrics().await.expect("metrics");
+        assert_eq!(metrics[0].status, "active");
     }
 
-    match state.proxy.proxy_request(proxy_request).await {
-        Ok(resp) => {
-            if let Some(tid) = token_id.as_deref() {
-                // 尝试从 Tavily JSON 回复中解析结构化状态码
-                let mut tavily_code: Option<i64> = None;
-                let mut result_status = "success";
-                #[allow(clippy::collapsible_if)]
-                {
-                    if let Ok(text) = std::str::from_utf8(&resp.body) {
-                        if let Ok(value) = serde_json::from_str::<serde_json::Value>(text) {
-                            if let Some(sc) = value
-                                .get("result")
-                                .and_then(|v| v.get("structuredContent"))
-                                .and_then(|v| v.get("status"))
-                                .and_then(|v| v.as_i64())
-                            {
-                                tavily_code = Some(sc);
-                                result_status = if sc == 432 {
-                                    "quota_exhausted"
-                                } else if sc >= 400 {
-                                    "error"
-                                } else {
-                                    "success"
-                                };
-                            } else if value
-                                .get("result")
-                                .and_then(|v| v.get("structuredContent"))
-                                .and_then(|v| v.get("isError"))
-                                .and_then(|v| v.as_bool())
-                                .unwrap_or(false)
-                            {
-                                result_status = "error";
-                            }
-                        }
-                    }
-                }
+    #[tokio::test]
+    async fn mock_upstream_only_sees_sanitized_request_headers() {
+        use crate::test_util::TestApp;
 
-                if result_status == "success" && !resp.status.is_success() {
-                    result_status = "error";
-                }
+        let app = TestApp::spawn(Default::default(), &["tvly-sanitize"])
+            .await
+            .expect("test app spawned");
+        let token = app.create_token().await.expect("token created");
+
+        let resp = app
+            .client()
+            .post(app.url("/mcp"))
+            .bearer_auth(&token)
+            .header("accept", "application/json, text/event-stream")
+            .header("accept-language", "de-DE")
+            .header("x-request-label", "kept")
+            .header("x-forwarded-for", "203.0.113.9")
+            .header("x-real-ip", "203.0.113.9")
+            .header("cf-connecting-ip", "203.0.113.9")
+            .header("via", "1.1 edge")
+            .header("forwarded", "for=203.0.113.9")
+            .json(&json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "tools/call",
+                "params": { "name": "tavily-search", "arguments": { "query": "headers" } },
+            }))
+            .send()
+            .await
+            .expect("tool call");
+        assert!(resp.status().is_success());
 
-                let http_code = resp.status.as_u16() as i64;
-                let _ = state
-                    .proxy
-                    .record_token_attempt(
-                        tid,
-                        &method,
-                        &path,
-                        parts.uri.query(),
-                        Some(http_code),
-                        tavily_code,
-                        billable_flag,
-                        result_status,
-                        None,
-                    )
-                    .await;
-            }
-            Ok(build_response(resp))
+        let requests = app.upstream.requests();
+        let seen = &requests.last().expect("upstream request").headers;
+        assert_eq!(seen["accept-language"], "de-DE");
+        assert_eq!(seen["x-request-label"], "kept");
+        for blocked in [
+            "x-forwarded-for",
+            "x-real-ip",
+            "cf-connecting-ip",
+            "via",
+            "forwarded",
+        ] {
+            assert!(!seen.contains_key(blocked), "{blocked} leaked upstream");
         }
-        Err(err) => {
-            eprintln!("proxy error: {err}");
-            if let Some(tid) = token_id.as_deref() {
-                let err_str = err.to_string();
-                let _ = state
-                    .proxy
-                    .record_token_attempt(
-                        tid,
-                        &method,
-                        &path,
-                        parts.uri.query(),
-                        None,
-                        None,
-                        billable_flag,
-                        "error",
-                        Some(err_str.as_str()),
-                    )
-                    .await;
-            }
-            Err(StatusCode::BAD_GATEWAY)
+        assert!(
+            seen.values()
+                .all(|value| !value.to_str().unwrap_or("").contains(&token)),
+            "the hikari token must not reach upstream"
+        );
+    }
+
+    #[tokio::test]
+    async fn soft_deleted_tokens_and_keys_can_be_listed_and_restored() {
+        use crate::test_util::TestApp;
+
+        let app = TestApp::spawn(Default::default(), &["tvly-restore"])
+            .await
+            .expect("test app spawned");
+        let token = app.create_token().await.expect("token created");
+        let token_id = token.split('-').nth(1).expect("token id").to_string();
+        let key_id = app.proxy.list_api_key_metrics().await.expect("keys")[0]
+            .id
+            .clone();
+
+        for path in [
+            format!("/api/tokens/{token_id}"),
+            format!("/api/keys/{key_id}"),
+        ] {
+            let resp = app
+                .admin(reqwest::Method::DELETE, &path)
+                .send()
+                .await
+                .expect("delete");
+            assert_eq!(resp.status(), reqwest::StatusCode::NO_CONTENT, "{path}");
+        }
+        assert!(!app.proxy.validate_access_token(&token).await.expect("auth"));
+
+        let tokens: Value = app
+            .admin(reqwest::Method::GET, "/api/tokens")
+            .send()
+            .await
+            .expect("tokens")
+            .json()
+            .await
+            .expect("tokens json");
+        assert_eq!(tokens["total"], 0);
+        let tokens: Value = app
+            .admin(reqwest::Method::GET, "/api/tokens?include_deleted=true")
+            .send()
+            .await
+            .expect("tokens with deleted")
+            .json()
+            .await
+            .expect("tokens json");
+        // The dev-open-admin placeholder row is soft-deleted too, but never listed.
+        assert_eq!(tokens["total"], 1);
+        assert_eq!(tokens["items"][0]["id"], token_id.as_str());
+        assert!(tokens["items"][0]["deleted_at"].as_i64().is_some());
+        let resp = app
+            .admin(reqwest::Method::POST, "/api/tokens/dev/restore")
+            .send()
+            .await
+            .expect("restore placeholder");
+        assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+
+        let keys: Value = app
+            .admin(reqwest::Method::GET, "/api/keys?include_deleted=true")
+            .send()
+            .await
+            .expect("keys with deleted")
+            .json()
+            .await
+            .expect("keys json");
+        assert_eq!(keys["total"], 1);
+        assert!(keys["items"][0]["deleted_at"].as_i64().is_some());
+
+        for path in [
+            format!("/api/tokens/{token_id}/restore"),
+            format!("/api/keys/{key_id}/restore"),
+        ] {
+            let resp = app
+                .admin(reqwest::Method::POST, &path)
+                .send()
+                .await
+                .expect("restore");
+            assert_eq!(resp.status(), reqwest::StatusCode::NO_CONTENT, "{path}");
+            let again = app
+                .admin(reqwest::Method::POST, &path)
+                .send()
+                .await
+                .expect("restore again");
+            assert_eq!(again.status(), reqwest::StatusCode::NOT_FOUND, "{path}");
         }
+
+        assert!(app.proxy.validate_access_token(&token).await.expect("auth"));
+        let keys = app.proxy.list_api_key_metrics().await.expect("keys");
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].status, "active");
+        let resp = app
+            .call_tool(&token, 1, "tavily-search", json!({ "query": "restored" }))
+            .await
+            .expect("tool call");
+        assert!(resp.status().is_success());
     }
-}
 
-fn clone_headers(headers: &HeaderMap) -> ReqHeaderMap {
-    let mut map = ReqHeaderMap::new();
-    for (name, value) in headers.iter() {
-        if let Ok(cloned) = ReqHeaderValue::from_bytes(value.as_bytes()) {
-            map.insert(name.clone(), cloned);
-        }
+    #[tokio::test]
+    async fn maintenance_mode_refuses_mcp_and_survives_restart() {
+        use crate::test_util::{MockUpstreamConfig, TestApp};
+
+        let app = TestApp::spawn(
+            MockUpstreamConfig::default().with_sse(),
+            &["tvly-maintenance"],
+        )
+        .await
+        .expect("spawn app");
+        let token = app.create_token().await.expect("token");
+
+        let resp = app
+            .admin(Method::POST, "/api/admin/maintenance")
+            .json(&json!({ "enabled": true, "durationSecs": 600, "message": "upgrading" }))
+            .send()
+            .await
+            .expect("enable maintenance");
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        let view: Value = resp.json().await.expect("maintenance json");
+        assert_eq!(view["enabled"], true);
+        assert_eq!(view["message"], "upgrading");
+
+        let resp = app
+            .call_tool(&token, 1, "tavily-search", json!({ "query": "hi" }))
+            .await
+            .expect("mcp call");
+        assert_eq!(resp.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+        let retry_after: i64 = resp.headers()[reqwest::header::RETRY_AFTER]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((1..=600).contains(&retry_after));
+        let body: Value = resp.json().await.expect("error json");
+        assert_eq!(body["id"], 1);
+        assert_eq!(body["error"]["message"], "upgrading");
+        assert_eq!(body["error"]["data"]["code"], "MAINTENANCE");
+        assert_eq!(body["error"]["data"]["retryAfterSecs"], retry_after);
+        assert!(app.upstream.tool_call_keys().is_empty());
+
+        // The /v1 REST passthrough is refused the same way.
+        let resp = app
+            .client()
+            .post(app.url("/v1/search"))
+            .bearer_auth(&token)
+            .json(&json!({ "query": "hi" }))
+            .send()
+            .await
+            .expect("rest call");
+        assert_eq!(resp.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+        assert!(resp.headers().contains_key(reqwest::header::RETRY_AFTER));
+        let body: Value = resp.json().await.expect("error json");
+        assert_eq!(body["error"]["data"]["code"], "MAINTENANCE");
+
+        // So are the /api/tavily HTTP endpoints.
+        let resp = app
+            .client()
+            .post(app.url("/api/tavily/search"))
+            .bearer_auth(&token)
+            .json(&json!({ "query": "hi" }))
+            .send()
+            .await
+            .expect("http search");
+        assert_eq!(resp.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+        assert!(resp.headers().contains_key(reqwest::header::RETRY_AFTER));
+        let body: Value = resp.json().await.expect("error json");
+        assert_eq!(body["error"]["data"]["code"], "MAINTENANCE");
+        assert!(app.upstream.requests().is_empty());
+
+        // Admin APIs stay up.
+        let resp = app
+            .admin(Method::GET, "/api/keys")
+            .send()
+            .await
+            .expect("list keys");
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+
+        let reopened =
+            TavilyProxy::with_endpoint(Vec::<String>::new(), DEFAULT_UPSTREAM, &app.db_path())
+                .await
+                .expect("reopen proxy");
+        let mode = reopened.maintenance().expect("maintenance persisted");
+        assert_eq!(mode.message.as_deref(), Some("upgrading"));
+
+        let resp = app
+            .admin(Method::POST, "/api/admin/maintenance")
+            .json(&json!({ "enabled": false }))
+            .send()
+            .await
+            .expect("disable maintenance");
+        let view: Value = resp.json().await.expect("maintenance json");
+        assert_eq!(view["enabled"], false);
+        let resp = app
+            .call_tool(&token, 2, "tavily-search", json!({ "query": "hi" }))
+            .await
+            .expect("mcp call");
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        assert!(app.proxy.maintenance().is_none());
     }
-    map
-}
 
-fn accepts_event_stream(headers: &HeaderMap) -> bool {
-    headers
-        .get(axum::http::header::ACCEPT)
-        .and_then(|value| value.to_str().ok())
-        .map(|raw| {
-            raw.split(',')
-                .any(|v| v.trim().eq_ignore_ascii_case("text/event-stream"))
-        })
-        .unwrap_or(false)
-}
+    #[tokio::test]
+    async fn upstream_header_rules_inject_templated_headers() {
+        use crate::test_util::{MockUpstreamConfig, TestApp};
 
-fn build_response(resp: ProxyResponse) -> Response<Body> {
-    let mut builder = Response::builder().status(resp.status);
-    if let Some(headers) = builder.headers_mut() {
-        for (name, value) in resp.headers.iter() {
-            if name == TRANSFER_ENCODING || name == CONNECTION || name == CONTENT_LENGTH {
-                continue;
-            }
-            headers.append(name.clone(), value.clone());
-        }
-        headers.insert(CONTENT_LENGTH, value_from_len(resp.body.len()));
-    }
-    builder
-        .body(Body::from(resp.body))
-        .unwrap_or_else(|_| Response::builder().status(500).body(Body::empty()).unwrap())
-}
+        let app = TestApp::spawn(
+            MockUpstreamConfig::default().with_sse(),
+            &["tvly-upstream-headers"],
+        )
+        .await
+        .expect("spawn app");
+        let token = app.create_token().await.expect("token");
+        let token_id = token
+            .strip_prefix("th-")
+            .and_then(|rest| rest.split('-').next())
+            .expect("token id")
+            .to_string();
 
-fn value_from_len(len: usize) -> axum::http::HeaderValue {
-    axum::http::HeaderValue::from_str(len.to_string().as_str())
-        .unwrap_or_else(|_| axum::http::HeaderValue::from_static("0"))
-}
+        let put = |rules: Value| {
+            app.admin(Method::PUT, "/api/config/upstream-headers")
+                .json(&rules)
+                .send()
+        };
+        let resp = put(json!({ "headers": { "Tavily-Api-Key": "x" } }))
+            .await
+            .expect("reserved header");
+        assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+        let resp = put(json!({ "headers": { "X-Client-Id": "{token}" } }))
+            .await
+            .expect("unknown placeholder");
+        assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
 
-fn request_limit_exceeded_response(
-    verdict: &TokenHourlyRequestVerdict,
-) -> Result<Response<Body>, StatusCode> {
-    let payload = json!({
-        "error": "quota_exhausted",
-        "window": "hour",
-        "hourlyAny": {
-            "limit": verdict.hourly_limit,
-            "used": verdict.hourly_used,
-        },
-    });
+        let resp = put(json!({
+            "headers": { "X-Client-Id": "hikari-{token_id}", "X-Team": "core" },
+            "tokens": { token_id.clone(): { "X-Team": "search" } },
+        }))
+        .await
+        .expect("set rules");
+        assert_eq!(resp.status(), reqwest::StatusCode::NO_CONTENT);
+        let rules: Value = app
+            .admin(Method::GET, "/api/config/upstream-headers")
+            .send()
+            .await
+            .expect("get rules")
+            .json()
+            .await
+            .expect("rules json");
+        assert_eq!(rules["headers"]["x-client-id"], "hikari-{token_id}");
 
-    Response::builder()
-        .status(StatusCode::TOO_MANY_REQUESTS)
-        .header(CONTENT_TYPE, "application/json; charset=utf-8")
-        .body(Body::from(payload.to_string()))
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
-}
+        let resp = app
+            .call_tool(&token, 1, "tavily-search", json!({ "query": "hi" }))
+            .await
+            .expect("mcp call");
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        let request = app
+            .upstream
+            .requests()
+            .into_iter()
+            .rfind(|r| r.rpc_method == "tools/call")
+            .expect("forwarded call");
+        assert_eq!(
+            request.headers["x-client-id"],
+            format!("hikari-{token_id}").as_str()
+        );
+        assert_eq!(request.headers["x-team"], "search");
 
-fn quota_exceeded_response(verdict: &TokenQuotaVerdict) -> Result<Response<Body>, StatusCode> {
-    let payload = json!({
-        "error": "quota_exceeded",
-        "window": verdict.window_name(),
-        "hourly": {
-            "limit": verdict.hourly_limit,
-            "used": verdict.hourly_used,
-        },
-        "daily": {
-            "limit": verdict.daily_limit,
-            "used": verdict.daily_used,
-        },
-        "monthly": {
-            "limit": verdict.monthly_limit,
-            "used": verdict.monthly_used,
-        },
-    });
+        let (history, _) = app
+            .proxy
+            .list_config_changes(Some("upstream_header_rules"), 1, 10)
+            .await
+            .expect("config history");
+        assert_eq!(history[0].new_value.as_deref(), Some("headers=2 tokens=1"));
+    }
 
-    Response::builder()
-        .status(StatusCode::TOO_MANY_REQUESTS)
-        .header(CONTENT_TYPE, "application/json; charset=utf-8")
-        .body(Body::from(payload.to_string()))
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
-}
+    #[tokio::test]
+    async fn db_health_reports_file_wal_and_pool_usage() {
+        use crate::test_util::{MockUpstreamConfig, TestApp};
 
-fn build_request_limit_error_message(verdict: &TokenHourlyRequestVerdict) -> String {
-    format!(
-        "token hourly request limit exceeded (limit {}, used {})",
-        verdict.hourly_limit, verdict.hourly_used
-    )
-}
+        let app = TestApp::spawn(MockUpstreamConfig::default(), &["tvly-db-health"])
+            .await
+            .expect("spawn app");
+        app.create_token().await.expect("token");
 
-fn build_quota_error_message(verdict: &TokenQuotaVerdict) -> String {
-    let (limit, used) = quota_window_stats(verdict);
-    let window = verdict.window_name().unwrap_or("unknown");
-    format!("token quota exceeded on {window} window (limit {limit}, used {used})")
-}
+        let resp = app
+            .client()
+            .get(app.url("/api/admin/db-health"))
+            .send()
+            .await
+            .expect("db health");
+        assert_eq!(resp.status(), reqwest::StatusCode::FORBIDDEN);
 
-fn quota_window_stats(verdict: &TokenQuotaVerdict) -> (i64, i64) {
-    match verdict.exceeded_window.unwrap_or(QuotaWindow::Hour) {
-        QuotaWindow::Hour => (verdict.hourly_limit, verdict.hourly_used),
-        QuotaWindow::Day => (verdict.daily_limit, verdict.daily_used),
-        QuotaWindow::Month => (verdict.monthly_limit, verdict.monthly_used),
+        let resp = app
+            .admin(Method::GET, "/api/admin/db-health")
+            .send()
+            .await
+            .expect("db health");
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        let health: Value = resp.json().await.expect("db health json");
+        assert!(health["dbFileBytes"].as_i64().unwrap() > 0);
+        assert!(health["walSizeBytes"].as_i64().unwrap() > 0);
+        assert!(health["pageCount"].as_i64().unwrap() > 0);
+        assert_eq!(health["walCheckpointIntervalSecs"], 3600);
+        let pool = &health["pool"];
+        assert_eq!(pool["maxConnections"], 5);
+        assert!(pool["size"].as_u64().unwrap() >= 1);
+        assert_eq!(
+            pool["inUse"].as_u64().unwrap(),
+            pool["size"].as_u64().unwrap() - pool["idle"].as_u64().unwrap()
+        );
+        let utilization = pool["utilizationPercent"].as_f64().unwrap();
+        assert!((0.0..=100.0).contains(&utilization));
     }
-}
 
-impl From<ApiKeyMetrics> for ApiKeyView {
-    fn from(metrics: ApiKeyMetrics) -> Self {
-        Self {
-            id: metrics.id,
-            status: metrics.status,
-            status_changed_at: metrics.status_changed_at,
-            last_used_at: metrics.last_used_at,
-            deleted_at: metrics.deleted_at,
-            quota_limit: metrics.quota_limit,
-            quota_remaining: metrics.quota_remaining,
-            quota_synced_at: metrics.quota_synced_at,
-            total_requests: metrics.total_requests,
-            success_count: metrics.success_count,
-            error_count: metrics.error_count,
-            quota_exhausted_count: metrics.quota_exhausted_count,
-        }
-    }
-}
+    #[tokio::test]
+    async fn public_dashboard_endpoints_report_usage_and_quota_for_a_full_token() {
+        use crate::test_util::TestApp;
 
-fn decode_body(bytes: &[u8]) -> Option<String> {
-    if bytes.is_empty() {
-        None
-    } else {
-        Some(String::from_utf8_lossy(bytes).into_owned())
-    }
-}
+        let app = TestApp::spawn(Default::default(), &["tvly-dashboard"])
+            .await
+            .expect("test app spawned");
+        let token = app.create_token().await.expect("token created");
+        let resp = app
+            .call_tool(&token, 1, "tavily-search", json!({ "query": "dashboard" }))
+            .await
+            .expect("tool call");
+        assert!(resp.status().is_success());
+        app.proxy
+            .rollup_token_usage_stats()
+            .await
+            .expect("usage rolled up");
 
-impl From<RequestLogRecord> for RequestLogView {
-    fn from(record: RequestLogRecord) -> Self {
-        Self {
-            id: record.id,
-            key_id: record.key_id,
-            auth_token_id: record.auth_token_id,
-            method: record.method,
-            path: record.path,
-            query: record.query,
-            http_status: record.status_code,
-            mcp_status: record.tavily_status_code,
-            result_status: record.result_status,
-            created_at: record.created_at,
-            error_message: record.error_message,
-            request_body: decode_body(&record.request_body),
-            response_body: decode_body(&record.response_body),
-            forwarded_headers: record.forwarded_headers,
-            dropped_headers: record.dropped_headers,
+        let series: Vec<Value> = app
+            .client()
+            .get(app.url("/api/public/usage-series"))
+            .query(&[("token", token.as_str())])
+            .send()
+            .await
+            .expect("usage series")
+            .json()
+            .await
+            .expect("usage series json");
+        let successes: i64 = series
+            .iter()
+            .map(|bucket| bucket["success_count"].as_i64().unwrap_or(0))
+            .sum();
+        assert_eq!(successes, 1);
+
+        let quota: Value = app
+            .client()
+            .get(app.url("/api/public/quota"))
+            .query(&[("token", token.as_str())])
+            .send()
+            .await
+            .expect("quota")
+            .json()
+            .await
+            .expect("quota json");
+        assert_eq!(quota["quotaState"], "normal");
+        assert_eq!(quota["quotaHourlyUsed"], 1);
+        assert_eq!(quota["quotaHourlyLimit"], effective_token_hourly_limit());
+        assert!(quota["quotaHourlyResetAt"].as_i64().is_some());
+
+        let short_id = token.split('-').nth(1).expect("token id").to_string();
+        for bad in [short_id.as_str(), "th-nope-secret"] {
+            for path in ["/api/public/usage-series", "/api/public/quota"] {
+                let resp = app
+                    .client()
+                    .get(app.url(path))
+                    .query(&[("token", bad)])
+                    .send()
+                    .await
+                    .expect("rejected request");
+                assert_eq!(resp.status(), reqwest::StatusCode::UNAUTHORIZED, "{path}");
+            }
         }
     }
-}
 
-impl From<ProxySummary> for SummaryView {
-    fn from(summary: ProxySummary) -> Self {
-        Self {
-            total_requests: summary.total_requests,
-            success_count: summary.success_count,
-            error_count: summary.error_count,
-            quota_exhausted_count: summary.quota_exhausted_count,
-            active_keys: summary.active_keys,
-            exhausted_keys: summary.exhausted_keys,
-            last_activity: summary.last_activity,
-            total_quota_limit: summary.total_quota_limit,
-            total_quota_remaining: summary.total_quota_remaining,
-        }
+    #[test]
+    fn jsonrpc_head_envelope_reads_members_before_the_cut() {
+        let envelope = |head: &str| {
+            jsonrpc_head_envelope(head.as_bytes())
+                .map(|bytes| serde_json::from_slice::<Value>(&bytes).expect("envelope json"))
+        };
+        assert_eq!(
+            envelope(
+                r#" {"jsonrpc":"2.0","id":"a\"b","method":"tools/call","params":{"name":"tavily-extract","arguments":{"urls":["https://exa"#
+            ),
+            Some(json!({
+                "jsonrpc": "2.0",
+                "id": "a\"b",
+                "method": "tools/call",
+                "params": { "name": "tavily-extract" },
+            }))
+        );
+        // Other members are skipped whole, nested strings and brackets included.
+        assert_eq!(
+            envelope(r#"{"meta":{"x":["}",1]},"method":"tools/list","id":12"#),
+            Some(json!({ "method": "tools/list" }))
+        );
+        // The tool name of a tools/call must come before the cut.
+        assert_eq!(
+            envelope(r#"{"method":"tools/call","params":{"arguments":{"query":"#),
+            None
+        );
+        assert_eq!(
+            envelope(r#"{"params":{"name":"tavily-search"},"id":1,"met"#),
+            None
+        );
+        assert_eq!(envelope(r#"[{"method":"tools/list"}"#), None);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use axum::Router;
-    use axum::extract::{Json, Query};
-    use axum::http::Method;
-    use axum::routing::{any, post};
-    use nanoid::nanoid;
-    use reqwest::Client;
-    use sqlx::Row;
-    use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions};
-    use std::collections::HashMap;
-    use std::path::PathBuf;
-    use tavily_hikari::DEFAULT_UPSTREAM;
-    use tokio::net::TcpListener;
+    #[tokio::test]
+    async fn large_mcp_bodies_are_streamed_upstream_and_logged_by_digest() {
+        use crate::test_util::TestApp;
 
-    fn temp_db_path(prefix: &str) -> PathBuf {
-        let file = format!("{}-{}.db", prefix, nanoid!(8));
-        std::env::temp_dir().join(file)
-    }
+        let _guard = crate::tests::env_lock().lock_owned().await;
+        unsafe {
+            std::env::set_var("MCP_STREAM_BODY_BYTES", "256");
+        }
+        let app = TestApp::spawn(Default::default(), &["tvly-stream-upload"])
+            .await
+            .expect("test app spawned");
+        let token = app.create_token().await.expect("token created");
 
-    async fn spawn_mock_upstream(expected_api_key: String) -> SocketAddr {
-        let app = Router::new().route(
-            "/mcp",
-            any({
-                move |Query(params): Query<HashMap<String, String>>| {
-                    let expected_api_key = expected_api_key.clone();
-                    async move {
-                        let received = params.get("tavilyApiKey").cloned();
-                        if received.as_deref() != Some(expected_api_key.as_str()) {
-                            return (
-                                StatusCode::UNAUTHORIZED,
-                                Body::from("missing or incorrect tavilyApiKey"),
-                            );
-                        }
-                        (StatusCode::OK, Body::from("{\"ok\":true}"))
-                    }
-                }
-            }),
+        let urls = json!(vec!["https://example.com/page"; 400]);
+        let body = format!(
+            r#"{{"jsonrpc":"2.0","id":1,"method":"tools/call","params":{{"name":"tavily-extract","arguments":{{"urls":{urls}}}}}}}"#
+        )
+        .into_bytes();
+        assert!(body.len() > 8 * 1024);
+        let resp = app
+            .client()
+            .post(app.url("/mcp"))
+            .bearer_auth(&token)
+            .header("content-type", "application/json")
+            .header("accept", "application/json, text/event-stream")
+            .body(body.clone())
+            .send()
+            .await
+            .expect("tool call");
+        let status = resp.status();
+        let reply = resp.text().await.expect("reply");
+        assert!(status.is_success(), "{status}: {reply}");
+        assert!(
+            reply.contains("mock result"),
+            "upstream parsed the whole body: {reply}"
         );
 
-        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
-        let addr = listener.local_addr().unwrap();
-        tokio::spawn(async move {
-            axum::serve(listener, app.into_make_service())
-                .await
-                .unwrap();
-        });
-        addr
+        let logs = app
+            .proxy
+            .recent_request_logs(5)
+            .await
+            .expect("request logs");
+        let log = app
+            .proxy
+            .request_log(logs[0].id)
+            .await
+            .expect("request log")
+            .expect("request log row");
+        assert_eq!(log.request_body, body[..256].to_vec());
+        assert_eq!(log.request_body_len, Some(body.len() as i64));
+        let digest: String = Sha256::digest(&body)
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        assert_eq!(log.request_body_sha256, Some(digest));
+
+        // Without the tool name in the head, quota and tool policies cannot apply.
+        let mut reordered =
+            br#"{"jsonrpc":"2.0","id":2,"method":"tools/call","params":{"arguments":{"query":""#
+                .to_vec();
+        reordered.extend(std::iter::repeat_n(b'x', 1024));
+        reordered.extend_from_slice(br#""},"name":"tavily-search"}}"#);
+        let resp = app
+            .client()
+            .post(app.url("/mcp"))
+            .bearer_auth(&token)
+            .header("content-type", "application/json")
+            .body(reordered)
+            .send()
+            .await
+            .expect("reordered call");
+        assert_eq!(resp.status(), reqwest::StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(app.upstream.tool_calls(), 1);
+
+        unsafe {
+            std::env::remove_var("MCP_STREAM_BODY_BYTES");
+        }
     }
 
-    async fn spawn_http_search_mock_asserting_api_key(expected_api_key: String) -> SocketAddr {
-        let app = Router::new().route(
-            "/search",
-            post({
-                move |Json(body): Json<Value>| {
-                    let expected_api_key = expected_api_key.clone();
-                    async move {
-                        let api_key = body.get("api_key").and_then(|v| v.as_str()).unwrap_or("");
-                        assert_eq!(
-                            api_key, expected_api_key,
-                            "upstream api_key should use Tavily key from pool"
-                        );
-                        assert!(
-                            !api_key.starts_with("th-"),
-                            "upstream api_key must not be Hikari token"
-                        );
-                        (
-                            StatusCode::OK,
-                            Json(serde_json::json!({
-                                "status": 200,
-                                "results": [],
-                            })),
-                        )
-                    }
-                }
-            }),
-        );
+    #[tokio::test]
+    async fn token_upstream_override_routes_to_allowlisted_upstream_with_tagged_keys() {
+        use crate::test_util::{MockUpstream, MockUpstreamConfig, TestApp};
 
-        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
-        let addr = listener.local_addr().unwrap();
-        tokio::spawn(async move {
-            axum::serve(listener, app.into_make_service())
-                .await
-                .unwrap();
-        });
-        addr
-    }
+        let _guard = crate::tests::env_lock().lock_owned().await;
+        let partner_key = "tvly-partner-pool-key";
+        let partner =
+            MockUpstream::spawn(MockUpstreamConfig::default().expect_api_key(partner_key)).await;
+        unsafe {
+            std::env::set_var(
+                "UPSTREAM_OVERRIDE_ALLOWLIST",
+                format!("partner={}", partner.url()),
+            );
+        }
+        let default_key = "tvly-default-pool-key";
+        let app = TestApp::spawn(
+            MockUpstreamConfig::default().expect_api_key(default_key),
+            &[default_key],
+        )
+        .await
+        .expect("test app spawned");
+        unsafe {
+            std::env::remove_var("UPSTREAM_OVERRIDE_ALLOWLIST");
+        }
 
-    async fn spawn_http_extract_mock_asserting_api_key(expected_api_key: String) -> SocketAddr {
-        let app = Router::new().route(
-            "/extract",
-            post({
-                move |Json(body): Json<Value>| {
-                    let expected_api_key = expected_api_key.clone();
-                    async move {
-                        let api_key = body.get("api_key").and_then(|v| v.as_str()).unwrap_or("");
-                        assert_eq!(
-                            api_key, expected_api_key,
-                            "upstream api_key for /extract should use Tavily key from pool"
-                        );
-                        assert!(
-                            !api_key.starts_with("th-"),
-                            "upstream /extract api_key must not be Hikari token"
-                        );
-                        (
-                            StatusCode::OK,
-                            Json(serde_json::json!({
-                                "status": 200,
-                                "results": [],
-                            })),
-                        )
-                    }
-                }
-            }),
-        );
+        let partner_key_id = app
+            .proxy
+            .add_or_undelete_key(partner_key)
+            .await
+            .expect("partner key added");
+        let resp = app
+            .admin(
+                reqwest::Method::PUT,
+                &format!("/api/keys/{partner_key_id}/tags"),
+            )
+            .json(&serde_json::json!({ "tags": ["upstream:partner"] }))
+            .send()
+            .await
+            .expect("tag key");
+        assert_eq!(resp.status(), reqwest::StatusCode::NO_CONTENT);
 
-        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
-        let addr = listener.local_addr().unwrap();
-        tokio::spawn(async move {
-            axum::serve(listener, app.into_make_service())
+        let default_token = app.create_token().await.expect("default token");
+        let partner_token = app.create_token().await.expect("partner token");
+        let partner_token_id = partner_token
+            .strip_prefix("th-")
+            .and_then(|rest| rest.split('-').next())
+            .expect("token id")
+            .to_string();
+
+        let rejected = app
+            .admin(
+                reqwest::Method::PATCH,
+                &format!("/api/tokens/{partner_token_id}/upstream"),
+            )
+            .json(&serde_json::json!({ "upstream": "unknown" }))
+            .send()
+            .await
+            .expect("reject unknown upstream");
+        assert_eq!(rejected.status(), reqwest::StatusCode::BAD_REQUEST);
+        let resp = app
+            .admin(
+                reqwest::Method::PATCH,
+                &format!("/api/tokens/{partner_token_id}/upstream"),
+            )
+            .json(&serde_json::json!({ "upstream": "partner" }))
+            .send()
+            .await
+            .expect("set upstream");
+        assert_eq!(resp.status(), reqwest::StatusCode::NO_CONTENT);
+
+        for (id, token) in [
+            (1, &partner_token),
+            (2, &default_token),
+            (3, &partner_token),
+        ] {
+            let resp = app
+                .call_tool(
+                    token,
+                    id,
+                    "tavily-search",
+                    serde_json::json!({ "query": "q" }),
+                )
                 .await
-                .unwrap();
-        });
-        addr
+                .expect("tool call");
+            assert!(resp.status().is_success(), "tool call {id} should succeed");
+        }
+
+        // Each upstream only ever saw its own pool's key (mocks reject anything else).
+        assert_eq!(partner.tool_calls(), 2);
+        assert_eq!(app.upstream.tool_calls(), 1);
     }
 
-    async fn spawn_http_crawl_mock_asserting_api_key(expected_api_key: String) -> SocketAddr {
-        let app = Router::new().route(
-            "/crawl",
-            post({
-                move |Json(body): Json<Value>| {
-                    let expected_api_key = expected_api_key.clone();
-                    async move {
-                        let api_key = body.get("api_key").and_then(|v| v.as_str()).unwrap_or("");
-                        assert_eq!(
-                            api_key, expected_api_key,
-                            "upstream api_key for /crawl should use Tavily key from pool"
-                        );
-                        assert!(
-                            !api_key.starts_with("th-"),
-                            "upstream /crawl api_key must not be Hikari token"
-                        );
-                        (
-                            StatusCode::OK,
-                            Json(serde_json::json!({
-                                "status": 200,
-                                "results": [],
-                            })),
-                        )
-                    }
-                }
-            }),
-        );
+    #[tokio::test]
+    async fn access_log_writes_json_lines_and_rotates_by_size() {
+        let log_path = std::env::temp_dir().join(format!("access-log-{}.jsonl", nanoid!(8)));
+        let app = {
+            let _guard = crate::tests::env_lock().lock_owned().await;
+            unsafe {
+                std::env::set_var("ACCESS_LOG", &log_path);
+                std::env::set_var("ACCESS_LOG_MAX_BYTES", "400");
+                std::env::set_var("ACCESS_LOG_MAX_FILES", "2");
+                std::env::set_var("TRUSTED_PROXIES", "127.0.0.1, 10.0.0.0/8");
+            }
+            let app = crate::test_util::TestApp::spawn(Default::default(), &["tvly-access-log"])
+                .await
+                .expect("spawn app");
+            unsafe {
+                std::env::remove_var("ACCESS_LOG");
+                std::env::remove_var("ACCESS_LOG_MAX_BYTES");
+                std::env::remove_var("ACCESS_LOG_MAX_FILES");
+                std::env::remove_var("TRUSTED_PROXIES");
+            }
+            app
+        };
 
-        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
-        let addr = listener.local_addr().unwrap();
-        tokio::spawn(async move {
-            axum::serve(listener, app.into_make_service())
+        let resp = app
+            .client()
+            .get(app.url("/health?secret=1"))
+            .header("x-forwarded-for", "203.0.113.7, 10.0.0.1")
+            .send()
+            .await
+            .expect("health");
+        assert!(resp.status().is_success());
+        let resp = app
+            .admin(Method::GET, "/api/summary")
+            .send()
+            .await
+            .expect("summary");
+        assert!(resp.status().is_success());
+
+        let lines: Vec<Value> = std::fs::read_to_string(&log_path)
+            .expect("read access log")
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("json line"))
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["method"], "GET");
+        assert_eq!(lines[0]["path"], "/health");
+        assert_eq!(lines[0]["status"], 200);
+        assert!(lines[0]["latency_ms"].is_u64());
+        assert_eq!(
+            lines[0]["client_ip_hash"],
+            Value::String(hash_client_ip("203.0.113.7"))
+        );
+        assert!(lines[0]["admin"].is_null());
+        assert_eq!(lines[1]["path"], "/api/summary");
+        assert_eq!(lines[1]["admin"], crate::test_util::ADMIN_USER);
+
+        // Keep writing until the 400-byte limit forces rotations; only two old files are kept.
+        for _ in 0..10 {
+            app.client()
+                .get(app.url("/health"))
+                .send()
                 .await
-                .unwrap();
-        });
-        addr
+                .expect("health");
+        }
+        let rotated = |i: usize| PathBuf::from(format!("{}.{i}", log_path.display()));
+        assert!(rotated(1).exists());
+        assert!(rotated(2).exists());
+        assert!(!rotated(3).exists());
+        assert!(std::fs::metadata(&log_path).expect("active log").len() <= 400);
+
+        for path in [log_path.clone(), rotated(1), rotated(2)] {
+            let _ = std::fs::remove_file(path);
+        }
     }
 
-    async fn spawn_http_map_mock_asserting_api_key(expected_api_key: String) -> SocketAddr {
-        let app = Router::new().route(
-            "/map",
-            post({
-                move |Json(body): Json<Value>| {
-                    let expected_api_key = expected_api_key.clone();
-                    async move {
-                        let api_key = body.get("api_key").and_then(|v| v.as_str()).unwrap_or("");
-                        assert_eq!(
-                            api_key, expected_api_key,
-                            "upstream api_key for /map should use Tavily key from pool"
-                        );
-                        assert!(
-                            !api_key.starts_with("th-"),
-                            "upstream /map api_key must not be Hikari token"
-                        );
-                        (
-                            StatusCode::OK,
-                            Json(serde_json::json!({
-                                "status": 200,
-                                "results": [],
-                            })),
-                        )
-                    }
-                }
-            }),
+    #[tokio::test]
+    async fn log_lists_omit_bodies_and_detail_endpoint_loads_them() {
+        let app = crate::test_util::TestApp::spawn(Default::default(), &["tvly-light-logs"])
+            .await
+            .expect("spawn app");
+        let token = app.create_token().await.expect("token");
+        let resp = app
+            .call_tool(
+                &token,
+                1,
+                "tavily-search",
+                json!({ "query": "lazy bodies" }),
+            )
+            .await
+            .expect("tool call");
+        assert!(resp.status().is_success());
+
+        let list: Value = app
+            .admin(Method::GET, "/api/logs?per_page=10")
+            .send()
+            .await
+            .expect("list logs")
+            .json()
+            .await
+            .expect("list json");
+        let item = &list["items"][0];
+        assert!(item["request_body"].is_null());
+        assert!(item["response_body"].is_null());
+        let id = item["id"].as_i64().expect("log id");
+
+        let detail: Value = app
+            .admin(Method::GET, &format!("/api/logs/{id}"))
+            .send()
+            .await
+            .expect("log detail")
+            .json()
+            .await
+            .expect("detail json");
+        assert_eq!(detail["id"], id);
+        assert!(
+            detail["request_body"]
+                .as_str()
+                .is_some_and(|body| body.contains("lazy bodies"))
         );
+        assert!(detail["response_body"].is_string());
 
-        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
-        let addr = listener.local_addr().unwrap();
-        tokio::spawn(async move {
-            axum::serve(listener, app.into_make_service())
-                .await
-                .unwrap();
-        });
-        addr
+        let missing = app
+            .admin(Method::GET, "/api/logs/999999")
+            .send()
+            .await
+            .expect("missing detail");
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+        let forbidden = app
+            .client()
+            .get(app.url(&format!("/api/logs/{id}")))
+            .send()
+            .await
+            .expect("anonymous detail");
+        assert_eq!(forbidden.status(), StatusCode::FORBIDDEN);
     }
 
-    async fn spawn_proxy_server(proxy: TavilyProxy, usage_base: String) -> SocketAddr {
-        spawn_proxy_server_with_dev(proxy, usage_base, false).await
-    }
+    #[tokio::test]
+    async fn warm_standby_follows_primary_snapshot_then_changes() {
+        let primary = crate::test_util::TestApp::spawn(Default::default(), &["tvly-replica-key"])
+            .await
+            .expect("spawn primary");
+        let token = primary.create_token().await.expect("token");
+        let token_id = token.split('-').nth(1).expect("token id").to_string();
+        let key_id = primary
+            .proxy
+            .list_api_key_metrics()
+            .await
+            .expect("keys")
+            .remove(0)
+            .id;
+        primary
+            .proxy
+            .set_api_key_tags(&key_id, &["eu".to_string(), "trial".to_string()])
+            .await
+            .expect("tags");
 
-    async fn spawn_proxy_server_with_dev(
-        proxy: TavilyProxy,
-        usage_base: String,
-        dev_open_admin: bool,
-    ) -> SocketAddr {
-        let state = Arc::new(AppState {
-            proxy,
-            static_dir: None,
-            forward_auth: ForwardAuthConfig::new(None, None, None, None),
-            dev_open_admin,
-            usage_base,
-        });
+        let db_path = std::env::temp_dir().join(format!("standby-{}.db", nanoid!(8)));
+        let standby = TavilyProxy::with_endpoint(
+            Vec::<String>::new(),
+            DEFAULT_UPSTREAM,
+            &db_path.to_string_lossy(),
+        )
+        .await
+        .expect("standby proxy");
+        let client = Client::new();
+        let primary_url = primary.url("");
+        let auth = (
+            crate::test_util::ADMIN_USER_HEADER.to_string(),
+            crate::test_util::ADMIN_USER.to_string(),
+        );
 
-        let app = Router::new()
-            .route("/mcp", any(proxy_handler))
-            .route("/mcp/*path", any(proxy_handler))
-            .route("/api/tavily/search", post(tavily_http_search))
-            .route("/api/tavily/extract", post(tavily_http_extract))
-            .route("/api/tavily/crawl", post(tavily_http_crawl))
-            .route("/api/tavily/map", post(tavily_http_map))
-            .route("/api/tavily/usage", get(tavily_http_usage))
-            .with_state(state);
+        let first = replication_sync_once(&standby, &client, &primary_url, Some(&auth))
+            .await
+            .expect("snapshot sync");
+        assert!(matches!(first, ReplicationSync::Snapshot(rows) if rows >= 4));
+        assert!(
+            standby
+                .validate_access_token(&token)
+                .await
+                .expect("validate")
+        );
+        assert_eq!(
+            standby.api_key_tags(&key_id).await.expect("standby tags"),
+            vec!["eu".to_string(), "trial".to_string()]
+        );
 
-        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
-        let addr = listener.local_addr().unwrap();
-        tokio::spawn(async move {
-            axum::serve(listener, app.into_make_service())
+        // Changes on the primary: a billable call, a disabled token and a removed tag.
+        let resp = primary
+            .call_tool(&token, 1, "tavily-search", json!({ "query": "replicate" }))
+            .await
+            .expect("tool call");
+        assert!(resp.status().is_success());
+        primary
+            .proxy
+            .set_access_token_enabled(&token_id, false)
+            .await
+            .expect("disable token");
+        primary
+            .proxy
+            .set_api_key_tags(&key_id, &["eu".to_string()])
+            .await
+            .expect("retag");
+
+        let second = replication_sync_once(&standby, &client, &primary_url, Some(&auth))
+            .await
+            .expect("changes sync");
+        assert!(matches!(second, ReplicationSync::Changes));
+        assert!(
+            !standby
+                .validate_access_token(&token)
                 .await
-                .unwrap();
-        });
-        addr
+                .expect("validate")
+        );
+        assert_eq!(
+            standby.api_key_tags(&key_id).await.expect("standby tags"),
+            vec!["eu".to_string()]
+        );
+        let month_count: i64 =
+            sqlx::query_scalar("SELECT month_count FROM auth_token_quota WHERE token_id = ?")
+                .bind(&token_id)
+                .fetch_one(&standby.key_store.pool)
+                .await
+                .expect("replicated quota");
+        assert_eq!(month_count, 1);
+
+        let progress = standby
+            .replication_progress()
+            .await
+            .expect("progress")
+            .expect("standby progress");
+        assert_eq!(progress.cursor, progress.primary_watermark);
+
+        let unauthorized = replication_sync_once(&standby, &client, &primary_url, None).await;
+        assert!(unauthorized.is_err());
+
+        let _ = std::fs::remove_file(db_path);
     }
 
-    async fn spawn_keys_admin_server(
-        proxy: TavilyProxy,
-        forward_auth: ForwardAuthConfig,
-        dev_open_admin: bool,
-    ) -> SocketAddr {
-        let state = Arc::new(AppState {
-            proxy,
-            static_dir: None,
-            forward_auth,
-            dev_open_admin,
-            usage_base: "http://127.0.0.1:58088".to_string(),
-        });
+    #[tokio::test]
+    async fn seed_demo_data_endpoint_is_hidden_without_dev_open_admin() {
+        let app = crate::test_util::TestApp::spawn(Default::default(), &["tvly-seed-key"])
+            .await
+            .expect("spawn app");
+        let resp = app
+            .admin(Method::POST, "/api/dev/seed-demo-data")
+            .send()
+            .await
+            .expect("seed request");
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            app.proxy.list_access_tokens().await.expect("tokens").len(),
+            0
+        );
 
-        let app = Router::new()
-            .route("/api/keys/batch", post(create_api_keys_batch))
-            .with_state(state);
+        let resp = app
+            .admin(Method::POST, "/api/admin/restore")
+            .json(&json!({ "file": "tavily_proxy-19700101T000000000Z.db" }))
+            .send()
+            .await
+            .expect("restore request");
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
 
+    #[tokio::test]
+    async fn database_backup_rotates_snapshots_and_restores_in_dev_mode() {
+        let db_path = temp_db_path("backup-restore");
+        let backup_dir = std::env::temp_dir().join(format!("backups-{}", nanoid!(8)));
+        let proxy = TavilyProxy::with_endpoint(
+            vec!["tvly-backup-key".to_string()],
+            DEFAULT_UPSTREAM,
+            &db_path.to_string_lossy(),
+        )
+        .await
+        .expect("proxy created")
+        .with_backup_dir(&backup_dir);
+        let app = app_router(
+            proxy.clone(),
+            None,
+            ForwardAuthConfig::new(None, None, None, None),
+            true,
+            "http://127.0.0.1:9".to_string(),
+        );
         let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
         let addr = listener.local_addr().unwrap();
         tokio::spawn(async move {
@@ -5159,1192 +15743,2519 @@ mod tests {
                 .await
                 .unwrap();
         });
-        addr
-    }
+        let client = Client::new();
 
-    #[tokio::test]
-    async fn tavily_http_search_returns_401_without_token() {
-        let db_path = temp_db_path("http-search-401-missing");
-        let db_str = db_path.to_string_lossy().to_string();
+        let resp = client
+            .post(format!("http://{addr}/api/admin/backup"))
+            .send()
+            .await
+            .expect("backup request");
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: Value = resp.json().await.expect("backup body");
+        let file = body["file"].as_str().expect("backup file").to_string();
+        assert!(body["bytes"].as_i64().unwrap_or(0) > 0);
+        assert!(backup_dir.join(&file).is_file());
 
-        let expected_api_key = "tvly-http-search-any-limit-key";
-        let proxy = TavilyProxy::with_endpoint(
-            vec![expected_api_key.to_string()],
-            DEFAULT_UPSTREAM,
-            &db_str,
-        )
-        .await
-        .expect("proxy created");
+        proxy
+            .add_or_undelete_key("tvly-added-after-backup")
+            .await
+            .expect("add key");
+        assert_eq!(proxy.list_api_key_metrics().await.expect("keys").len(), 2);
 
-        let upstream_addr =
-            spawn_http_search_mock_asserting_api_key(expected_api_key.to_string()).await;
-        let usage_base = format!("http://{}", upstream_addr);
-        let proxy_addr = spawn_proxy_server(proxy, usage_base).await;
+        for (payload, expected) in [
+            (
+                json!({ "file": "../tavily_proxy.db" }),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                json!({ "file": "tavily_proxy-19700101T000000000Z.db" }),
+                StatusCode::NOT_FOUND,
+            ),
+        ] {
+            let resp = client
+                .post(format!("http://{addr}/api/admin/restore"))
+                .json(&payload)
+                .send()
+                .await
+                .expect("restore request");
+            assert_eq!(resp.status(), expected);
+        }
 
-        let client = Client::new();
-        let url = format!("http://{}/api/tavily/search", proxy_addr);
         let resp = client
-            .post(url)
-            .json(&serde_json::json!({ "query": "test" }))
+            .post(format!("http://{addr}/api/admin/restore"))
+            .json(&json!({ "file": file }))
             .send()
             .await
-            .expect("request to proxy succeeds");
+            .expect("restore request");
+        assert_eq!(resp.status(), StatusCode::OK);
+        let keys = proxy.list_api_key_metrics().await.expect("keys");
+        assert_eq!(keys.len(), 1);
+        proxy
+            .add_or_undelete_key("tvly-added-after-restore")
+            .await
+            .expect("database writable after restore");
 
-        assert_eq!(resp.status(), reqwest::StatusCode::UNAUTHORIZED);
+        for _ in 0..3 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            let backup = proxy.backup_database(2).await.expect("backup");
+            assert!(backup.removed <= 2);
+        }
+        let remaining = std::fs::read_dir(&backup_dir).expect("backup dir").count();
+        assert_eq!(remaining, 2);
 
-        let body: serde_json::Value = resp.json().await.expect("parse json body");
+        let _ = std::fs::remove_dir_all(&backup_dir);
+        let _ = std::fs::remove_file(db_path);
+    }
+
+    #[tokio::test]
+    async fn request_logs_record_body_digests_and_detect_tampering() {
+        let app = crate::test_util::TestApp::spawn(Default::default(), &["tvly-digest-key"])
+            .await
+            .expect("spawn app");
+        let token = app.create_token().await.expect("token");
+        let resp = app
+            .call_tool(&token, 1, "tavily-search", json!({ "query": "digest" }))
+            .await
+            .expect("tool call");
+        assert!(resp.status().is_success());
+
+        let list: Value = app
+            .admin(Method::GET, "/api/logs?per_page=10")
+            .send()
+            .await
+            .expect("list logs")
+            .json()
+            .await
+            .expect("list json");
+        let item = &list["items"][0];
+        let id = item["id"].as_i64().expect("log id");
+        let response_sha256 = item["response_body_sha256"]
+            .as_str()
+            .expect("response digest")
+            .to_string();
+        assert_eq!(response_sha256.len(), 64);
+        assert!(
+            item["response_body_len"]
+                .as_i64()
+                .is_some_and(|len| len > 0)
+        );
+        assert!(item.get("bodies_intact").is_none());
+
+        let detail: Value = app
+            .admin(Method::GET, &format!("/api/logs/{id}"))
+            .send()
+            .await
+            .expect("log detail")
+            .json()
+            .await
+            .expect("detail json");
+        assert_eq!(detail["bodies_intact"], true);
         assert_eq!(
-            body.get("error"),
-            Some(&serde_json::Value::String("missing token".into()))
+            detail["response_body_len"].as_i64(),
+            detail["response_body"].as_str().map(|b| b.len() as i64)
         );
 
-        let _ = std::fs::remove_file(db_path);
+        let matches: Value = app
+            .admin(Method::GET, &format!("/api/logs/by-hash/{response_sha256}"))
+            .send()
+            .await
+            .expect("by hash")
+            .json()
+            .await
+            .expect("by hash json");
+        assert_eq!(matches[0]["id"], id);
+        let bad = app
+            .admin(Method::GET, "/api/logs/by-hash/not-a-digest")
+            .send()
+            .await
+            .expect("bad hash");
+        assert_eq!(bad.status(), StatusCode::BAD_REQUEST);
+
+        sqlx::query("UPDATE request_logs SET response_body = X'7B7D' WHERE id = ?")
+            .bind(id)
+            .execute(&app.proxy.key_store.pool)
+            .await
+            .expect("tamper body");
+        let tampered: Value = app
+            .admin(Method::GET, &format!("/api/logs/{id}"))
+            .send()
+            .await
+            .expect("tampered detail")
+            .json()
+            .await
+            .expect("tampered json");
+        assert_eq!(tampered["bodies_intact"], false);
     }
 
     #[tokio::test]
-    async fn tavily_http_search_dev_open_admin_does_not_fail_foreign_key() {
-        let db_path = temp_db_path("http-search-dev-open-admin-fk");
-        let db_str = db_path.to_string_lossy().to_string();
+    async fn jobs_can_be_paused_and_resumed() {
+        let app = crate::test_util::TestApp::spawn(Default::default(), &["tvly-pause-key"])
+            .await
+            .expect("spawn app");
 
-        let expected_api_key = "tvly-http-search-dev-open-admin-key";
-        let proxy = TavilyProxy::with_endpoint(
-            vec![expected_api_key.to_string()],
-            DEFAULT_UPSTREAM,
-            &db_str,
-        )
-        .await
-        .expect("proxy created");
+        let pause: Value = app
+            .admin(Method::POST, "/api/jobs/request_logs_gc/pause")
+            .json(&json!({ "durationSecs": 10_000_000 }))
+            .send()
+            .await
+            .expect("pause")
+            .json()
+            .await
+            .expect("pause json");
+        assert_eq!(pause["jobType"], "request_logs_gc");
+        let until = pause["pausedUntil"].as_i64().expect("paused until");
+        // Pauses are capped at JOB_PAUSE_MAX_SECS.
+        assert!(until <= Utc::now().timestamp() + crate::effective_job_pause_max_secs());
+        assert!(
+            app.proxy
+                .job_paused("request_logs_gc")
+                .await
+                .expect("paused")
+        );
+        assert!(
+            !app.proxy
+                .job_paused("quota_sync")
+                .await
+                .expect("not paused")
+        );
 
-        let upstream_addr =
-            spawn_http_search_mock_asserting_api_key(expected_api_key.to_string()).await;
-        let usage_base = format!("http://{}", upstream_addr);
-        let proxy_addr = spawn_proxy_server_with_dev(proxy, usage_base, true).await;
+        let jobs: Value = app
+            .admin(Method::GET, "/api/jobs")
+            .send()
+            .await
+            .expect("jobs")
+            .json()
+            .await
+            .expect("jobs json");
+        assert_eq!(jobs["paused"][0]["jobType"], "request_logs_gc");
 
-        let client = Client::new();
-        let url = format!("http://{}/api/tavily/search", proxy_addr);
-        let resp = client
-            .post(url)
-            .json(&serde_json::json!({ "query": "dev-open-admin fk" }))
+        let resumed = app
+            .admin(Method::POST, "/api/jobs/request_logs_gc/resume")
             .send()
             .await
-            .expect("request to proxy succeeds");
+            .expect("resume");
+        assert_eq!(resumed.status(), StatusCode::NO_CONTENT);
+        assert!(
+            !app.proxy
+                .job_paused("request_logs_gc")
+                .await
+                .expect("resumed")
+        );
+        let jobs: Value = app
+            .admin(Method::GET, "/api/jobs")
+            .send()
+            .await
+            .expect("jobs")
+            .json()
+            .await
+            .expect("jobs json");
+        assert_eq!(jobs["paused"], json!([]));
 
-        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        // Without a body the pause lasts the maximum duration.
+        let pause = app
+            .admin(Method::POST, "/api/jobs/quota_sync/pause")
+            .send()
+            .await
+            .expect("pause without body");
+        assert_eq!(pause.status(), StatusCode::OK);
+        let unknown = app
+            .admin(Method::POST, "/api/jobs/not_a_job/pause")
+            .send()
+            .await
+            .expect("unknown job");
+        assert_eq!(unknown.status(), StatusCode::NOT_FOUND);
+    }
 
-        let body: serde_json::Value = resp.json().await.expect("parse json body");
-        assert_eq!(body.get("status").and_then(|v| v.as_i64()), Some(200));
+    #[tokio::test]
+    async fn log_detail_accepts_public_id() {
+        let app = crate::test_util::TestApp::spawn(Default::default(), &["tvly-public-id-key"])
+            .await
+            .expect("spawn app");
+        let token = app.create_token().await.expect("token");
+        let resp = app
+            .call_tool(&token, 1, "tavily-search", json!({ "query": "uuid" }))
+            .await
+            .expect("tool call");
+        assert!(resp.status().is_success());
 
-        let _ = std::fs::remove_file(db_path);
+        let list: Value = app
+            .admin(Method::GET, "/api/logs?per_page=10")
+            .send()
+            .await
+            .expect("list logs")
+            .json()
+            .await
+            .expect("list json");
+        let id = list["items"][0]["id"].as_i64().expect("log id");
+        let public_id = list["items"][0]["public_id"]
+            .as_str()
+            .expect("public id")
+            .to_string();
+        assert!(crate::is_uuid(&public_id));
+        assert_eq!(&public_id[14..15], "7");
+
+        let detail: Value = app
+            .admin(Method::GET, &format!("/api/logs/{public_id}"))
+            .send()
+            .await
+            .expect("log detail")
+            .json()
+            .await
+            .expect("detail json");
+        assert_eq!(detail["id"], id);
+        assert_eq!(detail["public_id"], public_id.as_str());
+
+        let unknown = app
+            .admin(
+                Method::GET,
+                "/api/logs/00000000-0000-7000-8000-000000000000",
+            )
+            .send()
+            .await
+            .expect("unknown id");
+        assert_eq!(unknown.status(), StatusCode::NOT_FOUND);
+        let bad = app
+            .admin(Method::GET, "/api/logs/not-an-id")
+            .send()
+            .await
+            .expect("bad id");
+        assert_eq!(bad.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn jsonrpc_envelope_validation_modes() {
+        use JsonRpcValidation::{Lenient, Off, Strict};
+
+        let ok = br#"{"jsonrpc":"2.0","id":1,"method":"tools/list"}"#;
+        let notification = br#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#;
+        let response = br#"{"jsonrpc":"2.0","id":"s-1","result":{}}"#;
+        let batch = br#"[{"jsonrpc":"2.0","id":1,"method":"ping"}]"#;
+        for mode in [Lenient, Strict] {
+            assert!(validate_jsonrpc_envelope(ok, mode).is_ok());
+            assert!(validate_jsonrpc_envelope(notification, mode).is_ok());
+            assert!(validate_jsonrpc_envelope(response, mode).is_ok());
+        }
+        assert!(validate_jsonrpc_envelope(batch, Lenient).is_ok());
+        assert!(validate_jsonrpc_envelope(batch, Strict).is_err());
+
+        let parse = validate_jsonrpc_envelope(b"{not json", Lenient).unwrap_err();
+        assert_eq!(parse.code, JSONRPC_PARSE_ERROR);
+        assert!(validate_jsonrpc_envelope(b"{not json", Off).is_ok());
+
+        for bad in [
+            &br#"{}"#[..],
+            br#"[]"#,
+            br#""tools/list""#,
+            br#"{"jsonrpc":"1.0","id":1,"method":"ping"}"#,
+            br#"{"jsonrpc":"2.0","id":1,"method":7}"#,
+            br#"{"jsonrpc":"2.0","id":1}"#,
+        ] {
+            let err = validate_jsonrpc_envelope(bad, Lenient).unwrap_err();
+            assert_eq!(err.code, JSONRPC_INVALID_REQUEST);
+        }
+        let err =
+            validate_jsonrpc_envelope(br#"{"jsonrpc":"1.0","id":9,"method":"ping"}"#, Lenient)
+                .unwrap_err();
+        assert_eq!(err.id, json!(9));
+
+        // Only strict mode inspects member shapes.
+        for loose in [
+            &br#"{"jsonrpc":"2.0","id":{"x":1},"method":"ping"}"#[..],
+            br#"{"jsonrpc":"2.0","id":1,"method":"ping","params":"x"}"#,
+            br#"{"jsonrpc":"2.0","id":1,"method":""}"#,
+        ] {
+            assert!(validate_jsonrpc_envelope(loose, Lenient).is_ok());
+            assert!(validate_jsonrpc_envelope(loose, Strict).is_err());
+        }
     }
 
     #[tokio::test]
-    async fn api_keys_batch_returns_403_for_non_admin() {
-        let db_path = temp_db_path("keys-batch-403-non-admin");
-        let db_str = db_path.to_string_lossy().to_string();
+    async fn mcp_rejects_malformed_jsonrpc_before_leasing_a_key() {
+        let app = crate::test_util::TestApp::spawn(Default::default(), &["tvly-envelope-key"])
+            .await
+            .expect("spawn app");
+        let token = app.create_token().await.expect("token");
+
+        let resp = app
+            .client()
+            .post(app.url("/mcp"))
+            .bearer_auth(&token)
+            .header(CONTENT_TYPE.as_str(), "application/json")
+            .body(r#"{"id": 4, "method": "tools/call"}"#)
+            .send()
+            .await
+            .expect("malformed request");
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: Value = resp.json().await.expect("error json");
+        assert_eq!(body["jsonrpc"], "2.0");
+        assert_eq!(body["id"], 4);
+        assert_eq!(body["error"]["code"], JSONRPC_INVALID_REQUEST);
+
+        assert_eq!(app.upstream.hits(), 0);
+        let request_logs: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM request_logs")
+            .fetch_one(&app.proxy.key_store.pool)
+            .await
+            .expect("count request logs");
+        assert_eq!(request_logs, 0);
+        let (http_status, counts_quota): (i64, i64) = sqlx::query_as(
+            "SELECT http_status, counts_business_quota FROM auth_token_logs ORDER BY id DESC LIMIT 1",
+        )
+        .fetch_one(&app.proxy.key_store.pool)
+        .await
+        .expect("token log");
+        assert_eq!((http_status, counts_quota), (400, 0));
+    }
 
-        let proxy = TavilyProxy::with_endpoint(Vec::<String>::new(), DEFAULT_UPSTREAM, &db_str)
+    #[tokio::test]
+    async fn token_tool_policy_rejects_disallowed_tools() {
+        let app = crate::test_util::TestApp::spawn(Default::default(), &["tvly-tool-policy-key"])
             .await
-            .expect("proxy created");
+            .expect("spawn app");
+        let token = app.create_token().await.expect("token");
+        let token_id = token
+            .strip_prefix("th-")
+            .and_then(|rest| rest.split('-').next())
+            .expect("token id")
+            .to_string();
+        let stored = app
+            .proxy
+            .set_access_token_allowed_tools(&token_id, Some(&["tavily-search".to_string()]))
+            .await
+            .expect("set allowed tools");
+        assert_eq!(stored, Some(Some(vec!["search".to_string()])));
+
+        let call = |name: &str| {
+            json!({
+                "jsonrpc": "2.0",
+                "id": 9,
+                "method": "tools/call",
+                "params": { "name": name, "arguments": { "url": "https://example.com" } },
+            })
+        };
+        let resp = app
+            .client()
+            .post(app.url("/mcp"))
+            .bearer_auth(&token)
+            .json(&call("tavily-crawl"))
+            .send()
+            .await
+            .expect("crawl request");
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let body: Value = resp.json().await.expect("error json");
+        assert_eq!(body["id"], 9);
+        assert_eq!(body["error"]["code"], JSONRPC_TOOL_NOT_ALLOWED);
+        assert_eq!(body["error"]["data"]["tool"], "crawl");
+        assert_eq!(app.upstream.hits(), 0);
+
+        let resp = app
+            .client()
+            .post(app.url("/api/tavily/extract"))
+            .bearer_auth(&token)
+            .json(&json!({ "urls": ["https://example.com"] }))
+            .send()
+            .await
+            .expect("extract request");
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let body: Value = resp.json().await.expect("error json");
+        assert_eq!(body["error"], "tool_not_allowed");
+
+        let resp = app
+            .client()
+            .post(app.url("/mcp"))
+            .bearer_auth(&token)
+            .json(&call("tavily-search"))
+            .send()
+            .await
+            .expect("search request");
+        assert_ne!(resp.status(), StatusCode::FORBIDDEN);
+        assert_eq!(app.upstream.hits(), 1);
 
-        let forward_auth = ForwardAuthConfig::new(
-            Some(HeaderName::from_static("x-forward-user")),
-            Some("admin".to_string()),
-            None,
-            None,
-        );
-        let addr = spawn_keys_admin_server(proxy, forward_auth, false).await;
+        let (http_status, tool): (i64, Option<String>) = sqlx::query_as(
+            "SELECT http_status, tool FROM auth_token_logs WHERE http_status = 403 ORDER BY id LIMIT 1",
+        )
+        .fetch_one(&app.proxy.key_store.pool)
+        .await
+        .expect("rejection log");
+        assert_eq!((http_status, tool.as_deref()), (403, Some("crawl")));
+    }
 
-        let client = Client::new();
-        let url = format!("http://{}/api/keys/batch", addr);
-        let resp = client
-            .post(url)
-            .json(&serde_json::json!({ "api_keys": ["k1"] }))
+    #[tokio::test]
+    async fn mcp_responses_carry_configured_token_and_group_headers() {
+        let app = crate::test_util::TestApp::spawn(Default::default(), &["tvly-headers-key"])
+            .await
+            .expect("spawn app");
+        let token = app.create_token().await.expect("token");
+        let token_id = token
+            .strip_prefix("th-")
+            .and_then(|rest| rest.split('-').next())
+            .expect("token id")
+            .to_string();
+        sqlx::query("UPDATE auth_tokens SET group_name = 'partners' WHERE id = ?")
+            .bind(&token_id)
+            .execute(&app.proxy.key_store.pool)
+            .await
+            .expect("set group");
+
+        let group = app
+            .admin(Method::PUT, "/api/tokens/groups/partners/response-headers")
+            .json(&json!({ "headers": { "X-Partner-Id": "acme", "x-route": "group" } }))
+            .send()
+            .await
+            .expect("set group headers");
+        assert_eq!(group.status(), StatusCode::NO_CONTENT);
+        let own = app
+            .admin(
+                Method::PUT,
+                &format!("/api/tokens/{token_id}/response-headers"),
+            )
+            .json(&json!({ "headers": { "x-route": " token " } }))
+            .send()
+            .await
+            .expect("set token headers");
+        assert_eq!(own.status(), StatusCode::NO_CONTENT);
+        let reserved = app
+            .admin(
+                Method::PUT,
+                &format!("/api/tokens/{token_id}/response-headers"),
+            )
+            .json(&json!({ "headers": { "Content-Type": "text/plain" } }))
+            .send()
+            .await
+            .expect("reserved header");
+        assert_eq!(reserved.status(), StatusCode::BAD_REQUEST);
+
+        let view: Value = app
+            .admin(
+                Method::GET,
+                &format!("/api/tokens/{token_id}/response-headers"),
+            )
             .send()
             .await
-            .expect("request succeeds");
+            .expect("get headers")
+            .json()
+            .await
+            .expect("headers json");
+        assert_eq!(view["group"], "partners");
+        assert_eq!(
+            view["effective"],
+            json!({ "x-partner-id": "acme", "x-route": "token" })
+        );
 
-        assert_eq!(resp.status(), reqwest::StatusCode::FORBIDDEN);
+        let resp = app
+            .call_tool(&token, 1, "tavily-search", json!({ "query": "headers" }))
+            .await
+            .expect("tool call");
+        assert!(resp.status().is_success());
+        assert_eq!(resp.headers()["x-partner-id"], "acme");
+        assert_eq!(resp.headers()["x-route"], "token");
 
-        let _ = std::fs::remove_file(db_path);
+        let groups: Value = app
+            .admin(Method::GET, "/api/tokens/groups")
+            .send()
+            .await
+            .expect("groups")
+            .json()
+            .await
+            .expect("groups json");
+        let partners = groups
+            .as_array()
+            .and_then(|g| g.iter().find(|g| g["name"] == "partners"))
+            .expect("partners group");
+        assert_eq!(partners["responseHeaders"]["x-partner-id"], "acme");
     }
 
     #[tokio::test]
-    async fn api_keys_batch_rejects_over_limit() {
-        let db_path = temp_db_path("keys-batch-400-over-limit");
-        let db_str = db_path.to_string_lossy().to_string();
+    async fn cors_policy_applies_only_to_public_endpoints_for_allowed_origins() {
+        use crate::test_util::TestApp;
 
-        let proxy = TavilyProxy::with_endpoint(Vec::<String>::new(), DEFAULT_UPSTREAM, &db_str)
+        let _guard = crate::tests::env_lock().lock_owned().await;
+        unsafe {
+            std::env::set_var("CORS_ALLOWED_ORIGINS", "https://embed.example/");
+        }
+        let app = TestApp::spawn(Default::default(), &["tvly-cors-key"])
             .await
-            .expect("proxy created");
+            .expect("test app spawned");
+        unsafe {
+            std::env::remove_var("CORS_ALLOWED_ORIGINS");
+        }
 
-        let forward_auth = ForwardAuthConfig::new(
-            Some(HeaderName::from_static("x-forward-user")),
-            Some("admin".to_string()),
-            None,
-            None,
+        let preflight = app
+            .client()
+            .request(reqwest::Method::OPTIONS, app.url("/api/public/logs"))
+            .header("origin", "https://embed.example")
+            .header("access-control-request-method", "GET")
+            .send()
+            .await
+            .expect("preflight");
+        assert_eq!(preflight.status(), reqwest::StatusCode::NO_CONTENT);
+        let headers = preflight.headers();
+        assert_eq!(
+            headers["access-control-allow-origin"],
+            "https://embed.example"
         );
-        let addr = spawn_keys_admin_server(proxy, forward_auth, false).await;
-
-        let api_keys: Vec<String> = (0..=API_KEYS_BATCH_LIMIT)
-            .map(|i| format!("tvly-{i}"))
-            .collect();
-
-        let client = Client::new();
-        let url = format!("http://{}/api/keys/batch", addr);
-        let resp = client
-            .post(url)
-            .header("x-forward-user", "admin")
-            .json(&serde_json::json!({ "api_keys": api_keys }))
+        assert_eq!(headers["access-control-allow-methods"], "GET, OPTIONS");
+        assert_eq!(headers["access-control-allow-headers"], "content-type");
+        assert_eq!(headers["access-control-max-age"], "600");
+
+        let resp = app
+            .client()
+            .get(app.url("/api/public/logs"))
+            .header("origin", "https://embed.example")
             .send()
             .await
-            .expect("request succeeds");
-
-        assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
-
-        let body: serde_json::Value = resp.json().await.expect("parse json body");
+            .expect("public logs");
         assert_eq!(
-            body.get("error"),
-            Some(&serde_json::Value::String("too_many_items".into()))
+            resp.headers()["access-control-allow-origin"],
+            "https://embed.example"
         );
+        assert_eq!(resp.headers()["vary"], "origin");
 
-        let _ = std::fs::remove_file(db_path);
+        let resp = app
+            .client()
+            .get(app.url("/api/public/logs"))
+            .header("origin", "https://evil.example")
+            .send()
+            .await
+            .expect("public logs from other origin");
+        assert!(resp.headers().get("access-control-allow-origin").is_none());
+
+        let resp = app
+            .admin(reqwest::Method::GET, "/api/keys")
+            .header("origin", "https://embed.example")
+            .send()
+            .await
+            .expect("admin keys");
+        assert!(resp.headers().get("access-control-allow-origin").is_none());
     }
 
     #[tokio::test]
-    async fn api_keys_batch_reports_statuses_and_is_partial_success() {
-        let db_path = temp_db_path("keys-batch-mixed");
-        let db_str = db_path.to_string_lossy().to_string();
+    async fn admin_lists_revalidate_with_weak_etags() {
+        use crate::test_util::TestApp;
 
-        let proxy = TavilyProxy::with_endpoint(Vec::<String>::new(), DEFAULT_UPSTREAM, &db_str)
+        let app = TestApp::spawn(Default::default(), &["tvly-etag-key"])
             .await
-            .expect("proxy created");
+            .expect("test app spawned");
 
-        // Pre-create: one active existing key, and one soft-deleted key.
-        let _existing_id = proxy
-            .add_or_undelete_key("tvly-existing")
-            .await
-            .expect("existing key created");
-        let deleted_id = proxy
-            .add_or_undelete_key("tvly-deleted")
+        for path in ["/api/keys", "/api/tokens", "/api/summary"] {
+            let first = app
+                .admin(reqwest::Method::GET, path)
+                .send()
+                .await
+                .expect("first fetch");
+            assert_eq!(first.status(), reqwest::StatusCode::OK, "{path}");
+            let etag = first.headers()["etag"].to_str().unwrap().to_string();
+            assert!(etag.starts_with("W/\""), "{path}: {etag}");
+
+            let cached = app
+                .admin(reqwest::Method::GET, path)
+                .header("if-none-match", &etag)
+                .send()
+                .await
+                .expect("conditional fetch");
+            assert_eq!(cached.status(), reqwest::StatusCode::NOT_MODIFIED, "{path}");
+            assert_eq!(cached.headers()["etag"], etag.as_str());
+        }
+
+        let keys_etag = app
+            .admin(reqwest::Method::GET, "/api/keys")
+            .send()
             .await
-            .expect("deleted key created");
-        proxy
-            .soft_delete_key_by_id(&deleted_id)
+            .expect("keys")
+            .headers()["etag"]
+            .to_str()
+            .unwrap()
+            .to_string();
+        app.proxy
+            .add_or_undelete_key("tvly-etag-second")
             .await
-            .expect("key soft deleted");
-
-        // Create a trigger that forces a deterministic failure for one key.
-        let options = SqliteConnectOptions::new()
-            .filename(&db_str)
-            .create_if_missing(true)
-            .journal_mode(SqliteJournalMode::Wal)
-            .busy_timeout(Duration::from_secs(5));
-        let pool = SqlitePoolOptions::new()
-            .min_connections(1)
-            .max_connections(1)
-            .connect_with(options)
+            .expect("add key");
+        let resp = app
+            .admin(reqwest::Method::GET, "/api/keys")
+            .header("if-none-match", &keys_etag)
+            .send()
             .await
-            .expect("open db pool");
-        sqlx::query(
-            r#"
-            CREATE TRIGGER fail_insert_api_key
-            BEFORE INSERT ON api_keys
-            WHEN NEW.api_key = 'tvly-fail'
-            BEGIN
-                SELECT RAISE(ABORT, 'boom');
-            END;
-            "#,
-        )
-        .execute(&pool)
-        .await
-        .expect("create trigger");
+            .expect("keys after change");
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        let keys: Vec<serde_json::Value> = resp.json().await.expect("keys json");
+        assert_eq!(keys.len(), 2);
+    }
 
-        let forward_auth = ForwardAuthConfig::new(
-            Some(HeaderName::from_static("x-forward-user")),
-            Some("admin".to_string()),
-            None,
-            None,
-        );
-        let addr = spawn_keys_admin_server(proxy, forward_auth, false).await;
+    #[tokio::test]
+    async fn forward_auth_users_only_manage_their_own_keys_and_tokens() {
+        use crate::test_util::{ADMIN_USER, ADMIN_USER_HEADER, TestApp};
 
-        let input = vec![
-            "  tvly-new  ".to_string(),
-            "tvly-fail".to_string(),
-            "tvly-new-2".to_string(),
-            "tvly-existing".to_string(),
-            "tvly-deleted".to_string(),
-            "tvly-existing".to_string(),
-            "tvly-new-2".to_string(),
-            "".to_string(),
-            "   ".to_string(),
-        ];
+        let app = TestApp::spawn(Default::default(), &["tvly-shared-key"])
+            .await
+            .expect("test app spawned");
+        let as_user = |user: &str, method: Method, path: &str| {
+            app.client()
+                .request(method, app.url(path))
+                .header(ADMIN_USER_HEADER, user)
+        };
+        let shared_key: String = sqlx::query_scalar("SELECT id FROM api_keys LIMIT 1")
+            .fetch_one(&app.proxy.key_store.pool)
+            .await
+            .expect("shared key id");
 
-        let client = Client::new();
-        let url = format!("http://{}/api/keys/batch", addr);
-        let resp = client
-            .post(url)
-            .header("x-forward-user", "admin")
-            .json(&serde_json::json!({ "api_keys": input }))
+        let created: Value = as_user("alice", Method::POST, "/api/tokens")
+            .json(&json!({ "note": "alice" }))
             .send()
             .await
-            .expect("request succeeds");
-
-        assert_eq!(resp.status(), reqwest::StatusCode::OK);
-
-        let body: serde_json::Value = resp.json().await.expect("parse json body");
-        let summary = body.get("summary").expect("summary exists");
-        assert_eq!(summary.get("created").and_then(|v| v.as_u64()), Some(2));
-        assert_eq!(summary.get("undeleted").and_then(|v| v.as_u64()), Some(1));
-        assert_eq!(summary.get("existed").and_then(|v| v.as_u64()), Some(1));
+            .expect("create token")
+            .json()
+            .await
+            .expect("token body");
+        let token = created["token"].as_str().expect("token value");
+        let token_id = token.split('-').nth(1).expect("token id").to_string();
+
+        let list = |user: &'static str, path: &'static str| {
+            let request = as_user(user, Method::GET, path);
+            async move {
+                let resp = request.send().await.expect("list");
+                assert_eq!(resp.status(), StatusCode::OK);
+                resp.json::<Value>().await.expect("list body")
+            }
+        };
+        assert_eq!(list("alice", "/api/tokens").await["total"], 1);
+        assert_eq!(list("bob", "/api/tokens").await["total"], 0);
+        assert_eq!(list(ADMIN_USER, "/api/tokens").await["total"], 1);
+        let status = |user: &str, method: Method, path: String| {
+            let request = as_user(user, method, &path);
+            async move { request.send().await.expect("request").status() }
+        };
+        let token_path = format!("/api/tokens/{token_id}");
         assert_eq!(
-            summary.get("duplicate_in_input").and_then(|v| v.as_u64()),
-            Some(2)
+            status("alice", Method::GET, token_path.clone()).await,
+            StatusCode::OK
         );
-        assert_eq!(summary.get("failed").and_then(|v| v.as_u64()), Some(1));
         assert_eq!(
-            summary.get("ignored_empty").and_then(|v| v.as_u64()),
-            Some(2)
+            status("bob", Method::GET, token_path.clone()).await,
+            StatusCode::NOT_FOUND
         );
-
-        let results = body
-            .get("results")
-            .and_then(|v| v.as_array())
-            .expect("results array");
-        assert_eq!(results.len(), 7, "empty items are ignored in results");
-
-        let statuses: Vec<(&str, &str)> = results
-            .iter()
-            .map(|r| {
-                (
-                    r.get("api_key").and_then(|v| v.as_str()).unwrap_or(""),
-                    r.get("status").and_then(|v| v.as_str()).unwrap_or(""),
-                )
-            })
-            .collect();
         assert_eq!(
-            statuses,
-            vec![
-                ("tvly-new", "created"),
-                ("tvly-fail", "failed"),
-                ("tvly-new-2", "created"),
-                ("tvly-existing", "existed"),
-                ("tvly-deleted", "undeleted"),
-                ("tvly-existing", "duplicate_in_input"),
-                ("tvly-new-2", "duplicate_in_input"),
-            ]
+            status("bob", Method::DELETE, token_path.clone()).await,
+            StatusCode::NOT_FOUND
         );
 
-        // id is present only when we hit the DB successfully.
-        for (idx, expected_has_id) in [
-            (0, true),
-            (1, false),
-            (2, true),
-            (3, true),
-            (4, true),
-            (5, false),
-            (6, false),
-        ] {
-            let has_id = results[idx].get("id").and_then(|v| v.as_str()).is_some();
-            assert_eq!(has_id, expected_has_id, "result[{idx}] id presence");
+        // Only admins add keys to the pool; a user gets the same refusal whether or not the
+        // key is already pooled.
+        for api_key in ["tvly-alice-key", "tvly-shared-key"] {
+            let resp = as_user("alice", Method::POST, "/api/keys")
+                .json(&json!({ "api_key": api_key }))
+                .send()
+                .await
+                .expect("create key");
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+            assert_eq!(resp.bytes().await.expect("body").len(), 0);
         }
-
-        // error is required for failed.
-        let err = results[1]
-            .get("error")
-            .and_then(|v| v.as_str())
-            .unwrap_or("");
-        assert!(
-            err.contains("boom"),
-            "failed error should include trigger message"
+        let resp = as_user(ADMIN_USER, Method::POST, "/api/keys")
+            .json(&json!({ "api_key": "tvly-alice-key", "owner": "alice" }))
+            .send()
+            .await
+            .expect("create key");
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let alice_key = resp.json::<Value>().await.expect("key body")["id"]
+            .as_str()
+            .expect("key id")
+            .to_string();
+
+        let ids = |keys: Value| -> Vec<String> {
+            keys.as_array()
+                .expect("key array")
+                .iter()
+                .map(|key| key["id"].as_str().expect("id").to_string())
+                .collect()
+        };
+        assert_eq!(ids(list("alice", "/api/keys").await), [alice_key.as_str()]);
+        assert!(ids(list("bob", "/api/keys").await).is_empty());
+        assert_eq!(ids(list(ADMIN_USER, "/api/keys").await).len(), 2);
+        assert_eq!(
+            list("alice", "/api/keys?page=1").await["total"],
+            1,
+            "paged listing is scoped too"
+        );
+        assert_eq!(
+            status("alice", Method::GET, format!("/api/keys/{shared_key}")).await,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            status("alice", Method::GET, format!("/api/keys/{alice_key}")).await,
+            StatusCode::OK
         );
 
-        // DB side effects: soft-deleted key should be restored, failed key should not exist.
-        let deleted_at: Option<i64> =
-            sqlx::query_scalar("SELECT deleted_at FROM api_keys WHERE api_key = ?")
-                .bind("tvly-deleted")
-                .fetch_one(&pool)
-                .await
-                .expect("tvly-deleted exists");
-        assert!(deleted_at.is_none(), "tvly-deleted should be undeleted");
-
-        let fail_row: Option<String> =
-            sqlx::query_scalar("SELECT id FROM api_keys WHERE api_key = ?")
-                .bind("tvly-fail")
-                .fetch_optional(&pool)
-                .await
-                .expect("query fail key");
-        assert!(fail_row.is_none(), "tvly-fail should not be inserted");
-
-        let _ = std::fs::remove_file(db_path);
+        // Everything else stays admin-only, and anonymous callers are still rejected.
+        assert_eq!(
+            status("alice", Method::GET, "/api/jobs".to_string()).await,
+            StatusCode::FORBIDDEN
+        );
+        let resp = app
+            .client()
+            .get(app.url("/api/tokens"))
+            .send()
+            .await
+            .expect("anonymous list");
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
     }
 
     #[tokio::test]
-    async fn tavily_http_search_returns_401_for_invalid_token() {
-        let db_path = temp_db_path("http-search-401-invalid");
-        let db_str = db_path.to_string_lossy().to_string();
+    async fn admin_key_list_pages_filters_and_sorts() {
+        use crate::test_util::TestApp;
 
-        let proxy = TavilyProxy::with_endpoint(Vec::<String>::new(), DEFAULT_UPSTREAM, &db_str)
+        let app = TestApp::spawn(
+            Default::default(),
+            &["tvly-page-a", "tvly-page-b", "tvly-page-c"],
+        )
+        .await
+        .expect("test app spawned");
+        let pool = &app.proxy.key_store.pool;
+        let ids: Vec<String> = sqlx::query_scalar("SELECT id FROM api_keys ORDER BY id")
+            .fetch_all(pool)
             .await
-            .expect("proxy created");
-
-        let usage_base = "http://127.0.0.1:58088".to_string();
-        let proxy_addr = spawn_proxy_server(proxy, usage_base).await;
-
-        let client = Client::new();
-        let url = format!("http://{}/api/tavily/search", proxy_addr);
-        let resp = client
-            .post(url)
-            .header("Authorization", "Bearer th-invalid-token")
-            .json(&serde_json::json!({ "query": "test" }))
-            .send()
+            .expect("key ids");
+        // Error rates 50% / 10% / none; quota only synced for the first two keys.
+        for (id, total, errors, remaining) in
+            [(&ids[0], 10, 5, Some(100)), (&ids[1], 10, 1, Some(900))]
+        {
+            sqlx::query(
+                "INSERT INTO api_key_usage_buckets (api_key_id, bucket_start, bucket_secs, total_requests, success_count, error_count, quota_exhausted_count, updated_at) VALUES (?, 0, 86400, ?, ?, ?, 0, 0)",
+            )
+            .bind(id)
+            .bind(total)
+            .bind(total - errors)
+            .bind(errors)
+            .execute(pool)
             .await
-            .expect("request to proxy succeeds");
+            .expect("usage bucket");
+            sqlx::query("UPDATE api_keys SET quota_remaining = ? WHERE id = ?")
+                .bind(remaining)
+                .bind(id)
+                .execute(pool)
+                .await
+                .expect("quota");
+        }
+        sqlx::query("UPDATE api_keys SET status = 'disabled' WHERE id = ?")
+            .bind(&ids[2])
+            .execute(pool)
+            .await
+            .expect("disable key");
+
+        let list = |query: &'static str| {
+            let app = &app;
+            async move {
+                let resp = app
+                    .admin(reqwest::Method::GET, &format!("/api/keys{query}"))
+                    .send()
+                    .await
+                    .expect("list keys");
+                let status = resp.status();
+                (status, resp.json::<serde_json::Value>().await.ok())
+            }
+        };
+        let item_ids = |body: &serde_json::Value| -> Vec<String> {
+            body["items"]
+                .as_array()
+                .expect("items")
+                .iter()
+                .map(|item| item["id"].as_str().unwrap().to_string())
+                .collect()
+        };
+
+        // Without parameters the listing stays a bare array.
+        let (_, body) = list("").await;
+        assert_eq!(body.unwrap().as_array().map(Vec::len), Some(3));
 
-        assert_eq!(resp.status(), reqwest::StatusCode::UNAUTHORIZED);
+        let (_, body) = list("?page=2&per_page=2").await;
+        let body = body.unwrap();
+        assert_eq!(body["total"], 3);
+        assert_eq!(body["page"], 2);
+        assert_eq!(body["perPage"], 2);
+        assert_eq!(item_ids(&body).len(), 1);
 
-        let body: serde_json::Value = resp.json().await.expect("parse json body");
+        let (_, body) = list("?sort=error_rate&order=desc").await;
         assert_eq!(
-            body.get("error"),
-            Some(&serde_json::Value::String(
-                "invalid or disabled token".into()
-            ))
+            item_ids(&body.unwrap()),
+            [ids[0].as_str(), ids[1].as_str(), ids[2].as_str()]
+        );
+        let (_, body) = list("?sort=error_rate").await;
+        assert_eq!(
+            item_ids(&body.unwrap()),
+            [ids[1].as_str(), ids[0].as_str(), ids[2].as_str()]
+        );
+        let (_, body) = list("?sort=quota_remaining&order=desc").await;
+        assert_eq!(
+            item_ids(&body.unwrap()),
+            [ids[1].as_str(), ids[0].as_str(), ids[2].as_str()]
         );
 
-        let _ = std::fs::remove_file(db_path);
+        let (_, body) = list("?status=disabled").await;
+        let body = body.unwrap();
+        assert_eq!(body["total"], 1);
+        assert_eq!(item_ids(&body), [ids[2].as_str()]);
+
+        let needle = &ids[1][1..];
+        let resp = app
+            .admin(reqwest::Method::GET, &format!("/api/keys?q={needle}"))
+            .send()
+            .await
+            .expect("search keys");
+        let body: serde_json::Value = resp.json().await.expect("search json");
+        assert!(item_ids(&body).contains(&ids[1]));
+
+        let (status, _) = list("?sort=bogus").await;
+        assert_eq!(status, reqwest::StatusCode::BAD_REQUEST);
+        let (status, _) = list("?order=sideways").await;
+        assert_eq!(status, reqwest::StatusCode::BAD_REQUEST);
     }
 
     #[tokio::test]
-    async fn tavily_http_search_returns_429_when_quota_exhausted_and_logs_token_attempt() {
-        let db_path = temp_db_path("http-search-429-quota");
-        let db_str = db_path.to_string_lossy().to_string();
+    async fn openapi_spec_lists_every_routed_api_operation() {
+        use tower::ServiceExt;
+
+        // Everything under /api, /v1 and /claim goes through `api_routes`, where each route is
+        // registered from its handler's annotation; only the optional Swagger UI bypasses it.
+        let source = include_str!("server.rs");
+        let start = source.find("fn api_routes(").expect("api_routes");
+        let end = start
+            + source[start..]
+                .find("router = router\n        .route(\"/mcp\"")
+                .expect("end of build_router routes");
+        let unregistered: Vec<&str> = source[start..end]
+            .split(".route(\"")
+            .skip(1)
+            .filter_map(|chunk| chunk.split('"').next())
+            .filter(|path| {
+                ["/api/", "/v1/", "/claim/", "/health"]
+                    .iter()
+                    .any(|prefix| path.starts_with(prefix))
+                    && *path != "/api/docs"
+            })
+            .collect();
+        assert_eq!(
+            unregistered,
+            Vec::<&str>::new(),
+            "routes missing from the spec"
+        );
 
+        let db_path = temp_db_path("openapi-routes");
+        let db_str = db_path.to_string_lossy().to_string();
         let proxy = TavilyProxy::with_endpoint(Vec::<String>::new(), DEFAULT_UPSTREAM, &db_str)
             .await
             .expect("proxy created");
-        let token = proxy
-            .create_access_token(Some("quota-test"))
-            .await
-            .expect("create token");
-
-        // Pre-saturate hourly quota so that the next check in handler will block.
-        let hourly_limit = effective_token_hourly_limit();
-        for _ in 0..hourly_limit {
-            let verdict = proxy
-                .check_token_quota(&token.id)
+        let state = Arc::new(AppState {
+            proxy,
+            static_dir: None,
+            forward_auth: ForwardAuthConfig::new(None, None, None, None),
+            dev_open_admin: false,
+            usage_base: DEFAULT_UPSTREAM.to_string(),
+            access_log: None,
+            cors: None,
+            public_quota: Arc::new(PublicIpQuota::new(0)),
+            rate_limiter: None,
+            trusted_proxies: Arc::new(TrustedProxies::default()),
+        });
+        let spec = openapi_document(&state.forward_auth);
+        let router = build_router(state);
+
+        // An undocumented method on a routed path answers 405 with the routed methods in
+        // `Allow`, without running any handler; an unrouted path falls through to 404.
+        let paths = spec["paths"].as_object().expect("paths");
+        assert!(paths.len() > 100);
+        for (path, item) in paths {
+            if path == "/mcp" {
+                continue;
+            }
+            let mut documented: Vec<String> = item
+                .as_object()
+                .expect("path item")
+                .keys()
+                .map(|method| method.to_ascii_uppercase())
+                .collect();
+            documented.sort();
+            let uri = path
+                .split('/')
+                .map(|segment| {
+                    if segment.starts_with('{') {
+                        "x"
+                    } else {
+                        segment
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("/");
+            let response = router
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method(Method::TRACE)
+                        .uri(&uri)
+                        .body(Body::empty())
+                        .unwrap(),
+                )
                 .await
-                .expect("quota check ok");
-            assert!(
-                verdict.allowed,
-                "should be allowed within limit during warmup"
+                .unwrap();
+            assert_eq!(
+                response.status(),
+                StatusCode::METHOD_NOT_ALLOWED,
+                "{path} is documented but not routed"
             );
+            let allow = response
+                .headers()
+                .get(axum::http::header::ALLOW)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default();
+            let mut routed: Vec<String> = allow
+                .split(',')
+                .map(str::trim)
+                .filter(|method| !method.is_empty() && *method != "HEAD")
+                .map(str::to_string)
+                .collect();
+            routed.sort();
+            assert_eq!(routed, documented, "methods of {path}");
         }
 
-        let usage_base = "http://127.0.0.1:58088".to_string();
-        let proxy_addr = spawn_proxy_server(proxy.clone(), usage_base).await;
+        let _ = std::fs::remove_file(db_path);
+    }
 
-        let client = Client::new();
-        let url = format!("http://{}/api/tavily/search", proxy_addr);
-        let resp = client
-            .post(url)
-            .header("Authorization", format!("Bearer {}", token.token))
-            .json(&serde_json::json!({ "query": "test quota" }))
+    #[tokio::test]
+    async fn openapi_spec_and_optional_swagger_ui_are_served() {
+        use crate::test_util::TestApp;
+
+        let _guard = crate::tests::env_lock().lock_owned().await;
+        let previous = std::env::var("SWAGGER_UI_ENABLED").ok();
+        unsafe {
+            std::env::remove_var("SWAGGER_UI_ENABLED");
+        }
+        let app = TestApp::spawn(Default::default(), &["tvly-openapi-key"])
+            .await
+            .expect("test app spawned");
+        let spec: Value = app
+            .client()
+            .get(app.url("/api/openapi.json"))
             .send()
             .await
-            .expect("request to proxy succeeds");
-
-        assert_eq!(resp.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
-
-        let body: serde_json::Value = resp.json().await.expect("parse json body");
+            .expect("openapi")
+            .json()
+            .await
+            .expect("openapi json");
+        assert_eq!(spec["openapi"], "3.1.0");
+        let delete_key = &spec["paths"]["/api/keys/{id}"]["delete"];
+        assert_eq!(delete_key["security"][0]["forwardAuth"], json!([]));
+        assert_eq!(delete_key["parameters"][0]["in"], "path");
+        let list_keys = &spec["paths"]["/api/keys"]["get"];
+        assert!(
+            list_keys["parameters"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .any(|param| param["name"] == "sort" && param["in"] == "query")
+        );
+        let token_log = &spec["paths"]["/api/tokens/{id}/logs/{log_id}"]["get"];
+        let names: Vec<&str> = token_log["parameters"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|param| param["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["id", "log_id"]);
         assert_eq!(
-            body.get("error"),
-            Some(&serde_json::Value::String("quota_exhausted".into()))
+            spec["paths"]["/api/tavily/search"]["post"]["security"][0]["hikariToken"],
+            json!([])
         );
+        let docs = app
+            .client()
+            .get(app.url("/api/docs"))
+            .header("accept", "application/json")
+            .send()
+            .await
+            .expect("docs disabled");
+        assert_eq!(docs.status(), reqwest::StatusCode::NOT_FOUND);
 
-        // Verify token logs contain a quota_exhausted entry with HTTP 429.
-        let options = SqliteConnectOptions::new()
-            .filename(&db_str)
-            .create_if_missing(true)
-            .journal_mode(SqliteJournalMode::Wal)
-            .busy_timeout(Duration::from_secs(5));
-        let pool = SqlitePoolOptions::new()
-            .min_connections(1)
-            .max_connections(5)
-            .connect_with(options)
+        unsafe {
+            std::env::set_var("SWAGGER_UI_ENABLED", "true");
+        }
+        let app = TestApp::spawn(Default::default(), &["tvly-openapi-key"])
             .await
-            .expect("connect to sqlite");
+            .expect("test app with swagger ui");
+        let docs = app
+            .client()
+            .get(app.url("/api/docs"))
+            .send()
+            .await
+            .expect("docs");
+        assert_eq!(docs.status(), reqwest::StatusCode::OK);
+        assert!(docs.text().await.unwrap().contains("/api/openapi.json"));
 
-        let row = sqlx::query(
-            r#"
-            SELECT http_status, mcp_status, result_status
-            FROM auth_token_logs
-            WHERE token_id = ?
-            ORDER BY id DESC
-            LIMIT 1
-            "#,
+        unsafe {
+            match previous {
+                Some(value) => std::env::set_var("SWAGGER_UI_ENABLED", value),
+                None => std::env::remove_var("SWAGGER_UI_ENABLED"),
+            }
+        }
+    }
+    #[tokio::test]
+    async fn key_lookup_matches_full_or_prefix_secret_without_revealing_keys() {
+        use crate::test_util::TestApp;
+
+        let app = TestApp::spawn(
+            Default::default(),
+            &["tvly-lookup-alpha-0001", "tvly-lookup-beta-0002"],
         )
-        .bind(&token.id)
-        .fetch_one(&pool)
         .await
-        .expect("token log row exists");
+        .expect("test app spawned");
+        let lookup = |secret: &str| {
+            app.admin(reqwest::Method::POST, "/api/keys/lookup")
+                .json(&serde_json::json!({ "secret": secret }))
+                .send()
+        };
 
-        let http_status: Option<i64> = row.try_get("http_status").unwrap();
-        let mcp_status: Option<i64> = row.try_get("mcp_status").unwrap();
-        let result_status: String = row.try_get("result_status").unwrap();
+        let resp = lookup("tvly-lookup-alpha-0001").await.expect("full lookup");
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        let raw = resp.text().await.expect("body");
+        assert!(!raw.contains("tvly-lookup"));
+        let body: serde_json::Value = serde_json::from_str(&raw).expect("json");
+        let matches = body["matches"].as_array().expect("matches");
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0]["status"], "active");
+        assert_eq!(matches[0]["exact"], true);
+
+        let body: serde_json::Value = lookup("tvly-lookup-")
+            .await
+            .expect("prefix lookup")
+            .json()
+            .await
+            .expect("json");
+        assert_eq!(body["matches"].as_array().map(Vec::len), Some(2));
 
-        assert_eq!(http_status, Some(429));
-        assert_eq!(mcp_status, None);
-        assert_eq!(result_status, "quota_exhausted");
+        let body: serde_json::Value = lookup("tvly-lookup-gamma")
+            .await
+            .expect("unknown lookup")
+            .json()
+            .await
+            .expect("json");
+        assert_eq!(body["matches"].as_array().map(Vec::len), Some(0));
 
-        let _ = std::fs::remove_file(db_path);
+        let resp = lookup("tvly-").await.expect("short lookup");
+        assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
     }
 
     #[tokio::test]
-    async fn tavily_http_search_hourly_any_limit_429_is_non_billable_and_excluded_from_rollup() {
-        let db_path = temp_db_path("http-search-hourly-any-nonbillable");
-        let db_str = db_path.to_string_lossy().to_string();
+    async fn token_lookup_resolves_partial_tokens_without_revealing_secrets() {
+        use crate::test_util::TestApp;
 
-        // Preserve any existing env value to avoid cross-test leakage.
-        let previous_limit = std::env::var("TOKEN_HOURLY_REQUEST_LIMIT").ok();
-        unsafe {
-            std::env::set_var("TOKEN_HOURLY_REQUEST_LIMIT", "1");
-        }
+        let app = TestApp::spawn(Default::default(), &["tvly-token-lookup"])
+            .await
+            .expect("test app spawned");
+        let token = app.create_token().await.expect("token");
+        let (id, secret) = token
+            .strip_prefix("th-")
+            .and_then(|rest| rest.split_once('-'))
+            .expect("token parts");
+        let lookup = |prefix: String| {
+            app.admin(reqwest::Method::GET, "/api/tokens/lookup")
+                .query(&[("prefix", prefix)])
+                .send()
+        };
 
-        let expected_api_key = "tvly-http-search-hourly-any-key";
-        let proxy = TavilyProxy::with_endpoint(
-            vec![expected_api_key.to_string()],
-            DEFAULT_UPSTREAM,
-            &db_str,
-        )
-        .await
-        .expect("proxy created");
+        let resp = lookup(format!("th-{id}")).await.expect("id lookup");
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        let body: serde_json::Value = resp.json().await.expect("json");
+        let matches = body["matches"].as_array().expect("matches");
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0]["id"], id);
+        assert_eq!(matches[0]["note"], "test-util");
+        assert_eq!(matches[0]["deleted"], false);
+        assert!(matches[0]["secret_match"].is_null());
+
+        let resp = lookup(token.clone()).await.expect("full lookup");
+        let raw = resp.text().await.expect("body");
+        assert!(!raw.contains(secret));
+        let body: serde_json::Value = serde_json::from_str(&raw).expect("json");
+        assert_eq!(body["matches"][0]["secret_match"], "exact");
+
+        let body: serde_json::Value = lookup(format!("th-{id}-{}", &secret[..6]))
+            .await
+            .expect("partial lookup")
+            .json()
+            .await
+            .expect("json");
+        assert_eq!(body["matches"][0]["secret_match"], "unverified");
 
-        let access_token = proxy
-            .create_access_token(Some("hourly-any-e2e"))
+        let wrong = format!("th-{id}-{}", "x".repeat(secret.len()));
+        let body: serde_json::Value = lookup(wrong)
             .await
-            .expect("create token");
+            .expect("wrong lookup")
+            .json()
+            .await
+            .expect("json");
+        assert_eq!(body["matches"].as_array().map(Vec::len), Some(0));
 
-        let upstream_addr =
-            spawn_http_search_mock_asserting_api_key(expected_api_key.to_string()).await;
-        let usage_base = format!("http://{}", upstream_addr);
-        let proxy_addr = spawn_proxy_server(proxy.clone(), usage_base).await;
+        let resp = lookup("th-a".to_string()).await.expect("short lookup");
+        assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+    }
 
-        let client = Client::new();
-        let url = format!("http://{}/api/tavily/search", proxy_addr);
+    #[tokio::test]
+    async fn admins_annotate_request_and_token_logs() {
+        use crate::test_util::TestApp;
 
-        // 1st request should pass and hit mock upstream.
-        let first = client
-            .post(url.clone())
-            .json(&serde_json::json!({
-                "api_key": access_token.token,
-                "query": "hourly-any smoke"
-            }))
-            .send()
+        let app = TestApp::spawn(Default::default(), &["tvly-annotate-key"])
             .await
-            .expect("first request succeeds");
-        assert!(
-            first.status().is_success(),
-            "first request should be allowed, got {}",
-            first.status()
-        );
+            .expect("test app spawned");
+        let token = app.create_token().await.expect("token");
+        let token_id = token
+            .strip_prefix("th-")
+            .and_then(|rest| rest.split('-').next())
+            .expect("token id")
+            .to_string();
+        let resp = app
+            .call_tool(
+                &token,
+                1,
+                "tavily-search",
+                serde_json::json!({ "query": "q" }),
+            )
+            .await
+            .expect("tool call");
+        assert!(resp.status().is_success());
 
-        // 2nd request should be blocked by hourly-any limiter before upstream.
-        let second = client
-            .post(url)
-            .json(&serde_json::json!({
-                "api_key": access_token.token,
-                "query": "hourly-any blocked"
-            }))
+        let logs: serde_json::Value = app
+            .admin(reqwest::Method::GET, "/api/logs?per_page=10")
             .send()
             .await
-            .expect("second request succeeds");
-        assert_eq!(
-            second.status(),
-            reqwest::StatusCode::TOO_MANY_REQUESTS,
-            "expected hourly-any 429 on second request"
-        );
-
-        // Inspect latest auth_token_logs row for hourly-any 429.
-        let options = SqliteConnectOptions::new()
-            .filename(&db_str)
-            .create_if_missing(true)
-            .journal_mode(SqliteJournalMode::Wal)
-            .busy_timeout(Duration::from_secs(5));
-        let pool = SqlitePoolOptions::new()
-            .min_connections(1)
-            .max_connections(5)
-            .connect_with(options)
+            .expect("logs")
+            .json()
             .await
-            .expect("connect to sqlite");
+            .expect("logs json");
+        let request_log_id = logs["items"][0]["id"].as_i64().expect("request log id");
+        let token_logs: serde_json::Value = app
+            .admin(
+                reqwest::Method::GET,
+                &format!("/api/tokens/{token_id}/logs"),
+            )
+            .send()
+            .await
+            .expect("token logs")
+            .json()
+            .await
+            .expect("token logs json");
+        let token_log_id = token_logs[0]["id"].as_i64().expect("token log id");
 
-        let row = sqlx::query(
-            r#"
-            SELECT http_status, counts_business_quota
-            FROM auth_token_logs
-            WHERE token_id = ?
-            ORDER BY id DESC
-            LIMIT 1
-            "#,
+        let annotate = |path: String, note: &str| {
+            app.admin(reqwest::Method::POST, &path)
+                .json(&serde_json::json!({ "note": note }))
+                .send()
+        };
+        let resp = annotate(
+            format!("/api/logs/{request_log_id}/annotations"),
+            "reported by customer X, ticket 123",
         )
-        .bind(&access_token.id)
-        .fetch_one(&pool)
         .await
-        .expect("token log row exists");
-
-        let http_status: Option<i64> = row.try_get("http_status").unwrap();
-        let counts_business_quota: i64 = row.try_get("counts_business_quota").unwrap();
-        assert_eq!(
-            http_status,
-            Some(StatusCode::TOO_MANY_REQUESTS.as_u16() as i64),
-            "latest log should be hourly-any 429"
-        );
-        assert_eq!(
-            counts_business_quota, 0,
-            "hourly-any limiter blocks should be non-billable"
-        );
+        .expect("annotate request log");
+        assert_eq!(resp.status(), reqwest::StatusCode::CREATED);
+        let created: serde_json::Value = resp.json().await.expect("annotation json");
+        assert!(created["author"].is_string());
+
+        let resp = annotate(
+            format!("/api/tokens/{token_id}/logs/{token_log_id}/annotations"),
+            "same incident",
+        )
+        .await
+        .expect("annotate token log");
+        assert_eq!(resp.status(), reqwest::StatusCode::CREATED);
 
-        // Roll up and verify billable totals only include the first request.
-        let _ = proxy
-            .rollup_token_usage_stats()
+        let resp = annotate(format!("/api/logs/{request_log_id}/annotations"), "  ")
             .await
-            .expect("rollup token usage stats");
-        let summary = proxy
-            .token_summary_since(&access_token.id, 0, None)
+            .expect("empty note");
+        assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+        let resp = annotate("/api/logs/999999/annotations".to_string(), "missing")
             .await
-            .expect("summary since");
+            .expect("missing log");
+        assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
 
-        assert_eq!(
-            summary.total_requests, 1,
-            "billable totals should count only successful first request"
-        );
-        assert_eq!(summary.success_count, 1);
-        assert_eq!(
-            summary.quota_exhausted_count, 0,
-            "hourly-any 429 should not be included in billable totals"
+        let detail: serde_json::Value = app
+            .admin(reqwest::Method::GET, &format!("/api/logs/{request_log_id}"))
+            .send()
+            .await
+            .expect("log detail")
+            .json()
+            .await
+            .expect("log detail json");
+        assert_eq!(
+            detail["annotations"][0]["note"],
+            "reported by customer X, ticket 123"
         );
+        let detail: serde_json::Value = app
+            .admin(
+                reqwest::Method::GET,
+                &format!("/api/tokens/{token_id}/logs/{token_log_id}"),
+            )
+            .send()
+            .await
+            .expect("token log detail")
+            .json()
+            .await
+            .expect("token log detail json");
+        assert_eq!(detail["annotations"][0]["note"], "same incident");
 
-        unsafe {
-            if let Some(prev) = previous_limit {
-                std::env::set_var("TOKEN_HOURLY_REQUEST_LIMIT", prev);
-            } else {
-                std::env::remove_var("TOKEN_HOURLY_REQUEST_LIMIT");
-            }
-        }
-
-        let _ = std::fs::remove_file(db_path);
+        let resp = app
+            .admin(
+                reqwest::Method::DELETE,
+                &format!("/api/log-annotations/{}", created["id"]),
+            )
+            .send()
+            .await
+            .expect("delete annotation");
+        assert_eq!(resp.status(), reqwest::StatusCode::NO_CONTENT);
+        let detail: serde_json::Value = app
+            .admin(reqwest::Method::GET, &format!("/api/logs/{request_log_id}"))
+            .send()
+            .await
+            .expect("log detail")
+            .json()
+            .await
+            .expect("log detail json");
+        assert_eq!(detail["annotations"].as_array().map(Vec::len), Some(0));
     }
 
     #[tokio::test]
-    async fn tavily_http_search_replaces_body_api_key_with_tavily_key() {
-        let db_path = temp_db_path("http-search-replace-key");
+    async fn mcp_sse_replies_are_streamed_before_upstream_finishes() {
+        let db_path = temp_db_path("mcp-sse-streaming");
         let db_str = db_path.to_string_lossy().to_string();
 
-        let expected_api_key = "tvly-http-search-key";
+        let release = Arc::new(tokio::sync::Notify::new());
+        let app = Router::new().route(
+            "/mcp",
+            any({
+                let release = release.clone();
+                move || {
+                    let release = release.clone();
+                    async move {
+                        let body = stream! {
+                            yield Ok::<_, std::io::Error>(bytes::Bytes::from_static(
+                                b"event: message\ndata: {\"jsonrpc\":\"2.0\",\"method\":\"notifications/progress\"}\n\n",
+                            ));
+                            release.notified().await;
+                            yield Ok(bytes::Bytes::from_static(
+                                b"event: message\ndata: {\"jsonrpc\":\"2.0\",\"id\":1,\"result\":{\"structuredContent\":{\"status\":200}}}\n\n",
+                            ));
+                        };
+                        Response::builder()
+                            .header(CONTENT_TYPE, "text/event-stream")
+                            .body(Body::from_stream(body))
+                            .unwrap()
+                    }
+                }
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app.into_make_service())
+                .await
+                .unwrap();
+        });
+
         let proxy = TavilyProxy::with_endpoint(
-            vec![expected_api_key.to_string()],
-            DEFAULT_UPSTREAM,
+            vec!["tvly-sse-stream-key".to_string()],
+            &format!("http://{upstream_addr}/mcp"),
             &db_str,
         )
         .await
         .expect("proxy created");
-
-        let access_token = proxy
-            .create_access_token(Some("http-search"))
+        let token = proxy
+            .create_access_token(Some("sse-stream"))
             .await
             .expect("create token");
+        let proxy_addr = spawn_proxy_server(proxy.clone(), DEFAULT_UPSTREAM.to_string()).await;
 
-        let upstream_addr =
-            spawn_http_search_mock_asserting_api_key(expected_api_key.to_string()).await;
-        let usage_base = format!("http://{}", upstream_addr);
-
-        let proxy_addr = spawn_proxy_server(proxy.clone(), usage_base).await;
-
-        let client = Client::new();
-        let url = format!("http://{}/api/tavily/search", proxy_addr);
-        let resp = client
-            .post(url)
+        let mut resp = Client::new()
+            .post(format!("http://{proxy_addr}/mcp"))
+            .bearer_auth(&token.token)
+            .header("accept", "application/json, text/event-stream")
             .json(&serde_json::json!({
-                "api_key": access_token.token,
-                "query": "hello world"
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "tools/call",
+                "params": { "name": "tavily-search", "arguments": { "query": "rust" } },
             }))
             .send()
             .await
             .expect("request to proxy succeeds");
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        assert!(
+            resp.headers()
+                .get(reqwest::header::CONTENT_LENGTH)
+                .is_none()
+        );
 
-        assert!(resp.status().is_success());
-        let body: serde_json::Value = resp.json().await.expect("parse json body");
-        assert_eq!(body.get("status").and_then(|v| v.as_i64()), Some(200));
-
-        // Verify request_logs entry has success status, structured status, and redacted bodies.
-        let options = SqliteConnectOptions::new()
-            .filename(&db_str)
-            .create_if_missing(true)
-            .journal_mode(SqliteJournalMode::Wal)
-            .busy_timeout(Duration::from_secs(5));
-        let pool = SqlitePoolOptions::new()
-            .min_connections(1)
-            .max_connections(5)
-            .connect_with(options)
+        let first = tokio::time::timeout(Duration::from_secs(5), resp.chunk())
             .await
-            .expect("connect to sqlite");
-
-        let row = sqlx::query(
-            r#"
-            SELECT request_body, response_body, result_status, tavily_status_code
-            FROM request_logs
-            ORDER BY id DESC
-            LIMIT 1
-            "#,
-        )
-        .fetch_one(&pool)
-        .await
-        .expect("request log row exists");
-
-        let request_body: Vec<u8> = row.try_get("request_body").unwrap();
-        let response_body: Vec<u8> = row.try_get("response_body").unwrap();
-        let result_status: String = row.try_get("result_status").unwrap();
-        let tavily_status_code: Option<i64> = row.try_get("tavily_status_code").unwrap();
-
-        let req_text = String::from_utf8_lossy(&request_body);
-        let resp_text = String::from_utf8_lossy(&response_body);
+            .expect("first frame arrives while upstream is still open")
+            .expect("chunk read")
+            .expect("chunk present");
+        assert!(String::from_utf8_lossy(&first).contains("notifications/progress"));
+
+        release.notify_one();
+        let mut rest = Vec::new();
+        while let Some(chunk) = resp.chunk().await.expect("chunk read") {
+            rest.extend_from_slice(&chunk);
+        }
+        assert!(String::from_utf8_lossy(&rest).contains("structuredContent"));
+
+        let logs = proxy.recent_request_logs(10).await.expect("request logs");
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].result_status, "success");
+        assert_eq!(logs[0].tavily_status_code, Some(200));
+        let log = proxy
+            .request_log(logs[0].id)
+            .await
+            .expect("request log")
+            .expect("log exists");
+        let body = String::from_utf8_lossy(&log.response_body);
+        assert!(body.contains("notifications/progress") && body.contains("structuredContent"));
 
-        assert_eq!(result_status, "success");
-        assert_eq!(tavily_status_code, Some(200));
-        assert!(
-            !req_text.contains(expected_api_key)
-                && !req_text.contains(&access_token.token)
-                && !resp_text.contains(expected_api_key)
-                && !resp_text.contains(&access_token.token),
-            "request/response logs must not contain raw api_key secrets",
-        );
-        assert!(
-            req_text.contains("***redacted***") || !req_text.contains("api_key"),
-            "api_key fields in request logs should be redacted",
-        );
+        let token_logs = proxy
+            .token_recent_logs(&token.id, 10, None)
+            .await
+            .expect("token logs");
+        assert_eq!(token_logs.len(), 1);
+        assert_eq!(token_logs[0].result_status, "success");
 
         let _ = std::fs::remove_file(db_path);
     }
 
     #[tokio::test]
-    async fn tavily_http_usage_returns_daily_and_monthly_counts() {
-        let db_path = temp_db_path("http-usage-view");
+    async fn quota_exhausted_mcp_calls_fail_over_to_another_key() {
+        let _guard = crate::tests::env_lock().lock_owned().await;
+        let db_path = temp_db_path("quota-failover");
         let db_str = db_path.to_string_lossy().to_string();
 
-        let expected_api_key = "tvly-http-usage-key";
+        // The first call is answered with 432 whichever key it carries, later ones succeed.
+        let hits = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let app = Router::new().route(
+            "/mcp",
+            any({
+                let hits = hits.clone();
+                move || {
+                    let hits = hits.clone();
+                    async move {
+                        if hits.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0 {
+                            return (StatusCode::from_u16(432).unwrap(), Body::from("{}"));
+                        }
+                        (
+                            StatusCode::OK,
+                            Body::from(
+                                r#"{"jsonrpc":"2.0","id":1,"result":{"structuredContent":{"status":200}}}"#,
+                            ),
+                        )
+                    }
+                }
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app.into_make_service())
+                .await
+                .unwrap();
+        });
+
+        unsafe {
+            std::env::set_var("QUOTA_FAILOVER_RETRIES", "2");
+        }
         let proxy = TavilyProxy::with_endpoint(
-            vec![expected_api_key.to_string()],
-            DEFAULT_UPSTREAM,
+            vec!["tvly-failover-a", "tvly-failover-b"],
+            &format!("http://{upstream_addr}/mcp"),
             &db_str,
         )
         .await
         .expect("proxy created");
-
-        let access_token = proxy
-            .create_access_token(Some("http-usage"))
+        unsafe {
+            std::env::remove_var("QUOTA_FAILOVER_RETRIES");
+        }
+        let token = proxy
+            .create_access_token(Some("failover"))
             .await
             .expect("create token");
+        let proxy_addr = spawn_proxy_server(proxy.clone(), DEFAULT_UPSTREAM.to_string()).await;
 
-        let upstream_addr =
-            spawn_http_search_mock_asserting_api_key(expected_api_key.to_string()).await;
-        let usage_base = format!("http://{}", upstream_addr);
-
-        let proxy_addr = spawn_proxy_server(proxy.clone(), usage_base).await;
-
-        // One successful /search call to generate request_logs + token_logs.
-        let client = Client::new();
-        let search_url = format!("http://{}/api/tavily/search", proxy_addr);
-        let _ = client
-            .post(search_url)
+        let resp = Client::new()
+            .post(format!("http://{proxy_addr}/mcp"))
+            .bearer_auth(&token.token)
             .json(&serde_json::json!({
-                "api_key": access_token.token,
-                "query": "usage metrics test"
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "tools/call",
+                "params": { "name": "tavily-search", "arguments": { "query": "rust" } },
             }))
             .send()
             .await
             .expect("request to proxy succeeds");
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        assert!(
+            resp.text()
+                .await
+                .expect("body")
+                .contains("structuredContent")
+        );
+        assert_eq!(hits.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+        let mut logs = proxy.recent_request_logs(10).await.expect("request logs");
+        logs.sort_by_key(|log| log.attempt);
+        assert_eq!(logs.len(), 2);
+        assert_eq!(logs[0].attempt, 1);
+        assert_eq!(logs[0].status_code, Some(432));
+        assert_eq!(logs[1].attempt, 2);
+        assert_eq!(logs[1].result_status, "success");
+        assert_ne!(logs[0].key_id, logs[1].key_id);
+
+        let keys = proxy.list_api_key_metrics().await.expect("key metrics");
+        let exhausted = keys
+            .iter()
+            .find(|key| key.id == logs[0].key_id)
+            .expect("exhausted key");
+        assert_eq!(exhausted.status, "exhausted");
 
-        // Manually record one quota_exhausted attempt for this token so that monthly_quota_exhausted > 0.
-        let method = Method::GET;
-        proxy
-            .record_token_attempt(
-                &access_token.id,
-                &method,
-                "/api/tavily/search",
-                None,
-                Some(StatusCode::TOO_MANY_REQUESTS.as_u16() as i64),
-                None,
-                true,
-                "quota_exhausted",
-                Some("test quota exhaustion"),
-            )
-            .await
-            .expect("record token attempt");
+        let _ = std::fs::remove_file(db_path);
+    }
 
-        // Roll up auth_token_logs into token_usage_stats for the usage summary.
-        let _ = proxy
-            .rollup_token_usage_stats()
+    #[tokio::test]
+    async fn api_rate_limit_buckets_clients_by_forward_auth_user_or_ip() {
+        use crate::test_util::{MockUpstreamConfig, TestApp};
+
+        let _guard = crate::tests::env_lock().lock_owned().await;
+        unsafe {
+            std::env::set_var("RATE_LIMIT_RPS", "0.001");
+            std::env::set_var("RATE_LIMIT_BURST", "2");
+            std::env::set_var("TRUSTED_PROXIES", "127.0.0.1");
+        }
+        let app = TestApp::spawn(MockUpstreamConfig::default(), &["tvly-rate-limit"])
             .await
-            .expect("rollup token usage stats");
+            .expect("test app spawned");
+        unsafe {
+            std::env::remove_var("RATE_LIMIT_RPS");
+            std::env::remove_var("RATE_LIMIT_BURST");
+            std::env::remove_var("TRUSTED_PROXIES");
+        }
 
-        // Query /api/tavily/usage.
-        let usage_url = format!("http://{}/api/tavily/usage", proxy_addr);
-        let resp = client
-            .get(usage_url)
-            .header("Authorization", format!("Bearer {}", access_token.token))
+        let fetch = |ip: &'static str| {
+            app.client()
+                .get(app.url("/api/public/metrics"))
+                .header("x-forwarded-for", ip)
+                .send()
+        };
+        for _ in 0..2 {
+            let resp = fetch("203.0.113.7").await.expect("public metrics");
+            assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        }
+        let resp = fetch("203.0.113.7").await.expect("public metrics");
+        assert_eq!(resp.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+        assert!(resp.headers().contains_key(reqwest::header::RETRY_AFTER));
+        let body: serde_json::Value = resp.json().await.expect("rate limit body");
+        assert_eq!(body["error"], "rate_limited");
+
+        let resp = fetch("203.0.113.8").await.expect("public metrics");
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+
+        // The admin is keyed by its forward-auth user, not by the IP it shares.
+        for _ in 0..2 {
+            let resp = app
+                .admin(reqwest::Method::GET, "/api/summary")
+                .header("x-forwarded-for", "203.0.113.7")
+                .send()
+                .await
+                .expect("summary");
+            assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        }
+        let resp = app
+            .admin(reqwest::Method::GET, "/api/summary")
             .send()
             .await
-            .expect("request to /api/tavily/usage succeeds");
-        let status = resp.status();
-        let text = resp.text().await.expect("read usage body");
+            .expect("summary");
+        assert_eq!(resp.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+    }
 
-        assert!(
-            status.is_success(),
-            "expected success from /api/tavily/usage, got status={} body={}",
-            status,
-            text
-        );
-        let body: serde_json::Value =
-            serde_json::from_str(&text).expect("parse json body from /api/tavily/usage");
+    #[tokio::test]
+    async fn api_rate_limit_ignores_forwarded_for_from_untrusted_peers() {
+        use crate::test_util::{MockUpstreamConfig, TestApp};
 
-        assert_eq!(
-            body.get("tokenId").and_then(|v| v.as_str()),
-            Some(access_token.id.as_str())
-        );
-        let daily_success = body
-            .get("dailySuccess")
-            .and_then(|v| v.as_i64())
-            .unwrap_or(-1);
-        let daily_error = body
-            .get("dailyError")
-            .and_then(|v| v.as_i64())
-            .unwrap_or(-1);
-        let monthly_success = body
-            .get("monthlySuccess")
-            .and_then(|v| v.as_i64())
-            .unwrap_or(-1);
-        let monthly_quota_exhausted = body
-            .get("monthlyQuotaExhausted")
-            .and_then(|v| v.as_i64())
-            .unwrap_or(-1);
+        let _guard = crate::tests::env_lock().lock_owned().await;
+        unsafe {
+            std::env::set_var("RATE_LIMIT_RPS", "0.001");
+            std::env::set_var("RATE_LIMIT_BURST", "2");
+        }
+        let app = TestApp::spawn(MockUpstreamConfig::default(), &["tvly-rate-limit-spoof"])
+            .await
+            .expect("test app spawned");
+        unsafe {
+            std::env::remove_var("RATE_LIMIT_RPS");
+            std::env::remove_var("RATE_LIMIT_BURST");
+        }
 
-        assert!(
-            daily_success >= 1,
-            "daily_success should be at least 1, got {daily_success}"
+        // No trusted proxies: every request is keyed on the TCP peer, whatever it claims.
+        for (i, spoofed) in ["203.0.113.1", "203.0.113.2", "203.0.113.3"]
+            .into_iter()
+            .enumerate()
+        {
+            let resp = app
+                .client()
+                .get(app.url("/api/public/metrics"))
+                .header("x-forwarded-for", spoofed)
+                .header("x-real-ip", spoofed)
+                .send()
+                .await
+                .expect("public metrics");
+            let expected = if i < 2 {
+                reqwest::StatusCode::OK
+            } else {
+                reqwest::StatusCode::TOO_MANY_REQUESTS
+            };
+            assert_eq!(resp.status(), expected);
+        }
+    }
+
+    #[test]
+    fn trusted_proxies_take_the_right_most_untrusted_hop() {
+        let trusted = TrustedProxies::parse("127.0.0.1, 10.0.0.0/8").expect("parse");
+        let proxy: SocketAddr = "127.0.0.1:4000".parse().unwrap();
+        let stranger: SocketAddr = "198.51.100.4:4000".parse().unwrap();
+        let headers = |xff: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                "x-forwarded-for",
+                axum::http::HeaderValue::from_str(xff).unwrap(),
+            );
+            headers
+        };
+
+        let spoofed = headers("1.1.1.1, 203.0.113.7, 10.0.0.2");
+        assert_eq!(
+            trusted.client_ip(&spoofed, Some(proxy)).as_deref(),
+            Some("203.0.113.7")
         );
-        assert_eq!(daily_error, 0, "no error requests expected in this test");
-        assert!(
-            monthly_success >= daily_success,
-            "monthly_success should be >= daily_success"
+        assert_eq!(
+            trusted.client_ip(&spoofed, Some(stranger)).as_deref(),
+            Some("198.51.100.4")
         );
-        assert!(
-            monthly_quota_exhausted >= 1,
-            "expected at least one quota_exhausted event, got {monthly_quota_exhausted}"
+        assert_eq!(
+            trusted
+                .client_ip(&headers("10.0.0.3, 10.0.0.2"), Some(proxy))
+                .as_deref(),
+            Some("10.0.0.3")
+        );
+        assert_eq!(
+            trusted
+                .client_ip(&headers("bogus, 10.0.0.2"), Some(proxy))
+                .as_deref(),
+            Some("10.0.0.2")
+        );
+        let mut real_ip = HeaderMap::new();
+        real_ip.insert(
+            "x-real-ip",
+            axum::http::HeaderValue::from_static("203.0.113.9"),
         );
+        assert_eq!(
+            trusted.client_ip(&real_ip, Some(proxy)).as_deref(),
+            Some("203.0.113.9")
+        );
+        assert!(TrustedProxies::parse("10.0.0.0/33").is_err());
+    }
 
-        let _ = std::fs::remove_file(db_path);
+    #[test]
+    fn public_quota_evicts_least_recently_seen_ips_when_full() {
+        let quota = PublicIpQuota::new(1);
+        let now = 7_200;
+        assert!(quota.admit("198.51.100.1", now));
+        for i in 1..PUBLIC_QUOTA_MAX_TRACKED_IPS {
+            assert!(quota.admit(
+                &format!("10.{}.{}.{}", i >> 16, (i >> 8) & 0xff, i & 0xff),
+                now
+            ));
+        }
+        // Touch the first IP again so it is the most recent entry.
+        assert!(!quota.admit("198.51.100.1", now));
+
+        // A newcomer gets its own counter instead of a shared overflow bucket.
+        assert!(quota.admit("203.0.113.1", now));
+        assert!(!quota.admit("203.0.113.1", now));
+        let view = quota.snapshot(now);
+        assert_eq!(
+            view.tracked_ips as usize,
+            PUBLIC_QUOTA_MAX_TRACKED_IPS - PUBLIC_QUOTA_EVICT_BATCH + 1
+        );
+        // The recently seen client kept its count; the oldest ones were dropped.
+        assert!(!quota.admit("198.51.100.1", now));
+        assert!(quota.admit("10.0.0.1", now));
     }
 
     #[tokio::test]
-    async fn tavily_http_extract_replaces_body_api_key_with_tavily_key() {
-        let db_path = temp_db_path("http-extract-replace-key");
-        let db_str = db_path.to_string_lossy().to_string();
-
-        let expected_api_key = "tvly-http-extract-key";
-        let proxy = TavilyProxy::with_endpoint(
-            vec![expected_api_key.to_string()],
-            DEFAULT_UPSTREAM,
-            &db_str,
-        )
-        .await
-        .expect("proxy created");
+    async fn public_metrics_enforce_soft_per_ip_quota_and_report_counts() {
+        use crate::test_util::{MockUpstreamConfig, TestApp};
 
-        let access_token = proxy
-            .create_access_token(Some("http-extract"))
+        let _guard = crate::tests::env_lock().lock_owned().await;
+        unsafe {
+            std::env::set_var("PUBLIC_IP_HOURLY_LIMIT", "2");
+            std::env::set_var("TRUSTED_PROXIES", "127.0.0.1");
+        }
+        let app = TestApp::spawn(MockUpstreamConfig::default(), &["tvly-public-quota"])
             .await
-            .expect("create token");
-
-        let upstream_addr =
-            spawn_http_extract_mock_asserting_api_key(expected_api_key.to_string()).await;
-        let usage_base = format!("http://{}", upstream_addr);
+            .expect("test app spawned");
+        unsafe {
+            std::env::remove_var("PUBLIC_IP_HOURLY_LIMIT");
+            std::env::remove_var("TRUSTED_PROXIES");
+        }
 
-        let proxy_addr = spawn_proxy_server(proxy.clone(), usage_base).await;
+        let fetch = |ip: &'static str| {
+            app.client()
+                .get(app.url("/api/public/metrics"))
+                .header("x-forwarded-for", ip)
+                .send()
+        };
+        for _ in 0..2 {
+            let resp = fetch("198.51.100.9").await.expect("public metrics");
+            assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        }
+        let resp = fetch("198.51.100.9").await.expect("public metrics");
+        assert_eq!(resp.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+        assert!(resp.headers().contains_key(reqwest::header::RETRY_AFTER));
+        let resp = fetch("198.51.100.10").await.expect("public metrics");
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
 
-        let client = Client::new();
-        let url = format!("http://{}/api/tavily/extract", proxy_addr);
-        let resp = client
-            .post(url)
-            .json(&serde_json::json!({
-                "api_key": access_token.token,
-                "urls": ["https://example.com"]
-            }))
+        let metrics: serde_json::Value = app
+            .admin(reqwest::Method::GET, "/api/debug/metrics")
             .send()
             .await
-            .expect("request to proxy succeeds");
-
-        assert!(resp.status().is_success());
-        let body: serde_json::Value = resp.json().await.expect("parse json body");
-        assert_eq!(body.get("status").and_then(|v| v.as_i64()), Some(200));
-
-        let _ = std::fs::remove_file(db_path);
+            .expect("self metrics")
+            .json()
+            .await
+            .expect("self metrics json");
+        let quota = &metrics["publicQuota"];
+        assert_eq!(quota["hourlyLimit"], 2);
+        assert_eq!(quota["trackedIps"], 2);
+        assert_eq!(quota["windowRequests"], 4);
+        assert_eq!(quota["windowRejected"], 1);
+        assert_eq!(
+            quota["topClients"][0]["client"],
+            hash_client_ip("198.51.100.9")
+        );
+        assert_eq!(quota["topClients"][0]["requests"], 3);
     }
 
     #[tokio::test]
-    async fn tavily_http_crawl_replaces_body_api_key_with_tavily_key() {
-        let db_path = temp_db_path("http-crawl-replace-key");
+    async fn identical_searches_are_served_from_the_response_cache() {
+        let _guard = crate::tests::env_lock().lock_owned().await;
+        let db_path = temp_db_path("response-cache");
         let db_str = db_path.to_string_lossy().to_string();
 
-        let expected_api_key = "tvly-http-crawl-key";
+        let hits = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let app = Router::new()
+            .route(
+                "/search",
+                post({
+                    let hits = hits.clone();
+                    move || {
+                        let hits = hits.clone();
+                        async move {
+                            hits.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                            Json(serde_json::json!({
+                                "query": "rust",
+                                "results": [{ "title": "Rust", "url": "https://www.rust-lang.org" }],
+                            }))
+                        }
+                    }
+                }),
+            )
+            .route(
+                "/mcp",
+                any({
+                    let hits = hits.clone();
+                    move |Json(body): Json<serde_json::Value>| {
+                        let hits = hits.clone();
+                        async move {
+                            hits.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                            let reply = serde_json::json!({
+                                "jsonrpc": "2.0",
+                                "id": body["id"],
+                                "result": { "structuredContent": { "status": 200, "results": [] } },
+                            });
+                            Response::builder()
+                                .header(CONTENT_TYPE, "text/event-stream")
+                                .body(Body::from(format!("event: message\ndata: {reply}\n\n")))
+                                .unwrap()
+                        }
+                    }
+                }),
+            );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app.into_make_service())
+                .await
+                .unwrap();
+        });
+        let upstream = format!("http://{upstream_addr}");
+
+        unsafe {
+            std::env::set_var("RESPONSE_CACHE_TTL_SECS", "60");
+        }
         let proxy = TavilyProxy::with_endpoint(
-            vec![expected_api_key.to_string()],
-            DEFAULT_UPSTREAM,
+            vec!["tvly-response-cache"],
+            &format!("{upstream}/mcp"),
             &db_str,
         )
         .await
         .expect("proxy created");
-
-        let access_token = proxy
-            .create_access_token(Some("http-crawl"))
+        unsafe {
+            std::env::remove_var("RESPONSE_CACHE_TTL_SECS");
+        }
+        let token = proxy
+            .create_access_token(Some("response-cache"))
             .await
             .expect("create token");
+        let proxy_addr = spawn_proxy_server(proxy.clone(), upstream.clone()).await;
+        let client = Client::new();
+        let upstream_hits = || hits.load(std::sync::atomic::Ordering::SeqCst);
 
-        let upstream_addr =
-            spawn_http_crawl_mock_asserting_api_key(expected_api_key.to_string()).await;
-        let usage_base = format!("http://{}", upstream_addr);
+        // Field order and the caller's api_key do not change the cache key.
+        for body in [
+            r#"{"query":"rust","max_results":3}"#,
+            r#"{"max_results":3,"api_key":"ignored","query":"rust"}"#,
+        ] {
+            let resp = client
+                .post(format!("http://{proxy_addr}/api/tavily/search"))
+                .bearer_auth(&token.token)
+                .header(CONTENT_TYPE, "application/json")
+                .body(body)
+                .send()
+                .await
+                .expect("search");
+            assert_eq!(resp.status(), reqwest::StatusCode::OK);
+            let json: serde_json::Value = resp.json().await.expect("search json");
+            assert_eq!(json["results"][0]["title"], "Rust");
+        }
+        assert_eq!(upstream_hits(), 1);
+
+        for id in [7, 8] {
+            let resp = client
+                .post(format!("http://{proxy_addr}/mcp"))
+                .bearer_auth(&token.token)
+                .json(&serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "method": "tools/call",
+                    "params": { "name": "tavily-search", "arguments": { "query": "rust" } },
+                }))
+                .send()
+                .await
+                .expect("mcp search");
+            assert_eq!(resp.status(), reqwest::StatusCode::OK);
+            let text = resp.text().await.expect("mcp body");
+            assert!(text.contains(&format!("\"id\":{id}")), "{text}");
+            assert!(text.contains("structuredContent"));
+        }
+        assert_eq!(upstream_hits(), 2);
 
-        let proxy_addr = spawn_proxy_server(proxy.clone(), usage_base).await;
+        let logs = proxy.recent_request_logs(10).await.expect("request logs");
+        assert_eq!(logs.len(), 2);
+        assert!(logs.iter().all(|log| log.cache_hits == 1));
 
-        let client = Client::new();
-        let url = format!("http://{}/api/tavily/crawl", proxy_addr);
+        let snapshot = proxy.response_cache_snapshot().await;
+        assert!(snapshot.enabled);
+        assert_eq!(snapshot.entries.len(), 2);
+        assert_eq!(snapshot.hits, 2);
+        assert_eq!(snapshot.misses, 2);
+
+        assert_eq!(proxy.flush_response_cache().await, 2);
         let resp = client
-            .post(url)
-            .json(&serde_json::json!({
-                "api_key": access_token.token,
-                "urls": ["https://example.com/page"]
-            }))
+            .post(format!("http://{proxy_addr}/api/tavily/search"))
+            .bearer_auth(&token.token)
+            .header(CONTENT_TYPE, "application/json")
+            .body(r#"{"query":"rust","max_results":3}"#)
             .send()
             .await
-            .expect("request to proxy succeeds");
-
-        assert!(resp.status().is_success());
-        let body: serde_json::Value = resp.json().await.expect("parse json body");
-        assert_eq!(body.get("status").and_then(|v| v.as_i64()), Some(200));
+            .expect("search after flush");
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        assert_eq!(upstream_hits(), 3);
 
         let _ = std::fs::remove_file(db_path);
     }
 
     #[tokio::test]
-    async fn tavily_http_map_replaces_body_api_key_with_tavily_key() {
-        let db_path = temp_db_path("http-map-replace-key");
-        let db_str = db_path.to_string_lossy().to_string();
-
-        let expected_api_key = "tvly-http-map-key";
-        let proxy = TavilyProxy::with_endpoint(
-            vec![expected_api_key.to_string()],
-            DEFAULT_UPSTREAM,
-            &db_str,
-        )
-        .await
-        .expect("proxy created");
-
-        let access_token = proxy
-            .create_access_token(Some("http-map"))
+    async fn request_logs_export_as_csv_and_jsonl() {
+        let app = crate::test_util::TestApp::spawn(Default::default(), &["tvly-export-logs"])
             .await
-            .expect("create token");
-
-        let upstream_addr =
-            spawn_http_map_mock_asserting_api_key(expected_api_key.to_string()).await;
-        let usage_base = format!("http://{}", upstream_addr);
+            .expect("spawn app");
+        let token = app.create_token().await.expect("token");
+        for (id, query) in [(1, "export one"), (2, "export, \"two\"")] {
+            let resp = app
+                .call_tool(&token, id, "tavily-search", json!({ "query": query }))
+                .await
+                .expect("tool call");
+            assert!(resp.status().is_success());
+        }
 
-        let proxy_addr = spawn_proxy_server(proxy.clone(), usage_base).await;
+        let resp = app
+            .admin(Method::GET, "/api/logs/export")
+            .send()
+            .await
+            .expect("jsonl export");
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers()[CONTENT_TYPE].to_str().unwrap(),
+            "application/x-ndjson"
+        );
+        let body = resp.text().await.expect("jsonl body");
+        let rows: Vec<Value> = body
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("jsonl row"))
+            .collect();
+        assert_eq!(rows.len(), 2);
+        assert!(rows[0]["id"].as_i64() < rows[1]["id"].as_i64());
+        assert!(
+            rows[1]["request_body"]
+                .as_str()
+                .is_some_and(|body| body.contains("export,"))
+        );
 
-        let client = Client::new();
-        let url = format!("http://{}/api/tavily/map", proxy_addr);
-        let resp = client
-            .post(url)
-            .json(&serde_json::json!({
-                "api_key": access_token.token,
-                "url": "https://example.com"
-            }))
+        let csv = app
+            .admin(Method::GET, "/api/logs/export?format=csv")
             .send()
             .await
-            .expect("request to proxy succeeds");
+            .expect("csv export")
+            .text()
+            .await
+            .expect("csv body");
+        let mut lines = csv.split("\r\n").filter(|line| !line.is_empty());
+        assert!(
+            lines
+                .next()
+                .is_some_and(|header| header.starts_with("id,public_id,"))
+        );
+        assert!(csv.contains("\"\""));
 
-        assert!(resp.status().is_success());
-        let body: serde_json::Value = resp.json().await.expect("parse json body");
-        assert_eq!(body.get("status").and_then(|v| v.as_i64()), Some(200));
+        let resp = app
+            .admin(Method::GET, "/api/logs/export?until=2000-01-01T00:00:00Z")
+            .send()
+            .await
+            .expect("empty range");
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.text().await.expect("empty body").is_empty());
 
-        let _ = std::fs::remove_file(db_path);
+        for bad in [
+            "/api/logs/export?format=xml",
+            "/api/logs/export?since=yesterday",
+        ] {
+            let resp = app
+                .admin(Method::GET, bad)
+                .send()
+                .await
+                .expect("bad export");
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        }
+        let resp = app
+            .client()
+            .get(app.url("/api/logs/export"))
+            .send()
+            .await
+            .expect("anonymous export");
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
     }
 
     #[tokio::test]
-    async fn mcp_accepts_token_from_query_param() {
-        let db_path = temp_db_path("e2e-query-token");
-        let db_str = db_path.to_string_lossy().to_string();
-
-        let expected_api_key = "tvly-e2e-upstream-key";
-        let upstream_addr = spawn_mock_upstream(expected_api_key.to_string()).await;
-        let upstream = format!("http://{}", upstream_addr);
-
-        let proxy =
-            TavilyProxy::with_endpoint(vec![expected_api_key.to_string()], &upstream, &db_str)
-                .await
-                .expect("proxy created");
-
-        let access_token = proxy
-            .create_access_token(Some("e2e-query-param"))
+    async fn invited_token_secret_is_revealed_once_through_its_claim_url() {
+        let app = crate::test_util::TestApp::spawn(Default::default(), &["tvly-invite"])
             .await
-            .expect("create access token");
+            .expect("test app");
 
-        let proxy_addr =
-            spawn_proxy_server(proxy.clone(), "https://api.tavily.com".to_string()).await;
+        let resp = app
+            .admin(Method::POST, "/api/tokens/invite")
+            .json(&json!({ "note": "new hire", "ttlSecs": 0 }))
+            .send()
+            .await
+            .expect("invalid ttl");
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
 
-        let client = Client::new();
-        let url = format!(
-            "http://{}/mcp?tavilyApiKey={}",
-            proxy_addr, access_token.token
-        );
-        let resp = client
-            .post(url)
-            .body("{}")
+        let resp = app
+            .admin(Method::POST, "/api/tokens/invite")
+            .json(&json!({ "note": "new hire", "ttlSecs": 3600 }))
             .send()
             .await
-            .expect("request to proxy succeeds");
+            .expect("create invite");
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let invite: Value = resp.json().await.expect("invite body");
+        let id = invite["id"].as_str().expect("invite id").to_string();
+        let claim_url = invite["claimUrl"].as_str().expect("claim url").to_string();
+        assert!(claim_url.starts_with(&format!("http://{}/claim/", app.addr)));
+
+        // Nothing to reveal before the claim.
+        let resp = app
+            .admin(Method::GET, &format!("/api/tokens/{id}/secret"))
+            .send()
+            .await
+            .expect("secret before claim");
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let detail: Value = app
+            .admin(Method::GET, &format!("/api/tokens/{id}"))
+            .send()
+            .await
+            .expect("detail")
+            .json()
+            .await
+            .expect("detail body");
+        assert_eq!(detail["claim"]["state"], "pending");
 
-        assert!(
-            resp.status().is_success(),
-            "expected success from /mcp using query param token, got {}",
-            resp.status()
+        let resp = app.client().get(&claim_url).send().await.expect("claim");
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers()[axum::http::header::CACHE_CONTROL],
+            "no-store"
         );
+        let claimed: Value = resp.json().await.expect("claim body");
+        assert_eq!(claimed["id"], id.as_str());
+        let token = claimed["token"].as_str().expect("token").to_string();
+        let resp = app
+            .call_tool(&token, 1, "tavily-search", json!({ "query": "claimed" }))
+            .await
+            .expect("tool call");
+        assert!(resp.status().is_success());
 
-        let _ = std::fs::remove_file(db_path);
+        let resp = app.client().get(&claim_url).send().await.expect("reclaim");
+        assert_eq!(resp.status(), StatusCode::GONE);
+        let body: Value = resp.json().await.expect("reclaim body");
+        assert_eq!(body["error"], "already_claimed");
+        let resp = app
+            .client()
+            .get(app.url("/claim/not-a-real-code"))
+            .send()
+            .await
+            .expect("unknown claim");
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let detail: Value = app
+            .admin(Method::GET, &format!("/api/tokens/{id}"))
+            .send()
+            .await
+            .expect("detail")
+            .json()
+            .await
+            .expect("detail body");
+        assert_eq!(detail["claim"]["state"], "claimed");
+        assert_eq!(detail["claim"]["claimed_from"], "127.0.0.1");
     }
 
     #[tokio::test]
-    async fn mcp_non_tool_calls_are_ignored_by_business_quota() {
-        let db_path = temp_db_path("mcp-non-tool-ignored");
-        let db_str = db_path.to_string_lossy().to_string();
-
-        // Tighten business hourly quota to 1 so that the token is quickly exhausted
-        // for TokenQuota, while the per-hour raw request limiter still uses default.
-        unsafe {
-            std::env::set_var("TOKEN_HOURLY_LIMIT", "1");
+    async fn token_group_quota_is_shared_by_member_tokens() {
+        let app = crate::test_util::TestApp::spawn(Default::default(), &["tvly-group-quota"])
+            .await
+            .expect("spawn app");
+        let resp = app
+            .admin(Method::PUT, "/api/tokens/groups/team")
+            .json(&json!({ "note": "shared", "hourlyLimit": 2 }))
+            .send()
+            .await
+            .expect("create group");
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let resp = app
+            .admin(Method::PUT, "/api/tokens/groups/team")
+            .json(&json!({ "hourlyLimit": 0 }))
+            .send()
+            .await
+            .expect("invalid limit");
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let first = app.create_token().await.expect("first token");
+        let second = app.create_token().await.expect("second token");
+        let tokens = app.proxy.list_access_tokens().await.expect("tokens");
+        for token in &tokens {
+            let resp = app
+                .admin(Method::PUT, &format!("/api/tokens/{}/group", token.id))
+                .json(&json!({ "group": "team" }))
+                .send()
+                .await
+                .expect("assign group");
+            assert_eq!(resp.status(), StatusCode::NO_CONTENT);
         }
+        let resp = app
+            .admin(Method::PUT, "/api/tokens/nope/group")
+            .json(&json!({ "group": "team" }))
+            .send()
+            .await
+            .expect("assign unknown token");
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
 
-        let expected_api_key = "tvly-mcp-non-tool-key";
-        let upstream_addr = spawn_mock_upstream(expected_api_key.to_string()).await;
-        let upstream = format!("http://{}", upstream_addr);
-
-        let proxy =
-            TavilyProxy::with_endpoint(vec![expected_api_key.to_string()], &upstream, &db_str)
+        for (id, token) in [(1, &first), (2, &second)] {
+            let resp = app
+                .call_tool(token, id, "tavily-search", json!({ "query": "group" }))
                 .await
-                .expect("proxy created");
-
-        let access_token = proxy
-            .create_access_token(Some("mcp-non-tool"))
+                .expect("tool call");
+            assert!(resp.status().is_success());
+        }
+        let resp = app
+            .call_tool(&first, 3, "tavily-search", json!({ "query": "group" }))
+            .await
+            .expect("over group quota");
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        let body: Value = resp.json().await.expect("quota body");
+        assert_eq!(body["error"]["data"]["code"], "QUOTA_EXCEEDED");
+        assert_eq!(body["error"]["data"]["group"]["name"], "team");
+        assert_eq!(body["error"]["data"]["group"]["window"], "hour");
+
+        let group: Value = app
+            .admin(Method::GET, "/api/tokens/groups/team")
+            .send()
+            .await
+            .expect("group detail")
+            .json()
+            .await
+            .expect("group json");
+        assert_eq!(group["tokenCount"], tokens.len());
+        assert_eq!(group["note"], "shared");
+        assert_eq!(group["hourlyLimit"], 2);
+        assert_eq!(group["usage"]["hourlyUsed"], 3);
+        assert_eq!(group["usage"]["monthlyUsed"], 3);
+
+        let resp = app
+            .admin(Method::DELETE, "/api/tokens/groups/team")
+            .send()
             .await
-            .expect("create access token");
+            .expect("delete group");
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let resp = app
+            .admin(Method::GET, "/api/tokens/groups/team")
+            .send()
+            .await
+            .expect("deleted group");
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let resp = app
+            .call_tool(&first, 4, "tavily-search", json!({ "query": "ungrouped" }))
+            .await
+            .expect("tool call after delete");
+        assert!(resp.status().is_success());
+    }
 
-        // Pre-exhaust business quota for this token.
-        let hourly_limit = effective_token_hourly_limit();
-        for _ in 0..=hourly_limit {
-            let _ = proxy
-                .check_token_quota(&access_token.id)
+    #[tokio::test]
+    async fn token_group_usage_exports_monthly_counts_per_token() {
+        let app = crate::test_util::TestApp::spawn(Default::default(), &["tvly-group-export"])
+            .await
+            .expect("spawn app");
+        let resp = app
+            .admin(Method::PUT, "/api/tokens/groups/billing")
+            .json(&json!({ "note": "invoiced" }))
+            .send()
+            .await
+            .expect("create group");
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let token = app.create_token().await.expect("token");
+        let token_id = token.split('-').nth(1).expect("token id").to_string();
+        let resp = app
+            .admin(Method::PUT, &format!("/api/tokens/{token_id}/group"))
+            .json(&json!({ "group": "billing" }))
+            .send()
+            .await
+            .expect("assign group");
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        for id in 1..=2 {
+            let resp = app
+                .call_tool(&token, id, "tavily-search", json!({ "query": "invoice" }))
                 .await
-                .expect("quota check ok");
+                .expect("tool call");
+            assert!(resp.status().is_success());
         }
+        app.proxy
+            .rollup_token_usage_stats()
+            .await
+            .expect("rollup usage");
 
-        let proxy_addr =
-            spawn_proxy_server(proxy.clone(), "https://api.tavily.com".to_string()).await;
+        // An older month, as left behind by earlier rollups.
+        let now = Utc::now();
+        let this_month = start_of_month_dt(now);
+        let last_month = start_of_month_dt(this_month - ChronoDuration::days(1));
+        sqlx::query(
+            r#"INSERT INTO token_usage_stats
+               (token_id, bucket_start, bucket_secs, success_count, system_failure_count,
+                external_failure_count, quota_exhausted_count)
+               VALUES (?, ?, 3600, 5, 1, 2, 3)"#,
+        )
+        .bind(&token_id)
+        .bind(last_month.timestamp())
+        .execute(&app.proxy.key_store.pool)
+        .await
+        .expect("seed last month");
 
-        let client = Client::new();
-        let url = format!(
-            "http://{}/mcp?tavilyApiKey={}",
-            proxy_addr, access_token.token
-        );
-        // MCP 非工具调用：tools/list 应当被业务配额忽略，但仍经过“任意请求”限频。
-        let resp = client
-            .post(url)
-            .json(&serde_json::json!({ "method": "tools/list" }))
+        let export = |query: String| {
+            app.admin(
+                Method::GET,
+                &format!("/api/tokens/groups/billing/usage/export{query}"),
+            )
             .send()
-            .await
-            .expect("request to proxy succeeds");
+        };
+        let resp = export(String::new()).await.expect("current month");
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()["content-type"], "text/csv; charset=utf-8");
+        let csv = resp.text().await.expect("csv body");
+        let current = this_month.format("%Y-%m").to_string();
+        let previous = last_month.format("%Y-%m").to_string();
+        assert_eq!(
+            csv,
+            format!(
+                "month,token_id,note,success_count,error_count,quota_exhausted_count\r\n\
+                 {current},{token_id},test-util,2,0,0\r\n"
+            )
+        );
 
-        assert!(
-            resp.status().is_success(),
-            "non-tool MCP call (tools/list) should not be blocked by business quota, got {}",
-            resp.status()
+        let csv = export(format!("?from={previous}&to={current}"))
+            .await
+            .expect("two months")
+            .text()
+            .await
+            .expect("csv body");
+        let rows: Vec<&str> = csv.split("\r\n").filter(|l| !l.is_empty()).collect();
+        assert_eq!(
+            rows[1..],
+            [
+                format!("{previous},{token_id},test-util,5,3,3"),
+                format!("{current},{token_id},test-util,2,0,0"),
+            ]
         );
 
-        // Verify that the most recent auth_token_logs entry is not billable.
-        let options = SqliteConnectOptions::new()
-            .filename(&db_str)
-            .create_if_missing(true)
-            .journal_mode(SqliteJournalMode::Wal)
-            .busy_timeout(Duration::from_secs(5));
-        let pool = SqlitePoolOptions::new()
-            .min_connections(1)
-            .max_connections(5)
-            .connect_with(options)
+        for query in [
+            format!("?from={current}&to={previous}"),
+            "?from=2024-13".into(),
+        ] {
+            let resp = export(query).await.expect("invalid period");
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        }
+        let resp = app
+            .admin(Method::GET, "/api/tokens/groups/nope/usage/export")
+            .send()
             .await
-            .expect("connect to sqlite");
+            .expect("unknown group");
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
 
-        let row = sqlx::query(
-            r#"
-            SELECT counts_business_quota
-            FROM auth_token_logs
-            WHERE token_id = ?
-            ORDER BY id DESC
-            LIMIT 1
-            "#,
-        )
-        .bind(&access_token.id)
-        .fetch_one(&pool)
-        .await
-        .expect("token log row exists");
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<StdMutex<Vec<u8>>>);
 
-        let counts_business_quota: i64 = row.try_get("counts_business_quota").unwrap();
-        assert_eq!(counts_business_quota, 0);
+    impl std::io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
 
-        unsafe {
-            std::env::remove_var("TOKEN_HOURLY_LIMIT");
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
         }
-        let _ = std::fs::remove_file(db_path);
     }
 
     #[tokio::test]
-    async fn mcp_tools_list_does_not_increment_billable_totals_after_rollup() {
-        let db_path = temp_db_path("mcp-nonbillable-rollup");
-        let db_str = db_path.to_string_lossy().to_string();
-
-        let expected_api_key = "tvly-mcp-nonbillable-key";
-        let upstream_addr = spawn_mock_upstream(expected_api_key.to_string()).await;
-        let upstream = format!("http://{}", upstream_addr);
-
-        let proxy =
-            TavilyProxy::with_endpoint(vec![expected_api_key.to_string()], &upstream, &db_str)
-                .await
-                .expect("proxy created");
-
-        let access_token = proxy
-            .create_access_token(Some("mcp-nonbillable-rollup"))
+    async fn proxied_requests_are_traced_with_key_token_and_outcome() {
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .json()
+            .with_current_span(true)
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let app = crate::test_util::TestApp::spawn(Default::default(), &["tvly-trace"])
             .await
-            .expect("create access token");
-
-        let proxy_addr = spawn_proxy_server(proxy.clone(), upstream.clone()).await;
-
-        let client = Client::new();
-        let url = format!(
-            "http://{}/mcp?tavilyApiKey={}",
-            proxy_addr, access_token.token
-        );
-        let resp = client
-            .post(url)
-            .json(&serde_json::json!({ "method": "tools/list" }))
-            .send()
+            .expect("spawn app");
+        let token = app.create_token().await.expect("token");
+        let token_id = token.split('-').nth(1).expect("token id").to_string();
+        let resp = app
+            .call_tool(&token, 1, "tavily-search", json!({ "query": "trace" }))
             .await
-            .expect("request to proxy succeeds");
-
+            .expect("tool call");
+        assert!(resp.status().is_success());
+        resp.bytes().await.expect("body");
+
+        let output = String::from_utf8(logs.0.lock().unwrap().clone()).expect("utf8 logs");
+        let completed = output
+            .lines()
+            .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+            .find(|line| line["fields"]["message"] == "request completed")
+            .expect("request completed event");
+        assert_eq!(completed["fields"]["status"], 200);
+        assert!(completed["fields"]["latency_ms"].is_u64());
+        let span = &completed["span"];
+        assert_eq!(span["name"], "proxy_request");
+        assert_eq!(span["path"], "/mcp");
+        assert_eq!(span["token_id"], token_id.as_str());
+        assert_eq!(span["outcome"], "success");
+        assert!(span["key_id"].as_str().is_some_and(|id| !id.is_empty()));
         assert!(
-            resp.status().is_success(),
-            "expected success from /mcp tools/list, got {}",
-            resp.status()
+            !output.contains("tvly-trace"),
+            "key secrets must not be logged"
         );
+    }
 
-        let _ = proxy
-            .rollup_token_usage_stats()
+    #[tokio::test]
+    async fn latency_metrics_report_recorded_round_trips() {
+        let app = crate::test_util::TestApp::spawn(Default::default(), &["tvly-latency"])
             .await
-            .expect("rollup token usage stats");
-
-        let summary = proxy
-            .token_summary_since(&access_token.id, 0, None)
+            .expect("spawn app");
+        let token = app.create_token().await.expect("token");
+        let resp = app
+            .call_tool(&token, 1, "tavily-search", json!({ "query": "latency" }))
             .await
-            .expect("summary since");
+            .expect("tool call");
+        assert!(resp.status().is_success());
+        resp.bytes().await.expect("body");
 
-        assert_eq!(
-            summary.total_requests, 0,
-            "non-billable MCP tools/list should not affect billable totals"
-        );
-        assert_eq!(summary.success_count, 0);
-        assert_eq!(summary.quota_exhausted_count, 0);
+        let logs = app.proxy.recent_request_logs(10).await.expect("logs");
+        assert!(logs.iter().all(|log| log.latency_ms.is_some()));
 
-        let _ = std::fs::remove_file(db_path);
+        let report: Value = app
+            .admin(Method::GET, "/api/metrics/latency?window=1h")
+            .send()
+            .await
+            .expect("latency metrics")
+            .json()
+            .await
+            .expect("latency json");
+        assert_eq!(report["windowSecs"], 3600);
+        assert_eq!(report["overall"]["count"], logs.len());
+        assert!(report["overall"]["p99Ms"].is_i64());
+        assert_eq!(report["keys"].as_array().expect("keys").len(), 1);
+        assert!(report["keys"][0]["keyId"].is_string());
+        assert!(report["keys"][0]["p50Ms"].is_i64());
+
+        for window in ["0h", "24", "1w"] {
+            let resp = app
+                .admin(
+                    Method::GET,
+                    &format!("/api/metrics/latency?window={window}"),
+                )
+                .send()
+                .await
+                .expect("bad window");
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        }
+        let resp = app
+            .client()
+            .get(app.url("/api/metrics/latency"))
+            .send()
+            .await
+            .expect("anonymous");
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
     }
 
     #[tokio::test]
-    async fn mcp_rejects_invalid_token_in_query_param() {
-        let db_path = temp_db_path("e2e-query-token-invalid");
-        let db_str = db_path.to_string_lossy().to_string();
-
-        let expected_api_key = "tvly-e2e-upstream-key-invalid";
-        let upstream_addr = spawn_mock_upstream(expected_api_key.to_string()).await;
-        let upstream = format!("http://{}", upstream_addr);
-
-        let proxy =
-            TavilyProxy::with_endpoint(vec![expected_api_key.to_string()], &upstream, &db_str)
+    async fn expired_tokens_are_rejected_then_disabled_until_extended_and_reenabled() {
+        let app = crate::test_util::TestApp::spawn(Default::default(), &["tvly-expiry"])
+            .await
+            .expect("spawn app");
+        let token = app.create_token().await.expect("token");
+        let token_id = token.split('-').nth(1).expect("token id").to_string();
+        let expiry_path = format!("/api/tokens/{token_id}/expiry");
+
+        for body in [
+            json!({ "expires_at": "tomorrow" }),
+            json!({ "extend_days": 0 }),
+            json!({ "expires_at": "2030-01-01T00:00:00Z", "extend_days": 1 }),
+        ] {
+            let resp = app
+                .admin(Method::PATCH, &expiry_path)
+                .json(&body)
+                .send()
                 .await
-                .expect("proxy created");
+                .expect("bad expiry");
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{body}");
+        }
+        let resp = app
+            .admin(Method::PATCH, "/api/tokens/nope/expiry")
+            .json(&json!({ "extend_days": 1 }))
+            .send()
+            .await
+            .expect("unknown token");
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
 
-        let proxy_addr =
-            spawn_proxy_server(proxy.clone(), "https://api.tavily.com".to_string()).await;
+        let past = (Utc::now() - ChronoDuration::minutes(1)).to_rfc3339();
+        let body: Value = app
+            .admin(Method::PATCH, &expiry_path)
+            .json(&json!({ "expires_at": past }))
+            .send()
+            .await
+            .expect("set expiry")
+            .json()
+            .await
+            .expect("expiry json");
+        assert!(body["expires_at"].as_i64().expect("expires_at") < Utc::now().timestamp());
 
-        let client = Client::new();
-        let url = format!(
-            "http://{}/mcp?tavilyApiKey={}",
-            proxy_addr, "th-invalid-unknown"
+        let resp = app
+            .call_tool(&token, 1, "tavily-search", json!({ "query": "expired" }))
+            .await
+            .expect("expired call");
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let disabled = app.proxy.disable_expired_tokens().await.expect("disable");
+        assert_eq!(disabled, vec![token_id.clone()]);
+        assert!(
+            app.proxy
+                .disable_expired_tokens()
+                .await
+                .expect("second run")
+                .is_empty()
         );
-        let resp = client
-            .post(url)
-            .body("{}")
+
+        let body: Value = app
+            .admin(Method::PATCH, &expiry_path)
+            .json(&json!({ "extend_days": 7 }))
             .send()
             .await
-            .expect("request to proxy succeeds");
+            .expect("extend expiry")
+            .json()
+            .await
+            .expect("extend json");
+        let extended = body["expires_at"].as_i64().expect("extended");
+        assert!(extended >= Utc::now().timestamp() + 7 * 24 * 3600 - 5);
 
-        assert_eq!(
-            resp.status(),
-            reqwest::StatusCode::UNAUTHORIZED,
-            "expected 401 for invalid query param token"
-        );
+        let tokens: Value = app
+            .admin(Method::GET, "/api/tokens")
+            .send()
+            .await
+            .expect("list tokens")
+            .json()
+            .await
+            .expect("tokens json");
+        let listed = tokens["items"]
+            .as_array()
+            .expect("items")
+            .iter()
+            .find(|t| t["id"] == token_id.as_str())
+            .expect("listed token");
+        assert_eq!(listed["enabled"], false);
+        assert_eq!(listed["expires_at"], extended);
+
+        let resp = app
+            .admin(Method::PATCH, &format!("/api/tokens/{token_id}/status"))
+            .json(&json!({ "enabled": true }))
+            .send()
+            .await
+            .expect("re-enable");
+        assert!(resp.status().is_success());
+        let resp = app
+            .call_tool(&token, 2, "tavily-search", json!({ "query": "extended" }))
+            .await
+            .expect("extended call");
+        assert!(resp.status().is_success());
 
-        let _ = std::fs::remove_file(db_path);
+        let resp = app
+            .admin(Method::PATCH, &expiry_path)
+            .json(&json!({ "expires_at": null }))
+            .send()
+            .await
+            .expect("clear expiry");
+        let body: Value = resp.json().await.expect("clear json");
+        assert_eq!(body["expires_at"], Value::Null);
     }
 
-    #[test]
-    fn extract_token_from_query_none_or_empty() {
-        let (q, t) = extract_token_from_query(None);
-        assert_eq!(q, None);
-        assert_eq!(t, None);
+    #[tokio::test]
+    async fn bulk_token_operations_apply_atomically() {
+        let app = crate::test_util::TestApp::spawn(Default::default(), &["tvly-bulk"])
+            .await
+            .expect("spawn app");
+        let mut tokens = Vec::new();
+        for _ in 0..3 {
+            tokens.push(app.create_token().await.expect("token"));
+        }
+        let ids: Vec<String> = tokens
+            .iter()
+            .map(|t| t.split('-').nth(1).expect("token id").to_string())
+            .collect();
+        let bulk = |body: Value| {
+            app.admin(Method::POST, "/api/tokens/bulk")
+                .json(&body)
+                .send()
+        };
 
-        let (q, t) = extract_token_from_query(Some(""));
-        assert_eq!(q, None);
-        assert_eq!(t, None);
-    }
+        for body in [
+            json!({ "ids": [ids[0]], "action": "explode" }),
+            json!({ "ids": [], "action": "disable" }),
+        ] {
+            let resp = bulk(body).await.expect("bad bulk");
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        }
 
-    #[test]
-    fn extract_token_from_query_single_param_case_insensitive() {
-        let (q, t) = extract_token_from_query(Some("TavilyApiKey=th-abc-xyz"));
-        assert_eq!(q, None, "no other params → query should be None");
-        assert_eq!(t.as_deref(), Some("th-abc-xyz"));
-    }
+        let resp = bulk(json!({ "ids": [ids[0], "nope"], "action": "disable" }))
+            .await
+            .expect("unknown id");
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let body: Value = resp.json().await.expect("missing json");
+        assert_eq!(body["missing"], json!(["nope"]));
+        let resp = app
+            .call_tool(
+                &tokens[0],
+                1,
+                "tavily-search",
+                json!({ "query": "still on" }),
+            )
+            .await
+            .expect("untouched token");
+        assert!(resp.status().is_success());
 
-    #[test]
-    fn extract_token_from_query_strips_param_and_preserves_others() {
-        let (q, t) = extract_token_from_query(Some("foo=1&tavilyApiKey=th-abc-xyz&bar=2"));
-        assert_eq!(t.as_deref(), Some("th-abc-xyz"));
-        // Order should be preserved for non-auth params.
-        assert_eq!(q.as_deref(), Some("foo=1&bar=2"));
-    }
+        let body: Value = bulk(json!({ "ids": [ids[0], ids[1], ids[0]], "action": "disable" }))
+            .await
+            .expect("disable")
+            .json()
+            .await
+            .expect("disable json");
+        assert_eq!(body["updated"], 2);
+        assert_eq!(body["ids"], json!([ids[0], ids[1]]));
+        let resp = app
+            .call_tool(&tokens[1], 2, "tavily-search", json!({ "query": "off" }))
+            .await
+            .expect("disabled token");
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        bulk(json!({ "ids": [ids[0], ids[1]], "action": "enable" }))
+            .await
+            .expect("enable");
 
-    #[test]
-    fn extract_token_from_query_uses_first_non_empty_token() {
-        let (q, t) =
-            extract_token_from_query(Some("tavilyApiKey=&tavilyApiKey=th-abc-xyz&foo=bar"));
-        assert_eq!(t.as_deref(), Some("th-abc-xyz"));
-        assert_eq!(q.as_deref(), Some("foo=bar"));
-    }
+        let body: Value = bulk(json!({ "ids": [ids[0]], "action": "rotate" }))
+            .await
+            .expect("rotate")
+            .json()
+            .await
+            .expect("rotate json");
+        let rotated = body["tokens"][0]
+            .as_str()
+            .expect("rotated token")
+            .to_string();
+        assert!(rotated.starts_with(&format!("th-{}-", ids[0])));
+        let resp = app
+            .call_tool(&tokens[0], 3, "tavily-search", json!({ "query": "old" }))
+            .await
+            .expect("old secret");
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let resp = app
+            .call_tool(&rotated, 4, "tavily-search", json!({ "query": "new" }))
+            .await
+            .expect("new secret");
+        assert!(resp.status().is_success());
 
-    #[test]
-    fn extract_token_from_query_ignores_additional_token_params() {
-        let (q, t) = extract_token_from_query(Some("tavilyApiKey=th-1&tavilyApiKey=th-2&foo=bar"));
-        assert_eq!(t.as_deref(), Some("th-1"));
-        assert_eq!(q.as_deref(), Some("foo=bar"));
+        bulk(json!({ "ids": [ids[1], ids[2]], "action": "move_group", "group": " ops " }))
+            .await
+            .expect("move group");
+        let group: Value = app
+            .admin(Method::GET, "/api/tokens/groups/ops")
+            .send()
+            .await
+            .expect("group detail")
+            .json()
+            .await
+            .expect("group json");
+        assert_eq!(group["tokenCount"], 2);
+
+        let body: Value = bulk(json!({ "ids": [ids[2]], "action": "delete" }))
+            .await
+            .expect("delete")
+            .json()
+            .await
+            .expect("delete json");
+        assert_eq!(body["updated"], 1);
+        let resp = bulk(json!({ "ids": [ids[2]], "action": "enable" }))
+            .await
+            .expect("deleted id");
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
     }
 }
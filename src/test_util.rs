@@ -0,0 +1,449 @@
+//! Test support for black-box tests: a configurable mock Tavily MCP upstream and helpers
+//! that run the full axum app in-process against a throwaway SQLite database.
+//!
+//! Compiled for the crate's own tests and for downstream crates via the `test-util` feature.
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::Router;
+use axum::body::Body;
+use axum::extract::{Json, Query};
+use axum::http::{HeaderMap, Method, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::routing::any;
+use nanoid::nanoid;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use tokio::net::TcpListener;
+
+use crate::server::{ForwardAuthConfig, app_router};
+use crate::{ProxyError, TavilyProxy};
+
+/// How [`MockUpstream`] answers one `tools/call` request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MockToolReply {
+    /// A normal tool result with `structuredContent.status` 200.
+    Success,
+    /// A tool result flagged `isError` with `structuredContent.status` 432 (quota exhausted).
+    QuotaExhausted,
+    /// A JSON-RPC `error` object with the given message.
+    Error(String),
+    /// A bare HTTP response with this status code and a Tavily-style JSON error body.
+    Status(u16),
+}
+
+/// Behaviour knobs for [`MockUpstream`].
+#[derive(Debug, Clone, Default)]
+pub struct MockUpstreamConfig {
+    /// Reject requests whose `tavilyApiKey` query param differs (401).
+    pub expected_api_key: Option<String>,
+    /// Reply with `text/event-stream` frames instead of plain JSON.
+    pub sse: bool,
+    /// Artificial delay applied before every response.
+    pub latency: Duration,
+    /// After this many successful `tools/call` requests, answer with a 432 (quota exhausted).
+    pub quota_exhausted_after: Option<usize>,
+    /// Replies for the first `tools/call` requests, in order; later calls fall back to the
+    /// rules below.
+    pub script: Vec<MockToolReply>,
+    /// Fixed reply for every `tools/call` made with a given `tavilyApiKey`.
+    pub key_replies: HashMap<String, MockToolReply>,
+}
+
+impl MockUpstreamConfig {
+    pub fn expect_api_key(mut self, key: impl Into<String>) -> Self {
+        self.expected_api_key = Some(key.into());
+        self
+    }
+
+    pub fn with_sse(mut self) -> Self {
+        self.sse = true;
+        self
+    }
+
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    pub fn exhaust_after(mut self, tool_calls: usize) -> Self {
+        self.quota_exhausted_after = Some(tool_calls);
+        self
+    }
+
+    pub fn script(mut self, replies: impl IntoIterator<Item = MockToolReply>) -> Self {
+        self.script = replies.into_iter().collect();
+        self
+    }
+
+    pub fn reply_for_key(mut self, key: impl Into<String>, reply: MockToolReply) -> Self {
+        self.key_replies.insert(key.into(), reply);
+        self
+    }
+}
+
+/// One request as seen by [`MockUpstream`].
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: Method,
+    /// The `tavilyApiKey` query parameter, if any.
+    pub api_key: Option<String>,
+    /// JSON-RPC method of the body (empty when absent).
+    pub rpc_method: String,
+    pub headers: HeaderMap,
+}
+
+/// Minimal Tavily MCP server answering `initialize`, `tools/list` and `tools/call` on `/mcp`.
+pub struct MockUpstream {
+    pub addr: SocketAddr,
+    hits: Arc<AtomicUsize>,
+    tool_calls: Arc<AtomicUsize>,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+}
+
+impl MockUpstream {
+    pub async fn spawn(config: MockUpstreamConfig) -> Self {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let tool_calls = Arc::new(AtomicUsize::new(0));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let config = Arc::new(config);
+
+        let handler = {
+            let hits = hits.clone();
+            let tool_calls = tool_calls.clone();
+            let requests = requests.clone();
+            move |method: Method,
+                  headers: HeaderMap,
+                  Query(params): Query<HashMap<String, String>>,
+                  Json(body): Json<Value>| {
+                let hits = hits.clone();
+                let tool_calls = tool_calls.clone();
+                let requests = requests.clone();
+                let config = config.clone();
+                async move {
+                    hits.fetch_add(1, Ordering::SeqCst);
+                    requests
+                        .lock()
+                        .expect("mock upstream requests")
+                        .push(RecordedRequest {
+                            method,
+                            api_key: params.get("tavilyApiKey").cloned(),
+                            rpc_method: body["method"].as_str().unwrap_or("").to_string(),
+                            headers,
+                        });
+                    mock_mcp_response(&config, &tool_calls, &params, body).await
+                }
+            }
+        };
+        let app = Router::new()
+            .route("/mcp", any(handler.clone()))
+            .route("/mcp/*path", any(handler));
+
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind mock upstream");
+        let addr = listener.local_addr().expect("mock upstream addr");
+        tokio::spawn(async move {
+            axum::serve(listener, app.into_make_service())
+                .await
+                .expect("mock upstream server");
+        });
+
+        Self {
+            addr,
+            hits,
+            tool_calls,
+            requests,
+        }
+    }
+
+    /// Base URL suitable for `TavilyProxy::with_endpoint` and as the usage base.
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Total requests received, including rejected ones.
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::SeqCst)
+    }
+
+    /// Number of `tools/call` requests received.
+    pub fn tool_calls(&self) -> usize {
+        self.tool_calls.load(Ordering::SeqCst)
+    }
+
+    /// Every request received so far, oldest first.
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests
+            .lock()
+            .expect("mock upstream requests")
+            .clone()
+    }
+
+    /// `tavilyApiKey` of each `tools/call` request received, oldest first.
+    pub fn tool_call_keys(&self) -> Vec<Option<String>> {
+        self.requests()
+            .into_iter()
+            .filter(|request| request.rpc_method == "tools/call")
+            .map(|request| request.api_key)
+            .collect()
+    }
+}
+
+async fn mock_mcp_response(
+    config: &MockUpstreamConfig,
+    tool_calls: &AtomicUsize,
+    params: &HashMap<String, String>,
+    body: Value,
+) -> Response {
+    if !config.latency.is_zero() {
+        tokio::time::sleep(config.latency).await;
+    }
+
+    if let Some(expected) = config.expected_api_key.as_deref()
+        && params.get("tavilyApiKey").map(String::as_str) != Some(expected)
+    {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Body::from("missing or incorrect tavilyApiKey"),
+        )
+            .into_response();
+    }
+
+    let id = body.get("id").cloned();
+    let Some(id) = id else {
+        // Notifications carry no id and expect no payload.
+        return StatusCode::ACCEPTED.into_response();
+    };
+
+    let method = body.get("method").and_then(|v| v.as_str()).unwrap_or("");
+    let message = match method {
+        "initialize" => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "result": {
+                "protocolVersion": body["params"]["protocolVersion"],
+                "capabilities": { "tools": {} },
+                "serverInfo": { "name": "mock-tavily", "version": "0.0.0" },
+            },
+        }),
+        "tools/list" => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "result": {
+                "tools": [
+                    { "name": "tavily-search", "inputSchema": { "type": "object" } },
+                    { "name": "tavily-extract", "inputSchema": { "type": "object" } },
+                ],
+            },
+        }),
+        "tools/call" => {
+            let served = tool_calls.fetch_add(1, Ordering::SeqCst);
+            let reply = config
+                .script
+                .get(served)
+                .or_else(|| {
+                    params
+                        .get("tavilyApiKey")
+                        .and_then(|key| config.key_replies.get(key))
+                })
+                .cloned()
+                .unwrap_or_else(|| {
+                    if config
+                        .quota_exhausted_after
+                        .is_some_and(|limit| served >= limit)
+                    {
+                        MockToolReply::QuotaExhausted
+                    } else {
+                        MockToolReply::Success
+                    }
+                });
+            match reply {
+                MockToolReply::Success => {
+                    let tool = body["params"]["name"].clone();
+                    json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "result": {
+                            "content": [{ "type": "text", "text": format!("mock result for {tool}") }],
+                            "structuredContent": { "status": 200, "results": [] },
+                        },
+                    })
+                }
+                MockToolReply::QuotaExhausted => json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "result": {
+                        "content": [{ "type": "text", "text": "usage limit exceeded" }],
+                        "structuredContent": { "status": 432, "error": "usage limit exceeded" },
+                        "isError": true,
+                    },
+                }),
+                MockToolReply::Error(message) => json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": -32000, "message": message },
+                }),
+                MockToolReply::Status(code) => {
+                    let status = StatusCode::from_u16(code).expect("valid mock status code");
+                    return (
+                        status,
+                        Json(json!({ "status": code, "error": "mock upstream status" })),
+                    )
+                        .into_response();
+                }
+            }
+        }
+        _ => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": -32601, "message": format!("method not found: {method}") },
+        }),
+    };
+
+    if config.sse {
+        (
+            [(header::CONTENT_TYPE, "text/event-stream")],
+            format!("event: message\ndata: {message}\n\n"),
+        )
+            .into_response()
+    } else {
+        Json(message).into_response()
+    }
+}
+
+/// Forward-auth user header that [`TestApp`] treats as the admin identity.
+pub const ADMIN_USER_HEADER: &str = "x-test-user";
+/// Value of [`ADMIN_USER_HEADER`] granting admin access.
+pub const ADMIN_USER: &str = "admin";
+
+/// The full application served on an ephemeral port, wired to a [`MockUpstream`].
+pub struct TestApp {
+    pub addr: SocketAddr,
+    pub proxy: TavilyProxy,
+    pub upstream: MockUpstream,
+    db_path: PathBuf,
+    client: reqwest::Client,
+}
+
+impl TestApp {
+    /// Spawns a mock upstream with `config`, a proxy seeded with `api_keys` and the app
+    /// router. Access tokens are enforced as in production; admin endpoints require
+    /// [`ADMIN_USER_HEADER`] (see [`TestApp::admin`]).
+    pub async fn spawn(config: MockUpstreamConfig, api_keys: &[&str]) -> Result<Self, ProxyError> {
+        let upstream = MockUpstream::spawn(config).await;
+        let db_path = std::env::temp_dir().join(format!("test-util-{}.db", nanoid!(8)));
+        let db_str = db_path.to_string_lossy().to_string();
+        let proxy =
+            TavilyProxy::with_endpoint(api_keys.iter().copied(), &upstream.url(), &db_str).await?;
+
+        let app = app_router(
+            proxy.clone(),
+            None,
+            ForwardAuthConfig::new(
+                Some(header::HeaderName::from_static(ADMIN_USER_HEADER)),
+                Some(ADMIN_USER.to_string()),
+                None,
+                None,
+            ),
+            false,
+            upstream.url(),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind test app");
+        let addr = listener.local_addr().expect("test app addr");
+        tokio::spawn(async move {
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await
+            .expect("test app server");
+        });
+
+        Ok(Self {
+            addr,
+            proxy,
+            upstream,
+            db_path,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    pub fn client(&self) -> &reqwest::Client {
+        &self.client
+    }
+
+    /// SQLite file backing the proxy, for reopening it as after a restart.
+    pub fn db_path(&self) -> String {
+        self.db_path.to_string_lossy().to_string()
+    }
+
+    /// Request builder for an admin endpoint, carrying the admin forward-auth header.
+    pub fn admin(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        self.client
+            .request(method, self.url(path))
+            .header(ADMIN_USER_HEADER, ADMIN_USER)
+    }
+
+    /// Creates an access token and returns its full `th-<id>-<secret>` value.
+    pub async fn create_token(&self) -> Result<String, ProxyError> {
+        Ok(self
+            .proxy
+            .create_access_token(Some("test-util"))
+            .await?
+            .token)
+    }
+
+    /// Sends a JSON-RPC request to `/mcp` authenticated with `token`.
+    pub async fn mcp(
+        &self,
+        token: &str,
+        id: i64,
+        method: &str,
+        params: Value,
+    ) -> reqwest::Result<reqwest::Response> {
+        self.client
+            .post(self.url("/mcp"))
+            .bearer_auth(token)
+            .header(
+                header::ACCEPT.as_str(),
+                "application/json, text/event-stream",
+            )
+            .json(&json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }))
+            .send()
+            .await
+    }
+
+    /// Shorthand for a `tools/call` request.
+    pub async fn call_tool(
+        &self,
+        token: &str,
+        id: i64,
+        tool: &str,
+        arguments: Value,
+    ) -> reqwest::Result<reqwest::Response> {
+        self.mcp(
+            token,
+            id,
+            "tools/call",
+            json!({ "name": tool, "arguments": arguments }),
+        )
+        .await
+    }
+}
+
+impl Drop for TestApp {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.db_path);
+    }
+}
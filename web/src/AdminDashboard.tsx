@@ -13,6 +13,7 @@ import {
   deleteApiKey,
   setKeyStatus,
   fetchProfile,
+  fetchRequestLog,
   fetchRequestLogs,
   fetchSummary,
   fetchVersion,
@@ -253,7 +254,7 @@ function statusLabel(status: string, strings: AdminTranslations): string {
 }
 
 function formatErrorMessage(log: RequestLog, errorsStrings: AdminTranslations['logs']['errors']): string {
-  const message = log.error_message?.trim()
+  const message = log.error_message?.trim() || log.response_summary?.trim()
   if (message) {
     return message
   }
@@ -1033,6 +1034,8 @@ function AdminDashboard(): JSX.Element {
     setSubmitting(true)
     try {
       const { token } = await createToken(note || undefined)
+      // The server only reveals a secret for a short while after it is issued.
+      tokenSecretCacheRef.current.set(token.split('-')[1], token)
       setNewTokenNote('')
       try { await navigator.clipboard?.writeText(token) } catch {}
       const controller = new AbortController()
@@ -2787,8 +2790,20 @@ function LogDetails({ log, strings }: { log: RequestLog; strings: AdminTranslati
   const dropped = (log.dropped_headers ?? []).filter((value) => value.trim().length > 0)
   const httpLabel = `${strings.logs.table.httpStatus}: ${log.http_status ?? strings.logs.errors.none}`
   const mcpLabel = `${strings.logs.table.mcpStatus}: ${log.mcp_status ?? strings.logs.errors.none}`
-  const requestBody = log.request_body ?? strings.logDetails.noBody
-  const responseBody = log.response_body ?? strings.logDetails.noBody
+  const [bodies, setBodies] = useState<Pick<RequestLog, 'request_body' | 'response_body'> | null>(null)
+
+  useEffect(() => {
+    const controller = new AbortController()
+    fetchRequestLog(log.id, controller.signal)
+      .then((detail) => setBodies({ request_body: detail.request_body, response_body: detail.response_body }))
+      .catch(() => {
+        if (!controller.signal.aborted) setBodies({ request_body: null, response_body: null })
+      })
+    return () => controller.abort()
+  }, [log.id])
+
+  const requestBody = bodies ? bodies.request_body ?? strings.logDetails.noBody : '…'
+  const responseBody = bodies ? bodies.response_body ?? strings.logDetails.noBody : '…'
 
   return (
     <div className="log-details-panel">
@@ -200,6 +200,9 @@ function PublicHome(): JSX.Element {
             quotaDailyLimit: data.token.quotaDailyLimit ?? TOKEN_DAILY_LIMIT,
             quotaMonthlyUsed: data.token.quotaMonthlyUsed ?? 0,
             quotaMonthlyLimit: data.token.quotaMonthlyLimit ?? TOKEN_MONTHLY_LIMIT,
+            slaWindowDays: data.token.slaWindowDays ?? 30,
+            slaSuccessRate: data.token.slaSuccessRate ?? null,
+            slaP95LatencyMs: data.token.slaP95LatencyMs ?? null,
           }
           setTokenMetrics(next)
           setRecentTokenUsage(next)
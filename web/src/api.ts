@@ -25,6 +25,9 @@ export interface TokenMetrics {
   quotaDailyLimit: number
   quotaMonthlyUsed: number
   quotaMonthlyLimit: number
+  slaWindowDays: number
+  slaSuccessRate: number | null
+  slaP95LatencyMs: number | null
 }
 
 export interface TokenHourlyBucket {
@@ -44,6 +47,8 @@ export interface TokenUsageBucket {
 // Public token logs (per access token)
 export interface PublicTokenLog {
   id: number
+  /** UUIDv7 for external references. */
+  public_id: string | null
   method: string
   path: string
   query: string | null
@@ -57,6 +62,7 @@ export interface PublicTokenLog {
 // Server returns camelCase. Define the server shape and map to snake_case used in UI.
 interface ServerPublicTokenLog {
   id: number
+  publicId: string | null
   method: string
   path: string
   query: string | null
@@ -76,14 +82,21 @@ export interface ApiKeyStats {
   quota_limit: number | null
   quota_remaining: number | null
   quota_synced_at: number | null
+  quota_next_sync_at: number | null
   total_requests: number
   success_count: number
   error_count: number
   quota_exhausted_count: number
+  projected_month_usage: number | null
+  projected_overage: number | null
+  in_flight: number
+  cooldown_until: number | null
 }
 
 export interface RequestLog {
   id: number
+  /** UUIDv7 for external references; also accepted by `/api/logs/:id`. */
+  public_id: string | null
   key_id: string
   auth_token_id: string | null
   method: string
@@ -98,6 +111,29 @@ export interface RequestLog {
   response_body: string | null
   forwarded_headers: string[]
   dropped_headers: string[]
+  /** Upstream round-trip time; null on rows logged before latency was recorded. */
+  latency_ms: number | null
+  /** 1-based; above 1 when quota failover retried the call on another key. */
+  attempt: number
+  /** First result title or error text parsed from the response; null on older rows. */
+  response_summary: string | null
+  /** Times this response was replayed from the response cache. */
+  cache_hits: number
+  request_body_sha256: string | null
+  request_body_len: number | null
+  response_body_sha256: string | null
+  response_body_len: number | null
+  /** Only present on `/api/logs/:id`: whether the stored bodies still match their digests. */
+  bodies_intact?: boolean
+  /** Only present on `/api/logs/:id`. */
+  annotations?: LogAnnotation[]
+}
+
+export interface LogAnnotation {
+  id: number
+  note: string
+  author: string | null
+  created_at: number
 }
 
 export interface ApiKeySecret {
@@ -113,6 +149,10 @@ export interface AuthToken {
   total_requests: number
   created_at: number
   last_used_at: number | null
+  /** `default` unless moved by an admin or a tier policy. */
+  tier: string
+  /** Unix seconds after which the token is rejected and then disabled; null never expires. */
+  expires_at: number | null
   quota_state: 'normal' | 'hour' | 'day' | 'month'
   quota_hourly_used: number
   quota_hourly_limit: number
@@ -123,6 +163,7 @@ export interface AuthToken {
   quota_hourly_reset_at: number | null
   quota_daily_reset_at: number | null
   quota_monthly_reset_at: number | null
+  deleted_at?: number // only on soft-deleted tokens (include_deleted=true)
 }
 
 export interface AuthTokenSecret {
@@ -173,6 +214,7 @@ export async function fetchPublicLogs(token: string, limit = 20, signal?: AbortS
   const data = (await res.json()) as ServerPublicTokenLog[]
   return data.map((it) => ({
     id: it.id,
+    public_id: it.publicId,
     method: it.method,
     path: it.path,
     query: it.query,
@@ -193,6 +235,70 @@ export function fetchApiKeyDetail(id: string, signal?: AbortSignal): Promise<Api
   return requestJson(`/api/keys/${encoded}`, { signal })
 }
 
+export interface KeyErrorDigest {
+  statusCode: number | null
+  tavilyStatusCode: number | null
+  resultStatus: string
+  message: string | null
+  count: number
+  firstSeen: number
+  lastSeen: number
+}
+
+/** Most recent distinct failures of a key, newest first. */
+export function fetchApiKeyErrors(id: string, limit = 20, signal?: AbortSignal): Promise<KeyErrorDigest[]> {
+  const encoded = encodeURIComponent(id)
+  return requestJson(`/api/keys/${encoded}/errors?limit=${limit}`, { signal })
+}
+
+export interface KeySpendForecast {
+  keyId: string
+  quotaLimit: number
+  quotaRemaining: number
+  dailyBurn: number
+  projectedMonthUsage: number
+  projectedOverage: number
+}
+
+export interface KeysForecast {
+  generatedAt: number
+  keys: KeySpendForecast[]
+}
+
+export interface KeyLookupMatch {
+  id: string
+  status: string
+  exact: boolean
+}
+
+/** Find stored keys by a full secret or a prefix of at least 12 characters. */
+export function lookupApiKeys(secret: string): Promise<{ matches: KeyLookupMatch[] }> {
+  return requestJson('/api/keys/lookup', {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify({ secret }),
+  })
+}
+
+export interface TokenLookupMatch {
+  id: string
+  note: string | null
+  group: string | null
+  enabled: boolean
+  deleted: boolean
+  secret_match: 'exact' | 'prefix' | 'unverified' | null
+}
+
+/** Find tokens a partial `th-<id>-<secret>` value may belong to. */
+export function lookupTokens(prefix: string): Promise<{ matches: TokenLookupMatch[] }> {
+  return requestJson(`/api/tokens/lookup?${new URLSearchParams({ prefix }).toString()}`)
+}
+
+/** Month-end spending projection per key, from synced quota deltas. */
+export function fetchKeysForecast(signal?: AbortSignal): Promise<KeysForecast> {
+  return requestJson('/api/keys/forecast', { signal })
+}
+
 export function fetchApiKeySecret(id: string, signal?: AbortSignal): Promise<ApiKeySecret> {
   const encoded = encodeURIComponent(id)
   return requestJson(`/api/keys/${encoded}/secret`, { signal })
@@ -214,8 +320,34 @@ export async function syncApiKeyUsage(id: string): Promise<void> {
   }
 }
 
+export interface KeyVerification {
+  keyId: string
+  verdict: 'valid' | 'invalid' | 'quota_exhausted'
+  upstreamStatus: number
+  status: string
+  quotaLimit: number | null
+  quotaRemaining: number | null
+}
+
+export async function verifyApiKey(id: string): Promise<KeyVerification> {
+  const encoded = encodeURIComponent(id)
+  const res = await fetch(`/api/keys/${encoded}/verify`, { method: 'POST' })
+  if (!res.ok) {
+    let message = ''
+    try {
+      const data = await res.json()
+      message = (data?.detail as string) ?? (data?.error as string) ?? ''
+    } catch {
+      message = await res.text().catch(() => '')
+    }
+    throw new Error((message || 'Failed to verify key') + ` (HTTP ${res.status})`)
+  }
+  return res.json()
+}
+
 export interface JobLogView {
   id: number
+  public_id: string | null
   job_type: string
   key_id: string | null
   status: string
@@ -248,6 +380,18 @@ export async function addApiKey(apiKey: string): Promise<CreateKeyResponse> {
   })
 }
 
+export interface DemoDataSummary {
+  apiKeys: number
+  tokens: number
+  requestLogs: number
+  tokenLogs: number
+}
+
+/** Dev only (`--dev-open-admin`): seeds demo keys, tokens and usage history. */
+export async function seedDemoData(): Promise<DemoDataSummary> {
+  return await requestJson('/api/dev/seed-demo-data', { method: 'POST' })
+}
+
 export interface AddApiKeysBatchSummary {
   input_lines: number
   valid_lines: number
@@ -286,6 +430,12 @@ export async function deleteApiKey(id: string): Promise<void> {
   })
 }
 
+export async function restoreApiKey(id: string): Promise<void> {
+  const encoded = encodeURIComponent(id)
+  const res = await fetch(`/api/keys/${encoded}/restore`, { method: 'POST' })
+  if (!res.ok) throw new Error(`Failed to restore key: ${res.status}`)
+}
+
 export type KeyAdminStatus = 'active' | 'disabled'
 
 export async function setKeyStatus(id: string, status: KeyAdminStatus): Promise<void> {
@@ -352,12 +502,53 @@ export function fetchRequestLogs(
   return requestJson(`/api/logs?${params.toString()}`, { signal })
 }
 
+/** List endpoints omit bodies; load them on demand for the detail view. */
+export function fetchRequestLog(id: number | string, signal?: AbortSignal): Promise<RequestLog> {
+  return requestJson(`/api/logs/${id}`, { signal })
+}
+
+/** Attach an admin note to a request log (`id` or `public_id`). */
+export function annotateRequestLog(id: number | string, note: string): Promise<LogAnnotation> {
+  return requestJson(`/api/logs/${id}/annotations`, {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify({ note }),
+  })
+}
+
+/** Attach an admin note to one entry of a token's log. */
+export function annotateTokenLog(tokenId: string, logId: number, note: string): Promise<LogAnnotation> {
+  const encoded = encodeURIComponent(tokenId)
+  return requestJson(`/api/tokens/${encoded}/logs/${logId}/annotations`, {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify({ note }),
+  })
+}
+
+export async function deleteLogAnnotation(id: number): Promise<void> {
+  const res = await fetch(`/api/log-annotations/${id}`, { method: 'DELETE' })
+  if (!res.ok) {
+    throw new Error(`Failed to delete annotation: ${res.status}`)
+  }
+}
+
+export interface JobPause {
+  jobType: string
+  pausedUntil: number
+}
+
+export interface JobsPage extends Paginated<JobLogView> {
+  /** Scheduled job types currently paused by an operator. */
+  paused: JobPause[]
+}
+
 export function fetchJobs(
   page = 1,
   perPage = 10,
   group: JobGroup = 'all',
   signal?: AbortSignal,
-): Promise<Paginated<JobLogView>> {
+): Promise<JobsPage> {
   const params = new URLSearchParams({
     page: String(page),
     per_page: String(perPage),
@@ -368,10 +559,166 @@ export function fetchJobs(
   return requestJson(`/api/jobs?${params.toString()}`, { signal })
 }
 
+export async function pauseJob(jobType: string, durationSecs?: number): Promise<JobPause> {
+  return await requestJson(`/api/jobs/${encodeURIComponent(jobType)}/pause`, {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify(durationSecs === undefined ? {} : { durationSecs }),
+  })
+}
+
+export async function resumeJob(jobType: string): Promise<void> {
+  const res = await fetch(`/api/jobs/${encodeURIComponent(jobType)}/resume`, { method: 'POST' })
+  if (!res.ok) throw new Error(`Failed to resume job: ${res.status}`)
+}
+
+export interface MaintenanceMode {
+  enabled: boolean
+  since: number | null
+  until: number | null
+  message: string | null
+  retryAfterSecs: number | null
+}
+
+export async function fetchMaintenance(signal?: AbortSignal): Promise<MaintenanceMode> {
+  return requestJson('/api/admin/maintenance', { signal })
+}
+
+export async function setMaintenance(
+  enabled: boolean,
+  options: { durationSecs?: number; message?: string } = {},
+): Promise<MaintenanceMode> {
+  return await requestJson('/api/admin/maintenance', {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify({ enabled, ...options }),
+  })
+}
+
+export interface ResponseCacheEntry {
+  key: string
+  path: string
+  storedAt: number
+  expiresAt: number
+  hits: number
+  sizeBytes: number
+}
+
+export interface ResponseCache {
+  enabled: boolean
+  ttlSecs: number
+  maxEntries: number
+  maxBytes: number
+  sizeBytes: number
+  /** Lookups answered from / missing the cache since startup. */
+  hits: number
+  misses: number
+  entries: ResponseCacheEntry[]
+}
+
+export function fetchResponseCache(signal?: AbortSignal): Promise<ResponseCache> {
+  return requestJson('/api/cache', { signal })
+}
+
+export async function flushResponseCache(): Promise<{ removed: number }> {
+  return await requestJson('/api/cache', { method: 'DELETE' })
+}
+
+export function requestLogsExportUrl(format: 'csv' | 'jsonl', since?: string, until?: string): string {
+  const params = new URLSearchParams({ format })
+  if (since) params.set('since', since)
+  if (until) params.set('until', until)
+  return `/api/logs/export?${params.toString()}`
+}
+
+export interface AvailabilityDay {
+  dayStart: number
+  totalMinutes: number
+  availableMinutes: number
+  noActiveKeyMinutes: number
+  lowSuccessMinutes: number
+  requests: number
+  successes: number
+  thresholdPercent: number
+  computedAt: number
+}
+
+export interface AvailabilityReport {
+  since: number
+  until: number
+  totalMinutes: number
+  availableMinutes: number
+  availabilityPercent: number | null
+  days: AvailabilityDay[]
+}
+
+export function fetchAvailabilityReport(
+  since?: string,
+  until?: string,
+  signal?: AbortSignal,
+): Promise<AvailabilityReport> {
+  const params = new URLSearchParams()
+  if (since) params.set('since', since)
+  if (until) params.set('until', until)
+  const query = params.toString()
+  return requestJson(`/api/reports/availability${query ? `?${query}` : ''}`, { signal })
+}
+
+export interface LatencyPercentiles {
+  count: number
+  p50Ms: number | null
+  p95Ms: number | null
+  p99Ms: number | null
+}
+
+export interface KeyLatency extends LatencyPercentiles {
+  keyId: string
+}
+
+export interface LatencyReport {
+  windowSecs: number
+  since: number
+  overall: LatencyPercentiles
+  /** Slowest p95 first. */
+  keys: KeyLatency[]
+}
+
+/** `window` is `<n>m`, `<n>h` or `<n>d`; the server defaults to `24h`. */
+export function fetchLatencyReport(window?: string, signal?: AbortSignal): Promise<LatencyReport> {
+  const query = window ? `?window=${encodeURIComponent(window)}` : ''
+  return requestJson(`/api/metrics/latency${query}`, { signal })
+}
+
+export interface TokenGroupThrottle {
+  active: boolean
+  factorPercent: number
+  requests: number
+  externalFailures: number
+  startedAt: number
+  expiresAt: number
+  liftedAt: number | null
+}
+
 export interface TokenGroup {
   name: string
   tokenCount: number
   latestCreatedAt: number
+  throttle: TokenGroupThrottle | null
+  /** Static headers added to `/mcp` responses of the group's tokens. */
+  responseHeaders: Record<string, string>
+  note: string | null
+  /** Limits on the summed usage of all member tokens; `null` is unlimited. */
+  hourlyLimit: number | null
+  dailyLimit: number | null
+  monthlyLimit: number | null
+  usage: { hourlyUsed: number; dailyUsed: number; monthlyUsed: number }
+}
+
+export interface TokenGroupSettings {
+  note?: string | null
+  hourlyLimit?: number | null
+  dailyLimit?: number | null
+  monthlyLimit?: number | null
 }
 
 export function fetchTokens(
@@ -403,6 +750,12 @@ export async function deleteToken(id: string): Promise<void> {
   if (!res.ok) throw new Error(`Failed to delete token: ${res.status}`)
 }
 
+export async function restoreToken(id: string): Promise<void> {
+  const encoded = encodeURIComponent(id)
+  const res = await fetch(`/api/tokens/${encoded}/restore`, { method: 'POST' })
+  if (!res.ok) throw new Error(`Failed to restore token: ${res.status}`)
+}
+
 export async function setTokenEnabled(id: string, enabled: boolean): Promise<void> {
   const encoded = encodeURIComponent(id)
   const res = await fetch(`/api/tokens/${encoded}/status`, {
@@ -423,6 +776,44 @@ export async function updateTokenNote(id: string, note: string): Promise<void> {
   if (!res.ok) throw new Error(`Failed to update token note: ${res.status}`)
 }
 
+export async function updateTokenTier(id: string, tier: string): Promise<void> {
+  const encoded = encodeURIComponent(id)
+  const res = await fetch(`/api/tokens/${encoded}/tier`, {
+    method: 'PATCH',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify({ tier }),
+  })
+  if (!res.ok) throw new Error(`Failed to update token tier: ${res.status}`)
+}
+
+/** Sets (`expiresAt` ISO string), clears (`null`) or extends (`extendDays`) a token's expiry. */
+export function updateTokenExpiry(
+  id: string,
+  change: { expiresAt: string | null } | { extendDays: number },
+): Promise<{ expires_at: number | null }> {
+  const body = 'extendDays' in change ? { extend_days: change.extendDays } : { expires_at: change.expiresAt }
+  return requestJson(`/api/tokens/${encodeURIComponent(id)}/expiry`, {
+    method: 'PATCH',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify(body),
+  })
+}
+
+export interface TokenTierChange {
+  id: number
+  fromTier: string
+  toTier: string
+  /** Policy rule name, or `admin`. */
+  rule: string
+  reason: string | null
+  changedAt: number
+}
+
+export function fetchTokenTierChanges(id: string, signal?: AbortSignal): Promise<TokenTierChange[]> {
+  const encoded = encodeURIComponent(id)
+  return requestJson(`/api/tokens/${encoded}/tier-changes`, { signal })
+}
+
 export function fetchTokenSecret(id: string, signal?: AbortSignal): Promise<AuthTokenSecret> {
   const encoded = encodeURIComponent(id)
   return requestJson(`/api/tokens/${encoded}/secret`, { signal })
@@ -445,10 +836,104 @@ export async function createTokensBatch(group: string, count: number, note?: str
   })
 }
 
+export type BulkTokenAction = 'enable' | 'disable' | 'delete' | 'rotate' | 'move_group'
+
+export interface BulkTokensResponse {
+  action: BulkTokenAction
+  updated: number
+  ids: string[]
+  /** New full tokens, only for `rotate`. */
+  tokens?: string[]
+}
+
+/** Applies `action` to all `ids` at once; nothing changes (HTTP 404) if an id is unknown. */
+export function bulkTokens(ids: string[], action: BulkTokenAction, group?: string | null): Promise<BulkTokensResponse> {
+  return requestJson('/api/tokens/bulk', {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify({ ids, action, group }),
+  })
+}
+
 export function fetchTokenGroups(signal?: AbortSignal): Promise<TokenGroup[]> {
   return requestJson('/api/tokens/groups', { signal })
 }
 
+export function saveTokenGroup(name: string, settings: TokenGroupSettings): Promise<TokenGroup> {
+  return requestJson(`/api/tokens/groups/${encodeURIComponent(name)}`, {
+    method: 'PUT',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify(settings),
+  })
+}
+
+export async function deleteTokenGroup(name: string): Promise<void> {
+  const res = await fetch(`/api/tokens/groups/${encodeURIComponent(name)}`, { method: 'DELETE' })
+  if (!res.ok) throw new Error(`Failed to delete token group: ${res.status}`)
+}
+
+export async function setTokenGroup(id: string, group: string | null): Promise<void> {
+  const res = await fetch(`/api/tokens/${encodeURIComponent(id)}/group`, {
+    method: 'PUT',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify({ group }),
+  })
+  if (!res.ok) throw new Error(`Failed to set token group: ${res.status}`)
+}
+
+export async function liftTokenGroupThrottle(name: string): Promise<void> {
+  const encoded = encodeURIComponent(name)
+  const res = await fetch(`/api/tokens/groups/${encoded}/throttle`, { method: 'DELETE' })
+  if (!res.ok) throw new Error(`Failed to lift group throttle: ${res.status}`)
+}
+
+export interface TokenResponseHeaders {
+  group: string | null
+  groupHeaders: Record<string, string>
+  tokenHeaders: Record<string, string>
+  /** Group headers overridden by the token's own; what `/mcp` responses carry. */
+  effective: Record<string, string>
+}
+
+export function fetchTokenResponseHeaders(id: string, signal?: AbortSignal): Promise<TokenResponseHeaders> {
+  return requestJson(`/api/tokens/${encodeURIComponent(id)}/response-headers`, { signal })
+}
+
+async function putResponseHeaders(url: string, headers: Record<string, string>): Promise<void> {
+  const res = await fetch(url, {
+    method: 'PUT',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify({ headers }),
+  })
+  if (!res.ok) throw new Error(`Failed to update response headers: ${res.status}`)
+}
+
+export function updateTokenResponseHeaders(id: string, headers: Record<string, string>): Promise<void> {
+  return putResponseHeaders(`/api/tokens/${encodeURIComponent(id)}/response-headers`, headers)
+}
+
+export function updateTokenGroupResponseHeaders(name: string, headers: Record<string, string>): Promise<void> {
+  return putResponseHeaders(`/api/tokens/groups/${encodeURIComponent(name)}/response-headers`, headers)
+}
+
+export interface UpstreamHeaderRules {
+  headers: Record<string, string>
+  tokens: Record<string, Record<string, string>>
+}
+
+export function fetchUpstreamHeaderRules(signal?: AbortSignal): Promise<UpstreamHeaderRules> {
+  return requestJson('/api/config/upstream-headers', { signal })
+}
+
+export async function updateUpstreamHeaderRules(rules: UpstreamHeaderRules): Promise<void> {
+  const res = await fetch('/api/config/upstream-headers', {
+    method: 'PUT',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify(rules),
+  })
+  if (!res.ok) throw new Error(`Failed to update upstream headers: ${res.status}`)
+}
+
 export function fetchTokenHourlyBuckets(id: string, hours = 25, signal?: AbortSignal): Promise<TokenHourlyBucket[]> {
   const encoded = encodeURIComponent(id)
   const params = new URLSearchParams({ hours: String(hours) })
@@ -468,6 +953,26 @@ export function fetchTokenUsageSeries(
   return requestJson(`/api/tokens/${encoded}/metrics/usage-series?${search.toString()}`, { signal })
 }
 
+export interface TokenQuotaSnapshot {
+  snapshot_at: number
+  monthly_used: number
+  monthly_limit: number
+  monthly_remaining: number
+}
+
+export function fetchTokenQuotaBurndown(
+  id: string,
+  params: { since?: string; until?: string } = {},
+  signal?: AbortSignal,
+): Promise<TokenQuotaSnapshot[]> {
+  const encoded = encodeURIComponent(id)
+  const search = new URLSearchParams()
+  if (params.since) search.set('since', params.since)
+  if (params.until) search.set('until', params.until)
+  const query = search.toString()
+  return requestJson(`/api/tokens/${encoded}/metrics/burndown${query ? `?${query}` : ''}`, { signal })
+}
+
 export type TokenLeaderboardPeriod = 'day' | 'month' | 'all'
 export type TokenLeaderboardFocus = 'usage' | 'errors' | 'other'
 
@@ -855,7 +855,7 @@ export const translations: Record<Language, TranslationShape> = {
           httpStatus: 'HTTP Status',
           mcpStatus: 'MCP Status',
           result: 'Result',
-          error: 'Error',
+          error: 'Error / Summary',
         },
         toggles: {
           show: 'Show request details',
@@ -1338,7 +1338,7 @@ export const translations: Record<Language, TranslationShape> = {
           httpStatus: 'HTTP 状态码',
           mcpStatus: 'MCP 状态码',
           result: '结果',
-          error: '错误',
+          error: '错误 / 摘要',
         },
         toggles: {
           show: '展开请求详情',
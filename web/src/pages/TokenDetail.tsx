@@ -26,6 +26,17 @@ interface TokenDetailInfo {
   quota_hourly_reset_at: number | null
   quota_daily_reset_at: number | null
   quota_monthly_reset_at: number | null
+  sla?: TokenSla
+}
+
+interface TokenSla {
+  window_days: number
+  success_count: number
+  system_failure_count: number
+  external_failure_count: number
+  quota_exhausted_count: number
+  success_rate: number | null
+  p95_latency_ms: number | null
 }
 
 interface TokenSummary {